@@ -4,14 +4,27 @@ fn main() {
     initialize_mta().unwrap();
 
     println!("Found the following output devices:");
-    for device in &DeviceCollection::new(&Direction::Render).unwrap() {
-        let dev = device.unwrap();
-        let state = &dev.get_state().unwrap();
-        println!(
-            "Device: {:?}. State: {:?}",
-            &dev.get_friendlyname().unwrap(),
-            state
-        );
+    for info in DeviceCollection::new(&Direction::Render)
+        .unwrap()
+        .get_all_infos()
+        .unwrap()
+    {
+        println!("Device: {:?}. State: {:?}", info.friendly_name, info.state);
+        for error in info.errors.iter() {
+            println!("  Error: {}", error);
+        }
+    }
+
+    println!("Output devices by adapter:");
+    for adapter in DeviceCollection::new(&Direction::Render)
+        .unwrap()
+        .get_devices_by_adapter()
+        .unwrap()
+    {
+        println!("{}", adapter.adapter_name);
+        for dev in adapter.devices.iter() {
+            println!("  {}", dev.get_friendlyname().unwrap());
+        }
     }
 
     println!("Default output devices:");
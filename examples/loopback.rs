@@ -14,9 +14,8 @@ type Res<T> = Result<T, Box<dyn error::Error>>;
 fn playback_loop(rx_play: std::sync::mpsc::Receiver<Vec<u8>>) -> Res<()> {
     let device = get_default_device(&Direction::Render)?;
     let mut audio_client = device.get_iaudioclient()?;
-    let desired_format = WaveFormat::new(32, 32, &SampleType::Float, 44100, 2, None);
+    let desired_format = formats::float_44k_stereo();
 
-    let blockalign = desired_format.get_blockalign();
     debug!("Desired playback format: {:?}", desired_format);
 
     let (def_time, min_time) = audio_client.get_periods()?;
@@ -33,46 +32,33 @@ fn playback_loop(rx_play: std::sync::mpsc::Receiver<Vec<u8>>) -> Res<()> {
 
     let h_event = audio_client.set_get_eventhandle()?;
 
-    let mut buffer_frame_count = audio_client.get_bufferframecount()?;
-
     let render_client = audio_client.get_audiorenderclient()?;
-    let mut sample_queue: VecDeque<u8> = VecDeque::with_capacity(
-        100 * blockalign as usize * (1024 + 2 * buffer_frame_count as usize),
-    );
+    let mut writer = RenderWriter::new(render_client, &UnderrunPolicy::FillSilence);
+    writer.set_underrun_callback(|missing_frames| {
+        warn!(
+            "Playback underrun, filling {} frames with silence",
+            missing_frames
+        )
+    });
     audio_client.start_stream()?;
     loop {
-        buffer_frame_count = audio_client.get_available_space_in_frames()?;
-        trace!("New buffer frame count {}", buffer_frame_count);
-        while sample_queue.len() < (blockalign as usize * buffer_frame_count as usize) {
-            debug!("need more samples");
+        loop {
             match rx_play.try_recv() {
                 Ok(chunk) => {
                     trace!("got chunk");
-                    for element in chunk.iter() {
-                        sample_queue.push_back(*element);
-                    }
-                }
-                Err(mpsc::TryRecvError::Empty) => {
-                    warn!("no data, filling with zeros");
-                    for _ in 0..((blockalign as usize * buffer_frame_count as usize)
-                        - sample_queue.len())
-                    {
-                        sample_queue.push_back(0);
-                    }
+                    writer.push(&chunk);
                 }
+                Err(mpsc::TryRecvError::Empty) => break,
                 Err(_) => {
                     error!("Channel is closed");
-                    break;
+                    audio_client.stop_stream()?;
+                    return Ok(());
                 }
             }
         }
 
         trace!("write");
-        render_client.write_to_device_from_deque(
-            buffer_frame_count as usize,
-            &mut sample_queue,
-            None,
-        )?;
+        writer.write()?;
         trace!("write ok");
         if h_event.wait_for_event(100000).is_err() {
             error!("error, stopping playback");
@@ -88,7 +74,7 @@ fn capture_loop(tx_capt: std::sync::mpsc::SyncSender<Vec<u8>>, chunksize: usize)
     let device = get_default_device(&Direction::Capture)?;
     let mut audio_client = device.get_iaudioclient()?;
 
-    let desired_format = WaveFormat::new(32, 32, &SampleType::Float, 44100, 2, None);
+    let desired_format = formats::float_44k_stereo();
 
     let blockalign = desired_format.get_blockalign();
     debug!("Desired capture format: {:?}", desired_format);
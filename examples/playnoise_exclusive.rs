@@ -42,6 +42,14 @@ fn main() {
 
     let (def_period, min_period) = audio_client.get_periods().unwrap();
 
+    match audio_client.get_buffer_size_limits(&desired_format, true) {
+        Ok((min_buffer, max_buffer)) => debug!(
+            "buffer duration limits in 100ns units, minimum {}, maximum {}",
+            min_buffer, max_buffer
+        ),
+        Err(err) => debug!("Unable to read buffer duration limits: {}", err),
+    }
+
     // Set some period as an example, using 128 byte alignment to satisfy for example Intel HDA devices.
     let desired_period = audio_client
         .calculate_aligned_period_near(3 * min_period / 2, Some(128), &desired_format)
@@ -1,38 +1,11 @@
-use std::f64::consts::PI;
 use std::rc::Rc;
+use wasapi::examples_common::*;
 use wasapi::*;
 
 #[macro_use]
 extern crate log;
 use simplelog::*;
 
-struct SineGenerator {
-    time: f64,
-    freq: f64,
-    delta_t: f64,
-    amplitude: f64,
-}
-
-impl SineGenerator {
-    fn new(freq: f64, fs: f64, amplitude: f64) -> Self {
-        SineGenerator {
-            time: 0.0,
-            freq,
-            delta_t: 1.0 / fs,
-            amplitude,
-        }
-    }
-}
-
-impl Iterator for SineGenerator {
-    type Item = f32;
-    fn next(&mut self) -> Option<f32> {
-        self.time += self.delta_t;
-        let output = ((self.freq * self.time * PI * 2.).sin() * self.amplitude) as f32;
-        Some(output)
-    }
-}
-
 // Main loop
 fn main() {
     let _ = SimpleLogger::init(
@@ -75,16 +48,31 @@ fn main() {
 
     let render_client = audio_client.get_audiorenderclient().unwrap();
 
-    let mut callbacks = EventCallbacks::new();
-
-    callbacks.set_simple_volume_callback(move |vol, mute, _guid| {
-        println!("New simple volume {}, mute {}", vol, mute);
-    });
-    callbacks.set_state_callback(|state| println!("New state: {:?}", state));
-    callbacks.set_channel_volume_callback(|index, vol, _guid| {
-        println!("New channel volume {}, channel {}", vol, index)
-    });
-    callbacks.set_disconnected_callback(|reason| println!("Disconnected, reason: {:?}", reason));
+    let mut nbr_volume_changes = 0;
+    let callbacks = EventCallbacks::builder()
+        .on_volume(move |vol, mute, _guid| {
+            nbr_volume_changes += 1;
+            println!(
+                "New simple volume {}, mute {}, change number {}",
+                vol, mute, nbr_volume_changes
+            );
+        })
+        .on_state(|event| {
+            println!(
+                "New state: {:?}, session: {:?}",
+                event.state, event.session_id
+            )
+        })
+        .on_channel_volume(|index, vol, _guid| {
+            println!("New channel volume {}, channel {}", vol, index)
+        })
+        .on_disconnected(|event| {
+            println!(
+                "Disconnected, reason: {:?}, device: {:?}",
+                event.reason, event.device_id
+            )
+        })
+        .build();
 
     let callbacks_rc = Rc::new(callbacks);
     let callbacks_weak = Rc::downgrade(&callbacks_rc);
@@ -94,30 +82,27 @@ fn main() {
         .register_session_notification(callbacks_weak)
         .unwrap();
 
-    audio_client.start_stream().unwrap();
-    loop {
-        let buffer_frame_count = audio_client.get_available_space_in_frames().unwrap();
-
-        let mut data = vec![0u8; buffer_frame_count as usize * blockalign as usize];
-        for frame in data.chunks_exact_mut(blockalign as usize) {
-            let sample = gen.next().unwrap();
-            let sample_bytes = sample.to_le_bytes();
-            for value in frame.chunks_exact_mut(blockalign as usize / channels) {
-                for (bufbyte, sinebyte) in value.iter_mut().zip(sample_bytes.iter()) {
-                    *bufbyte = *sinebyte;
-                }
-            }
-        }
+    let endpoint_volume = device.get_endpointvolume().unwrap();
+    let _volume_notifications = endpoint_volume
+        .register_volume_notification(|event| {
+            println!(
+                "New device volume {}, mute {}, channels {:?}",
+                event.master_volume, event.mute, event.channel_volumes
+            )
+        })
+        .unwrap();
 
-        trace!("write");
-        render_client
-            .write_to_device(buffer_frame_count as usize, &data, None)
-            .unwrap();
-        trace!("write ok");
-        if h_event.wait_for_event(1000).is_err() {
-            error!("error, stopping playback");
-            audio_client.stop_stream().unwrap();
-            break;
-        }
-    }
+    audio_client.start_stream().unwrap();
+    run_render_loop(
+        &audio_client,
+        &render_client,
+        &h_event,
+        blockalign as usize,
+        1000,
+        |data| {
+            gen.fill_interleaved_f32(data, channels);
+            true
+        },
+    )
+    .unwrap();
 }
@@ -1,4 +1,3 @@
-use std::collections::VecDeque;
 use std::error;
 use std::fs::File;
 use std::io::prelude::*;
@@ -20,9 +19,8 @@ fn capture_loop(tx_capt: std::sync::mpsc::SyncSender<Vec<u8>>, chunksize: usize)
 
     let mut audio_client = device.get_iaudioclient()?;
 
-    let desired_format = WaveFormat::new(32, 32, &SampleType::Float, 44100, 2, None);
+    let desired_format = formats::float_44k_stereo();
 
-    let blockalign = desired_format.get_blockalign();
     debug!("Desired capture format: {:?}", desired_format);
 
     let (def_time, min_time) = audio_client.get_periods()?;
@@ -39,35 +37,12 @@ fn capture_loop(tx_capt: std::sync::mpsc::SyncSender<Vec<u8>>, chunksize: usize)
 
     let h_event = audio_client.set_get_eventhandle()?;
 
-    let buffer_frame_count = audio_client.get_bufferframecount()?;
-
-    let render_client = audio_client.get_audiocaptureclient()?;
-    let mut sample_queue: VecDeque<u8> = VecDeque::with_capacity(
-        100 * blockalign as usize * (1024 + 2 * buffer_frame_count as usize),
-    );
+    let capture_client = audio_client.get_audiocaptureclient()?;
     let session_control = audio_client.get_audiosessioncontrol()?;
-
     debug!("state before start: {:?}", session_control.get_state());
-    audio_client.start_stream()?;
-    debug!("state after start: {:?}", session_control.get_state());
 
-    loop {
-        while sample_queue.len() > (blockalign as usize * chunksize) {
-            debug!("pushing samples");
-            let mut chunk = vec![0u8; blockalign as usize * chunksize];
-            for element in chunk.iter_mut() {
-                *element = sample_queue.pop_front().unwrap();
-            }
-            tx_capt.send(chunk)?;
-        }
-        trace!("capturing");
-        render_client.read_from_device_to_deque(&mut sample_queue)?;
-        if h_event.wait_for_event(3000).is_err() {
-            error!("timeout error, stopping capture");
-            audio_client.stop_stream()?;
-            break;
-        }
-    }
+    let mut pump = CapturePump::new(chunksize, &ChannelFullPolicy::Block);
+    pump.run(&audio_client, &capture_client, &h_event, 3000, &tx_capt)?;
     Ok(())
 }
 
@@ -43,6 +43,9 @@ fn capture_loop(
 
     let mut sample_queue: VecDeque<u8> = VecDeque::new(); // just eat the reallocation because querying the buffer size gives massive values.
 
+    // The stream keeps running after the process exits, watch it to know when to stop.
+    let exit_watcher = ProcessExitWatcher::new(process_id)?;
+
     audio_client.start_stream().unwrap();
 
     loop {
@@ -65,6 +68,11 @@ fn capture_loop(
                 .read_from_device_to_deque(&mut sample_queue)
                 .unwrap();
         }
+        if exit_watcher.has_exited() {
+            info!("process {} exited, stopping capture", process_id);
+            audio_client.stop_stream().unwrap();
+            break;
+        }
         if h_event.wait_for_event(3000).is_err() {
             error!("timeout error, stopping capture");
             audio_client.stop_stream().unwrap();
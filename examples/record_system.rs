@@ -0,0 +1,68 @@
+use std::error::{self};
+use std::fs::File;
+use std::io::prelude::*;
+
+use wasapi::*;
+
+#[macro_use]
+extern crate log;
+use simplelog::*;
+type Res<T> = Result<T, Box<dyn error::Error>>;
+
+// Main loop
+fn main() -> Res<()> {
+    let _ = SimpleLogger::init(
+        LevelFilter::Debug,
+        ConfigBuilder::new()
+            .set_time_format_rfc3339()
+            .set_time_offset_to_local()
+            .unwrap()
+            .build(),
+    );
+
+    initialize_mta().ok().unwrap();
+
+    let desired_format = WaveFormat::new(32, 32, &SampleType::Float, 48000, 2, None);
+    let blockalign = desired_format.get_blockalign() as usize;
+    debug!("Desired capture format: {:?}", desired_format);
+
+    // Capture everything except the sounds made by this process
+    let process_id = std::process::id();
+    info!("Capturing all audio except from process ID: {}", process_id);
+    let mut audio_client = AudioClient::new_system_loopback_excluding(process_id)?;
+    audio_client.initialize_client(
+        &desired_format,
+        0,
+        &Direction::Capture,
+        &ShareMode::Shared,
+        true,
+    )?;
+
+    let h_event = audio_client.set_get_eventhandle()?;
+    let capture_client = audio_client.get_audiocaptureclient()?;
+
+    let mut outfile = File::create("recorded.raw")?;
+    info!("Saving captured raw data to 'recorded.raw'");
+
+    // Record for 10 seconds
+    let mut frames_left = 10 * desired_format.get_samplespersec() as usize;
+    let mut data = Vec::new();
+    audio_client.start_stream()?;
+    while frames_left > 0 {
+        while let Some(nbr_frames) = capture_client.get_next_nbr_frames()? {
+            if nbr_frames == 0 {
+                break;
+            }
+            data.resize(nbr_frames as usize * blockalign, 0);
+            let (nbr_read, _flags) = capture_client.read_from_device(&mut data)?;
+            outfile.write_all(&data[..nbr_read as usize * blockalign])?;
+            frames_left = frames_left.saturating_sub(nbr_read as usize);
+        }
+        if h_event.wait_for_event(3000).is_err() {
+            error!("timeout error, stopping capture");
+            break;
+        }
+    }
+    audio_client.stop_stream()?;
+    Ok(())
+}
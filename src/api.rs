@@ -1,53 +1,104 @@
 use num_integer::Integer;
+use std::cell::Cell;
 use std::cmp;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::c_void;
+use std::io::{self, Read};
 use std::mem::{size_of, ManuallyDrop};
 use std::ops::Deref;
 use std::pin::Pin;
 use std::rc::Weak;
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{error, fmt, ptr, slice};
 use widestring::U16CString;
+use windows::Wdk::System::SystemServices::RtlGetVersion;
 use windows::Win32::Media::Audio::{
     ActivateAudioInterfaceAsync, IActivateAudioInterfaceAsyncOperation,
     IActivateAudioInterfaceCompletionHandler, IActivateAudioInterfaceCompletionHandler_Impl,
+    PKEY_AudioEndpoint_Association, PKEY_AudioEndpoint_FormFactor,
+    PKEY_AudioEndpoint_FullRangeSpeakers, PKEY_AudioEndpoint_GUID,
+    PKEY_AudioEndpoint_PhysicalSpeakers, PKEY_AudioEngine_DeviceFormat,
     AUDIOCLIENT_ACTIVATION_PARAMS, AUDIOCLIENT_ACTIVATION_PARAMS_0,
     AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK, AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS,
-    PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE,
+    PROCESS_LOOPBACK_MODE, PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE,
     PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE, VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK,
 };
-use windows::Win32::System::Variant::VT_BLOB;
-use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
+use windows::Win32::System::SystemInformation::OSVERSIONINFOW;
+use windows::Win32::System::Variant::{
+    VT_BLOB, VT_BOOL, VT_BSTR, VT_CLSID, VT_EMPTY, VT_LPWSTR, VT_UI4,
+};
+use windows::Win32::UI::Shell::PropertiesSystem::{IPropertyStore, PROPERTYKEY};
 use windows::{
-    core::{HRESULT, PCSTR},
+    core::{GUID, HRESULT, PCSTR, PCWSTR, PWSTR},
     Win32::Devices::FunctionDiscovery::{
-        PKEY_DeviceInterface_FriendlyName, PKEY_Device_DeviceDesc, PKEY_Device_FriendlyName,
+        PKEY_DeviceInterface_FriendlyName, PKEY_Device_ContainerId, PKEY_Device_DeviceDesc,
+        PKEY_Device_FriendlyName,
+    },
+    Win32::Foundation::{
+        CloseHandle, CO_E_NOTINITIALIZED, E_ACCESSDENIED, HANDLE, RPC_E_CHANGED_MODE, S_OK,
+        WAIT_OBJECT_0, WAIT_TIMEOUT,
+    },
+    Win32::Media::Audio::Endpoints::{
+        IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioMeterInformation,
     },
-    Win32::Foundation::{HANDLE, WAIT_OBJECT_0},
     Win32::Media::Audio::{
-        eCapture, eCommunications, eConsole, eMultimedia, eRender, AudioSessionStateActive,
-        AudioSessionStateExpired, AudioSessionStateInactive, IAudioCaptureClient, IAudioClient,
-        IAudioClock, IAudioRenderClient, IAudioSessionControl, IAudioSessionEvents, IMMDevice,
-        IMMDeviceCollection, IMMDeviceEnumerator, MMDeviceEnumerator,
-        AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY, AUDCLNT_BUFFERFLAGS_SILENT,
-        AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR, AUDCLNT_SHAREMODE_EXCLUSIVE, AUDCLNT_SHAREMODE_SHARED,
-        AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
-        AUDCLNT_STREAMFLAGS_LOOPBACK, AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY, DEVICE_STATE_ACTIVE,
+        eCapture, eCommunications, eConsole, eMultimedia, eRender, AudioCategory_Alerts,
+        AudioCategory_Communications, AudioCategory_FarFieldSpeech,
+        AudioCategory_ForegroundOnlyMedia, AudioCategory_GameChat, AudioCategory_GameEffects,
+        AudioCategory_GameMedia, AudioCategory_Media, AudioCategory_Movie, AudioCategory_Other,
+        AudioCategory_SoundEffects, AudioCategory_Speech, AudioCategory_UniformSpeech,
+        AudioCategory_VoiceTyping, AudioClientProperties, AudioSessionStateActive,
+        AudioSessionStateExpired, AudioSessionStateInactive, EDataFlow, ERole, IAudioCaptureClient,
+        IAudioClient, IAudioClient2, IAudioClient3, IAudioClock, IAudioRenderClient,
+        IAudioSessionControl, IAudioSessionControl2, IAudioSessionEvents, IAudioSessionManager2,
+        IAudioSessionNotification, IAudioVolumeDuckNotification, IDeviceTopology, IMMDevice,
+        IMMDeviceCollection, IMMDeviceEnumerator, IMMEndpoint, IMMNotificationClient, IPart,
+        ISimpleAudioVolume, MMDeviceEnumerator, AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY,
+        AUDCLNT_BUFFERFLAGS_SILENT, AUDCLNT_BUFFERFLAGS_TIMESTAMP_ERROR,
+        AUDCLNT_E_UNSUPPORTED_FORMAT, AUDCLNT_E_WRONG_ENDPOINT_TYPE, AUDCLNT_SHAREMODE_EXCLUSIVE,
+        AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+        AUDCLNT_STREAMFLAGS_CROSSPROCESS, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+        AUDCLNT_STREAMFLAGS_LOOPBACK, AUDCLNT_STREAMFLAGS_NOPERSIST,
+        AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY, AUDCLNT_STREAMOPTIONS,
+        AUDCLNT_STREAMOPTIONS_AMBISONICS, AUDCLNT_STREAMOPTIONS_MATCH_FORMAT,
+        AUDCLNT_STREAMOPTIONS_RAW, AUDIO_STREAM_CATEGORY, DEVICE_STATE, DEVICE_STATE_ACTIVE,
         DEVICE_STATE_DISABLED, DEVICE_STATE_NOTPRESENT, DEVICE_STATE_UNPLUGGED, WAVEFORMATEX,
-        WAVEFORMATEXTENSIBLE,
+        WAVE_FORMAT_PCM,
+    },
+    Win32::Media::KernelStreaming::{
+        IKsFormatSupport, KSDATAFORMAT, KSDATAFORMAT_0, KSDATAFORMAT_SPECIFIER_WAVEFORMATEX,
+        KSDATAFORMAT_SUBTYPE_PCM, KSDATAFORMAT_TYPE_AUDIO,
     },
-    Win32::Media::KernelStreaming::WAVE_FORMAT_EXTENSIBLE,
-    Win32::System::Com::StructuredStorage::PropVariantToStringAlloc,
-    Win32::System::Com::STGM_READ,
+    Win32::Media::Multimedia::{KSDATAFORMAT_SUBTYPE_IEEE_FLOAT, WAVE_FORMAT_IEEE_FLOAT},
+    Win32::System::Com::StructuredStorage::{PropVariantToStringAlloc, PropVariantToUInt32},
     Win32::System::Com::{
-        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_APARTMENTTHREADED,
+        CoCreateGuid, CoCreateInstance, CoGetApartmentType, CoInitializeEx, CoTaskMemAlloc,
+        CoTaskMemFree, CoUninitialize, IIDFromString, APTTYPE, APTTYPEQUALIFIER, APTTYPE_MAINSTA,
+        APTTYPE_MTA, APTTYPE_STA, CLSCTX_ALL, CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED,
         COINIT_MULTITHREADED,
     },
-    Win32::System::Threading::{CreateEventA, WaitForSingleObject},
+    Win32::System::Com::{STGM_READ, STGM_READWRITE},
+    Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency},
+    Win32::System::Threading::{
+        CreateEventA, CreateWaitableTimerExW, OpenProcess, ResetEvent, SetEvent, SetWaitableTimer,
+        WaitForMultipleObjects, WaitForSingleObject, CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+        INFINITE, PROCESS_SYNCHRONIZE, TIMER_ALL_ACCESS,
+    },
 };
 use windows_core::{implement, IUnknown, Interface, PROPVARIANT};
 
-use crate::{make_channelmasks, AudioSessionEvents, EventCallbacks, WaveFormat};
+use crate::registration::AgileRegistration;
+use crate::rtassert::NoAllocGuard;
+use crate::simulate::check_simulated_error;
+use crate::watcher::wait_for_startup;
+use crate::{
+    make_channelmasks, AudioSessionEvents, ChannelSendError, ChannelSender,
+    DeviceNotificationCallbacks, DeviceNotifier, DeviceTopology, DuckNotificationCallbacks,
+    DuckNotifier, EndpointVolumeEvent, EndpointVolumeNotifier, EventCallbacks, FormatInfo,
+    SessionCreatedNotifier, SessionEvent, SessionEventSink, SpeakerMask, WaveFormat, Worker,
+};
 
 pub(crate) type WasapiRes<T> = Result<T, Box<dyn error::Error>>;
 
@@ -77,6 +128,158 @@ impl WasapiError {
     }
 }
 
+/// The types of notifications that can be registered for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NotificationType {
+    /// Audio session events, see [AudioSessionControl::register_session_notification].
+    SessionEvents,
+    /// Device notifications from the device enumerator.
+    DeviceNotifications,
+    /// Endpoint volume changes, see [AudioEndpointVolume::register_volume_notification].
+    EndpointVolume,
+    /// Creation of new audio sessions, see [AudioSessionManager2::register_session_notification].
+    SessionCreated,
+    /// Ducking of other streams by communication streams, see [AudioSessionManager2::register_duck_notification].
+    VolumeDuck,
+}
+
+impl fmt::Display for NotificationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            NotificationType::SessionEvents => write!(f, "SessionEvents"),
+            NotificationType::DeviceNotifications => write!(f, "DeviceNotifications"),
+            NotificationType::EndpointVolume => write!(f, "EndpointVolume"),
+            NotificationType::SessionCreated => write!(f, "SessionCreated"),
+            NotificationType::VolumeDuck => write!(f, "VolumeDuck"),
+        }
+    }
+}
+
+/// Error returned when registering for notifications fails.
+/// It tells which type of notification failed, and on which object.
+/// The underlying [windows::core::Error] is available as the error source.
+#[derive(Debug)]
+pub struct RegistrationError {
+    notification_type: NotificationType,
+    object: String,
+    source: windows::core::Error,
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to register {} notifications on {}, {}",
+            self.notification_type, self.object, self.source
+        )
+    }
+}
+
+impl error::Error for RegistrationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl RegistrationError {
+    pub(crate) fn new(
+        notification_type: NotificationType,
+        object: &str,
+        source: windows::core::Error,
+    ) -> Self {
+        RegistrationError {
+            notification_type,
+            object: object.to_owned(),
+            source,
+        }
+    }
+
+    /// Get the type of notification that failed.
+    pub fn get_notification_type(&self) -> NotificationType {
+        self.notification_type
+    }
+
+    /// Get a description of the object the registration was attempted on.
+    pub fn get_object(&self) -> &str {
+        &self.object
+    }
+
+    /// Get the HRESULT of the failed registration.
+    pub fn get_hresult(&self) -> HRESULT {
+        self.source.code()
+    }
+}
+
+/// Error returned by [AudioClient::initialize_client] when the endpoint doesn't support a stream
+/// in the requested direction and sharemode.
+/// For example when rendering to a capture device,
+/// or when capturing from a render device in exclusive mode, since loopback capture requires shared mode.
+///
+/// It is returned boxed like all other errors, use `downcast_ref` to get it.
+#[derive(Debug)]
+pub struct EndpointMismatchError {
+    expected: Direction,
+    actual: Direction,
+    sharemode: ShareMode,
+}
+
+impl fmt::Display for EndpointMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Wrong endpoint type, the endpoint supports {} but {} was requested, in {} mode",
+            self.expected, self.actual, self.sharemode
+        )
+    }
+}
+
+impl error::Error for EndpointMismatchError {}
+
+impl EndpointMismatchError {
+    /// Get the direction of the streams that the endpoint supports in the requested sharemode.
+    pub fn get_expected(&self) -> Direction {
+        self.expected
+    }
+
+    /// Get the direction that was requested.
+    pub fn get_actual(&self) -> Direction {
+        self.actual
+    }
+
+    /// Get the sharemode that was requested.
+    pub fn get_sharemode(&self) -> ShareMode {
+        self.sharemode
+    }
+}
+
+/// Convert a string allocated by COM to a String, and free the COM allocation.
+pub(crate) fn take_pwstr(pwstr: PWSTR) -> String {
+    let wide_str = unsafe { U16CString::from_ptr_str(pwstr.0) };
+    unsafe { CoTaskMemFree(Some(pwstr.0 as *const _)) };
+    wide_str.to_string_lossy()
+}
+
+/// Read the current value of the performance counter,
+/// the time base used by the timestamps of the audio APIs.
+pub fn query_performance_counter() -> WasapiRes<i64> {
+    let mut counter = 0;
+    unsafe { QueryPerformanceCounter(&mut counter)? };
+    Ok(counter)
+}
+
+/// Read the frequency of the performance counter, in counts per second.
+pub fn query_performance_frequency() -> WasapiRes<i64> {
+    let mut frequency = 0;
+    unsafe { QueryPerformanceFrequency(&mut frequency)? };
+    Ok(frequency)
+}
+
+/// Generate a new random GUID, for example for creating a new session with [AudioClient::set_session].
+pub fn generate_session_guid() -> WasapiRes<GUID> {
+    let guid = unsafe { CoCreateGuid()? };
+    Ok(guid)
+}
+
 /// Initializes COM for use by the calling thread for the multi-threaded apartment (MTA).
 pub fn initialize_mta() -> HRESULT {
     unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }
@@ -92,8 +295,187 @@ pub fn deinitialize() {
     unsafe { CoUninitialize() }
 }
 
+/// The type of COM apartment of a thread.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Apartment {
+    /// The multi-threaded apartment (MTA), preferred for most uses of Wasapi.
+    MultiThreaded,
+    /// A single-threaded apartment (STA).
+    /// Activating a process loopback client on some older Windows versions requires this.
+    SingleThreaded,
+}
+
+impl fmt::Display for Apartment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Apartment::MultiThreaded => write!(f, "MultiThreaded"),
+            Apartment::SingleThreaded => write!(f, "SingleThreaded"),
+        }
+    }
+}
+
+/// Error returned when COM can't be initialized in the requested apartment,
+/// usually because the thread has already been initialized in the other type of apartment.
+#[derive(Debug)]
+pub struct ApartmentError {
+    requested: Apartment,
+    current: Option<Apartment>,
+    hresult: HRESULT,
+}
+
+impl fmt::Display for ApartmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.current {
+            Some(current) => write!(
+                f,
+                "Unable to initialize COM in the {} apartment, the thread is already in the {} apartment",
+                self.requested, current
+            ),
+            None => write!(
+                f,
+                "Unable to initialize COM in the {} apartment, {}",
+                self.requested,
+                windows::core::Error::from(self.hresult)
+            ),
+        }
+    }
+}
+
+impl error::Error for ApartmentError {}
+
+impl ApartmentError {
+    /// Get the requested apartment.
+    pub fn get_requested(&self) -> Apartment {
+        self.requested
+    }
+
+    /// Get the apartment the thread is already in, if known.
+    pub fn get_current(&self) -> Option<Apartment> {
+        self.current
+    }
+
+    /// Get the HRESULT returned by `CoInitializeEx`.
+    pub fn get_hresult(&self) -> HRESULT {
+        self.hresult
+    }
+}
+
+/// Initialize COM for the calling thread in the given apartment.
+/// Initializing again in the same apartment is not an error,
+/// but each successful call must be balanced by a call to [deinitialize].
+/// Returns an [ApartmentError] if the thread is already in the other type of apartment.
+pub fn initialize_apartment(apartment: &Apartment) -> Result<(), ApartmentError> {
+    let hresult = match apartment {
+        Apartment::MultiThreaded => initialize_mta(),
+        Apartment::SingleThreaded => initialize_sta(),
+    };
+    if hresult.is_ok() {
+        return Ok(());
+    }
+    let current = if hresult == RPC_E_CHANGED_MODE {
+        get_current_apartment().ok().flatten()
+    } else {
+        None
+    };
+    Err(ApartmentError {
+        requested: *apartment,
+        current,
+        hresult,
+    })
+}
+
+/// Get the COM apartment of the calling thread,
+/// or `None` if COM has not been initialized on the thread.
+/// A thread that hasn't initialized COM, in a process where another thread is in the MTA,
+/// is implicitly in the MTA and reported as [Apartment::MultiThreaded].
+pub fn get_current_apartment() -> WasapiRes<Option<Apartment>> {
+    let mut apttype = APTTYPE::default();
+    let mut qualifier = APTTYPEQUALIFIER::default();
+    match unsafe { CoGetApartmentType(&mut apttype, &mut qualifier) } {
+        Ok(()) => {}
+        Err(err) if err.code() == CO_E_NOTINITIALIZED => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let apartment = match apttype {
+        APTTYPE_MTA => Some(Apartment::MultiThreaded),
+        APTTYPE_STA | APTTYPE_MAINSTA => Some(Apartment::SingleThreaded),
+        _ => None,
+    };
+    trace!("current apartment: {:?}, {:?}", apttype, qualifier);
+    Ok(apartment)
+}
+
+/// The first Windows build with [IAudioClient3](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioclient3), Windows 10.
+const BUILD_AUDIOCLIENT3: u32 = 10240;
+/// The first Windows build with process loopback capture.
+const BUILD_PROCESS_LOOPBACK: u32 = 20348;
+/// The first Windows build with [IAudioEffectsManager](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioeffectsmanager), Windows 11.
+const BUILD_EFFECTS_MANAGER: u32 = 22000;
+
+/// `PKEY_Devices_AudioDevice_RawProcessingSupported`, defined here to avoid enabling the storage features of the windows crate.
+const PKEY_DEVICES_AUDIODEVICE_RAWPROCESSINGSUPPORTED: PROPERTYKEY = PROPERTYKEY {
+    fmtid: GUID::from_u128(0x8943b373_388c_4395_b557_bc6dbaffafdb),
+    pid: 2,
+};
+
+/// Get the build number of the running Windows version, for example 19045 for Windows 10 22H2.
+///
+/// This uses `RtlGetVersion`, which reports the real version
+/// also for applications without a compatibility manifest.
+pub fn get_windows_build() -> WasapiRes<u32> {
+    let mut info = OSVERSIONINFOW {
+        dwOSVersionInfoSize: size_of::<OSVERSIONINFOW>() as u32,
+        ..Default::default()
+    };
+    unsafe { RtlGetVersion(&mut info) }.ok()?;
+    debug!(
+        "Windows version {}.{}, build {}",
+        info.dwMajorVersion, info.dwMinorVersion, info.dwBuildNumber
+    );
+    Ok(info.dwBuildNumber)
+}
+
+/// Check if the running Windows version is at least the given build.
+/// If the build can't be read, this returns false.
+fn windows_build_is_at_least(build: u32) -> bool {
+    match get_windows_build() {
+        Ok(current) => current >= build,
+        Err(err) => {
+            warn!("Unable to read the Windows version, {}", err);
+            false
+        }
+    }
+}
+
+/// Check if [AudioClient::get_shared_mode_engine_period] and other methods
+/// using [IAudioClient3](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioclient3) are supported.
+///
+/// When there is a default playback device, this is checked by querying its audio client for the interface.
+/// Otherwise the check is based on the Windows version.
+pub fn supports_audioclient3() -> bool {
+    let client = get_default_device(&Direction::Render).and_then(|dev| dev.get_iaudioclient());
+    match client {
+        Ok(client) => client.client.cast::<IAudioClient3>().is_ok(),
+        Err(_) => windows_build_is_at_least(BUILD_AUDIOCLIENT3),
+    }
+}
+
+/// Check if the [IAudioEffectsManager](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioeffectsmanager)
+/// interface for controlling audio effects is available.
+/// The interface can only be probed on an initialized client, so this check is based on the Windows version.
+pub fn supports_effects_manager() -> bool {
+    windows_build_is_at_least(BUILD_EFFECTS_MANAGER)
+}
+
+/// Check if capturing the audio of a process, with [AudioClient::new_application_loopback_client]
+/// or [AudioClient::new_system_loopback_excluding], is supported. This check is based on the Windows version.
+pub fn supports_process_loopback() -> bool {
+    windows_build_is_at_least(BUILD_PROCESS_LOOPBACK)
+}
+
 /// Audio direction, playback or capture.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Direction {
     Render,
     Capture,
@@ -145,6 +527,7 @@ impl fmt::Display for ShareMode {
 
 /// Sample type, float or integer
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum SampleType {
     Float,
     Int,
@@ -161,7 +544,7 @@ impl fmt::Display for SampleType {
 
 /// Possible states for an [AudioSessionControl], an enum representing the
 /// [AudioSessionStateXxx constants](https://learn.microsoft.com/en-us/windows/win32/api/audiosessiontypes/ne-audiosessiontypes-audiosessionstate)
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum SessionState {
     /// The audio session is active. (At least one of the streams in the session is running.)
     Active,
@@ -183,7 +566,8 @@ impl fmt::Display for SessionState {
 
 /// Possible states for an [IMMDevice], an enum representing the
 /// [DEVICE_STATE_XXX constants](https://learn.microsoft.com/en-us/windows/win32/coreaudio/device-state-xxx-constants)
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum DeviceState {
     /// The audio endpoint device is active. That is, the audio adapter that connects to the
     /// endpoint device is present and enabled. In addition, if the endpoint device plugs int
@@ -213,207 +597,2135 @@ impl fmt::Display for DeviceState {
     }
 }
 
-/// Get the default playback or capture device for the console role
-pub fn get_default_device(direction: &Direction) -> WasapiRes<Device> {
-    get_default_device_for_role(direction, &Role::Console)
+/// The key of a device property, a wrapper for
+/// [PROPERTYKEY](https://learn.microsoft.com/en-us/windows/win32/api/wtypes/ns-wtypes-propertykey).
+/// The keys defined by Windows, like `PKEY_Device_FriendlyName`, can be converted with `From`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PropertyKey {
+    /// The format id of the property.
+    pub fmtid: GUID,
+    /// The property id.
+    pub pid: u32,
 }
 
-/// Get the default playback or capture device for a specific role
-pub fn get_default_device_for_role(direction: &Direction, role: &Role) -> WasapiRes<Device> {
-    let dir = match direction {
-        Direction::Capture => eCapture,
-        Direction::Render => eRender,
-    };
+impl From<PROPERTYKEY> for PropertyKey {
+    fn from(key: PROPERTYKEY) -> Self {
+        PropertyKey {
+            fmtid: key.fmtid,
+            pid: key.pid,
+        }
+    }
+}
 
-    let e_role = match role {
-        Role::Console => eConsole,
-        Role::Multimedia => eMultimedia,
-        Role::Communications => eCommunications,
-    };
+impl From<PropertyKey> for PROPERTYKEY {
+    fn from(key: PropertyKey) -> Self {
+        PROPERTYKEY {
+            fmtid: key.fmtid,
+            pid: key.pid,
+        }
+    }
+}
 
-    let enumerator: IMMDeviceEnumerator =
-        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
-    let device = unsafe { enumerator.GetDefaultAudioEndpoint(dir, e_role)? };
+impl fmt::Display for PropertyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?},{}", self.fmtid, self.pid)
+    }
+}
 
-    let dev = Device {
-        device,
-        direction: *direction,
-    };
-    debug!("default device {:?}", dev.get_friendlyname());
-    Ok(dev)
+/// The value of a device property.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    /// The property has no value.
+    Empty,
+    /// A string, from a `VT_LPWSTR` or `VT_BSTR` value.
+    String(String),
+    /// A `VT_UI4` value.
+    U32(u32),
+    /// A `VT_BOOL` value.
+    Bool(bool),
+    /// A `VT_BLOB` value.
+    Blob(Vec<u8>),
+    /// A `VT_CLSID` value.
+    Guid(GUID),
+    /// A value of a type that is not supported, with the `VARTYPE` of the value.
+    Unsupported(u16),
 }
 
-/// Calculate a period in units of 100ns that corresponds to the given number of buffer frames at the given sample rate.
-/// See the [IAudioClient documentation](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient-initialize#remarks).
-pub fn calculate_period_100ns(frames: i64, samplerate: i64) -> i64 {
-    ((10000.0 * 1000.0 / samplerate as f64 * frames as f64) + 0.5) as i64
+impl PropertyValue {
+    /// Convert a [PROPVARIANT] to a [PropertyValue].
+    fn from_propvariant(prop: &PROPVARIANT) -> WasapiRes<Self> {
+        let raw = prop.as_raw();
+        let vt = unsafe { raw.Anonymous.Anonymous.vt };
+        let value = match vt {
+            _ if vt == VT_EMPTY.0 => PropertyValue::Empty,
+            _ if vt == VT_LPWSTR.0 || vt == VT_BSTR.0 => {
+                let propstr = unsafe { PropVariantToStringAlloc(prop)? };
+                PropertyValue::String(take_pwstr(propstr))
+            }
+            _ if vt == VT_UI4.0 => PropertyValue::U32(u32::try_from(prop)?),
+            _ if vt == VT_BOOL.0 => PropertyValue::Bool(bool::try_from(prop)?),
+            _ if vt == VT_BLOB.0 => {
+                let blob = unsafe { raw.Anonymous.Anonymous.Anonymous.blob };
+                if blob.pBlobData.is_null() {
+                    PropertyValue::Blob(Vec::new())
+                } else {
+                    let data =
+                        unsafe { slice::from_raw_parts(blob.pBlobData, blob.cbSize as usize) };
+                    PropertyValue::Blob(data.to_vec())
+                }
+            }
+            _ if vt == VT_CLSID.0 => {
+                let guid_ptr = unsafe { raw.Anonymous.Anonymous.Anonymous.puuid };
+                if guid_ptr.is_null() {
+                    return Err(WasapiError::new("Got a null GUID property").into());
+                }
+                // The raw GUID has the same layout as GUID
+                PropertyValue::Guid(unsafe { *(guid_ptr as *const GUID) })
+            }
+            _ => PropertyValue::Unsupported(vt),
+        };
+        Ok(value)
+    }
+
+    /// Convert a [PropertyValue] to a [PROPVARIANT].
+    /// Strings are stored as `VT_LPWSTR`, the type used by the endpoint properties.
+    fn to_propvariant(&self) -> WasapiRes<PROPVARIANT> {
+        let (vt, value) = match self {
+            PropertyValue::Empty => return Ok(PROPVARIANT::default()),
+            PropertyValue::U32(value) => return Ok(PROPVARIANT::from(*value)),
+            PropertyValue::Bool(value) => return Ok(PROPVARIANT::from(*value)),
+            PropertyValue::String(value) => {
+                let wide = U16CString::from_str(value)?;
+                let nbr_bytes = (wide.len() + 1) * size_of::<u16>();
+                let ptr = copy_to_cotaskmem(wide.as_ptr() as *const u8, nbr_bytes)?;
+                (
+                    VT_LPWSTR.0,
+                    windows_core::imp::PROPVARIANT_0_0_0 {
+                        pwszVal: ptr as *mut u16,
+                    },
+                )
+            }
+            PropertyValue::Blob(data) => {
+                let ptr = copy_to_cotaskmem(data.as_ptr(), data.len())?;
+                (
+                    VT_BLOB.0,
+                    windows_core::imp::PROPVARIANT_0_0_0 {
+                        blob: windows_core::imp::BLOB {
+                            cbSize: data.len() as u32,
+                            pBlobData: ptr,
+                        },
+                    },
+                )
+            }
+            PropertyValue::Guid(guid) => {
+                let ptr = copy_to_cotaskmem(guid as *const GUID as *const u8, size_of::<GUID>())?;
+                (
+                    VT_CLSID.0,
+                    windows_core::imp::PROPVARIANT_0_0_0 {
+                        puuid: ptr as *mut _,
+                    },
+                )
+            }
+            PropertyValue::Unsupported(vt) => {
+                return Err(WasapiError::new(&format!(
+                    "Unable to write a value of unsupported type {}",
+                    vt
+                ))
+                .into())
+            }
+        };
+        let raw_prop = windows_core::imp::PROPVARIANT {
+            Anonymous: windows_core::imp::PROPVARIANT_0 {
+                Anonymous: windows_core::imp::PROPVARIANT_0_0 {
+                    vt,
+                    wReserved1: 0,
+                    wReserved2: 0,
+                    wReserved3: 0,
+                    Anonymous: value,
+                },
+            },
+        };
+        // The PROPVARIANT takes ownership of the allocation, and frees it when dropped.
+        Ok(unsafe { PROPVARIANT::from_raw(raw_prop) })
+    }
 }
 
-/// Struct wrapping an [IMMDeviceCollection](https://docs.microsoft.com/en-us/windows/win32/api/mmdeviceapi/nn-mmdeviceapi-immdevicecollection).
-pub struct DeviceCollection {
-    collection: IMMDeviceCollection,
-    direction: Direction,
+/// Copy data to a new allocation made with `CoTaskMemAlloc`.
+fn copy_to_cotaskmem(data: *const u8, nbr_bytes: usize) -> WasapiRes<*mut u8> {
+    let ptr = unsafe { CoTaskMemAlloc(cmp::max(nbr_bytes, 1)) } as *mut u8;
+    if ptr.is_null() {
+        return Err(WasapiError::new("Unable to allocate memory for a property value").into());
+    }
+    unsafe { ptr::copy_nonoverlapping(data, ptr, nbr_bytes) };
+    Ok(ptr)
 }
 
-impl DeviceCollection {
-    /// Get an [IMMDeviceCollection] of all active playback or capture devices
-    pub fn new(direction: &Direction) -> WasapiRes<DeviceCollection> {
-        let dir = match direction {
-            Direction::Capture => eCapture,
-            Direction::Render => eRender,
-        };
-        let enumerator: IMMDeviceEnumerator =
-            unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
-        let devs = unsafe { enumerator.EnumAudioEndpoints(dir, DEVICE_STATE_ACTIVE)? };
-        Ok(DeviceCollection {
-            collection: devs,
-            direction: *direction,
-        })
+/// The access mode for opening a [PropertyStore].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StoreAccess {
+    /// Read only access.
+    Read,
+    /// Read and write access, this requires administrator privileges.
+    ReadWrite,
+}
+
+impl fmt::Display for StoreAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StoreAccess::Read => write!(f, "Read"),
+            StoreAccess::ReadWrite => write!(f, "ReadWrite"),
+        }
     }
+}
 
-    /// Get the number of devices in an [IMMDeviceCollection]
-    pub fn get_nbr_devices(&self) -> WasapiRes<u32> {
-        let count = unsafe { self.collection.GetCount()? };
+/// Struct wrapping an [IPropertyStore](https://learn.microsoft.com/en-us/windows/win32/api/propsys/nn-propsys-ipropertystore)
+/// of an endpoint device, see [Device::open_property_store].
+pub struct PropertyStore {
+    store: IPropertyStore,
+    access: StoreAccess,
+}
+
+impl PropertyStore {
+    /// Get the number of properties in the store.
+    pub fn get_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.store.GetCount()? };
         Ok(count)
     }
 
-    /// Get a device from an [IMMDeviceCollection] using index
-    pub fn get_device_at_index(&self, idx: u32) -> WasapiRes<Device> {
-        let device = unsafe { self.collection.Item(idx)? };
-        Ok(Device {
-            device,
-            direction: self.direction,
-        })
+    /// Get the key of the property at an index.
+    pub fn get_key_at(&self, index: u32) -> WasapiRes<PropertyKey> {
+        let mut key = PROPERTYKEY::default();
+        unsafe { self.store.GetAt(index, &mut key)? };
+        Ok(PropertyKey::from(key))
     }
 
-    /// Get a device from an [IMMDeviceCollection] using name
-    pub fn get_device_with_name(&self, name: &str) -> WasapiRes<Device> {
-        let count = unsafe { self.collection.GetCount()? };
-        trace!("nbr devices {}", count);
-        for n in 0..count {
-            let device = self.get_device_at_index(n)?;
-            let devname = device.get_friendlyname()?;
-            if name == devname {
-                return Ok(device);
-            }
+    /// Read the value of a property.
+    pub fn get_value(&self, key: &PropertyKey) -> WasapiRes<PropertyValue> {
+        let prop = unsafe { self.store.GetValue(&PROPERTYKEY::from(*key))? };
+        PropertyValue::from_propvariant(&prop)
+    }
+
+    /// Read all properties in the store.
+    /// Values of unsupported types are returned as [PropertyValue::Unsupported].
+    pub fn get_all(&self) -> WasapiRes<Vec<(PropertyKey, PropertyValue)>> {
+        let count = self.get_count()?;
+        let mut properties = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let key = self.get_key_at(index)?;
+            let value = self.get_value(&key)?;
+            trace!("property {}: {:?}", key, value);
+            properties.push((key, value));
         }
-        Err(WasapiError::new(format!("Unable to find device {}", name).as_str()).into())
+        Ok(properties)
     }
 
-    /// Get the direction for this [DeviceCollection]
-    pub fn get_direction(&self) -> Direction {
-        self.direction
+    /// Set the value of a property.
+    /// The store must have been opened with [StoreAccess::ReadWrite].
+    /// The change is saved by [PropertyStore::commit].
+    pub fn set_value(&self, key: &PropertyKey, value: &PropertyValue) -> WasapiRes<()> {
+        if self.access != StoreAccess::ReadWrite {
+            return Err(WasapiError::new("The property store was opened as read only").into());
+        }
+        let prop = value.to_propvariant()?;
+        unsafe { self.store.SetValue(&PROPERTYKEY::from(*key), &prop)? };
+        debug!("set property {} to {:?}", key, value);
+        Ok(())
     }
-}
 
-/// Iterator for [DeviceCollection]
-pub struct DeviceCollectionIter<'a> {
-    collection: &'a DeviceCollection,
-    index: u32,
+    /// Save the changes made with [PropertyStore::set_value].
+    pub fn commit(&self) -> WasapiRes<()> {
+        unsafe { self.store.Commit()? };
+        Ok(())
+    }
 }
 
-impl<'a> Iterator for DeviceCollectionIter<'a> {
-    type Item = WasapiRes<Device>;
+/// The physical type of an endpoint device, an enum representing the values of the
+/// [EndpointFormFactor enum](https://learn.microsoft.com/en-us/windows/win32/api/mmdeviceapi/ne-mmdeviceapi-endpointformfactor)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum FormFactor {
+    /// An audio endpoint device that the user accesses remotely through a network.
+    RemoteNetworkDevice,
+    /// A set of speakers.
+    Speakers,
+    /// An audio endpoint device that sends a line-level analog signal to a line-input jack,
+    /// or receives one from a line-output jack.
+    LineLevel,
+    /// A set of headphones.
+    Headphones,
+    /// A microphone.
+    Microphone,
+    /// An earphone or a pair of earphones with an attached mouthpiece.
+    Headset,
+    /// The part of a telephone that is held in the hand.
+    Handset,
+    /// An endpoint device that connects to a digital passthrough connector of unknown type.
+    UnknownDigitalPassthrough,
+    /// An endpoint device that connects to an S/PDIF connector.
+    Spdif,
+    /// An endpoint device that connects to a digital display, like HDMI or DisplayPort.
+    DigitalAudioDisplayDevice,
+    /// An endpoint device of unknown type.
+    Unknown,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index < self.collection.get_nbr_devices().unwrap() {
-            let device = self.collection.get_device_at_index(self.index);
-            self.index += 1;
-            Some(device)
-        } else {
-            None
+impl fmt::Display for FormFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FormFactor::RemoteNetworkDevice => write!(f, "RemoteNetworkDevice"),
+            FormFactor::Speakers => write!(f, "Speakers"),
+            FormFactor::LineLevel => write!(f, "LineLevel"),
+            FormFactor::Headphones => write!(f, "Headphones"),
+            FormFactor::Microphone => write!(f, "Microphone"),
+            FormFactor::Headset => write!(f, "Headset"),
+            FormFactor::Handset => write!(f, "Handset"),
+            FormFactor::UnknownDigitalPassthrough => write!(f, "UnknownDigitalPassthrough"),
+            FormFactor::Spdif => write!(f, "Spdif"),
+            FormFactor::DigitalAudioDisplayDevice => write!(f, "DigitalAudioDisplayDevice"),
+            FormFactor::Unknown => write!(f, "Unknown"),
         }
     }
 }
 
-/// Implement iterator for [DeviceCollection]
-impl<'a> IntoIterator for &'a DeviceCollection {
-    type Item = WasapiRes<Device>;
-    type IntoIter = DeviceCollectionIter<'a>;
+/// The result of checking if a format is supported, see [AudioClient::check_format_support].
+#[derive(Clone, Debug)]
+pub enum FormatSupport {
+    /// The format is supported as is.
+    Supported,
+    /// The format is not supported, but the given format is the closest match.
+    /// This is only returned in shared mode.
+    ClosestMatch(WaveFormat),
+    /// The format is not supported, with a description of the reason.
+    NotSupported(String),
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        DeviceCollectionIter {
-            collection: self,
-            index: 0,
+impl fmt::Display for FormatSupport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatSupport::Supported => write!(f, "Supported"),
+            FormatSupport::ClosestMatch(_) => write!(f, "ClosestMatch"),
+            FormatSupport::NotSupported(reason) => write!(f, "NotSupported: {}", reason),
         }
     }
 }
 
-/// Struct wrapping an [IMMDevice](https://docs.microsoft.com/en-us/windows/win32/api/mmdeviceapi/nn-mmdeviceapi-immdevice).
+/// Audio stream categories, an enum representing the
+/// [AUDIO_STREAM_CATEGORY](https://learn.microsoft.com/en-us/windows/win32/api/audiosessiontypes/ne-audiosessiontypes-audio_stream_category) values.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StreamCategory {
+    Other,
+    ForegroundOnlyMedia,
+    Communications,
+    Alerts,
+    SoundEffects,
+    GameEffects,
+    GameMedia,
+    GameChat,
+    Speech,
+    Movie,
+    Media,
+    FarFieldSpeech,
+    UniformSpeech,
+    VoiceTyping,
+}
+
+impl fmt::Display for StreamCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StreamCategory::Other => write!(f, "Other"),
+            StreamCategory::ForegroundOnlyMedia => write!(f, "ForegroundOnlyMedia"),
+            StreamCategory::Communications => write!(f, "Communications"),
+            StreamCategory::Alerts => write!(f, "Alerts"),
+            StreamCategory::SoundEffects => write!(f, "SoundEffects"),
+            StreamCategory::GameEffects => write!(f, "GameEffects"),
+            StreamCategory::GameMedia => write!(f, "GameMedia"),
+            StreamCategory::GameChat => write!(f, "GameChat"),
+            StreamCategory::Speech => write!(f, "Speech"),
+            StreamCategory::Movie => write!(f, "Movie"),
+            StreamCategory::Media => write!(f, "Media"),
+            StreamCategory::FarFieldSpeech => write!(f, "FarFieldSpeech"),
+            StreamCategory::UniformSpeech => write!(f, "UniformSpeech"),
+            StreamCategory::VoiceTyping => write!(f, "VoiceTyping"),
+        }
+    }
+}
+
+impl StreamCategory {
+    fn to_audio_stream_category(self) -> AUDIO_STREAM_CATEGORY {
+        match self {
+            StreamCategory::Other => AudioCategory_Other,
+            StreamCategory::ForegroundOnlyMedia => AudioCategory_ForegroundOnlyMedia,
+            StreamCategory::Communications => AudioCategory_Communications,
+            StreamCategory::Alerts => AudioCategory_Alerts,
+            StreamCategory::SoundEffects => AudioCategory_SoundEffects,
+            StreamCategory::GameEffects => AudioCategory_GameEffects,
+            StreamCategory::GameMedia => AudioCategory_GameMedia,
+            StreamCategory::GameChat => AudioCategory_GameChat,
+            StreamCategory::Speech => AudioCategory_Speech,
+            StreamCategory::Movie => AudioCategory_Movie,
+            StreamCategory::Media => AudioCategory_Media,
+            StreamCategory::FarFieldSpeech => AudioCategory_FarFieldSpeech,
+            StreamCategory::UniformSpeech => AudioCategory_UniformSpeech,
+            StreamCategory::VoiceTyping => AudioCategory_VoiceTyping,
+        }
+    }
+}
+
+/// Stream options, representing the
+/// [AUDCLNT_STREAMOPTIONS](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/ne-audioclient-audclnt_streamoptions) flags.
+/// The default value has all options disabled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamOptions {
+    /// Raw mode, the stream bypasses all signal processing.
+    /// Check that the device supports it with [Device::supports_raw_mode].
+    pub raw: bool,
+    /// The audio engine uses the format given at initialization, if it is supported.
+    pub match_format: bool,
+    /// The stream is ambisonics.
+    pub ambisonics: bool,
+}
+
+impl StreamOptions {
+    fn to_streamoptions(self) -> AUDCLNT_STREAMOPTIONS {
+        let mut options = 0;
+        if self.raw {
+            options |= AUDCLNT_STREAMOPTIONS_RAW.0;
+        }
+        if self.match_format {
+            options |= AUDCLNT_STREAMOPTIONS_MATCH_FORMAT.0;
+        }
+        if self.ambisonics {
+            options |= AUDCLNT_STREAMOPTIONS_AMBISONICS.0;
+        }
+        AUDCLNT_STREAMOPTIONS(options)
+    }
+}
+
+/// Get the bytes of a [WAVEFORMATEX] structure, including the `cbSize` bytes of extra information.
+/// The `cbSize` field is ignored for plain PCM formats.
+unsafe fn waveformat_bytes<'a>(format_ptr: *const WAVEFORMATEX) -> &'a [u8] {
+    let format = format_ptr.read_unaligned();
+    let extra_bytes = if format.wFormatTag as u32 == WAVE_FORMAT_PCM {
+        0
+    } else {
+        format.cbSize as usize
+    };
+    slice::from_raw_parts(
+        format_ptr as *const u8,
+        size_of::<WAVEFORMATEX>() + extra_bytes,
+    )
+}
+
+fn available_space_in_frames(
+    client: &IAudioClient,
+    sharemode: Option<ShareMode>,
+) -> WasapiRes<u32> {
+    check_simulated_error()?;
+    let frames = match sharemode {
+        Some(ShareMode::Exclusive) => {
+            let buffer_frame_count = unsafe { client.GetBufferSize()? };
+            rt_trace!("buffer_frame_count {}", buffer_frame_count);
+            buffer_frame_count
+        }
+        Some(ShareMode::Shared) => {
+            let padding_count = unsafe { client.GetCurrentPadding()? };
+            let buffer_frame_count = unsafe { client.GetBufferSize()? };
+
+            buffer_frame_count - padding_count
+        }
+        _ => return Err(WasapiError::new("Client has not been initialized").into()),
+    };
+    Ok(frames)
+}
+
+fn direction_to_edataflow(direction: &Direction) -> EDataFlow {
+    match direction {
+        Direction::Capture => eCapture,
+        Direction::Render => eRender,
+    }
+}
+
+pub(crate) fn role_to_erole(role: &Role) -> ERole {
+    match role {
+        Role::Console => eConsole,
+        Role::Multimedia => eMultimedia,
+        Role::Communications => eCommunications,
+    }
+}
+
+/// Get the device with the given endpoint id, as returned by [Device::get_id].
+/// This can be used to reopen exactly the same device later, for example from a stored setting.
+/// The direction of the device is read from the device.
+/// Devices that are not active can also be opened, use [Device::get_state] to check the state.
+pub fn get_device_by_id(id: &str) -> WasapiRes<Device> {
+    let enumerator: IMMDeviceEnumerator =
+        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    let wide_id = U16CString::from_str(id)?;
+    let device = unsafe { enumerator.GetDevice(PCWSTR(wide_id.as_ptr()))? };
+    let endpoint: IMMEndpoint = device.cast()?;
+    #[allow(non_upper_case_globals)]
+    let direction = match unsafe { endpoint.GetDataFlow()? } {
+        eRender => Direction::Render,
+        eCapture => Direction::Capture,
+        flow => {
+            return Err(WasapiError::new(&format!("Got an illegal data flow: {:?}", flow)).into())
+        }
+    };
+    debug!("opened {} device with id {}", direction, id);
+    Ok(Device {
+        device,
+        direction,
+        cache: None,
+    })
+}
+
+/// A reference to an endpoint device, as delivered by device notifications.
+///
+/// Only the endpoint id is stored, so a reference can be sent to other threads, stored and compared.
+/// The device is opened with [DeviceRef::open] when it is needed,
+/// which avoids enumerating the devices to find the one that a notification is about.
+/// Opening fails if the device has been removed after the notification was sent.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DeviceRef {
+    id: String,
+}
+
+impl DeviceRef {
+    /// Create a reference to the device with the given endpoint id, as returned by [Device::get_id].
+    pub fn new(id: &str) -> Self {
+        DeviceRef { id: id.to_string() }
+    }
+
+    /// Get the endpoint id of the device.
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    /// Open the device, see [get_device_by_id].
+    /// COM must be initialized on the calling thread, and the device can only be used on that thread.
+    pub fn open(&self) -> WasapiRes<Device> {
+        get_device_by_id(&self.id)
+    }
+}
+
+impl fmt::Display for DeviceRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+/// Get the device with the given id
+fn get_device_with_id(id: &str, direction: &Direction) -> WasapiRes<Device> {
+    let enumerator: IMMDeviceEnumerator =
+        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    let wide_id = U16CString::from_str(id)?;
+    let device = unsafe { enumerator.GetDevice(PCWSTR(wide_id.as_ptr()))? };
+    Ok(Device {
+        device,
+        direction: *direction,
+        cache: None,
+    })
+}
+
+/// Observer for changes of the default device for one direction and role.
+///
+/// Windows sends a notification for each role when the default device changes,
+/// and sometimes repeats them. The observer filters these so that the callback is called
+/// only once for each change, and only for the selected role.
+/// The callback is called with a [DeviceRef] for the new default device,
+/// or `None` if there is no longer any default device for the direction and role.
+///
+/// The callback is called from a thread owned by the system, and must not block.
+/// Notifications for different roles may arrive on several threads at once,
+/// which is why the callback must be `Sync`.
+/// Open the device with [DeviceRef::open] on the thread that is going to use it,
+/// for example after passing the reference over a channel.
+/// Notifications stop when the observer is dropped.
+/// The observer must not be dropped from within the callback,
+/// since unregistering waits for the callbacks that are in progress.
+pub struct DefaultDeviceObserver {
+    _registration: EndpointRegistration,
+}
+
+impl DefaultDeviceObserver {
+    /// Start observing the default device for the given direction and role.
+    pub fn new(
+        direction: &Direction,
+        role: &Role,
+        callback: impl Fn(Option<DeviceRef>) + Send + Sync + 'static,
+    ) -> WasapiRes<Self> {
+        let current = get_default_device_for_role(direction, role)
+            .and_then(|device| device.get_id())
+            .ok()
+            .map(|id| DeviceRef::new(&id));
+        // Only forward the notifications where the device differs from the previous one
+        let last = Mutex::new(current);
+        let (direction, role) = (*direction, *role);
+        let mut callbacks = DeviceNotificationCallbacks::new();
+        callbacks.set_default_device_callback(move |flow, changed_role, device| {
+            if flow != direction || changed_role != role {
+                return;
+            }
+            {
+                let mut last = match last.lock() {
+                    Ok(last) => last,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                if *last == device {
+                    return;
+                }
+                *last = device.clone();
+            }
+            // The lock is released before calling the callback,
+            // so that a slow callback doesn't block other notifications.
+            callback(device);
+        });
+        let notifier: IMMNotificationClient = DeviceNotifier::new(callbacks).into();
+        Ok(DefaultDeviceObserver {
+            _registration: register_endpoint_notifier(notifier)?,
+        })
+    }
+}
+
+/// Registration of callbacks for device notifications, like devices being added or removed,
+/// changes of device state, default device changes, and property changes.
+/// See [DeviceNotificationCallbacks] for the available callbacks.
+///
+/// The callbacks are called from threads owned by the system, and should return quickly.
+/// Notifications stop when this is dropped.
+pub struct DeviceNotifications {
+    _registration: EndpointRegistration,
+}
+
+impl DeviceNotifications {
+    /// Register the callbacks for device notifications.
+    pub fn new(callbacks: DeviceNotificationCallbacks) -> WasapiRes<Self> {
+        let notifier: IMMNotificationClient = DeviceNotifier::new(callbacks).into();
+        Ok(DeviceNotifications {
+            _registration: register_endpoint_notifier(notifier)?,
+        })
+    }
+}
+
+/// Registration of an [IMMNotificationClient] on a device enumerator.
+type EndpointRegistration = AgileRegistration<IMMDeviceEnumerator, IMMNotificationClient>;
+
+/// Register a notification client on a new device enumerator.
+fn register_endpoint_notifier(notifier: IMMNotificationClient) -> WasapiRes<EndpointRegistration> {
+    let enumerator: IMMDeviceEnumerator =
+        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    AgileRegistration::register(
+        &enumerator,
+        notifier,
+        |enumerator, notifier| {
+            unsafe { enumerator.RegisterEndpointNotificationCallback(notifier) }.map_err(|err| {
+                RegistrationError::new(
+                    NotificationType::DeviceNotifications,
+                    "device enumerator",
+                    err,
+                )
+                .into()
+            })
+        },
+        |enumerator, notifier| unsafe {
+            enumerator.UnregisterEndpointNotificationCallback(notifier)
+        },
+        "device notifications",
+    )
+}
+
+/// The default devices of all roles for one direction, as endpoint ids.
+///
+/// Take a new snapshot when a default device change is notified,
+/// and compare it with the previous one to see which roles changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DefaultDevices {
+    direction: Direction,
+    console: Option<String>,
+    multimedia: Option<String>,
+    communications: Option<String>,
+}
+
+impl DefaultDevices {
+    /// Read the current default devices for the given direction.
+    /// A role without a default device, for example when no device is available, gives `None`.
+    pub fn snapshot(direction: &Direction) -> WasapiRes<Self> {
+        let read_id = |role: &Role| match get_default_device_for_role(direction, role) {
+            Ok(device) => device.get_id().map(Some),
+            Err(err) => {
+                debug!("No default {} device for {}, {}", direction, role, err);
+                Ok(None)
+            }
+        };
+        Ok(DefaultDevices {
+            direction: *direction,
+            console: read_id(&Role::Console)?,
+            multimedia: read_id(&Role::Multimedia)?,
+            communications: read_id(&Role::Communications)?,
+        })
+    }
+
+    /// Create a snapshot from the ids of the console, multimedia and communications devices.
+    #[cfg(test)]
+    pub(crate) fn from_ids(direction: &Direction, ids: [Option<&str>; 3]) -> Self {
+        let [console, multimedia, communications] = ids;
+        DefaultDevices {
+            direction: *direction,
+            console: console.map(str::to_string),
+            multimedia: multimedia.map(str::to_string),
+            communications: communications.map(str::to_string),
+        }
+    }
+
+    /// Get the direction of the snapshot.
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get the id of the default device for a role.
+    pub fn get_id(&self, role: &Role) -> Option<&str> {
+        match role {
+            Role::Console => self.console.as_deref(),
+            Role::Multimedia => self.multimedia.as_deref(),
+            Role::Communications => self.communications.as_deref(),
+        }
+    }
+
+    /// Open the default device for a role, or return `None` if the role has no default device.
+    pub fn get_device(&self, role: &Role) -> WasapiRes<Option<Device>> {
+        match self.get_id(role) {
+            Some(id) => get_device_with_id(id, &self.direction).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Check if all roles have the same default device.
+    pub fn is_same_for_all_roles(&self) -> bool {
+        self.console == self.multimedia && self.console == self.communications
+    }
+
+    /// Get the roles where the default device differs from another snapshot.
+    pub fn changed_roles(&self, other: &DefaultDevices) -> Vec<Role> {
+        [Role::Console, Role::Multimedia, Role::Communications]
+            .iter()
+            .filter(|role| self.get_id(role) != other.get_id(role))
+            .copied()
+            .collect()
+    }
+}
+
+/// Get the default playback or capture device for the console role
+pub fn get_default_device(direction: &Direction) -> WasapiRes<Device> {
+    get_default_device_for_role(direction, &Role::Console)
+}
+
+/// Get the default playback or capture device for a specific role
+pub fn get_default_device_for_role(direction: &Direction, role: &Role) -> WasapiRes<Device> {
+    let dir = direction_to_edataflow(direction);
+    let e_role = role_to_erole(role);
+
+    let enumerator: IMMDeviceEnumerator =
+        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    let device = unsafe { enumerator.GetDefaultAudioEndpoint(dir, e_role)? };
+
+    let dev = Device {
+        device,
+        direction: *direction,
+        cache: None,
+    };
+    debug!("default device {:?}", dev.get_friendlyname());
+    Ok(dev)
+}
+
+/// Calculate a period in units of 100ns that corresponds to the given number of buffer frames at the given sample rate.
+/// See the [IAudioClient documentation](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient-initialize#remarks).
+pub fn calculate_period_100ns(frames: i64, samplerate: i64) -> i64 {
+    ((10000.0 * 1000.0 / samplerate as f64 * frames as f64) + 0.5) as i64
+}
+
+/// Struct wrapping an [IMMDeviceCollection](https://docs.microsoft.com/en-us/windows/win32/api/mmdeviceapi/nn-mmdeviceapi-immdevicecollection).
+pub struct DeviceCollection {
+    collection: IMMDeviceCollection,
+    direction: Direction,
+}
+
+impl DeviceCollection {
+    /// Get an [IMMDeviceCollection] of all active playback or capture devices
+    pub fn new(direction: &Direction) -> WasapiRes<DeviceCollection> {
+        DeviceCollection::new_with_states(direction, &[DeviceState::Active])
+    }
+
+    /// Get an [IMMDeviceCollection] of all playback or capture devices that are in any of the given states.
+    /// For example, use all four [DeviceState] values to list all endpoints, including disabled and unplugged ones.
+    /// Devices that are not active can't be used for streaming, but their properties can be read.
+    pub fn new_with_states(
+        direction: &Direction,
+        states: &[DeviceState],
+    ) -> WasapiRes<DeviceCollection> {
+        let dir = match direction {
+            Direction::Capture => eCapture,
+            Direction::Render => eRender,
+        };
+        let mask = states.iter().fold(0, |mask, state| {
+            mask | match state {
+                DeviceState::Active => DEVICE_STATE_ACTIVE.0,
+                DeviceState::Disabled => DEVICE_STATE_DISABLED.0,
+                DeviceState::NotPresent => DEVICE_STATE_NOTPRESENT.0,
+                DeviceState::Unplugged => DEVICE_STATE_UNPLUGGED.0,
+            }
+        });
+        if mask == 0 {
+            return Err(WasapiError::new("At least one device state must be given").into());
+        }
+        let enumerator: IMMDeviceEnumerator =
+            unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+        let devs = unsafe { enumerator.EnumAudioEndpoints(dir, DEVICE_STATE(mask))? };
+        Ok(DeviceCollection {
+            collection: devs,
+            direction: *direction,
+        })
+    }
+
+    /// Get the number of devices in an [IMMDeviceCollection]
+    pub fn get_nbr_devices(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.collection.GetCount()? };
+        Ok(count)
+    }
+
+    /// Get a device from an [IMMDeviceCollection] using index
+    pub fn get_device_at_index(&self, idx: u32) -> WasapiRes<Device> {
+        let device = unsafe { self.collection.Item(idx)? };
+        Ok(Device {
+            device,
+            direction: self.direction,
+            cache: None,
+        })
+    }
+
+    /// Get a device from an [IMMDeviceCollection] using name
+    pub fn get_device_with_name(&self, name: &str) -> WasapiRes<Device> {
+        let count = unsafe { self.collection.GetCount()? };
+        trace!("nbr devices {}", count);
+        for n in 0..count {
+            let device = self.get_device_at_index(n)?;
+            let devname = device.get_friendlyname()?;
+            if name == devname {
+                return Ok(device);
+            }
+        }
+        Err(WasapiError::new(format!("Unable to find device {}", name).as_str()).into())
+    }
+
+    /// Get the direction for this [DeviceCollection]
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get the devices grouped by the audio adapter they are attached to,
+    /// using the adapter friendly name, see [Device::get_interface_friendlyname].
+    /// The adapters are sorted by name, and the devices of each adapter by their friendly name.
+    pub fn get_devices_by_adapter(&self) -> WasapiRes<Vec<AdapterDevices>> {
+        let mut adapters: BTreeMap<String, Vec<(String, Device)>> = BTreeMap::new();
+        for device in self {
+            let device = device?;
+            let adapter_name = device.get_interface_friendlyname()?;
+            let name = device.get_friendlyname()?;
+            adapters
+                .entry(adapter_name)
+                .or_default()
+                .push((name, device));
+        }
+        let groups = adapters
+            .into_iter()
+            .map(|(adapter_name, mut devices)| {
+                devices.sort_by(|a, b| a.0.cmp(&b.0));
+                AdapterDevices {
+                    adapter_name,
+                    devices: devices.into_iter().map(|(_name, device)| device).collect(),
+                }
+            })
+            .collect();
+        Ok(groups)
+    }
+
+    /// Get the devices grouped by the physical device they belong to, see [group_devices_by_container].
+    pub fn get_devices_by_container(&self) -> WasapiRes<Vec<ContainerDevices>> {
+        let devices = self.into_iter().collect::<WasapiRes<Vec<Device>>>()?;
+        group_devices_by_container(devices)
+    }
+
+    /// Read the id, names and state of every device in the collection, see [DeviceInfo].
+    ///
+    /// Failures are isolated to each device and property,
+    /// so that one misbehaving driver doesn't prevent listing the other devices.
+    /// Properties that could not be read are `None`, and the reason is added to [DeviceInfo::errors].
+    /// An error is only returned if the number of devices can't be read.
+    pub fn get_all_infos(&self) -> WasapiRes<Vec<DeviceInfo>> {
+        let count = self.get_nbr_devices()?;
+        let infos = (0..count)
+            .map(|index| match self.get_device_at_index(index) {
+                Ok(device) => DeviceInfo::from_device(Some(index), &device),
+                Err(err) => DeviceInfo {
+                    index: Some(index),
+                    direction: self.direction,
+                    id: None,
+                    friendly_name: None,
+                    description: None,
+                    interface_friendly_name: None,
+                    state: None,
+                    formfactor: None,
+                    default_format: None,
+                    errors: vec![format!("device: {}", err)],
+                },
+            })
+            .collect();
+        Ok(infos)
+    }
+}
+
+/// Information about a device, see [DeviceCollection::get_all_infos] and [Device::get_info].
+/// This is plain data, that can be sent to other threads,
+/// and serialized when the `serde` feature is enabled.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceInfo {
+    /// The index of the device in the collection, `None` when read with [Device::get_info].
+    pub index: Option<u32>,
+    /// The direction of the device.
+    pub direction: Direction,
+    /// The id of the device, see [Device::get_id].
+    pub id: Option<String>,
+    /// The friendly name of the device, see [Device::get_friendlyname].
+    pub friendly_name: Option<String>,
+    /// The description of the device, see [Device::get_description].
+    pub description: Option<String>,
+    /// The friendly name of the adapter of the device, see [Device::get_interface_friendlyname].
+    pub interface_friendly_name: Option<String>,
+    /// The state of the device, see [Device::get_state].
+    pub state: Option<DeviceState>,
+    /// The form factor of the device, see [Device::get_formfactor].
+    pub formfactor: Option<FormFactor>,
+    /// The default shared mode format of the device, see [Device::get_device_format].
+    pub default_format: Option<FormatInfo>,
+    /// The errors from reading the device and its properties,
+    /// each prefixed with the name of what was being read.
+    pub errors: Vec<String>,
+}
+
+impl DeviceInfo {
+    fn from_device(index: Option<u32>, device: &Device) -> Self {
+        let mut errors = Vec::new();
+        let mut check = |what: &str, result: WasapiRes<String>| match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                errors.push(format!("{}: {}", what, err));
+                None
+            }
+        };
+        let id = check("id", device.get_id());
+        let friendly_name = check("friendly name", device.get_friendlyname());
+        let description = check("description", device.get_description());
+        let interface_friendly_name = check(
+            "interface friendly name",
+            device.get_interface_friendlyname(),
+        );
+        let state = match device.get_state() {
+            Ok(state) => Some(state),
+            Err(err) => {
+                errors.push(format!("state: {}", err));
+                None
+            }
+        };
+        let formfactor = match device.get_formfactor() {
+            Ok(formfactor) => Some(formfactor),
+            Err(err) => {
+                errors.push(format!("form factor: {}", err));
+                None
+            }
+        };
+        let default_format = match device.get_device_format() {
+            Ok(format) => Some(FormatInfo::from(&format)),
+            Err(err) => {
+                errors.push(format!("default format: {}", err));
+                None
+            }
+        };
+        if !errors.is_empty() {
+            let name = match index {
+                Some(index) => index.to_string(),
+                None => id.clone().unwrap_or_default(),
+            };
+            warn!(
+                "Failed to read some properties of device {}: {:?}",
+                name, errors
+            );
+        }
+        DeviceInfo {
+            index,
+            direction: device.get_direction(),
+            id,
+            friendly_name,
+            description,
+            interface_friendly_name,
+            state,
+            formfactor,
+            default_format,
+            errors,
+        }
+    }
+
+    /// Check if all the information was read without errors.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A group of devices attached to the same audio adapter, see [DeviceCollection::get_devices_by_adapter].
+pub struct AdapterDevices {
+    /// The friendly name of the adapter (for example "XYZ Audio Adapter").
+    pub adapter_name: String,
+    /// The devices attached to the adapter (for example "Speakers" and "Headphones").
+    pub devices: Vec<Device>,
+}
+
+/// A group of devices that belong to the same physical device, see [group_devices_by_container].
+pub struct ContainerDevices {
+    /// The container id shared by the devices, see [Device::get_container_id].
+    pub container_id: GUID,
+    /// The friendly name of the adapter of the first device (for example "Focusrite 2i2").
+    pub name: String,
+    /// The devices of the container, sorted by direction and friendly name.
+    pub devices: Vec<Device>,
+}
+
+/// Group devices by the physical device they belong to, using their container id.
+/// Unlike [DeviceCollection::get_devices_by_adapter], this can group render and capture devices together,
+/// for example by chaining the devices of a render and a capture [DeviceCollection].
+/// The groups are sorted by name.
+pub fn group_devices_by_container(
+    devices: impl IntoIterator<Item = Device>,
+) -> WasapiRes<Vec<ContainerDevices>> {
+    let mut containers: Vec<ContainerDevices> = Vec::new();
+    for device in devices {
+        let container_id = device.get_container_id()?;
+        match containers
+            .iter_mut()
+            .find(|container| container.container_id == container_id)
+        {
+            Some(container) => container.devices.push(device),
+            None => containers.push(ContainerDevices {
+                container_id,
+                name: device.get_interface_friendlyname()?,
+                devices: vec![device],
+            }),
+        }
+    }
+    for container in containers.iter_mut() {
+        let mut keyed = container
+            .devices
+            .drain(..)
+            .map(|device| {
+                let key = (
+                    device.get_direction() == Direction::Capture,
+                    device.get_friendlyname()?,
+                );
+                Ok((key, device))
+            })
+            .collect::<WasapiRes<Vec<_>>>()?;
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        container.devices = keyed.into_iter().map(|(_key, device)| device).collect();
+    }
+    containers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(containers)
+}
+
+/// Iterator for [DeviceCollection]
+pub struct DeviceCollectionIter<'a> {
+    collection: &'a DeviceCollection,
+    index: u32,
+}
+
+impl<'a> Iterator for DeviceCollectionIter<'a> {
+    type Item = WasapiRes<Device>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index < self.collection.get_nbr_devices().unwrap() {
+            let device = self.collection.get_device_at_index(self.index);
+            self.index += 1;
+            Some(device)
+        } else {
+            None
+        }
+    }
+}
+
+/// Implement iterator for [DeviceCollection]
+impl<'a> IntoIterator for &'a DeviceCollection {
+    type Item = WasapiRes<Device>;
+    type IntoIter = DeviceCollectionIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        DeviceCollectionIter {
+            collection: self,
+            index: 0,
+        }
+    }
+}
+
+/// Struct wrapping an [IMMDevice](https://docs.microsoft.com/en-us/windows/win32/api/mmdeviceapi/nn-mmdeviceapi-immdevice).
 pub struct Device {
     device: IMMDevice,
     direction: Direction,
+    cache: Option<Arc<PropertyCache>>,
+}
+
+/// Cache for the string properties and the id of a [Device].
+/// The properties are keyed by the GUID and pid of their [PROPERTYKEY].
+#[derive(Default)]
+struct PropertyCache {
+    id: Mutex<Option<String>>,
+    properties: Mutex<BTreeMap<(u128, u32), String>>,
+}
+
+impl PropertyCache {
+    fn clear_properties(&self) {
+        self.properties
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clear();
+    }
+}
+
+/// Watcher that clears the property cache of a [Device] when any of its properties change,
+/// see [Device::watch_property_changes].
+///
+/// The cache is cleared from a thread owned by the system.
+/// Notifications stop when the watcher is dropped.
+pub struct PropertyCacheWatcher {
+    _registration: EndpointRegistration,
+}
+
+impl Device {
+    /// Create a [Device] from a raw [IMMDevice] pointer, for interoperability with other crates.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid `IMMDevice` pointer, for an endpoint with the given direction.
+    /// The [Device] takes ownership of the reference held by the pointer.
+    pub unsafe fn from_raw(raw: *mut c_void, direction: &Direction) -> Self {
+        Device {
+            device: IMMDevice::from_raw(raw),
+            direction: *direction,
+            cache: None,
+        }
+    }
+
+    /// Consume the [Device] and return the raw [IMMDevice] pointer.
+    /// The caller becomes responsible for releasing the reference.
+    pub fn into_raw(self) -> *mut c_void {
+        self.device.into_raw()
+    }
+
+    /// Get an [IAudioClient] from an [IMMDevice]
+    pub fn get_iaudioclient(&self) -> WasapiRes<AudioClient> {
+        let audio_client = unsafe { self.device.Activate::<IAudioClient>(CLSCTX_ALL, None)? };
+        Ok(AudioClient {
+            client: audio_client,
+            direction: self.direction,
+            sharemode: None,
+            bytes_per_frame: None,
+            samplerate: None,
+            device_id: self.get_id().ok(),
+            stream_category: None,
+            stream_options: None,
+            init_info: None,
+            engine_period: Cell::new(None),
+            session_guid: None,
+            cross_process: false,
+            process_loopback: false,
+            role: None,
+            no_persist: false,
+            extra_stream_flags: 0,
+        })
+    }
+
+    /// Get an [IAudioClient] from an [IMMDevice], tagged with the role it is intended for.
+    /// The role does not change how the client behaves,
+    /// but it can be read back with [AudioClient::get_role], so that code handling the client
+    /// later, for example when following default device changes, knows which role to use.
+    pub fn get_iaudioclient_for_role(&self, role: &Role) -> WasapiRes<AudioClient> {
+        let mut audio_client = self.get_iaudioclient()?;
+        audio_client.role = Some(*role);
+        Ok(audio_client)
+    }
+
+    /// Read state from an [IMMDevice]
+    pub fn get_state(&self) -> WasapiRes<DeviceState> {
+        let mut pdwstate: u32 = 0;
+        let state = unsafe { self.device.GetState(&mut pdwstate) };
+        trace!("state: {:?}, pdwstate: {}", state, pdwstate);
+        let state_enum = match pdwstate {
+            _ if pdwstate == DEVICE_STATE_ACTIVE.0 => DeviceState::Active,
+            _ if pdwstate == DEVICE_STATE_DISABLED.0 => DeviceState::Disabled,
+            _ if pdwstate == DEVICE_STATE_NOTPRESENT.0 => DeviceState::NotPresent,
+            _ if pdwstate == DEVICE_STATE_UNPLUGGED.0 => DeviceState::Unplugged,
+            x => {
+                return Err(
+                    WasapiError::new(&format!("Got an illegal state: DEVICE_STATE({})", x)).into(),
+                )
+            }
+        };
+        Ok(state_enum)
+    }
+
+    /// Read the friendly name of the endpoint device (for example, "Speakers (XYZ Audio Adapter)")
+    pub fn get_friendlyname(&self) -> WasapiRes<String> {
+        self.get_string_property(&PKEY_Device_FriendlyName)
+    }
+
+    /// Read the friendly name of the audio adapter to which the endpoint device is attached (for example, "XYZ Audio Adapter")
+    pub fn get_interface_friendlyname(&self) -> WasapiRes<String> {
+        self.get_string_property(&PKEY_DeviceInterface_FriendlyName)
+    }
+
+    /// Read the device description of the endpoint device (for example, "Speakers")
+    pub fn get_description(&self) -> WasapiRes<String> {
+        self.get_string_property(&PKEY_Device_DeviceDesc)
+    }
+
+    /// Open the property store of the device.
+    /// Opening it for writing requires administrator privileges,
+    /// and returns an error saying so when the process is not elevated.
+    /// Changes of properties are seen by other applications,
+    /// for example a new friendly name is shown in the Sound control panel.
+    pub fn open_property_store(&self, access: &StoreAccess) -> WasapiRes<PropertyStore> {
+        let mode = match access {
+            StoreAccess::Read => STGM_READ,
+            StoreAccess::ReadWrite => STGM_READWRITE,
+        };
+        let store =
+            match unsafe { self.device.OpenPropertyStore(mode) } {
+                Ok(store) => store,
+                Err(err) if err.code() == E_ACCESSDENIED => return Err(WasapiError::new(
+                    "Access denied, writing device properties requires administrator privileges",
+                )
+                .into()),
+                Err(err) => return Err(err.into()),
+            };
+        Ok(PropertyStore {
+            store,
+            access: *access,
+        })
+    }
+
+    /// Read the value of a property from the property store of the device.
+    /// The cache is not used, see [Device::enable_property_cache].
+    pub fn get_property(&self, key: &PropertyKey) -> WasapiRes<PropertyValue> {
+        self.open_property_store(&StoreAccess::Read)?.get_value(key)
+    }
+
+    /// Read all properties in the property store of the device.
+    /// Values of unsupported types are returned as [PropertyValue::Unsupported].
+    pub fn get_properties(&self) -> WasapiRes<Vec<(PropertyKey, PropertyValue)>> {
+        self.open_property_store(&StoreAccess::Read)?.get_all()
+    }
+
+    /// Read the form factor of the endpoint device (`PKEY_AudioEndpoint_FormFactor`),
+    /// for example to show an icon for the device, or to prefer headsets for voice.
+    pub fn get_formfactor(&self) -> WasapiRes<FormFactor> {
+        use windows::Win32::Media::Audio as audio;
+        let store = unsafe { self.device.OpenPropertyStore(STGM_READ)? };
+        let prop = unsafe { store.GetValue(&PKEY_AudioEndpoint_FormFactor)? };
+        let value = unsafe { PropVariantToUInt32(&prop)? };
+        trace!("form factor: {}", value);
+        let formfactor = match audio::EndpointFormFactor(value as i32) {
+            audio::RemoteNetworkDevice => FormFactor::RemoteNetworkDevice,
+            audio::Speakers => FormFactor::Speakers,
+            audio::LineLevel => FormFactor::LineLevel,
+            audio::Headphones => FormFactor::Headphones,
+            audio::Microphone => FormFactor::Microphone,
+            audio::Headset => FormFactor::Headset,
+            audio::Handset => FormFactor::Handset,
+            audio::UnknownDigitalPassthrough => FormFactor::UnknownDigitalPassthrough,
+            audio::SPDIF => FormFactor::Spdif,
+            audio::DigitalAudioDisplayDevice => FormFactor::DigitalAudioDisplayDevice,
+            _ => FormFactor::Unknown,
+        };
+        Ok(formfactor)
+    }
+
+    /// Read the default format of the endpoint device in shared mode (`PKEY_AudioEngine_DeviceFormat`),
+    /// the format that is selected as "Default Format" in the Sound control panel.
+    pub fn get_device_format(&self) -> WasapiRes<WaveFormat> {
+        match self.get_property(&PropertyKey::from(PKEY_AudioEngine_DeviceFormat))? {
+            PropertyValue::Blob(bytes) => WaveFormat::from_bytes(&bytes),
+            _ => Err(WasapiError::new("The device format property is not a blob").into()),
+        }
+    }
+
+    /// Read the configuration of the physical speakers of the endpoint device (`PKEY_AudioEndpoint_PhysicalSpeakers`),
+    /// as selected in the speaker setup of the Sound control panel.
+    /// This is the actual layout, that may have fewer speakers than the channels of the mix format.
+    /// Returns `None` if the property is not set, which is common for capture devices.
+    pub fn get_physical_speakers(&self) -> WasapiRes<Option<SpeakerMask>> {
+        self.get_speaker_mask_property(&PKEY_AudioEndpoint_PhysicalSpeakers)
+    }
+
+    /// Read the speakers that are full-range in the speaker setup of the endpoint device
+    /// (`PKEY_AudioEndpoint_FullRangeSpeakers`).
+    /// The low frequencies of the other speakers are redirected to the subwoofer, if there is one.
+    /// Returns `None` if the property is not set.
+    pub fn get_fullrange_speakers(&self) -> WasapiRes<Option<SpeakerMask>> {
+        self.get_speaker_mask_property(&PKEY_AudioEndpoint_FullRangeSpeakers)
+    }
+
+    fn get_speaker_mask_property(&self, key: &PROPERTYKEY) -> WasapiRes<Option<SpeakerMask>> {
+        match self.get_property(&PropertyKey::from(*key))? {
+            PropertyValue::U32(mask) => Ok(Some(SpeakerMask::from_mask(mask))),
+            PropertyValue::Empty => Ok(None),
+            other => Err(WasapiError::new(&format!(
+                "Unexpected value for the speaker configuration: {:?}",
+                other
+            ))
+            .into()),
+        }
+    }
+
+    /// Check if the endpoint device supports raw mode (`PKEY_Devices_AudioDevice_RawProcessingSupported`),
+    /// meaning that a stream can be initialized with [StreamOptions::raw] to bypass all signal processing.
+    /// Returns false if the property is not set.
+    pub fn supports_raw_mode(&self) -> WasapiRes<bool> {
+        match self.get_property(&PropertyKey::from(
+            PKEY_DEVICES_AUDIODEVICE_RAWPROCESSINGSUPPORTED,
+        ))? {
+            PropertyValue::Bool(supported) => Ok(supported),
+            PropertyValue::Empty => Ok(false),
+            other => Err(WasapiError::new(&format!(
+                "Unexpected value for raw processing support: {:?}",
+                other
+            ))
+            .into()),
+        }
+    }
+
+    /// Read the id, names, state, form factor and default format of the device in one go, see [DeviceInfo].
+    /// Properties that could not be read are `None`, and the reason is added to [DeviceInfo::errors].
+    pub fn get_info(&self) -> DeviceInfo {
+        DeviceInfo::from_device(None, self)
+    }
+
+    /// Read the container id of the device (`PKEY_Device_ContainerId`).
+    /// All endpoints of the same physical device, both render and capture, have the same container id.
+    pub fn get_container_id(&self) -> WasapiRes<GUID> {
+        match self.get_property(&PropertyKey::from(PKEY_Device_ContainerId))? {
+            PropertyValue::Guid(guid) => Ok(guid),
+            PropertyValue::String(guid_str) => {
+                let wide_guid = U16CString::from_str(&guid_str)?;
+                let guid = unsafe { IIDFromString(PCWSTR(wide_guid.as_ptr()))? };
+                Ok(guid)
+            }
+            other => Err(WasapiError::new(&format!(
+                "Unexpected value for the container id: {:?}",
+                other
+            ))
+            .into()),
+        }
+    }
+
+    /// Read the endpoint GUID of the endpoint device (`PKEY_AudioEndpoint_GUID`).
+    /// This is the GUID that older APIs like DirectSound use to identify the device.
+    pub fn get_endpoint_guid(&self) -> WasapiRes<GUID> {
+        self.get_guid_property(&PKEY_AudioEndpoint_GUID)
+    }
+
+    /// Read the KS node type of the association of the endpoint device (`PKEY_AudioEndpoint_Association`),
+    /// for example `KSNODETYPE_SPEAKER` or `KSNODETYPE_MICROPHONE`.
+    pub fn get_association(&self) -> WasapiRes<GUID> {
+        self.get_guid_property(&PKEY_AudioEndpoint_Association)
+    }
+
+    /// Read a string property containing a GUID in the form `{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}`
+    fn get_guid_property(&self, key: &PROPERTYKEY) -> WasapiRes<GUID> {
+        let guid_str = self.get_string_property(key)?;
+        let wide_guid = U16CString::from_str(&guid_str)?;
+        let guid = unsafe { IIDFromString(PCWSTR(wide_guid.as_ptr())) }.map_err(|err| {
+            WasapiError::new(&format!("Invalid GUID string '{}', {}", guid_str, err))
+        })?;
+        Ok(guid)
+    }
+
+    /// Enable caching of the id and the string properties,
+    /// such as the friendly name and description.
+    ///
+    /// With the cache enabled, each property is read from the property store only once,
+    /// which helps when the properties are read repeatedly, for example by a device picker
+    /// that refreshes frequently. Properties can change while the device exists,
+    /// use [Device::watch_property_changes] to clear the cache when they do,
+    /// or clear it manually with [Device::clear_property_cache].
+    pub fn enable_property_cache(&mut self) {
+        if self.cache.is_none() {
+            self.cache = Some(Arc::new(PropertyCache::default()));
+        }
+    }
+
+    /// Clear the cached properties. This does nothing if the cache isn't enabled.
+    pub fn clear_property_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear_properties();
+        }
+    }
+
+    /// Enable the property cache, see [Device::enable_property_cache],
+    /// and start watching for property changes of this device.
+    /// The cached properties are cleared whenever a property of the device changes,
+    /// until the returned [PropertyCacheWatcher] is dropped.
+    pub fn watch_property_changes(&mut self) -> WasapiRes<PropertyCacheWatcher> {
+        self.enable_property_cache();
+        let id = self.get_id()?;
+        let cache = Arc::clone(self.cache.as_ref().unwrap());
+        let mut callbacks = DeviceNotificationCallbacks::new();
+        callbacks.set_property_value_callback(move |device, _, _| {
+            if device.get_id() == id {
+                trace!("Property changed for device {}", id);
+                cache.clear_properties();
+            }
+        });
+        let notifier: IMMNotificationClient = DeviceNotifier::new(callbacks).into();
+        Ok(PropertyCacheWatcher {
+            _registration: register_endpoint_notifier(notifier)?,
+        })
+    }
+
+    /// Read a string property, from the cache if it is enabled
+    fn get_string_property(&self, key: &PROPERTYKEY) -> WasapiRes<String> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return self.read_string_property(key),
+        };
+        let cache_key = (key.fmtid.to_u128(), key.pid);
+        if let Some(value) = cache
+            .properties
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&cache_key)
+        {
+            return Ok(value.clone());
+        }
+        let value = self.read_string_property(key)?;
+        cache
+            .properties
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(cache_key, value.clone());
+        Ok(value)
+    }
+
+    /// Read a string property from the property store of an [IMMDevice]
+    fn read_string_property(&self, key: &PROPERTYKEY) -> WasapiRes<String> {
+        let store = unsafe { self.device.OpenPropertyStore(STGM_READ)? };
+        let prop = unsafe { store.GetValue(key)? };
+        let propstr = unsafe { PropVariantToStringAlloc(&prop)? };
+        let name = take_pwstr(propstr);
+        trace!("name: {}", name);
+        Ok(name)
+    }
+
+    /// Get the Id of an [IMMDevice]
+    pub fn get_id(&self) -> WasapiRes<String> {
+        if let Some(cache) = &self.cache {
+            let mut cached_id = cache
+                .id
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if cached_id.is_none() {
+                *cached_id = Some(self.read_id()?);
+            }
+            return Ok(cached_id.clone().unwrap());
+        }
+        self.read_id()
+    }
+
+    /// Read the Id of an [IMMDevice]
+    fn read_id(&self) -> WasapiRes<String> {
+        let idstr = unsafe { self.device.GetId()? };
+        let wide_id = unsafe { U16CString::from_ptr_str(idstr.0) };
+        let id = wide_id.to_string_lossy();
+        trace!("id: {}", id);
+        Ok(id)
+    }
+
+    /// Get the direction for this Device
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get the [DeviceTopology] of this endpoint device,
+    /// for walking the [device topology](https://learn.microsoft.com/en-us/windows/win32/coreaudio/device-topologies)
+    /// down to the audio adapter.
+    pub fn get_device_topology(&self) -> WasapiRes<DeviceTopology> {
+        let topology = unsafe { self.device.Activate::<IDeviceTopology>(CLSCTX_ALL, None)? };
+        Ok(DeviceTopology::new(topology))
+    }
+
+    /// Get the [KsFormatSupport] of the audio adapter this endpoint device is connected to,
+    /// for querying the formats supported by the driver.
+    ///
+    /// This uses the [device topology](https://learn.microsoft.com/en-us/windows/win32/coreaudio/device-topologies)
+    /// to find the connector of the adapter that the endpoint is connected to.
+    /// Not all drivers support this, and virtual devices usually don't.
+    pub fn get_ksformatsupport(&self) -> WasapiRes<KsFormatSupport> {
+        let topology = unsafe { self.device.Activate::<IDeviceTopology>(CLSCTX_ALL, None)? };
+        let connector = unsafe { topology.GetConnector(0)? };
+        let adapter_connector = unsafe { connector.GetConnectedTo()? };
+        let part: IPart = adapter_connector.cast()?;
+        let mut support_ptr = ptr::null_mut();
+        unsafe {
+            part.Activate(
+                CLSCTX_INPROC_SERVER.0,
+                &IKsFormatSupport::IID,
+                Some(&mut support_ptr),
+            )?
+        };
+        let support = unsafe { IKsFormatSupport::from_raw(support_ptr) };
+        Ok(KsFormatSupport { support })
+    }
+
+    /// Get the [AudioEndpointVolume] of this device, for controlling the volume of the device.
+    pub fn get_endpointvolume(&self) -> WasapiRes<AudioEndpointVolume> {
+        let volume = unsafe {
+            self.device
+                .Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None)?
+        };
+        Ok(AudioEndpointVolume { volume })
+    }
+
+    /// Get the mute state of the device, see [AudioEndpointVolume::get_mute].
+    pub fn get_mute(&self) -> WasapiRes<bool> {
+        self.get_endpointvolume()?.get_mute()
+    }
+
+    /// Set the mute state of the device, see [AudioEndpointVolume::set_mute].
+    pub fn set_mute(&self, mute: bool) -> WasapiRes<()> {
+        self.get_endpointvolume()?.set_mute(mute, None)
+    }
+
+    /// Check if the device is effectively muted.
+    /// This is the case if the device itself is muted,
+    /// or if there are active sessions and all of them are muted.
+    /// This matches what Windows shows for example in the microphone privacy indicator.
+    pub fn is_effectively_muted(&self) -> WasapiRes<bool> {
+        if self.get_mute()? {
+            return Ok(true);
+        }
+        let mut active_sessions = 0;
+        for session in self.get_sessionmanager()?.get_sessions()? {
+            if session.get_state()? != SessionState::Active {
+                continue;
+            }
+            active_sessions += 1;
+            match session.upgrade().volume {
+                Some(volume) if volume.get_mute()? => {}
+                _ => return Ok(false),
+            }
+        }
+        trace!("all {} active sessions are muted", active_sessions);
+        Ok(active_sessions > 0)
+    }
+
+    /// Get the [AudioSessionManager2] of this device, for enumerating and controlling its sessions.
+    pub fn get_sessionmanager(&self) -> WasapiRes<AudioSessionManager2> {
+        let manager = unsafe {
+            self.device
+                .Activate::<IAudioSessionManager2>(CLSCTX_ALL, None)?
+        };
+        Ok(AudioSessionManager2 {
+            manager,
+            direction: self.direction,
+            device_id: self.get_id().ok(),
+        })
+    }
+
+    /// Mute all sessions on this device, optionally except the sessions of one process.
+    /// Pass the id of the own process, see [std::process::id], to mute everything except the own application.
+    ///
+    /// Sessions that are already muted are left as they are.
+    /// The returned [MutedSessions] can be used to unmute the sessions again.
+    /// If muting a session fails, the sessions that were muted so far are unmuted
+    /// before the error is returned.
+    pub fn mute_all_sessions(&self, except_process_id: Option<u32>) -> WasapiRes<MutedSessions> {
+        let sessions = self.get_sessionmanager()?.get_sessions()?;
+        let mute = |volume: &SimpleAudioVolume| -> WasapiRes<bool> {
+            if volume.get_mute()? {
+                return Ok(false);
+            }
+            volume.set_mute(true, None)?;
+            Ok(true)
+        };
+        let mut muted = MutedSessions {
+            sessions: Vec::new(),
+        };
+        for session in sessions.iter() {
+            let interfaces = session.upgrade();
+            if let (Some(pid), Some(control2)) = (except_process_id, &interfaces.control2) {
+                if control2.get_process_id().ok() == Some(pid) {
+                    continue;
+                }
+            }
+            let volume = match interfaces.volume {
+                Some(volume) => volume,
+                None => continue,
+            };
+            match mute(&volume) {
+                Ok(true) => muted.sessions.push(volume),
+                Ok(false) => {}
+                Err(err) => {
+                    // Failures to unmute are logged by restore.
+                    let _ = muted.restore();
+                    return Err(err);
+                }
+            }
+        }
+        debug!("muted {} of {} sessions", muted.len(), sessions.len());
+        Ok(muted)
+    }
+
+    /// Create and initialize a shared mode [AudioClient] for a communications render stream,
+    /// that does not duck the other audio streams of the system.
+    ///
+    /// The steps must be performed in a specific order, which this helper takes care of:
+    /// 1. The stream category is set to [StreamCategory::Communications] with [AudioClient::set_client_properties].
+    ///    This has to be done before initialization.
+    /// 2. The ducking preference of the default session is set to opt out of the automatic ducking,
+    ///    see [AudioSessionControl2::set_ducking_preference].
+    ///    This also has to be done before the stream is initialized, since the system decides
+    ///    whether to duck other streams when the communications stream is opened.
+    /// 3. The client is initialized with the given format, buffer duration and automatic conversion setting.
+    ///
+    /// The stream is part of the default session of the process.
+    /// Communications streams are not ducked by other communications streams, so opting out of
+    /// the ducking means that the stream neither ducks others nor gets ducked.
+    pub fn get_communications_render_client(
+        &self,
+        wavefmt: &WaveFormat,
+        period: i64,
+        convert: bool,
+    ) -> WasapiRes<AudioClient> {
+        if self.direction != Direction::Render {
+            return Err(
+                WasapiError::new("Communications render streams need a render device").into(),
+            );
+        }
+        let mut audio_client = self.get_iaudioclient_for_role(&Role::Communications)?;
+        audio_client.set_client_properties(
+            &StreamCategory::Communications,
+            &StreamOptions::default(),
+            false,
+        )?;
+        let session = self.get_sessionmanager()?.get_audiosessioncontrol(None)?;
+        match session.upgrade().control2 {
+            Some(control2) => control2.set_ducking_preference(true)?,
+            None => {
+                return Err(
+                    WasapiError::new("The session does not support IAudioSessionControl2").into(),
+                )
+            }
+        }
+        audio_client.initialize_client(
+            wavefmt,
+            period,
+            &Direction::Render,
+            &ShareMode::Shared,
+            convert,
+        )?;
+        debug!("initialized communications render client without ducking");
+        Ok(audio_client)
+    }
+}
+
+/// Struct wrapping an [IAudioEndpointVolume](https://learn.microsoft.com/en-us/windows/win32/api/endpointvolume/nn-endpointvolume-iaudioendpointvolume),
+/// see [Device::get_endpointvolume].
+pub struct AudioEndpointVolume {
+    volume: IAudioEndpointVolume,
+}
+
+impl AudioEndpointVolume {
+    /// Get the master volume of the device, as a value between 0.0 and 1.0.
+    pub fn get_master_volume_scalar(&self) -> WasapiRes<f32> {
+        let volume = unsafe { self.volume.GetMasterVolumeLevelScalar()? };
+        Ok(volume)
+    }
+
+    /// Set the master volume of the device, as a value between 0.0 and 1.0.
+    /// The optional `event_context` is passed on to the volume change notifications.
+    pub fn set_master_volume_scalar(
+        &self,
+        volume: f32,
+        event_context: Option<&GUID>,
+    ) -> WasapiRes<()> {
+        let context = event_context.map_or(ptr::null(), |ctx| ctx as *const GUID);
+        unsafe { self.volume.SetMasterVolumeLevelScalar(volume, context)? };
+        Ok(())
+    }
+
+    /// Get the master volume of the device in dB.
+    pub fn get_master_volume_db(&self) -> WasapiRes<f32> {
+        let volume = unsafe { self.volume.GetMasterVolumeLevel()? };
+        Ok(volume)
+    }
+
+    /// Get the number of channels of the device.
+    pub fn get_channel_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.volume.GetChannelCount()? };
+        Ok(count)
+    }
+
+    /// Get the volume of a channel, as a value between 0.0 and 1.0.
+    pub fn get_channel_volume_scalar(&self, channel: u32) -> WasapiRes<f32> {
+        let volume = unsafe { self.volume.GetChannelVolumeLevelScalar(channel)? };
+        Ok(volume)
+    }
+
+    /// Get the mute state of the device.
+    pub fn get_mute(&self) -> WasapiRes<bool> {
+        let mute = unsafe { self.volume.GetMute()? };
+        Ok(mute.as_bool())
+    }
+
+    /// Set the mute state of the device.
+    /// The optional `event_context` is passed on to the volume change notifications.
+    pub fn set_mute(&self, mute: bool, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map_or(ptr::null(), |ctx| ctx as *const GUID);
+        unsafe { self.volume.SetMute(mute, context)? };
+        Ok(())
+    }
+
+    /// Register a callback that is called when the master volume, the mute state
+    /// or the volume of any channel of the device changes, with the new values in an [EndpointVolumeEvent].
+    /// Changes made with this crate get the event context given to the setter,
+    /// so an application can recognize and skip its own changes.
+    ///
+    /// The callback is called from threads owned by the system, possibly several at once,
+    /// and should return quickly.
+    /// Notifications stop when the returned [EndpointVolumeNotifications] is dropped.
+    pub fn register_volume_notification(
+        &self,
+        callback: impl Fn(EndpointVolumeEvent) + Send + Sync + 'static,
+    ) -> WasapiRes<EndpointVolumeNotifications> {
+        let notifier: IAudioEndpointVolumeCallback =
+            EndpointVolumeNotifier::new(Box::new(callback)).into();
+        let registration = AgileRegistration::register(
+            &self.volume,
+            notifier,
+            |volume, notifier| {
+                unsafe { volume.RegisterControlChangeNotify(notifier) }.map_err(|err| {
+                    RegistrationError::new(NotificationType::EndpointVolume, "endpoint volume", err)
+                        .into()
+                })
+            },
+            |volume, notifier| unsafe { volume.UnregisterControlChangeNotify(notifier) },
+            "endpoint volume notifications",
+        )?;
+        Ok(EndpointVolumeNotifications {
+            _registration: registration,
+        })
+    }
+}
+
+/// Registration of a volume change callback, see [AudioEndpointVolume::register_volume_notification].
+/// Notifications stop when this is dropped.
+pub struct EndpointVolumeNotifications {
+    _registration: AgileRegistration<IAudioEndpointVolume, IAudioEndpointVolumeCallback>,
+}
+
+/// Struct wrapping an [IKsFormatSupport](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iksformatsupport),
+/// see [Device::get_ksformatsupport].
+///
+/// This asks the driver directly, which is faster than probing formats
+/// with [AudioClient::is_supported] in exclusive mode.
+pub struct KsFormatSupport {
+    support: IKsFormatSupport,
+}
+
+impl KsFormatSupport {
+    /// Check if the driver supports a format.
+    pub fn is_supported(&self, wave_fmt: &WaveFormat) -> WasapiRes<bool> {
+        let format_bytes = unsafe { waveformat_bytes(wave_fmt.as_waveformatex_ref()) };
+        let header_size = size_of::<KSDATAFORMAT>();
+        let total_size = header_size + format_bytes.len();
+        let header = KSDATAFORMAT {
+            Anonymous: KSDATAFORMAT_0 {
+                FormatSize: total_size as u32,
+                Flags: 0,
+                SampleSize: 0,
+                Reserved: 0,
+                MajorFormat: KSDATAFORMAT_TYPE_AUDIO,
+                SubFormat: ks_subformat(wave_fmt)?,
+                Specifier: KSDATAFORMAT_SPECIFIER_WAVEFORMATEX,
+            },
+        };
+        // Use a buffer of u64 to get the alignment required by KSDATAFORMAT.
+        let mut buffer = vec![0u64; Integer::div_ceil(&total_size, &8)];
+        let buffer_ptr = buffer.as_mut_ptr() as *mut u8;
+        let mut supported = false.into();
+        unsafe {
+            ptr::write(buffer_ptr as *mut KSDATAFORMAT, header);
+            ptr::copy_nonoverlapping(
+                format_bytes.as_ptr(),
+                buffer_ptr.add(header_size),
+                format_bytes.len(),
+            );
+            self.support.IsFormatSupported(
+                buffer_ptr as *mut KSDATAFORMAT,
+                total_size as u32,
+                &mut supported,
+            )?
+        };
+        trace!("driver supports format: {}", supported.as_bool());
+        Ok(supported.as_bool())
+    }
+
+    /// Get the format preferred by the driver.
+    pub fn get_device_preferred_format(&self) -> WasapiRes<WaveFormat> {
+        let format_ptr = unsafe { self.support.GetDevicePreferredFormat()? };
+        let header = unsafe { (*format_ptr).Anonymous };
+        let header_size = size_of::<KSDATAFORMAT>();
+        let result = if header.Specifier != KSDATAFORMAT_SPECIFIER_WAVEFORMATEX
+            || (header.FormatSize as usize) < header_size
+        {
+            Err(WasapiError::new("The preferred format is not a WAVEFORMATEX format").into())
+        } else {
+            let format_bytes = unsafe {
+                slice::from_raw_parts(
+                    (format_ptr as *const u8).add(header_size),
+                    header.FormatSize as usize - header_size,
+                )
+            };
+            WaveFormat::from_bytes(format_bytes)
+        };
+        unsafe { CoTaskMemFree(Some(format_ptr as *const _)) };
+        result
+    }
+}
+
+/// Get the KS subformat GUID for a format.
+fn ks_subformat(wave_fmt: &WaveFormat) -> WasapiRes<GUID> {
+    let subformat = match wave_fmt.as_waveformatex_ref().wFormatTag as u32 {
+        WAVE_FORMAT_PCM => KSDATAFORMAT_SUBTYPE_PCM,
+        WAVE_FORMAT_IEEE_FLOAT => KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        _ => match wave_fmt.get_subformat()? {
+            SampleType::Int => KSDATAFORMAT_SUBTYPE_PCM,
+            SampleType::Float => KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        },
+    };
+    Ok(subformat)
+}
+
+/// Struct wrapping an [IAudioSessionManager2](https://learn.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessionmanager2).
+#[derive(Clone)]
+pub struct AudioSessionManager2 {
+    manager: IAudioSessionManager2,
+    direction: Direction,
+    device_id: Option<String>,
 }
 
-impl Device {
-    /// Get an [IAudioClient] from an [IMMDevice]
-    pub fn get_iaudioclient(&self) -> WasapiRes<AudioClient> {
-        let audio_client = unsafe { self.device.Activate::<IAudioClient>(CLSCTX_ALL, None)? };
-        Ok(AudioClient {
-            client: audio_client,
+impl AudioSessionManager2 {
+    /// Get the [AudioSessionControl] of a session of the own process.
+    /// Give `None` to get the default session, which is the one used by streams
+    /// that are initialized without a session GUID.
+    /// The session is created if it doesn't exist yet.
+    pub fn get_audiosessioncontrol(
+        &self,
+        session_guid: Option<&GUID>,
+    ) -> WasapiRes<AudioSessionControl> {
+        let guid_ptr = session_guid.map(|guid| guid as *const GUID);
+        let control = unsafe { self.manager.GetAudioSessionControl(guid_ptr, 0)? };
+        Ok(AudioSessionControl {
+            control,
             direction: self.direction,
-            sharemode: None,
-            bytes_per_frame: None,
+            device_id: self.device_id.clone(),
         })
     }
 
-    /// Read state from an [IMMDevice]
-    pub fn get_state(&self) -> WasapiRes<DeviceState> {
-        let mut pdwstate: u32 = 0;
-        let state = unsafe { self.device.GetState(&mut pdwstate) };
-        trace!("state: {:?}, pdwstate: {}", state, pdwstate);
-        let state_enum = match pdwstate {
-            _ if pdwstate == DEVICE_STATE_ACTIVE.0 => DeviceState::Active,
-            _ if pdwstate == DEVICE_STATE_DISABLED.0 => DeviceState::Disabled,
-            _ if pdwstate == DEVICE_STATE_NOTPRESENT.0 => DeviceState::NotPresent,
-            _ if pdwstate == DEVICE_STATE_UNPLUGGED.0 => DeviceState::Unplugged,
-            x => {
-                return Err(
-                    WasapiError::new(&format!("Got an illegal state: DEVICE_STATE({})", x)).into(),
-                )
-            }
+    /// Get the [AudioSessionControl] of a session that is shared between processes,
+    /// see [AudioClient::set_session].
+    pub fn get_crossprocess_audiosessioncontrol(
+        &self,
+        session_guid: &GUID,
+    ) -> WasapiRes<AudioSessionControl> {
+        let control = unsafe {
+            self.manager
+                .GetAudioSessionControl(Some(session_guid), AUDCLNT_STREAMFLAGS_CROSSPROCESS)?
         };
-        Ok(state_enum)
+        Ok(AudioSessionControl {
+            control,
+            direction: self.direction,
+            device_id: self.device_id.clone(),
+        })
     }
 
-    /// Read the friendly name of the endpoint device (for example, "Speakers (XYZ Audio Adapter)")
-    pub fn get_friendlyname(&self) -> WasapiRes<String> {
-        self.get_string_property(&PKEY_Device_FriendlyName)
+    /// Get the [SimpleAudioVolume] of a session, or of the default session if `session_guid` is `None`.
+    /// Set `cross_process` for sessions shared between processes, see [AudioClient::set_session].
+    pub fn get_simple_volume(
+        &self,
+        session_guid: Option<&GUID>,
+        cross_process: bool,
+    ) -> WasapiRes<SimpleAudioVolume> {
+        let guid_ptr = session_guid.map(|guid| guid as *const GUID);
+        let flags = if cross_process {
+            AUDCLNT_STREAMFLAGS_CROSSPROCESS
+        } else {
+            0
+        };
+        let volume = unsafe { self.manager.GetSimpleAudioVolume(guid_ptr, flags)? };
+        Ok(SimpleAudioVolume { volume })
     }
 
-    /// Read the friendly name of the audio adapter to which the endpoint device is attached (for example, "XYZ Audio Adapter")
-    pub fn get_interface_friendlyname(&self) -> WasapiRes<String> {
-        self.get_string_property(&PKEY_DeviceInterface_FriendlyName)
+    /// Get all the sessions of the device.
+    /// This is a snapshot, sessions that are created later are not included.
+    pub fn get_sessions(&self) -> WasapiRes<Vec<AudioSessionControl>> {
+        let enumerator = unsafe { self.manager.GetSessionEnumerator()? };
+        let count = unsafe { enumerator.GetCount()? };
+        trace!("nbr sessions {}", count);
+        let mut sessions = Vec::with_capacity(count as usize);
+        for idx in 0..count {
+            let control = unsafe { enumerator.GetSession(idx)? };
+            sessions.push(AudioSessionControl {
+                control,
+                direction: self.direction,
+                device_id: self.device_id.clone(),
+            });
+        }
+        Ok(sessions)
     }
 
-    /// Read the device description of the endpoint device (for example, "Speakers")
-    pub fn get_description(&self) -> WasapiRes<String> {
-        self.get_string_property(&PKEY_Device_DeviceDesc)
+    /// Get a [SessionList] with the current sessions of the device,
+    /// that can later be refreshed to find out which sessions were added and removed.
+    pub fn get_session_list(&self) -> WasapiRes<SessionList> {
+        let mut list = SessionList {
+            manager: self.clone(),
+            sessions: BTreeMap::new(),
+        };
+        list.refresh()?;
+        Ok(list)
     }
 
-    /// Read the FriendlyName of an [IMMDevice]
-    fn get_string_property(&self, key: &PROPERTYKEY) -> WasapiRes<String> {
-        let store = unsafe { self.device.OpenPropertyStore(STGM_READ)? };
-        let prop = unsafe { store.GetValue(key)? };
-        let propstr = unsafe { PropVariantToStringAlloc(&prop)? };
-        let wide_name = unsafe { U16CString::from_ptr_str(propstr.0) };
-        let name = wide_name.to_string_lossy();
-        trace!("name: {}", name);
-        Ok(name)
+    /// Register a callback that is called with the [AudioSessionControl] of each new session
+    /// that is created on the device, by any application.
+    ///
+    /// The callback is called from threads owned by the system, possibly several at once,
+    /// and should return quickly.
+    /// Notifications stop when the returned [SessionNotifications] is dropped.
+    pub fn register_session_notification(
+        &self,
+        callback: impl Fn(AudioSessionControl) + Send + Sync + 'static,
+    ) -> WasapiRes<SessionNotifications> {
+        // Windows only sends notifications once the session enumerator has been retrieved.
+        let _enumerator = unsafe { self.manager.GetSessionEnumerator()? };
+        let direction = self.direction;
+        let device_id = self.device_id.clone();
+        let forward = move |control: IAudioSessionControl| {
+            callback(AudioSessionControl {
+                control,
+                direction,
+                device_id: device_id.clone(),
+            })
+        };
+        let notifier: IAudioSessionNotification =
+            SessionCreatedNotifier::new(Box::new(forward)).into();
+        let registration = AgileRegistration::register(
+            &self.manager,
+            notifier,
+            |manager, notifier| {
+                unsafe { manager.RegisterSessionNotification(notifier) }.map_err(|err| {
+                    let object = self.describe();
+                    RegistrationError::new(NotificationType::SessionCreated, &object, err).into()
+                })
+            },
+            |manager, notifier| unsafe { manager.UnregisterSessionNotification(notifier) },
+            "session notifications",
+        )?;
+        Ok(SessionNotifications {
+            _registration: registration,
+        })
     }
 
-    /// Get the Id of an [IMMDevice]
-    pub fn get_id(&self) -> WasapiRes<String> {
-        let idstr = unsafe { self.device.GetId()? };
-        let wide_id = unsafe { U16CString::from_ptr_str(idstr.0) };
-        let id = wide_id.to_string_lossy();
-        trace!("id: {}", id);
-        Ok(id)
+    /// Register callbacks for ducking notifications, sent when a communication stream
+    /// makes the system duck other streams, and when it restores them.
+    /// See [DuckNotificationCallbacks] for the available callbacks.
+    ///
+    /// An application that does its own ducking passes the instance identifier of its own session,
+    /// see [AudioSessionControl2::get_session_instance_identifier],
+    /// to opt out of the system ducking of that session.
+    /// Pass `None` to only get the notifications.
+    ///
+    /// The callbacks are called from a thread owned by the system, and should return quickly.
+    /// Notifications stop when the returned [DuckNotifications] is dropped.
+    pub fn register_duck_notification(
+        &self,
+        session_instance_id: Option<&str>,
+        callbacks: DuckNotificationCallbacks,
+    ) -> WasapiRes<DuckNotifications> {
+        let notifier: IAudioVolumeDuckNotification = DuckNotifier::new(callbacks).into();
+        let wide_id = session_instance_id.map(U16CString::from_str).transpose()?;
+        let id_ptr = match &wide_id {
+            Some(id) => PCWSTR(id.as_ptr()),
+            None => PCWSTR::null(),
+        };
+        let registration = AgileRegistration::register(
+            &self.manager,
+            notifier,
+            |manager, notifier| {
+                unsafe { manager.RegisterDuckNotification(id_ptr, notifier) }.map_err(|err| {
+                    let object = self.describe();
+                    RegistrationError::new(NotificationType::VolumeDuck, &object, err).into()
+                })
+            },
+            |manager, notifier| unsafe { manager.UnregisterDuckNotification(notifier) },
+            "duck notifications",
+        )?;
+        Ok(DuckNotifications {
+            _registration: registration,
+        })
     }
 
-    /// Get the direction for this Device
-    pub fn get_direction(&self) -> Direction {
-        self.direction
+    /// Describe the session manager, for error messages.
+    fn describe(&self) -> String {
+        format!(
+            "session manager of device {}",
+            self.device_id.as_deref().unwrap_or("(unknown)")
+        )
+    }
+}
+
+/// Registration of callbacks for ducking notifications, see [AudioSessionManager2::register_duck_notification].
+/// Notifications stop when this is dropped.
+pub struct DuckNotifications {
+    _registration: AgileRegistration<IAudioSessionManager2, IAudioVolumeDuckNotification>,
+}
+
+/// Registration of a callback for new sessions, see [AudioSessionManager2::register_session_notification].
+/// Notifications stop when this is dropped.
+pub struct SessionNotifications {
+    _registration: AgileRegistration<IAudioSessionManager2, IAudioSessionNotification>,
+}
+
+/// A list of the sessions of a device, that can be refreshed by polling.
+/// The sessions are identified by their session instance identifier,
+/// see [AudioSessionControl2::get_session_instance_identifier].
+/// This is unique for each session, unlike the session identifier that
+/// is shared by all sessions of an application.
+pub struct SessionList {
+    manager: AudioSessionManager2,
+    sessions: BTreeMap<String, AudioSessionControl>,
+}
+
+/// The changes found by [SessionList::refresh].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SessionChanges {
+    /// The instance identifiers of the sessions that appeared since the last refresh.
+    pub added: Vec<String>,
+    /// The instance identifiers of the sessions that disappeared since the last refresh.
+    pub removed: Vec<String>,
+}
+
+impl SessionChanges {
+    /// Check if there were no changes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl SessionList {
+    /// Read the current sessions from the device, and return the changes since the last refresh.
+    /// Sessions that don't provide an instance identifier are skipped.
+    pub fn refresh(&mut self) -> WasapiRes<SessionChanges> {
+        let mut sessions = BTreeMap::new();
+        for session in self.manager.get_sessions()? {
+            let instance_id = match session.upgrade().control2 {
+                Some(control2) => control2.get_session_instance_identifier(),
+                None => continue,
+            };
+            match instance_id {
+                Ok(id) => {
+                    sessions.insert(id, session);
+                }
+                Err(err) => warn!("Unable to read session instance identifier: {}", err),
+            }
+        }
+        let added = sessions
+            .keys()
+            .filter(|id| !self.sessions.contains_key(*id))
+            .cloned()
+            .collect();
+        let removed = self
+            .sessions
+            .keys()
+            .filter(|id| !sessions.contains_key(*id))
+            .cloned()
+            .collect();
+        self.sessions = sessions;
+        let changes = SessionChanges { added, removed };
+        debug!("session list refreshed, changes: {:?}", changes);
+        Ok(changes)
+    }
+
+    /// Get the session with the given instance identifier, if it is in the list.
+    pub fn get_session(&self, instance_id: &str) -> Option<&AudioSessionControl> {
+        self.sessions.get(instance_id)
+    }
+
+    /// Iterate over the sessions, as pairs of instance identifier and [AudioSessionControl].
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &AudioSessionControl)> {
+        self.sessions
+            .iter()
+            .map(|(id, session)| (id.as_str(), session))
+    }
+
+    /// Get the number of sessions in the list.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Check if the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+/// The sessions muted by [Device::mute_all_sessions].
+/// Call [MutedSessions::restore] to unmute them again.
+/// Dropping this without calling `restore` leaves the sessions muted.
+pub struct MutedSessions {
+    sessions: Vec<SimpleAudioVolume>,
+}
+
+impl MutedSessions {
+    /// Get the number of sessions that were muted.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Check if no sessions were muted.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Unmute all the muted sessions.
+    /// Only the mute state is restored, so volume changes made while the sessions were muted are kept.
+    /// All sessions are unmuted even if some of them fail,
+    /// and then the first error is returned.
+    pub fn restore(self) -> WasapiRes<()> {
+        let mut result = Ok(());
+        for volume in self.sessions.iter() {
+            if let Err(err) = volume.set_mute(false, None) {
+                warn!("Unable to unmute session: {}", err);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        debug!("restored {} sessions", self.sessions.len());
+        result
     }
 }
 
@@ -440,15 +2752,182 @@ impl IActivateAudioInterfaceCompletionHandler_Impl for Handler {
     }
 }
 
+/// A snapshot of the fill level of the buffer of an [AudioClient], see [AudioClient::get_buffer_stats].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferStats {
+    /// The number of frames in the buffer, see [AudioClient::get_current_padding].
+    pub padding: u32,
+    /// The size of the buffer in frames, see [AudioClient::get_bufferframecount].
+    pub buffer_frames: u32,
+    /// The padding as a fraction of the buffer size, from 0.0 for empty to 1.0 for full.
+    pub fill_ratio: f32,
+}
+
+impl BufferStats {
+    fn new(padding: u32, buffer_frames: u32) -> Self {
+        let fill_ratio = if buffer_frames > 0 {
+            padding as f32 / buffer_frames as f32
+        } else {
+            0.0
+        };
+        BufferStats {
+            padding,
+            buffer_frames,
+            fill_ratio,
+        }
+    }
+}
+
+/// Helper for recording the minimum and maximum fill level of the buffer of an [AudioClient] over time.
+///
+/// Call [BufferFillSampler::sample] once per period, for example just before reading or writing,
+/// and read the results when the stream has been running for a while.
+/// This is useful when tuning the buffer size, a buffer that never gets close
+/// to empty for render, or full for capture, can be made smaller to reduce the latency.
+/// Each sample only reads the padding and buffer size, so it has very little impact on the timing.
+#[derive(Clone, Debug, Default)]
+pub struct BufferFillSampler {
+    min: Option<BufferStats>,
+    max: Option<BufferStats>,
+    nbr_samples: u64,
+}
+
+impl BufferFillSampler {
+    /// Create a new [BufferFillSampler] with no samples.
+    pub fn new() -> Self {
+        BufferFillSampler::default()
+    }
+
+    /// Sample the current fill level of the buffer, and update the minimum and maximum.
+    /// Returns the new sample.
+    pub fn sample(&mut self, audio_client: &AudioClient) -> WasapiRes<BufferStats> {
+        let stats = audio_client.get_buffer_stats()?;
+        if self.min.map_or(true, |min| stats.padding < min.padding) {
+            self.min = Some(stats);
+        }
+        if self.max.map_or(true, |max| stats.padding > max.padding) {
+            self.max = Some(stats);
+        }
+        self.nbr_samples += 1;
+        Ok(stats)
+    }
+
+    /// Get the sample with the lowest fill level, or None if nothing has been sampled yet.
+    pub fn get_min_fill(&self) -> Option<BufferStats> {
+        self.min
+    }
+
+    /// Get the sample with the highest fill level, or None if nothing has been sampled yet.
+    pub fn get_max_fill(&self) -> Option<BufferStats> {
+        self.max
+    }
+
+    /// Get the number of samples taken since the sampler was created or reset.
+    pub fn get_nbr_samples(&self) -> u64 {
+        self.nbr_samples
+    }
+
+    /// Clear all the samples.
+    pub fn reset(&mut self) {
+        *self = BufferFillSampler::default();
+    }
+}
+
 /// Struct wrapping an [IAudioClient](https://docs.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioclient).
 pub struct AudioClient {
     client: IAudioClient,
     direction: Direction,
     sharemode: Option<ShareMode>,
     bytes_per_frame: Option<usize>,
+    samplerate: Option<usize>,
+    device_id: Option<String>,
+    stream_category: Option<StreamCategory>,
+    stream_options: Option<StreamOptions>,
+    init_info: Option<InitInfo>,
+    engine_period: Cell<Option<u32>>,
+    session_guid: Option<GUID>,
+    cross_process: bool,
+    process_loopback: bool,
+    role: Option<Role>,
+    no_persist: bool,
+    extra_stream_flags: u32,
+}
+
+/// The parameters used to initialize an [AudioClient], and the resulting buffer size.
+/// See [AudioClient::get_init_info].
+#[derive(Clone, Debug)]
+pub struct InitInfo {
+    /// The requested format.
+    pub format: WaveFormat,
+    /// The requested period in 100-nanosecond units.
+    pub period: i64,
+    /// The direction of the stream, capture from a render device means loopback.
+    pub direction: Direction,
+    /// The sharemode of the stream.
+    pub sharemode: ShareMode,
+    /// Whether automatic format conversion was enabled.
+    pub convert: bool,
+    /// The size of the allocated buffer in frames.
+    pub buffer_frames: u32,
+}
+
+impl fmt::Debug for AudioClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioClient")
+            .field("direction", &self.direction)
+            .field("sharemode", &self.sharemode)
+            .field("bytes_per_frame", &self.bytes_per_frame)
+            .field("samplerate", &self.samplerate)
+            .field("device_id", &self.device_id)
+            .field("stream_category", &self.stream_category)
+            .field("stream_options", &self.stream_options)
+            .field("init_info", &self.init_info)
+            .field("session_guid", &self.session_guid)
+            .field("cross_process", &self.cross_process)
+            .field("process_loopback", &self.process_loopback)
+            .field("role", &self.role)
+            .field("no_persist", &self.no_persist)
+            .field("extra_stream_flags", &self.extra_stream_flags)
+            .finish()
+    }
 }
 
 impl AudioClient {
+    /// Create an [AudioClient] from a raw [IAudioClient] pointer, for interoperability with other crates.
+    /// The client is treated as not initialized, and must be initialized with [AudioClient::initialize_client].
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid `IAudioClient` pointer that has not been initialized,
+    /// for an endpoint with the given direction.
+    /// The [AudioClient] takes ownership of the reference held by the pointer.
+    pub unsafe fn from_raw(raw: *mut c_void, direction: &Direction) -> Self {
+        AudioClient {
+            client: IAudioClient::from_raw(raw),
+            direction: *direction,
+            sharemode: None,
+            bytes_per_frame: None,
+            samplerate: None,
+            device_id: None,
+            stream_category: None,
+            stream_options: None,
+            init_info: None,
+            engine_period: Cell::new(None),
+            session_guid: None,
+            cross_process: false,
+            process_loopback: false,
+            role: None,
+            no_persist: false,
+            extra_stream_flags: 0,
+        }
+    }
+
+    /// Consume the [AudioClient] and return the raw [IAudioClient] pointer.
+    /// The caller becomes responsible for releasing the reference.
+    pub fn into_raw(self) -> *mut c_void {
+        self.client.into_raw()
+    }
+
     /// Creates a loopback capture [AudioClient] for a specific process.
     ///
     /// `include_tree` is equivalent to [PROCESS_LOOPBACK_MODE](https://learn.microsoft.com/en-us/windows/win32/api/audioclientactivationparams/ne-audioclientactivationparams-process_loopback_mode).
@@ -487,6 +2966,50 @@ impl AudioClient {
     /// audio_client.initialize_client(&desired_format, hnsbufferduration, autoconvert).unwrap();
     /// ```
     pub fn new_application_loopback_client(process_id: u32, include_tree: bool) -> WasapiRes<Self> {
+        let mode = if include_tree {
+            PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE
+        } else {
+            PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE
+        };
+        Self::new_process_loopback_client(process_id, mode)
+    }
+
+    /// Creates a loopback capture [AudioClient] that captures the audio from all processes,
+    /// except the given process and its child processes.
+    ///
+    /// This is useful for example for screen recorders, that want to capture the system audio
+    /// without including their own sounds. Pass the id of the own process to get this behavior,
+    /// see [std::process::id].
+    ///
+    /// This uses [PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE](https://learn.microsoft.com/en-us/windows/win32/api/audioclientactivationparams/ne-audioclientactivationparams-process_loopback_mode),
+    /// and the resulting client has the same limitations as the one from
+    /// [AudioClient::new_application_loopback_client].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use wasapi::{AudioClient, Direction, ShareMode, WaveFormat, SampleType, initialize_mta};
+    /// let desired_format = WaveFormat::new(32, 32, &SampleType::Float, 48000, 2, None);
+    /// let process_id = std::process::id();
+    ///
+    /// initialize_mta().ok().unwrap(); // Don't do this on a UI thread
+    /// let mut audio_client = AudioClient::new_system_loopback_excluding(process_id).unwrap();
+    /// audio_client.initialize_client(&desired_format, 0, &Direction::Capture, &ShareMode::Shared, true).unwrap();
+    /// ```
+    pub fn new_system_loopback_excluding(process_id: u32) -> WasapiRes<Self> {
+        Self::new_process_loopback_client(
+            process_id,
+            PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE,
+        )
+    }
+
+    fn new_process_loopback_client(
+        process_id: u32,
+        mode: PROCESS_LOOPBACK_MODE,
+    ) -> WasapiRes<Self> {
+        debug!(
+            "creating process loopback client for process {}, mode {:?}",
+            process_id, mode
+        );
         unsafe {
             // Create audio client
             let mut audio_client_activation_params = AUDIOCLIENT_ACTIVATION_PARAMS {
@@ -494,11 +3017,7 @@ impl AudioClient {
                 Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
                     ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
                         TargetProcessId: process_id,
-                        ProcessLoopbackMode: if include_tree {
-                            PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE
-                        } else {
-                            PROCESS_LOOPBACK_MODE_EXCLUDE_TARGET_PROCESS_TREE
-                        },
+                        ProcessLoopbackMode: mode,
                     },
                 },
             };
@@ -560,6 +3079,18 @@ impl AudioClient {
                 direction: Direction::Render,
                 sharemode: Some(ShareMode::Shared),
                 bytes_per_frame: None,
+                samplerate: None,
+                device_id: None,
+                stream_category: None,
+                stream_options: None,
+                init_info: None,
+                engine_period: Cell::new(None),
+                session_guid: None,
+                cross_process: false,
+                process_loopback: true,
+                role: None,
+                no_persist: false,
+                extra_stream_flags: 0,
             })
         }
     }
@@ -567,18 +3098,21 @@ impl AudioClient {
     /// Get MixFormat of the device. This is the format the device uses in shared mode and should always be accepted.
     pub fn get_mixformat(&self) -> WasapiRes<WaveFormat> {
         let temp_fmt_ptr = unsafe { self.client.GetMixFormat()? };
-        let temp_fmt = unsafe { *temp_fmt_ptr };
-        let mix_format =
-            if temp_fmt.cbSize == 22 && temp_fmt.wFormatTag as u32 == WAVE_FORMAT_EXTENSIBLE {
-                unsafe {
-                    WaveFormat {
-                        wave_fmt: (temp_fmt_ptr as *const _ as *const WAVEFORMATEXTENSIBLE).read(),
-                    }
-                }
-            } else {
-                WaveFormat::from_waveformatex(temp_fmt)?
-            };
-        Ok(mix_format)
+        let mix_format = WaveFormat::from_bytes(unsafe { waveformat_bytes(temp_fmt_ptr) });
+        unsafe { CoTaskMemFree(Some(temp_fmt_ptr as *const _)) };
+        mix_format
+    }
+
+    /// Get MixFormat of the device as raw bytes, without parsing.
+    /// The bytes are a [WAVEFORMATEX] structure, followed by `cbSize` bytes of extra format information.
+    /// Use this to access mix formats that can't be represented by a [WaveFormat],
+    /// for example to pass them on to other APIs.
+    pub fn get_mixformat_raw(&self) -> WasapiRes<Vec<u8>> {
+        let temp_fmt_ptr = unsafe { self.client.GetMixFormat()? };
+        let bytes = unsafe { waveformat_bytes(temp_fmt_ptr) }.to_vec();
+        unsafe { CoTaskMemFree(Some(temp_fmt_ptr as *const _)) };
+        trace!("raw mix format of {} bytes", bytes.len());
+        Ok(bytes)
     }
 
     /// Check if a format is supported.
@@ -602,58 +3136,117 @@ impl AudioClient {
         wave_fmt: &WaveFormat,
         sharemode: &ShareMode,
     ) -> WasapiRes<Option<WaveFormat>> {
-        let supported = match sharemode {
+        let (hresult, closest_match) = self.is_supported_with_hresult(wave_fmt, sharemode)?;
+        match (hresult, closest_match) {
+            (S_OK, _) => {
+                debug!("The requested format is supported");
+                Ok(None)
+            }
+            (_, Some(closest)) => {
+                debug!("The requested format is not supported but a simular one is");
+                Ok(Some(closest))
+            }
+            (_, None) => Err(WasapiError::new(
+                format!(
+                    "The requested format is not supported, and no closest match was given, HRESULT: {:#010x}",
+                    hresult.0
+                )
+                .as_str(),
+            )
+            .into()),
+        }
+    }
+
+    /// Check if a format is supported, in either shared or exclusive mode.
+    /// The result is a [FormatSupport] that tells if the format is supported as is,
+    /// if a closest match is available (only in shared mode), or if it is not supported.
+    /// Errors are only returned when the check itself fails, for example if the device was removed.
+    pub fn check_format_support(
+        &self,
+        wave_fmt: &WaveFormat,
+        sharemode: &ShareMode,
+    ) -> WasapiRes<FormatSupport> {
+        let (hresult, closest_match) = match self.is_supported_with_hresult(wave_fmt, sharemode) {
+            Ok(result) => result,
+            Err(err) => {
+                if let Some(werr) = err.downcast_ref::<windows::core::Error>() {
+                    if werr.code() == AUDCLNT_E_UNSUPPORTED_FORMAT {
+                        return Ok(FormatSupport::NotSupported(format!(
+                            "The format is not supported in {} mode",
+                            sharemode
+                        )));
+                    }
+                }
+                return Err(err);
+            }
+        };
+        let support = match (hresult, closest_match) {
+            (S_OK, _) => FormatSupport::Supported,
+            (_, Some(closest)) => FormatSupport::ClosestMatch(closest),
+            (_, None) => FormatSupport::NotSupported(format!(
+                "The format is not supported, and no closest match was given, HRESULT: {:#010x}",
+                hresult.0
+            )),
+        };
+        debug!("format support: {}", support);
+        Ok(support)
+    }
+
+    /// Check if a format is supported, and return the HRESULT of the call to
+    /// [IAudioClient::IsFormatSupported](https://docs.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient-isformatsupported)
+    /// together with the closest matching format, if one was given.
+    ///
+    /// The HRESULT is `S_OK` if the format is supported as is, and `S_FALSE` if it is not supported
+    /// but a closest match is available. The closest match is only given in shared mode,
+    /// and some drivers return `S_FALSE` without one.
+    /// Failure HRESULTs, like `AUDCLNT_E_UNSUPPORTED_FORMAT`, are returned as errors.
+    pub fn is_supported_with_hresult(
+        &self,
+        wave_fmt: &WaveFormat,
+        sharemode: &ShareMode,
+    ) -> WasapiRes<(HRESULT, Option<WaveFormat>)> {
+        match sharemode {
             ShareMode::Exclusive => {
-                unsafe {
-                    self.client
-                        .IsFormatSupported(
-                            AUDCLNT_SHAREMODE_EXCLUSIVE,
-                            wave_fmt.as_waveformatex_ref(),
-                            None,
-                        )
-                        .ok()?
+                let hresult = unsafe {
+                    self.client.IsFormatSupported(
+                        AUDCLNT_SHAREMODE_EXCLUSIVE,
+                        wave_fmt.as_waveformatex_ref(),
+                        None,
+                    )
                 };
-                None
+                hresult.ok()?;
+                Ok((hresult, None))
             }
             ShareMode::Shared => {
-                let mut supported_format: *mut WAVEFORMATEX = std::ptr::null_mut();
-                unsafe {
-                    self.client
-                        .IsFormatSupported(
-                            AUDCLNT_SHAREMODE_SHARED,
-                            wave_fmt.as_waveformatex_ref(),
-                            Some(&mut supported_format),
-                        )
-                        .ok()?
+                let mut supported_format: *mut WAVEFORMATEX = ptr::null_mut();
+                let hresult = unsafe {
+                    self.client.IsFormatSupported(
+                        AUDCLNT_SHAREMODE_SHARED,
+                        wave_fmt.as_waveformatex_ref(),
+                        Some(&mut supported_format),
+                    )
                 };
-                // Check if we got a pointer to a WAVEFORMATEX structure.
                 if supported_format.is_null() {
-                    // The pointer is still null, thus the format is supported as is.
-                    debug!("The requested format is supported");
-                    None
-                } else {
-                    // Read the structure
-                    let temp_fmt: WAVEFORMATEX = unsafe { supported_format.read() };
-                    debug!("The requested format is not supported but a simular one is");
-                    let new_fmt = if temp_fmt.cbSize == 22
-                        && temp_fmt.wFormatTag as u32 == WAVE_FORMAT_EXTENSIBLE
-                    {
-                        debug!("got the nearest matching format as a WAVEFORMATEXTENSIBLE");
-                        let temp_fmt_ext: WAVEFORMATEXTENSIBLE = unsafe {
-                            (supported_format as *const _ as *const WAVEFORMATEXTENSIBLE).read()
-                        };
-                        WaveFormat {
-                            wave_fmt: temp_fmt_ext,
-                        }
-                    } else {
-                        debug!("got the nearest matching format as a WAVEFORMATEX, converting..");
-                        WaveFormat::from_waveformatex(temp_fmt)?
-                    };
-                    Some(new_fmt)
+                    hresult.ok()?;
+                    return Ok((hresult, None));
+                }
+                // The closest match must be freed also if the call failed.
+                let closest_match =
+                    WaveFormat::from_bytes(unsafe { waveformat_bytes(supported_format) });
+                unsafe { CoTaskMemFree(Some(supported_format as *const _)) };
+                hresult.ok()?;
+                match closest_match {
+                    Ok(closest) => {
+                        debug!("got the nearest matching format: {:?}", closest);
+                        Ok((hresult, Some(closest)))
+                    }
+                    Err(err) => {
+                        warn!("Unable to read the nearest matching format: {}", err);
+                        Ok((hresult, None))
+                    }
                 }
             }
-        };
-        Ok(supported)
+        }
     }
 
     /// A helper function for checking if a format is supported.
@@ -718,6 +3311,146 @@ impl AudioClient {
         Ok((def_time, min_time))
     }
 
+    /// Get the minimum and maximum buffer durations in 100-nanosecond units that the hardware supports
+    /// for the given format, for event driven or timer driven buffering.
+    /// This is meant for exclusive mode, and can be used to choose a valid period before initializing.
+    /// Returns a tuple of (minimum, maximum).
+    pub fn get_buffer_size_limits(
+        &self,
+        wave_fmt: &WaveFormat,
+        event_driven: bool,
+    ) -> WasapiRes<(i64, i64)> {
+        let client2: IAudioClient2 = self.client.cast()?;
+        let mut min_duration = 0;
+        let mut max_duration = 0;
+        unsafe {
+            client2.GetBufferSizeLimits(
+                wave_fmt.as_waveformatex_ref(),
+                event_driven,
+                &mut min_duration,
+                &mut max_duration,
+            )?
+        };
+        trace!(
+            "min buffer duration {}, max buffer duration {}",
+            min_duration,
+            max_duration
+        );
+        Ok((min_duration, max_duration))
+    }
+
+    /// Get the limits of the shared mode engine period for a format, see
+    /// [IAudioClient3::GetSharedModeEnginePeriod](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient3-getsharedmodeengineperiod).
+    /// Returns the default, fundamental, minimum and maximum periods in frames.
+    /// The supported periods are the multiples of the fundamental period between the minimum and maximum.
+    /// This requires Windows 10 or later.
+    pub fn get_shared_mode_engine_period(
+        &self,
+        wave_fmt: &WaveFormat,
+    ) -> WasapiRes<(u32, u32, u32, u32)> {
+        let client3: IAudioClient3 = self.client.cast()?;
+        let mut default_period = 0;
+        let mut fundamental_period = 0;
+        let mut min_period = 0;
+        let mut max_period = 0;
+        unsafe {
+            client3.GetSharedModeEnginePeriod(
+                wave_fmt.as_waveformatex_ref(),
+                &mut default_period,
+                &mut fundamental_period,
+                &mut min_period,
+                &mut max_period,
+            )?
+        };
+        trace!(
+            "engine periods in frames, default {}, fundamental {}, min {}, max {}",
+            default_period,
+            fundamental_period,
+            min_period,
+            max_period
+        );
+        Ok((default_period, fundamental_period, min_period, max_period))
+    }
+
+    /// Get the current period of the shared mode audio engine in frames, see
+    /// [IAudioClient3::GetCurrentSharedModeEnginePeriod](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient3-getcurrentsharedmodeengineperiod).
+    /// The engine period is shared by all streams on the device,
+    /// and can be changed by other applications.
+    /// This requires Windows 10 or later.
+    pub fn get_current_engine_period(&self) -> WasapiRes<u32> {
+        let client3: IAudioClient3 = self.client.cast()?;
+        let mut format_ptr = ptr::null_mut();
+        let mut period = 0;
+        unsafe { client3.GetCurrentSharedModeEnginePeriod(&mut format_ptr, &mut period)? };
+        if !format_ptr.is_null() {
+            unsafe { CoTaskMemFree(Some(format_ptr as *const _)) };
+        }
+        trace!("current engine period {} frames", period);
+        Ok(period)
+    }
+
+    /// Check if the shared mode engine period has changed since the previous call.
+    /// Returns the new period in frames if it changed, and `None` otherwise.
+    /// The first call records the current period and returns `None`.
+    ///
+    /// There is no notification for engine period changes,
+    /// so a low latency client should call this regularly, for example once per buffer cycle,
+    /// to be able to adapt its buffering when another application changes the period.
+    pub fn engine_period_changed(&self) -> WasapiRes<Option<u32>> {
+        let period = self.get_current_engine_period()?;
+        let previous = self.engine_period.replace(Some(period));
+        match previous {
+            Some(previous) if previous != period => {
+                debug!(
+                    "engine period changed from {} to {} frames",
+                    previous, period
+                );
+                Ok(Some(period))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Set the category and options of the stream, see
+    /// [IAudioClient2::SetClientProperties](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient2-setclientproperties).
+    /// This must be called before [AudioClient::initialize_client].
+    /// The values are stored and can be read back with [AudioClient::get_stream_category]
+    /// and [AudioClient::get_stream_options].
+    pub fn set_client_properties(
+        &mut self,
+        category: &StreamCategory,
+        options: &StreamOptions,
+        offload: bool,
+    ) -> WasapiRes<()> {
+        let client2: IAudioClient2 = self.client.cast()?;
+        let properties = AudioClientProperties {
+            cbSize: size_of::<AudioClientProperties>() as u32,
+            bIsOffload: offload.into(),
+            eCategory: category.to_audio_stream_category(),
+            Options: options.to_streamoptions(),
+        };
+        unsafe { client2.SetClientProperties(&properties)? };
+        debug!(
+            "set client properties, category: {}, options: {:?}, offload: {}",
+            category, options, offload
+        );
+        self.stream_category = Some(*category);
+        self.stream_options = Some(*options);
+        Ok(())
+    }
+
+    /// Get the stream category set with [AudioClient::set_client_properties],
+    /// or `None` if it was never set.
+    pub fn get_stream_category(&self) -> Option<StreamCategory> {
+        self.stream_category
+    }
+
+    /// Get the stream options set with [AudioClient::set_client_properties],
+    /// or `None` if they were never set.
+    pub fn get_stream_options(&self) -> Option<StreamOptions> {
+        self.stream_options
+    }
+
     /// Helper function for calculating a period size in 100-nanosecond units that is near a desired value,
     /// and always larger than the minimum value supported by the device.
     /// The returned value leads to a device buffer size that is aligned both to the frame size of the format,
@@ -761,6 +3494,8 @@ impl AudioClient {
 
     /// Initialize an [IAudioClient] for the given direction, sharemode and format.
     /// Setting `convert` to true enables automatic samplerate and format conversion, meaning that almost any format will be accepted.
+    ///
+    /// Returns an [EndpointMismatchError] if the endpoint doesn't support the direction in this sharemode.
     pub fn initialize_client(
         &mut self,
         wavefmt: &WaveFormat,
@@ -774,15 +3509,45 @@ impl AudioClient {
                 WasapiError::new("Cant use automatic format conversion in exclusive mode").into(),
             );
         }
+        if sharemode == &ShareMode::Exclusive
+            && self.extra_stream_flags
+                & (AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY)
+                != 0
+        {
+            return Err(WasapiError::new(
+                "Cant use the extra stream flags for format conversion in exclusive mode",
+            )
+            .into());
+        }
+        if self.process_loopback {
+            if direction != &Direction::Capture {
+                return Err(EndpointMismatchError {
+                    expected: Direction::Capture,
+                    actual: *direction,
+                    sharemode: *sharemode,
+                }
+                .into());
+            }
+            if sharemode != &ShareMode::Shared {
+                return Err(WasapiError::new(
+                    "Application loopback clients can only be used in shared mode",
+                )
+                .into());
+            }
+        }
         let mut streamflags = match (&self.direction, direction, sharemode) {
             (Direction::Render, Direction::Capture, ShareMode::Shared) => {
                 AUDCLNT_STREAMFLAGS_EVENTCALLBACK | AUDCLNT_STREAMFLAGS_LOOPBACK
             }
-            (Direction::Render, Direction::Capture, ShareMode::Exclusive) => {
-                return Err(WasapiError::new("Cant use Loopback with exclusive mode").into());
-            }
-            (Direction::Capture, Direction::Render, _) => {
-                return Err(WasapiError::new("Cant render to a capture device").into());
+            // Loopback capture requires shared mode
+            (Direction::Render, Direction::Capture, ShareMode::Exclusive)
+            | (Direction::Capture, Direction::Render, _) => {
+                return Err(EndpointMismatchError {
+                    expected: self.direction,
+                    actual: *direction,
+                    sharemode: *sharemode,
+                }
+                .into());
             }
             _ => AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
         };
@@ -790,6 +3555,13 @@ impl AudioClient {
             streamflags |=
                 AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY;
         }
+        if self.cross_process {
+            streamflags |= AUDCLNT_STREAMFLAGS_CROSSPROCESS;
+        }
+        if self.no_persist {
+            streamflags |= AUDCLNT_STREAMFLAGS_NOPERSIST;
+        }
+        streamflags |= self.extra_stream_flags;
         let mode = match sharemode {
             ShareMode::Exclusive => AUDCLNT_SHAREMODE_EXCLUSIVE,
             ShareMode::Shared => AUDCLNT_SHAREMODE_SHARED,
@@ -799,20 +3571,134 @@ impl AudioClient {
             ShareMode::Shared => 0,
         };
         self.sharemode = Some(*sharemode);
-        unsafe {
+        let result = unsafe {
             self.client.Initialize(
                 mode,
                 streamflags,
                 period,
                 device_period,
                 wavefmt.as_waveformatex_ref(),
-                None,
-            )?;
+                self.session_guid.as_ref().map(|guid| guid as *const GUID),
+            )
+        };
+        if let Err(err) = result {
+            if err.code() == AUDCLNT_E_WRONG_ENDPOINT_TYPE {
+                let expected = if self.process_loopback {
+                    Direction::Capture
+                } else {
+                    self.direction
+                };
+                return Err(EndpointMismatchError {
+                    expected,
+                    actual: *direction,
+                    sharemode: *sharemode,
+                }
+                .into());
+            }
+            return Err(err.into());
         }
         self.bytes_per_frame = Some(wavefmt.get_blockalign() as usize);
+        self.samplerate = Some(wavefmt.get_samplespersec() as usize);
+        let buffer_frames = unsafe { self.client.GetBufferSize()? };
+        let init_info = InitInfo {
+            format: wavefmt.clone(),
+            period,
+            direction: *direction,
+            sharemode: *sharemode,
+            convert,
+            buffer_frames,
+        };
+        debug!("initialized client: {:?}", init_info);
+        self.init_info = Some(init_info);
+        Ok(())
+    }
+
+    /// Select the audio session that the client joins when it is initialized.
+    /// This must be called before [AudioClient::initialize_client].
+    ///
+    /// All clients initialized with the same session GUID on the same device belong to the same session,
+    /// and share a single entry in the volume mixer.
+    /// With `cross_process` set, the session is shared also with clients in other processes,
+    /// that use the same GUID and also set `cross_process`.
+    /// This lets an application consisting of several processes appear as a single application.
+    /// Use [generate_session_guid] to create a new GUID, and pass it to the other processes.
+    pub fn set_session(&mut self, session_guid: &GUID, cross_process: bool) -> WasapiRes<()> {
+        if self.init_info.is_some() {
+            return Err(WasapiError::new(
+                "The session must be set before the client is initialized",
+            )
+            .into());
+        }
+        self.session_guid = Some(*session_guid);
+        self.cross_process = cross_process;
+        Ok(())
+    }
+
+    /// Get the role the client was tagged with by [Device::get_iaudioclient_for_role], if any.
+    pub fn get_role(&self) -> Option<Role> {
+        self.role
+    }
+
+    /// Choose whether the volume and mute settings of the session are remembered by the system.
+    /// This must be called before [AudioClient::initialize_client].
+    ///
+    /// By default, the system stores the volume and mute settings of each application,
+    /// and restores them the next time the application opens a stream.
+    /// Setting `no_persist` uses the [AUDCLNT_STREAMFLAGS_NOPERSIST](https://learn.microsoft.com/en-us/windows/win32/coreaudio/audclnt-streamflags-xxx-constants) flag,
+    /// so that changes made while the stream is running are forgotten when it closes.
+    /// This is useful for transient streams, such as previews or notification sounds.
+    pub fn set_no_persist(&mut self, no_persist: bool) -> WasapiRes<()> {
+        if self.init_info.is_some() {
+            return Err(WasapiError::new(
+                "The persistence must be set before the client is initialized",
+            )
+            .into());
+        }
+        self.no_persist = no_persist;
         Ok(())
     }
 
+    /// Set additional [stream flags](https://learn.microsoft.com/en-us/windows/win32/coreaudio/audclnt-streamflags-xxx-constants)
+    /// to be passed to `IAudioClient::Initialize`, on top of the ones set by [AudioClient::initialize_client].
+    /// This must be called before [AudioClient::initialize_client].
+    ///
+    /// This is meant for flags that are not otherwise supported by this crate.
+    /// The flags for event driven mode and loopback are managed by the crate and are rejected.
+    /// The flags for format conversion are rejected when initializing in exclusive mode.
+    pub fn set_extra_stream_flags(&mut self, flags: u32) -> WasapiRes<()> {
+        if self.init_info.is_some() {
+            return Err(WasapiError::new(
+                "The stream flags must be set before the client is initialized",
+            )
+            .into());
+        }
+        if flags & (AUDCLNT_STREAMFLAGS_EVENTCALLBACK | AUDCLNT_STREAMFLAGS_LOOPBACK) != 0 {
+            return Err(WasapiError::new(
+                "The EVENTCALLBACK and LOOPBACK stream flags are set automatically and cant be given as extra flags",
+            )
+            .into());
+        }
+        self.extra_stream_flags = flags;
+        Ok(())
+    }
+
+    /// Get the extra stream flags set with [AudioClient::set_extra_stream_flags].
+    pub fn get_extra_stream_flags(&self) -> u32 {
+        self.extra_stream_flags
+    }
+
+    /// Get the session GUID set with [AudioClient::set_session], if any.
+    pub fn get_session_guid(&self) -> Option<GUID> {
+        self.session_guid
+    }
+
+    /// Get the parameters used in [AudioClient::initialize_client] and the resulting buffer size,
+    /// or `None` if the client has not been initialized.
+    /// This is useful for logging exactly what was negotiated with the device.
+    pub fn get_init_info(&self) -> Option<&InitInfo> {
+        self.init_info.as_ref()
+    }
+
     /// Create and return an event handle for an [IAudioClient]
     pub fn set_get_eventhandle(&self) -> WasapiRes<Handle> {
         let h_event = unsafe { CreateEventA(None, false, false, PCSTR::null())? };
@@ -820,6 +3706,24 @@ impl AudioClient {
         Ok(Handle { handle: h_event })
     }
 
+    /// Associate an existing event [Handle] with this [IAudioClient].
+    ///
+    /// Use this when a stream is recreated, for example after the device was invalidated,
+    /// to keep waiting on the same [Handle] instead of creating a new one with [AudioClient::set_get_eventhandle].
+    /// The client must be initialized, with event driven buffering.
+    /// The event is reset, so that a pending signal from the previous client doesn't cause a spurious wakeup.
+    pub fn set_eventhandle(&self, handle: &Handle) -> WasapiRes<()> {
+        if self.init_info.is_none() {
+            return Err(WasapiError::new(
+                "Client must be initialized for event driven buffering before setting the event handle",
+            )
+            .into());
+        }
+        unsafe { ResetEvent(handle.handle)? };
+        unsafe { self.client.SetEventHandle(handle.handle)? };
+        Ok(())
+    }
+
     /// Get buffer size in frames
     pub fn get_bufferframecount(&self) -> WasapiRes<u32> {
         let buffer_frame_count = unsafe { self.client.GetBufferSize()? };
@@ -827,32 +3731,36 @@ impl AudioClient {
         Ok(buffer_frame_count)
     }
 
+    /// Get the maximum latency of the stream in 100-nanosecond units, see
+    /// [IAudioClient::GetStreamLatency](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nf-audioclient-iaudioclient-getstreamlatency).
+    /// This is the latency added by the audio engine and the device, not including the buffer.
+    /// The client must be initialized.
+    pub fn get_stream_latency(&self) -> WasapiRes<i64> {
+        let latency = unsafe { self.client.GetStreamLatency()? };
+        trace!("stream latency {}", latency);
+        Ok(latency)
+    }
+
     /// Get current padding in frames.
     /// This represents the number of frames currently in the buffer, for both capture and render devices.
     pub fn get_current_padding(&self) -> WasapiRes<u32> {
+        check_simulated_error()?;
         let padding_count = unsafe { self.client.GetCurrentPadding()? };
-        trace!("padding_count {}", padding_count);
+        rt_trace!("padding_count {}", padding_count);
         Ok(padding_count)
     }
 
+    /// Get the current padding and buffer size, and the resulting fill ratio, see [BufferStats].
+    pub fn get_buffer_stats(&self) -> WasapiRes<BufferStats> {
+        let padding = unsafe { self.client.GetCurrentPadding()? };
+        let buffer_frames = unsafe { self.client.GetBufferSize()? };
+        Ok(BufferStats::new(padding, buffer_frames))
+    }
+
     /// Get buffer size minus padding in frames.
     /// Use this to find out how much free space is available in the buffer.
     pub fn get_available_space_in_frames(&self) -> WasapiRes<u32> {
-        let frames = match self.sharemode {
-            Some(ShareMode::Exclusive) => {
-                let buffer_frame_count = unsafe { self.client.GetBufferSize()? };
-                trace!("buffer_frame_count {}", buffer_frame_count);
-                buffer_frame_count
-            }
-            Some(ShareMode::Shared) => {
-                let padding_count = unsafe { self.client.GetCurrentPadding()? };
-                let buffer_frame_count = unsafe { self.client.GetBufferSize()? };
-
-                buffer_frame_count - padding_count
-            }
-            _ => return Err(WasapiError::new("Client has not been initialized").into()),
-        };
-        Ok(frames)
+        available_space_in_frames(&self.client, self.sharemode)
     }
 
     /// Start the stream on an [IAudioClient]
@@ -861,6 +3769,83 @@ impl AudioClient {
         Ok(())
     }
 
+    /// Start a render stream so that the first sample written after this call
+    /// is played as close as possible to the given time.
+    /// The time is a value of the performance counter, see [query_performance_counter].
+    ///
+    /// This writes silence to the device buffer, sized to fill the time until the target,
+    /// and then starts the stream. If the target is further ahead than the buffer can hold,
+    /// it first sleeps until the remaining time fits.
+    /// Call this after initializing the client, before writing any other data.
+    ///
+    /// Only shared mode streams are supported.
+    /// In exclusive mode the device only accepts writes of exactly one buffer,
+    /// so the silence can't be sized to the target time, and an error is returned.
+    ///
+    /// Returns the estimated error in 100-nanosecond units, positive if the first sample is late.
+    /// The estimate does not include the latency of the driver and hardware.
+    pub fn start_at(&self, qpc_time: i64) -> WasapiRes<i64> {
+        if self.direction != Direction::Render {
+            return Err(
+                WasapiError::new("Scheduled start is only possible for render streams").into(),
+            );
+        }
+        if self.sharemode == Some(ShareMode::Exclusive) {
+            return Err(WasapiError::new(
+                "Scheduled start is only possible for shared mode streams",
+            )
+            .into());
+        }
+        let samplerate = match self.samplerate {
+            Some(rate) => rate as i64,
+            None => return Err(WasapiError::new("Client has not been initialized").into()),
+        };
+        let qpc_freq = query_performance_frequency()?;
+        let buffer_frames = self.get_bufferframecount()? as i64;
+        let padding_frames = self.get_current_padding()? as i64;
+        let free_frames = buffer_frames - padding_frames;
+        let mut now = query_performance_counter()?;
+        if qpc_time <= now {
+            return Err(WasapiError::new("The start time has already passed").into());
+        }
+        let mut frames_to_target = (qpc_time - now) * samplerate / qpc_freq - padding_frames;
+        if frames_to_target > free_frames {
+            // Sleep until the remaining silence fits in the buffer, with a margin of half the free space.
+            let sleep_frames = frames_to_target - free_frames / 2;
+            let sleep_time =
+                Duration::from_nanos((sleep_frames * 1_000_000_000 / samplerate) as u64);
+            debug!("sleeping {:?} before scheduled start", sleep_time);
+            thread::sleep(sleep_time);
+            now = query_performance_counter()?;
+            if qpc_time <= now {
+                return Err(WasapiError::new("The start time passed while waiting").into());
+            }
+            frames_to_target = (qpc_time - now) * samplerate / qpc_freq - padding_frames;
+        }
+        let silent_frames = frames_to_target.clamp(0, free_frames);
+        if silent_frames > 0 {
+            let render_client = self.get_audiorenderclient()?;
+            let mut buffer = render_client.get_buffer(silent_frames as usize)?;
+            buffer.set_flags(BufferFlags {
+                data_discontinuity: false,
+                silent: true,
+                timestamp_error: false,
+            });
+            buffer.release()?;
+        }
+        unsafe { self.client.Start()? };
+        let start = query_performance_counter()?;
+        let queued_frames = padding_frames + silent_frames;
+        // Time in 100ns units from the start until the first sample after the silence is played.
+        let queued_time = queued_frames * 10_000_000 / samplerate;
+        let error = (start - qpc_time) * 10_000_000 / qpc_freq + queued_time;
+        debug!(
+            "scheduled start with {} frames of silence, estimated error {} x 100ns",
+            silent_frames, error
+        );
+        Ok(error)
+    }
+
     /// Stop the stream on an [IAudioClient]
     pub fn stop_stream(&self) -> WasapiRes<()> {
         unsafe { self.client.Stop()? };
@@ -878,6 +3863,8 @@ impl AudioClient {
         let client = unsafe { self.client.GetService::<IAudioRenderClient>()? };
         Ok(AudioRenderClient {
             client,
+            audio_client: self.client.clone(),
+            sharemode: self.sharemode,
             bytes_per_frame: self.bytes_per_frame.unwrap_or_default(),
         })
     }
@@ -889,79 +3876,418 @@ impl AudioClient {
             client,
             sharemode: self.sharemode,
             bytes_per_frame: self.bytes_per_frame.unwrap_or_default(),
+            metrics: Cell::new(StreamMetrics::default()),
+            glitch_callback: None,
+            pending_glitch: Cell::new(None),
+            last_glitch_warning: Cell::new(None),
+            glitches_since_warning: Cell::new(0),
+        })
+    }
+
+    /// Get the [AudioSessionControl]
+    pub fn get_audiosessioncontrol(&self) -> WasapiRes<AudioSessionControl> {
+        let control = unsafe { self.client.GetService::<IAudioSessionControl>()? };
+        Ok(AudioSessionControl {
+            control,
+            direction: self.direction,
+            device_id: self.device_id.clone(),
+        })
+    }
+
+    /// Get the [AudioClock]
+    pub fn get_audioclock(&self) -> WasapiRes<AudioClock> {
+        let clock = unsafe { self.client.GetService::<IAudioClock>()? };
+        Ok(AudioClock {
+            clock,
+            frequency: Cell::new(None),
+        })
+    }
+
+    /// Get the [SimpleAudioVolume] of the session this client belongs to.
+    /// This can be used to control the volume of the own application,
+    /// without going through the session manager.
+    pub fn get_simple_volume(&self) -> WasapiRes<SimpleAudioVolume> {
+        let volume = unsafe { self.client.GetService::<ISimpleAudioVolume>()? };
+        Ok(SimpleAudioVolume { volume })
+    }
+
+    /// Get the [AudioMeterInformation] of the session this client belongs to.
+    /// This can be used to show the output level of the own application,
+    /// without enumerating the sessions.
+    ///
+    /// The meter is not one of the services of the audio client,
+    /// so it is queried from the session control of the client.
+    /// Use a [LevelMeter](crate::LevelMeter) to smooth the values for display.
+    pub fn get_meter(&self) -> WasapiRes<AudioMeterInformation> {
+        let control = unsafe { self.client.GetService::<IAudioSessionControl>()? };
+        let meter = control.cast::<IAudioMeterInformation>()?;
+        Ok(AudioMeterInformation { meter })
+    }
+
+    /// Get the direction for this [AudioClient]
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Get the sharemode for this [AudioClient].
+    /// The sharemode is decided when the client is initialized.
+    pub fn get_sharemode(&self) -> Option<ShareMode> {
+        self.sharemode
+    }
+}
+
+/// Struct wrapping an [IAudioSessionControl](https://docs.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessioncontrol).
+pub struct AudioSessionControl {
+    control: IAudioSessionControl,
+    direction: Direction,
+    device_id: Option<String>,
+}
+
+impl AudioSessionControl {
+    /// Create an [AudioSessionControl] from a raw [IAudioSessionControl] pointer,
+    /// for interoperability with other crates.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid `IAudioSessionControl` pointer,
+    /// for a session on an endpoint with the given direction.
+    /// The [AudioSessionControl] takes ownership of the reference held by the pointer.
+    pub unsafe fn from_raw(raw: *mut c_void, direction: &Direction) -> Self {
+        AudioSessionControl {
+            control: IAudioSessionControl::from_raw(raw),
+            direction: *direction,
+            device_id: None,
+        }
+    }
+
+    /// Consume the [AudioSessionControl] and return the raw [IAudioSessionControl] pointer.
+    /// The caller becomes responsible for releasing the reference.
+    pub fn into_raw(self) -> *mut c_void {
+        self.control.into_raw()
+    }
+
+    /// Get the current state
+    pub fn get_state(&self) -> WasapiRes<SessionState> {
+        let state = unsafe { self.control.GetState()? };
+        #[allow(non_upper_case_globals)]
+        let sessionstate = match state {
+            _ if state == AudioSessionStateActive => SessionState::Active,
+            _ if state == AudioSessionStateInactive => SessionState::Inactive,
+            _ if state == AudioSessionStateExpired => SessionState::Expired,
+            x => {
+                return Err(
+                    WasapiError::new(&format!("Got an illegal session state {:?}", x)).into(),
+                );
+            }
+        };
+        Ok(sessionstate)
+    }
+
+    /// Get the grouping parameter of the session.
+    /// Sessions with the same grouping parameter are shown as a single entry in the volume mixer.
+    pub fn get_grouping_param(&self) -> WasapiRes<GUID> {
+        let grouping = unsafe { self.control.GetGroupingParam()? };
+        trace!("grouping param: {:?}", grouping);
+        Ok(grouping)
+    }
+
+    /// Set the grouping parameter of the session.
+    /// This is only allowed for sessions belonging to the calling process.
+    /// The optional `event_context` is passed on to the OnGroupingParamChanged notifications.
+    pub fn set_grouping_param(
+        &self,
+        grouping: &GUID,
+        event_context: Option<&GUID>,
+    ) -> WasapiRes<()> {
+        let context = event_context.map_or(ptr::null(), |ctx| ctx as *const GUID);
+        unsafe { self.control.SetGroupingParam(grouping, context)? };
+        Ok(())
+    }
+
+    /// Get the session identifier, a string that identifies the session
+    /// and is the same for all sessions of an application on the same device.
+    pub fn get_session_identifier(&self) -> WasapiRes<String> {
+        let control2: IAudioSessionControl2 = self.control.cast()?;
+        let idstr = unsafe { control2.GetSessionIdentifier()? };
+        let id = take_pwstr(idstr);
+        trace!("session identifier: {}", id);
+        Ok(id)
+    }
+
+    /// Query the session for the additional interfaces
+    /// [IAudioSessionControl2](https://learn.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessioncontrol2),
+    /// [ISimpleAudioVolume](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-isimpleaudiovolume) and
+    /// [IAudioMeterInformation](https://learn.microsoft.com/en-us/windows/win32/api/endpointvolume/nn-endpointvolume-iaudiometerinformation).
+    /// Each one is `None` if the session doesn't support it.
+    /// Sessions obtained from a session enumerator usually support all of them.
+    pub fn upgrade(&self) -> AudioSessionInterfaces {
+        let control2 = self
+            .control
+            .cast::<IAudioSessionControl2>()
+            .ok()
+            .map(|control| AudioSessionControl2 { control });
+        let volume = self
+            .control
+            .cast::<ISimpleAudioVolume>()
+            .ok()
+            .map(|volume| SimpleAudioVolume { volume });
+        let meter = self
+            .control
+            .cast::<IAudioMeterInformation>()
+            .ok()
+            .map(|meter| AudioMeterInformation { meter });
+        trace!(
+            "session supports control2: {}, volume: {}, meter: {}",
+            control2.is_some(),
+            volume.is_some(),
+            meter.is_some()
+        );
+        AudioSessionInterfaces {
+            control2,
+            volume,
+            meter,
+        }
+    }
+
+    /// Get the Id of the device the session belongs to, if known.
+    pub fn get_device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
+
+    /// Get the direction of the device the session belongs to.
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Register to receive notifications.
+    /// The state and disconnect notifications include the session identifier,
+    /// the device id and the direction, to tell apart events from different sessions.
+    pub fn register_session_notification(&self, callbacks: Weak<EventCallbacks>) -> WasapiRes<()> {
+        let session_id = self.get_session_identifier().ok();
+        let events: IAudioSessionEvents = AudioSessionEvents::new(
+            SessionEventSink::Callbacks(callbacks),
+            session_id.clone(),
+            self.device_id.clone(),
+            self.direction,
+        )
+        .into();
+
+        match unsafe { self.control.RegisterAudioSessionNotification(&events) } {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let object = format!(
+                    "audio session {}",
+                    session_id.as_deref().unwrap_or("(unknown)")
+                );
+                Err(RegistrationError::new(NotificationType::SessionEvents, &object, err).into())
+            }
+        }
+    }
+
+    /// Register to receive the notifications of the session as [SessionEvent] values over a channel,
+    /// instead of as calls to [EventCallbacks].
+    /// Any channel implementing [ChannelSender] can be used, and the receiver can be read from any thread.
+    ///
+    /// The notifications arrive on threads owned by the system, and are sent without blocking.
+    /// If a bounded channel is full, the notification is dropped.
+    /// Notifications stop when the returned [SessionEventChannel] is dropped.
+    pub fn register_session_notification_channel<S>(
+        &self,
+        sender: S,
+    ) -> WasapiRes<SessionEventChannel>
+    where
+        S: ChannelSender<SessionEvent> + Send + 'static,
+    {
+        let sender = Mutex::new(sender);
+        let registration = self.register_session_event_callback(Box::new(move |event| {
+            let sender = match sender.lock() {
+                Ok(sender) => sender,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match sender.try_send(event) {
+                Ok(()) => {}
+                Err(ChannelSendError::Full) => debug!("Channel is full, dropped session event"),
+                Err(ChannelSendError::Disconnected) => {
+                    trace!("Receiver was dropped, ignoring session event")
+                }
+            }
+        }))?;
+        Ok(SessionEventChannel {
+            _registration: registration,
         })
     }
 
-    /// Get the [AudioSessionControl]
-    pub fn get_audiosessioncontrol(&self) -> WasapiRes<AudioSessionControl> {
-        let control = unsafe { self.client.GetService::<IAudioSessionControl>()? };
-        Ok(AudioSessionControl { control })
+    /// Register a callback that gets all notifications of the session as [SessionEvent] values.
+    pub(crate) fn register_session_event_callback(
+        &self,
+        callback: Box<dyn Fn(SessionEvent) + Send + Sync>,
+    ) -> WasapiRes<SessionEventRegistration> {
+        let session_id = self.get_session_identifier().ok();
+        let events: IAudioSessionEvents = AudioSessionEvents::new(
+            SessionEventSink::Forward(callback),
+            session_id.clone(),
+            self.device_id.clone(),
+            self.direction,
+        )
+        .into();
+        AgileRegistration::register(
+            &self.control,
+            events,
+            |control, events| {
+                unsafe { control.RegisterAudioSessionNotification(events) }.map_err(|err| {
+                    let object = format!(
+                        "audio session {}",
+                        session_id.as_deref().unwrap_or("(unknown)")
+                    );
+                    RegistrationError::new(NotificationType::SessionEvents, &object, err).into()
+                })
+            },
+            |control, events| unsafe { control.UnregisterAudioSessionNotification(events) },
+            "session notifications",
+        )
+    }
+}
+
+/// Registration that sends the notifications of a session to a channel,
+/// see [AudioSessionControl::register_session_notification_channel].
+/// Notifications stop when this is dropped.
+pub struct SessionEventChannel {
+    _registration: SessionEventRegistration,
+}
+
+/// Registration of a [SessionEvent] callback on an [AudioSessionControl].
+/// Notifications stop when this is dropped.
+pub(crate) type SessionEventRegistration =
+    AgileRegistration<IAudioSessionControl, IAudioSessionEvents>;
+
+/// The additional interfaces of a session, see [AudioSessionControl::upgrade].
+pub struct AudioSessionInterfaces {
+    /// Extended session control, `None` if not supported.
+    pub control2: Option<AudioSessionControl2>,
+    /// Session volume control, `None` if not supported.
+    pub volume: Option<SimpleAudioVolume>,
+    /// Session peak meter, `None` if not supported.
+    pub meter: Option<AudioMeterInformation>,
+}
+
+/// Struct wrapping an [IAudioSessionControl2](https://learn.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessioncontrol2).
+pub struct AudioSessionControl2 {
+    control: IAudioSessionControl2,
+}
+
+impl AudioSessionControl2 {
+    /// Get the session identifier, a string that identifies the session
+    /// and is the same for all sessions of an application on the same device.
+    pub fn get_session_identifier(&self) -> WasapiRes<String> {
+        let idstr = unsafe { self.control.GetSessionIdentifier()? };
+        Ok(take_pwstr(idstr))
+    }
+
+    /// Get the session instance identifier, a string that is unique for each session.
+    pub fn get_session_instance_identifier(&self) -> WasapiRes<String> {
+        let idstr = unsafe { self.control.GetSessionInstanceIdentifier()? };
+        Ok(take_pwstr(idstr))
+    }
+
+    /// Get the id of the process that owns the session.
+    /// For a session with streams from several processes, this returns an error.
+    pub fn get_process_id(&self) -> WasapiRes<u32> {
+        let pid = unsafe { self.control.GetProcessId()? };
+        Ok(pid)
+    }
+
+    /// Check if this is the system sounds session.
+    pub fn is_system_sounds_session(&self) -> bool {
+        let hr = unsafe { self.control.IsSystemSoundsSession() };
+        hr == S_OK
+    }
+
+    /// Set the ducking preference of the session.
+    /// If `opt_out` is true, the session is not ducked when a communications stream is opened.
+    pub fn set_ducking_preference(&self, opt_out: bool) -> WasapiRes<()> {
+        unsafe { self.control.SetDuckingPreference(opt_out)? };
+        Ok(())
+    }
+}
+
+/// Struct wrapping an [ISimpleAudioVolume](https://learn.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-isimpleaudiovolume).
+pub struct SimpleAudioVolume {
+    volume: ISimpleAudioVolume,
+}
+
+impl SimpleAudioVolume {
+    /// Get the master volume of the session, as a value between 0.0 and 1.0.
+    pub fn get_master_volume(&self) -> WasapiRes<f32> {
+        let volume = unsafe { self.volume.GetMasterVolume()? };
+        Ok(volume)
     }
 
-    /// Get the [AudioClock]
-    pub fn get_audioclock(&self) -> WasapiRes<AudioClock> {
-        let clock = unsafe { self.client.GetService::<IAudioClock>()? };
-        Ok(AudioClock { clock })
+    /// Set the master volume of the session, as a value between 0.0 and 1.0.
+    /// The optional `event_context` is passed on to the OnSimpleVolumeChanged notifications.
+    pub fn set_master_volume(&self, volume: f32, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map_or(ptr::null(), |ctx| ctx as *const GUID);
+        unsafe { self.volume.SetMasterVolume(volume, context)? };
+        Ok(())
     }
 
-    /// Get the direction for this [AudioClient]
-    pub fn get_direction(&self) -> Direction {
-        self.direction
+    /// Get the mute state of the session.
+    pub fn get_mute(&self) -> WasapiRes<bool> {
+        let mute = unsafe { self.volume.GetMute()? };
+        Ok(mute.as_bool())
     }
 
-    /// Get the sharemode for this [AudioClient].
-    /// The sharemode is decided when the client is initialized.
-    pub fn get_sharemode(&self) -> Option<ShareMode> {
-        self.sharemode
+    /// Set the mute state of the session.
+    /// The optional `event_context` is passed on to the OnSimpleVolumeChanged notifications.
+    pub fn set_mute(&self, mute: bool, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map_or(ptr::null(), |ctx| ctx as *const GUID);
+        unsafe { self.volume.SetMute(mute, context)? };
+        Ok(())
     }
 }
 
-/// Struct wrapping an [IAudioSessionControl](https://docs.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessioncontrol).
-pub struct AudioSessionControl {
-    control: IAudioSessionControl,
+/// Struct wrapping an [IAudioMeterInformation](https://learn.microsoft.com/en-us/windows/win32/api/endpointvolume/nn-endpointvolume-iaudiometerinformation).
+pub struct AudioMeterInformation {
+    meter: IAudioMeterInformation,
 }
 
-impl AudioSessionControl {
-    /// Get the current state
-    pub fn get_state(&self) -> WasapiRes<SessionState> {
-        let state = unsafe { self.control.GetState()? };
-        #[allow(non_upper_case_globals)]
-        let sessionstate = match state {
-            _ if state == AudioSessionStateActive => SessionState::Active,
-            _ if state == AudioSessionStateInactive => SessionState::Inactive,
-            _ if state == AudioSessionStateExpired => SessionState::Expired,
-            x => {
-                return Err(
-                    WasapiError::new(&format!("Got an illegal session state {:?}", x)).into(),
-                );
-            }
-        };
-        Ok(sessionstate)
+impl AudioMeterInformation {
+    /// Get the peak sample value of all channels, as a value between 0.0 and 1.0.
+    pub fn get_peak_value(&self) -> WasapiRes<f32> {
+        let peak = unsafe { self.meter.GetPeakValue()? };
+        Ok(peak)
     }
 
-    /// Register to receive notifications
-    pub fn register_session_notification(&self, callbacks: Weak<EventCallbacks>) -> WasapiRes<()> {
-        let events: IAudioSessionEvents = AudioSessionEvents::new(callbacks).into();
+    /// Get the number of channels that are metered.
+    pub fn get_meter_channel_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.meter.GetMeteringChannelCount()? };
+        Ok(count)
+    }
 
-        match unsafe { self.control.RegisterAudioSessionNotification(&events) } {
-            Ok(()) => Ok(()),
-            Err(err) => {
-                Err(WasapiError::new(&format!("Failed to register notifications, {}", err)).into())
-            }
-        }
+    /// Get the peak sample values of each channel, as values between 0.0 and 1.0.
+    pub fn get_channels_peak_values(&self) -> WasapiRes<Vec<f32>> {
+        let count = self.get_meter_channel_count()?;
+        let mut peaks = vec![0.0; count as usize];
+        unsafe { self.meter.GetChannelsPeakValues(&mut peaks)? };
+        Ok(peaks)
     }
 }
 
 /// Struct wrapping an [IAudioClock](https://docs.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudioclock).
 pub struct AudioClock {
     clock: IAudioClock,
+    frequency: Cell<Option<u64>>,
 }
 
 impl AudioClock {
     /// Get the frequency for this [AudioClock].
     /// Note that the unit for the value is undefined.
+    /// The frequency is constant, and is only read from the device on the first call.
     pub fn get_frequency(&self) -> WasapiRes<u64> {
+        if let Some(freq) = self.frequency.get() {
+            return Ok(freq);
+        }
         let freq = unsafe { self.clock.GetFrequency()? };
+        self.frequency.set(Some(freq));
         Ok(freq)
     }
 
@@ -975,25 +4301,66 @@ impl AudioClock {
         unsafe { self.clock.GetPosition(&mut pos, Some(&mut timer))? };
         Ok((pos, timer))
     }
+
+    /// Get the current device position in seconds, as well as the value of the
+    /// performance counter at the time the position values was taken.
+    /// This uses the cached frequency, see [AudioClock::get_frequency],
+    /// so that only the position needs to be read from the device.
+    pub fn get_position_seconds(&self) -> WasapiRes<(f64, u64)> {
+        let freq = self.get_frequency()?;
+        let (pos, timer) = self.get_position()?;
+        Ok((pos as f64 / freq as f64, timer))
+    }
 }
 
 /// Struct wrapping an [IAudioRenderClient](https://docs.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudiorenderclient).
 pub struct AudioRenderClient {
     client: IAudioRenderClient,
+    audio_client: IAudioClient,
+    sharemode: Option<ShareMode>,
     bytes_per_frame: usize,
 }
 
 impl AudioRenderClient {
+    /// Create an [AudioRenderClient] from a raw [IAudioRenderClient] pointer,
+    /// for interoperability with other crates.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid `IAudioRenderClient` pointer,
+    /// obtained from the same `IAudioClient` as the one wrapped by `audio_client`.
+    /// The [AudioRenderClient] takes ownership of the reference held by the pointer.
+    pub unsafe fn from_raw(raw: *mut c_void, audio_client: &AudioClient) -> WasapiRes<Self> {
+        let bytes_per_frame = audio_client
+            .bytes_per_frame
+            .ok_or_else(|| WasapiError::new("Client has not been initialized"))?;
+        Ok(AudioRenderClient {
+            client: IAudioRenderClient::from_raw(raw),
+            audio_client: audio_client.client.clone(),
+            sharemode: audio_client.sharemode,
+            bytes_per_frame,
+        })
+    }
+
+    /// Consume the [AudioRenderClient] and return the raw [IAudioRenderClient] pointer.
+    /// The caller becomes responsible for releasing the reference.
+    pub fn into_raw(self) -> *mut c_void {
+        self.client.into_raw()
+    }
+
     /// Write raw bytes data to a device from a slice.
     /// The number of frames to write should first be checked with the
     /// `get_available_space_in_frames()` method on the [AudioClient].
     /// The buffer_flags argument can be used to mark a buffer as silent.
+    ///
+    /// This method does not allocate, except when returning an error.
     pub fn write_to_device(
         &self,
         nbr_frames: usize,
         data: &[u8],
         buffer_flags: Option<BufferFlags>,
     ) -> WasapiRes<()> {
+        let guard = NoAllocGuard::new();
         if nbr_frames == 0 {
             return Ok(());
         }
@@ -1009,15 +4376,14 @@ impl AudioRenderClient {
             )
             .into());
         }
-        let bufferptr = unsafe { self.client.GetBuffer(nbr_frames as u32)? };
-        let bufferslice = unsafe { slice::from_raw_parts_mut(bufferptr, nbr_bytes) };
-        bufferslice.copy_from_slice(data);
-        let flags = match buffer_flags {
-            Some(bflags) => bflags.to_u32(),
-            None => 0,
-        };
-        unsafe { self.client.ReleaseBuffer(nbr_frames as u32, flags)? };
-        trace!("wrote {} frames", nbr_frames);
+        let mut buffer = self.get_buffer(nbr_frames)?;
+        buffer.as_mut_slice().copy_from_slice(data);
+        if let Some(bflags) = buffer_flags {
+            buffer.set_flags(bflags);
+        }
+        buffer.release()?;
+        guard.assert_no_alloc("write_to_device");
+        rt_trace!("wrote {} frames", nbr_frames);
         Ok(())
     }
 
@@ -1025,12 +4391,15 @@ impl AudioRenderClient {
     /// The number of frames to write should first be checked with the
     /// `get_available_space_in_frames()` method on the [AudioClient].
     /// The buffer_flags argument can be used to mark a buffer as silent.
+    ///
+    /// This method does not allocate, except when returning an error.
     pub fn write_to_device_from_deque(
         &self,
         nbr_frames: usize,
         data: &mut VecDeque<u8>,
         buffer_flags: Option<BufferFlags>,
     ) -> WasapiRes<()> {
+        let guard = NoAllocGuard::new();
         if nbr_frames == 0 {
             return Ok(());
         }
@@ -1041,23 +4410,714 @@ impl AudioRenderClient {
             )
             .into());
         }
-        let bufferptr = unsafe { self.client.GetBuffer(nbr_frames as u32)? };
-        let bufferslice = unsafe { slice::from_raw_parts_mut(bufferptr, nbr_bytes) };
-        for element in bufferslice.iter_mut() {
-            *element = data.pop_front().unwrap();
+        let mut buffer = self.get_buffer(nbr_frames)?;
+        for (element, value) in buffer
+            .as_mut_slice()
+            .iter_mut()
+            .zip(data.drain(..nbr_bytes))
+        {
+            *element = value;
+        }
+        if let Some(bflags) = buffer_flags {
+            buffer.set_flags(bflags);
         }
-        let flags = match buffer_flags {
-            Some(bflags) => bflags.to_u32(),
-            None => 0,
+        buffer.release()?;
+        guard.assert_no_alloc("write_to_device_from_deque");
+        rt_trace!("wrote {} frames", nbr_frames);
+        Ok(())
+    }
+
+    /// Write as many whole frames from a slice as currently fit in the device buffer.
+    /// Returns the number of frames that were written.
+    /// The slice can have any length, and the caller is expected to pass
+    /// the frames that were not written in the next call.
+    /// In exclusive mode the whole device buffer must be written at once,
+    /// and nothing is written if the slice holds less than a full buffer.
+    /// The buffer_flags argument can be used to mark a buffer as silent.
+    ///
+    /// This method does not allocate, except when returning an error.
+    pub fn write_available(
+        &self,
+        data: &[u8],
+        buffer_flags: Option<BufferFlags>,
+    ) -> WasapiRes<usize> {
+        let guard = NoAllocGuard::new();
+        let available_frames =
+            available_space_in_frames(&self.audio_client, self.sharemode)? as usize;
+        let data_frames = data.len() / self.bytes_per_frame;
+        if self.sharemode == Some(ShareMode::Exclusive) && data_frames < available_frames {
+            return Ok(0);
+        }
+        let nbr_frames = cmp::min(available_frames, data_frames);
+        if nbr_frames == 0 {
+            return Ok(0);
+        }
+        let nbr_bytes = nbr_frames * self.bytes_per_frame;
+        let mut buffer = self.get_buffer(nbr_frames)?;
+        buffer.as_mut_slice().copy_from_slice(&data[..nbr_bytes]);
+        if let Some(bflags) = buffer_flags {
+            buffer.set_flags(bflags);
+        }
+        buffer.release()?;
+        guard.assert_no_alloc("write_available");
+        rt_trace!("wrote {} of {} frames", nbr_frames, data_frames);
+        Ok(nbr_frames)
+    }
+
+    /// Wait for the buffer event, and then write as many whole frames from a slice as fit in the device buffer,
+    /// like [AudioRenderClient::write_available].
+    /// The handle must be the event handle of the stream, see [AudioClient::set_get_eventhandle].
+    ///
+    /// The wait gives up at the deadline, and then nothing is written and
+    /// [DeadlineResult::DeadlineExceeded] is returned.
+    /// The deadline only limits the wait, the write itself takes a short and bounded time.
+    /// Returns the number of frames that were written.
+    ///
+    /// This method does not allocate, except when returning an error.
+    pub fn write_to_device_deadline(
+        &self,
+        handle: &Handle,
+        data: &[u8],
+        buffer_flags: Option<BufferFlags>,
+        deadline: Instant,
+    ) -> WasapiRes<DeadlineResult<usize>> {
+        if !handle.wait_until(deadline)? {
+            rt_trace!("deadline exceeded while waiting for render event");
+            return Ok(DeadlineResult::DeadlineExceeded);
+        }
+        let nbr_frames = self.write_available(data, buffer_flags)?;
+        Ok(DeadlineResult::Completed(nbr_frames))
+    }
+
+    /// Get a buffer of `nbr_frames` frames from the device, to be filled with data.
+    /// The number of frames should first be checked with the
+    /// `get_available_space_in_frames()` method on the [AudioClient].
+    ///
+    /// The returned [RenderBuffer] gives direct access to the device buffer,
+    /// which avoids copying the data via an intermediate slice.
+    /// The buffer is handed back to the device when the [RenderBuffer] is released or dropped.
+    ///
+    /// This method does not allocate, except when returning an error.
+    pub fn get_buffer(&self, nbr_frames: usize) -> WasapiRes<RenderBuffer<'_>> {
+        check_simulated_error()?;
+        let data: &mut [u8] = if nbr_frames > 0 {
+            let nbr_bytes = nbr_frames * self.bytes_per_frame;
+            let bufferptr = unsafe { self.client.GetBuffer(nbr_frames as u32)? };
+            unsafe { slice::from_raw_parts_mut(bufferptr, nbr_bytes) }
+        } else {
+            &mut []
         };
-        unsafe { self.client.ReleaseBuffer(nbr_frames as u32, flags)? };
-        trace!("wrote {} frames", nbr_frames);
+        Ok(RenderBuffer {
+            client: &self.client,
+            data,
+            nbr_frames: nbr_frames as u32,
+            flags: 0,
+            released: nbr_frames == 0,
+        })
+    }
+}
+
+/// A device buffer borrowed from an [AudioRenderClient] with [AudioRenderClient::get_buffer].
+///
+/// The buffer is handed back to the device with [RenderBuffer::release].
+/// If it is instead dropped, it is released automatically.
+/// When this happens during a panic, the buffer is marked as silent,
+/// since it may not have been completely filled.
+pub struct RenderBuffer<'a> {
+    client: &'a IAudioRenderClient,
+    data: &'a mut [u8],
+    nbr_frames: u32,
+    flags: u32,
+    released: bool,
+}
+
+impl RenderBuffer<'_> {
+    /// Get the number of frames in the buffer.
+    pub fn get_nbr_frames(&self) -> usize {
+        self.nbr_frames as usize
+    }
+
+    /// Get the buffer as a mutable slice of bytes, to be filled with data.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    /// Set the [BufferFlags] used when releasing the buffer, for example to mark it as silent.
+    pub fn set_flags(&mut self, flags: BufferFlags) {
+        self.flags = flags.to_u32();
+    }
+
+    /// Release the buffer, handing the data over to the device.
+    ///
+    /// This method does not allocate, except when returning an error.
+    pub fn release(mut self) -> WasapiRes<()> {
+        self.release_with_flags(self.flags)
+    }
+
+    fn release_with_flags(&mut self, flags: u32) -> WasapiRes<()> {
+        if self.released {
+            return Ok(());
+        }
+        self.released = true;
+        unsafe { self.client.ReleaseBuffer(self.nbr_frames, flags)? };
         Ok(())
     }
 }
 
+impl Drop for RenderBuffer<'_> {
+    fn drop(&mut self) {
+        let flags = if thread::panicking() {
+            AUDCLNT_BUFFERFLAGS_SILENT.0 as u32
+        } else {
+            self.flags
+        };
+        if let Err(err) = self.release_with_flags(flags) {
+            warn!("Failed to release render buffer: {}", err);
+        }
+    }
+}
+
+/// Policy for a [RenderWriter] when there is less data queued than there is space in the device buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnderrunPolicy {
+    /// Write the queued data, and fill the rest of the available space with silence.
+    FillSilence,
+    /// Write only the queued data, and leave the rest of the available space empty.
+    /// A partial buffer can't be written in exclusive mode,
+    /// and there this behaves like [UnderrunPolicy::FillSilence].
+    WritePartial,
+    /// Write nothing, and keep the data queued until there is enough to fill the available space.
+    Wait,
+    /// Write the queued data, and fill the rest of the available space
+    /// by repeating the data of the last complete write.
+    /// Silence is used if nothing has been written yet.
+    RepeatLast,
+    /// Write nothing, and return an error.
+    Fail,
+}
+
+impl fmt::Display for UnderrunPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            UnderrunPolicy::FillSilence => write!(f, "FillSilence"),
+            UnderrunPolicy::WritePartial => write!(f, "WritePartial"),
+            UnderrunPolicy::Wait => write!(f, "Wait"),
+            UnderrunPolicy::RepeatLast => write!(f, "RepeatLast"),
+            UnderrunPolicy::Fail => write!(f, "Fail"),
+        }
+    }
+}
+
+/// Adapter around an [AudioRenderClient] that accepts chunks of data of any size,
+/// and writes them to the device as space becomes available.
+///
+/// Data added with [RenderWriter::push] is queued, and [RenderWriter::write]
+/// fills the available space in the device buffer from the queue.
+/// This removes the need to match the size of the chunks from the producer
+/// with the size of the device periods.
+/// Chunks don't need to contain whole frames, any remainder stays queued until the rest arrives.
+///
+/// Underruns are counted, see [RenderWriter::get_underrun_count],
+/// and can be reported with a callback, see [RenderWriter::set_underrun_callback].
+///
+/// The queue grows as needed, but can be limited with [RenderWriter::set_max_queued_frames].
+/// Memory that is no longer needed after a period of high usage is released periodically.
+pub struct RenderWriter {
+    render_client: AudioRenderClient,
+    queue: VecDeque<u8>,
+    underrun_policy: UnderrunPolicy,
+    underrun_count: u64,
+    underrun_frames: u64,
+    last_buffer: Vec<u8>,
+    underrun_callback: Option<Box<dyn Fn(usize)>>,
+    max_queued_frames: Option<usize>,
+    dropped_frames: u64,
+    high_water_frames: usize,
+    recent_high_water_bytes: usize,
+    writes_since_shrink: usize,
+}
+
+/// The number of calls to [RenderWriter::write] between each attempt to shrink the queue.
+const RENDER_QUEUE_SHRINK_INTERVAL: usize = 1000;
+
+impl RenderWriter {
+    /// Create a new [RenderWriter] for an initialized [AudioRenderClient].
+    pub fn new(render_client: AudioRenderClient, underrun_policy: &UnderrunPolicy) -> Self {
+        RenderWriter {
+            render_client,
+            queue: VecDeque::new(),
+            underrun_policy: *underrun_policy,
+            underrun_count: 0,
+            underrun_frames: 0,
+            last_buffer: Vec::new(),
+            underrun_callback: None,
+            max_queued_frames: None,
+            dropped_frames: 0,
+            high_water_frames: 0,
+            recent_high_water_bytes: 0,
+            writes_since_shrink: 0,
+        }
+    }
+
+    /// Add data to the end of the queue.
+    /// If this makes the queue longer than the limit set by [RenderWriter::set_max_queued_frames],
+    /// the oldest frames are dropped.
+    pub fn push(&mut self, data: &[u8]) {
+        self.queue.extend(data.iter());
+        if let Some(max_frames) = self.max_queued_frames {
+            let queued_frames = self.get_queued_frames();
+            if queued_frames > max_frames {
+                let nbr_dropped = queued_frames - max_frames;
+                self.queue
+                    .drain(..nbr_dropped * self.render_client.bytes_per_frame);
+                self.dropped_frames += nbr_dropped as u64;
+                debug!(
+                    "render queue is full, dropped {} frames, {} dropped in total",
+                    nbr_dropped, self.dropped_frames
+                );
+            }
+        }
+        self.high_water_frames = cmp::max(self.high_water_frames, self.get_queued_frames());
+        self.recent_high_water_bytes = cmp::max(self.recent_high_water_bytes, self.queue.len());
+    }
+
+    /// Limit the number of frames in the queue, or remove the limit with `None`.
+    /// When the queue is full, the oldest frames are dropped to make room for new data.
+    /// This keeps the latency and memory use bounded when the producer is faster than the device.
+    pub fn set_max_queued_frames(&mut self, max_frames: Option<usize>) {
+        self.max_queued_frames = max_frames;
+    }
+
+    /// Get the number of frames dropped because the queue was full.
+    pub fn get_dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Get the highest number of frames that have been in the queue.
+    pub fn get_high_water_frames(&self) -> usize {
+        self.high_water_frames
+    }
+
+    /// Reset the dropped frames counter and the high water mark.
+    pub fn reset_queue_stats(&mut self) {
+        self.dropped_frames = 0;
+        self.high_water_frames = self.get_queued_frames();
+    }
+
+    /// Get the number of whole frames in the queue.
+    pub fn get_queued_frames(&self) -> usize {
+        self.queue.len() / self.render_client.bytes_per_frame
+    }
+
+    /// Write queued data to fill the available space in the device buffer.
+    /// Call this once for each buffer event, or each cycle when polling.
+    ///
+    /// If there isn't enough data queued to fill the space, the [UnderrunPolicy] decides what is written.
+    /// In exclusive mode, the available space is always the whole buffer.
+    /// Returns the number of frames written, including any silence.
+    pub fn write(&mut self) -> WasapiRes<usize> {
+        self.shrink_queue_if_due();
+        let available_frames = available_space_in_frames(
+            &self.render_client.audio_client,
+            self.render_client.sharemode,
+        )? as usize;
+        let queued_frames = self.get_queued_frames();
+        if queued_frames >= available_frames {
+            if self.underrun_policy == UnderrunPolicy::RepeatLast {
+                let nbr_bytes = available_frames * self.render_client.bytes_per_frame;
+                self.last_buffer.clear();
+                self.last_buffer
+                    .extend(self.queue.iter().take(nbr_bytes).copied());
+            }
+            return self.write_queued(available_frames);
+        }
+        let missing_frames = available_frames - queued_frames;
+        self.underrun_count += 1;
+        self.underrun_frames += missing_frames as u64;
+        if let Some(callback) = &self.underrun_callback {
+            callback(missing_frames);
+        }
+        match self.underrun_policy {
+            UnderrunPolicy::FillSilence => {
+                debug!(
+                    "render underrun, {} frames queued, filling {} frames with silence",
+                    queued_frames, missing_frames
+                );
+                self.write_queued_and_silence(queued_frames, available_frames)
+            }
+            UnderrunPolicy::WritePartial => {
+                if let Some(ShareMode::Exclusive) = self.render_client.sharemode {
+                    // A partial buffer can't be written in exclusive mode
+                    debug!(
+                        "render underrun, {} frames queued, filling {} frames with silence",
+                        queued_frames, missing_frames
+                    );
+                    return self.write_queued_and_silence(queued_frames, available_frames);
+                }
+                debug!(
+                    "render underrun, writing {} of {} frames",
+                    queued_frames, available_frames
+                );
+                self.write_queued(queued_frames)
+            }
+            UnderrunPolicy::Wait => {
+                debug!(
+                    "render underrun, waiting with {} of {} frames queued",
+                    queued_frames, available_frames
+                );
+                Ok(0)
+            }
+            UnderrunPolicy::RepeatLast => {
+                debug!(
+                    "render underrun, {} frames queued, repeating the last buffer for {} frames",
+                    queued_frames, missing_frames
+                );
+                self.write_queued_and_repeat(queued_frames, available_frames)
+            }
+            UnderrunPolicy::Fail => Err(WasapiError::new(&format!(
+                "Render underrun, {} frames queued but {} frames needed",
+                queued_frames, available_frames
+            ))
+            .into()),
+        }
+    }
+
+    /// Get the number of underruns, meaning calls to [RenderWriter::write]
+    /// where there wasn't enough data queued to fill the available space.
+    pub fn get_underrun_count(&self) -> u64 {
+        self.underrun_count
+    }
+
+    /// Get the total number of frames that were missing in the underruns.
+    pub fn get_underrun_frames(&self) -> u64 {
+        self.underrun_frames
+    }
+
+    /// Reset the underrun counters to zero.
+    pub fn reset_underrun_count(&mut self) {
+        self.underrun_count = 0;
+        self.underrun_frames = 0;
+    }
+
+    /// Set a callback that is called with the number of missing frames for each underrun.
+    /// The callback is called from the thread writing the data, before the [UnderrunPolicy] is applied,
+    /// and should return quickly.
+    pub fn set_underrun_callback(&mut self, c: impl Fn(usize) + 'static) {
+        self.underrun_callback = Some(Box::new(c));
+    }
+
+    /// Remove the underrun callback.
+    pub fn unset_underrun_callback(&mut self) {
+        self.underrun_callback = None;
+    }
+
+    /// Write as many of the queued frames as there is space for, regardless of the [UnderrunPolicy].
+    /// Use this to drain the queue at the end of the stream.
+    /// Returns the number of frames still queued.
+    pub fn flush(&mut self) -> WasapiRes<usize> {
+        let available_frames = available_space_in_frames(
+            &self.render_client.audio_client,
+            self.render_client.sharemode,
+        )? as usize;
+        let queued_frames = self.get_queued_frames();
+        if let Some(ShareMode::Exclusive) = self.render_client.sharemode {
+            // A partial buffer can't be written in exclusive mode
+            self.write_queued_and_silence(
+                cmp::min(queued_frames, available_frames),
+                available_frames,
+            )?;
+        } else {
+            self.write_queued(cmp::min(queued_frames, available_frames))?;
+        }
+        Ok(self.get_queued_frames())
+    }
+
+    /// Discard all queued data.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Get a reference to the wrapped [AudioRenderClient].
+    pub fn get_render_client(&self) -> &AudioRenderClient {
+        &self.render_client
+    }
+
+    /// Consume the [RenderWriter] and return the wrapped [AudioRenderClient].
+    /// Any queued data is discarded.
+    pub fn into_inner(self) -> AudioRenderClient {
+        self.render_client
+    }
+
+    fn write_queued(&mut self, nbr_frames: usize) -> WasapiRes<usize> {
+        self.render_client
+            .write_to_device_from_deque(nbr_frames, &mut self.queue, None)?;
+        Ok(nbr_frames)
+    }
+
+    fn write_queued_and_silence(
+        &mut self,
+        queued_frames: usize,
+        total_frames: usize,
+    ) -> WasapiRes<usize> {
+        if total_frames == 0 {
+            return Ok(0);
+        }
+        let nbr_bytes = queued_frames * self.render_client.bytes_per_frame;
+        let mut buffer = self.render_client.get_buffer(total_frames)?;
+        let (data, silence) = buffer.as_mut_slice().split_at_mut(nbr_bytes);
+        for (element, value) in data.iter_mut().zip(self.queue.drain(..nbr_bytes)) {
+            *element = value;
+        }
+        silence.fill(0);
+        buffer.release()?;
+        Ok(total_frames)
+    }
+
+    /// Release memory that the queue has not needed during the last interval.
+    fn shrink_queue_if_due(&mut self) {
+        self.writes_since_shrink += 1;
+        if self.writes_since_shrink < RENDER_QUEUE_SHRINK_INTERVAL {
+            return;
+        }
+        if self.queue.capacity() > 2 * self.recent_high_water_bytes {
+            trace!(
+                "shrinking render queue from {} to {} bytes",
+                self.queue.capacity(),
+                self.recent_high_water_bytes
+            );
+            self.queue.shrink_to(self.recent_high_water_bytes);
+        }
+        self.writes_since_shrink = 0;
+        self.recent_high_water_bytes = self.queue.len();
+    }
+
+    fn write_queued_and_repeat(
+        &mut self,
+        queued_frames: usize,
+        total_frames: usize,
+    ) -> WasapiRes<usize> {
+        if self.last_buffer.is_empty() {
+            return self.write_queued_and_silence(queued_frames, total_frames);
+        }
+        if total_frames == 0 {
+            return Ok(0);
+        }
+        let nbr_bytes = queued_frames * self.render_client.bytes_per_frame;
+        let mut buffer = self.render_client.get_buffer(total_frames)?;
+        let (data, repeated) = buffer.as_mut_slice().split_at_mut(nbr_bytes);
+        for (element, value) in data.iter_mut().zip(self.queue.drain(..nbr_bytes)) {
+            *element = value;
+        }
+        for (element, value) in repeated.iter_mut().zip(self.last_buffer.iter().cycle()) {
+            *element = *value;
+        }
+        buffer.release()?;
+        Ok(total_frames)
+    }
+}
+
+/// A transition to a new source in a [GaplessPlayer].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SourceTransition {
+    /// The id of the source, as returned when it was queued.
+    pub source_id: usize,
+    /// The position in the stream, in frames, of the first frame of the source.
+    /// This counts all frames written by the player, including silence.
+    pub frame: u64,
+}
+
+/// Source that repeats a block of data a number of times, or until it is skipped.
+struct RepeatedSource {
+    data: Vec<u8>,
+    position: usize,
+    repetitions: Option<usize>,
+}
+
+impl Read for RepeatedSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.data.is_empty() || self.repetitions == Some(0) {
+            return Ok(0);
+        }
+        if self.position == self.data.len() {
+            match self.repetitions {
+                Some(1) => return Ok(0),
+                Some(ref mut remaining) => *remaining -= 1,
+                None => {}
+            }
+            self.position = 0;
+        }
+        let nbr_bytes = cmp::min(buf.len(), self.data.len() - self.position);
+        buf[..nbr_bytes].copy_from_slice(&self.data[self.position..self.position + nbr_bytes]);
+        self.position += nbr_bytes;
+        Ok(nbr_bytes)
+    }
+}
+
+/// Player that renders a queue of sources back to back, without any gaps between them.
+///
+/// Each source is read until it reaches the end, and the next source continues at the following frame,
+/// within the same device buffer. The sources must contain raw data in the format of the stream.
+/// A source that ends with an incomplete frame is padded with silence to a whole frame.
+/// The position of each transition is recorded, see [GaplessPlayer::take_transitions].
+/// When the queue runs empty, the rest of the device buffer is filled with silence.
+pub struct GaplessPlayer {
+    render_client: AudioRenderClient,
+    sources: VecDeque<(usize, Box<dyn Read + Send>)>,
+    current_started: bool,
+    next_id: usize,
+    position: u64,
+    transitions: Vec<SourceTransition>,
+}
+
+impl GaplessPlayer {
+    /// Create a new [GaplessPlayer] for an initialized [AudioRenderClient].
+    pub fn new(render_client: AudioRenderClient) -> Self {
+        GaplessPlayer {
+            render_client,
+            sources: VecDeque::new(),
+            current_started: false,
+            next_id: 0,
+            position: 0,
+            transitions: Vec::new(),
+        }
+    }
+
+    /// Add a source to the end of the queue, and return its id.
+    pub fn enqueue(&mut self, source: impl Read + Send + 'static) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sources.push_back((id, Box::new(source)));
+        id
+    }
+
+    /// Add a block of data that is played `repetitions` times to the end of the queue, and return its id.
+    /// With `None`, the data loops until it is skipped with [GaplessPlayer::skip_current].
+    /// With `Some(0)`, nothing is played and the source ends immediately.
+    /// The repetitions are played without gaps, and are reported as a single [SourceTransition].
+    pub fn enqueue_repeated(&mut self, data: Vec<u8>, repetitions: Option<usize>) -> usize {
+        self.enqueue(RepeatedSource {
+            data,
+            position: 0,
+            repetitions,
+        })
+    }
+
+    /// Stop playing the current source, and continue with the next one at the next write.
+    pub fn skip_current(&mut self) {
+        if self.sources.pop_front().is_some() {
+            self.current_started = false;
+        }
+    }
+
+    /// Remove all sources from the queue.
+    pub fn clear(&mut self) {
+        self.sources.clear();
+        self.current_started = false;
+    }
+
+    /// Get the number of sources in the queue, including the one currently playing.
+    pub fn get_nbr_sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Get the number of frames written so far, including silence.
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    /// Return the transitions recorded since the last call, and clear the list.
+    /// The start of the first source is also recorded as a transition.
+    pub fn take_transitions(&mut self) -> Vec<SourceTransition> {
+        std::mem::take(&mut self.transitions)
+    }
+
+    /// Fill the available space in the device buffer from the queued sources.
+    /// Call this once for each buffer event, or each cycle when polling.
+    /// In exclusive mode, the available space is always the whole buffer.
+    /// Returns the number of frames written, including any silence.
+    ///
+    /// If reading a source fails, the rest of the buffer is filled with silence before the error is returned.
+    /// The source stays in the queue, use [GaplessPlayer::skip_current] to continue with the next one.
+    pub fn write(&mut self) -> WasapiRes<usize> {
+        let available_frames = available_space_in_frames(
+            &self.render_client.audio_client,
+            self.render_client.sharemode,
+        )? as usize;
+        if available_frames == 0 {
+            return Ok(0);
+        }
+        let bytes_per_frame = self.render_client.bytes_per_frame;
+        let mut buffer = self.render_client.get_buffer(available_frames)?;
+        let data = buffer.as_mut_slice();
+        let mut offset = 0;
+        let mut read_error = None;
+        while offset < data.len() {
+            let (id, source) = match self.sources.front_mut() {
+                Some(entry) => entry,
+                None => break,
+            };
+            if !self.current_started {
+                let transition = SourceTransition {
+                    source_id: *id,
+                    frame: self.position + (offset / bytes_per_frame) as u64,
+                };
+                debug!(
+                    "Starting source {} at frame {}",
+                    transition.source_id, transition.frame
+                );
+                self.transitions.push(transition);
+                self.current_started = true;
+            }
+            let nbr_bytes = match source.read(&mut data[offset..]) {
+                Ok(nbr_bytes) => nbr_bytes,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    warn!("Failed to read source {}: {}", id, err);
+                    read_error = Some(err);
+                    break;
+                }
+            };
+            if nbr_bytes > 0 {
+                offset += nbr_bytes;
+                continue;
+            }
+            let remainder = offset % bytes_per_frame;
+            if remainder > 0 {
+                data[offset..offset + bytes_per_frame - remainder].fill(0);
+                offset += bytes_per_frame - remainder;
+            }
+            self.sources.pop_front();
+            self.current_started = false;
+        }
+        if offset < data.len() {
+            rt_trace!(
+                "source queue is empty, filling {} frames with silence",
+                (data.len() - offset) / bytes_per_frame
+            );
+            data[offset..].fill(0);
+        }
+        buffer.release()?;
+        self.position += available_frames as u64;
+        match read_error {
+            Some(err) => Err(err.into()),
+            None => Ok(available_frames),
+        }
+    }
+
+    /// Get a reference to the wrapped [AudioRenderClient].
+    pub fn get_render_client(&self) -> &AudioRenderClient {
+        &self.render_client
+    }
+
+    /// Consume the [GaplessPlayer] and return the wrapped [AudioRenderClient].
+    /// Any queued sources are discarded.
+    pub fn into_inner(self) -> AudioRenderClient {
+        self.render_client
+    }
+}
+
 /// Struct representing the [ _AUDCLNT_BUFFERFLAGS enum values](https://docs.microsoft.com/en-us/windows/win32/api/audioclient/ne-audioclient-_audclnt_bufferflags).
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct BufferFlags {
     /// AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY
     pub data_discontinuity: bool,
@@ -1101,17 +5161,237 @@ impl BufferFlags {
     }
 }
 
+/// Information about a buffer read from a capture device.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferInfo {
+    /// The [BufferFlags] of the buffer.
+    pub flags: BufferFlags,
+    /// The device position of the first frame in the buffer, in frames.
+    pub index: u64,
+    /// The value of the performance counter when the first frame in the buffer was recorded,
+    /// converted to 100-nanosecond units.
+    pub timestamp: u64,
+}
+
+impl BufferInfo {
+    /// Create a [BufferInfo] with no flags set and zero position and timestamp.
+    pub fn none() -> Self {
+        BufferInfo {
+            flags: BufferFlags::none(),
+            index: 0,
+            timestamp: 0,
+        }
+    }
+}
+
+/// Converter from device positions to a continuous stream-relative frame index, for capture streams.
+///
+/// The device position in [BufferInfo::index] starts over when the stream is reset,
+/// and jumps forward when frames are lost in a glitch.
+/// The tracker maintains a `stream_frame_index` that starts at zero and only increases.
+/// Lost frames, detected as a jump in the device position, are counted,
+/// so that the index stays aligned with time and silence can be inserted for the gap.
+/// When the device position goes backwards, the stream is assumed to have been reset,
+/// and the index continues without a gap.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamFrameTracker {
+    next_frame_index: u64,
+    next_device_position: Option<u64>,
+    lost_frames: u64,
+    resets: u64,
+}
+
+/// The position of a captured packet, see [StreamFrameTracker::update].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StreamFramePosition {
+    /// The stream-relative index of the first frame of the packet.
+    pub stream_frame_index: u64,
+    /// The number of frames lost just before this packet.
+    /// These are included in the stream-relative index.
+    pub lost_frames: u64,
+    /// Whether the device position went backwards, meaning that the stream was reset.
+    pub reset: bool,
+}
+
+impl StreamFrameTracker {
+    /// Create a new [StreamFrameTracker].
+    pub fn new() -> Self {
+        StreamFrameTracker::default()
+    }
+
+    /// Update the tracker with a captured packet of `nbr_frames` frames,
+    /// and return the position of the packet in the stream.
+    /// Call this once for each packet, in the order they were read.
+    pub fn update(&mut self, info: &BufferInfo, nbr_frames: usize) -> StreamFramePosition {
+        let mut lost_frames = 0;
+        let mut reset = false;
+        if let Some(expected) = self.next_device_position {
+            if info.index > expected {
+                lost_frames = info.index - expected;
+                debug!(
+                    "device position jumped from {} to {}, {} frames lost",
+                    expected, info.index, lost_frames
+                );
+            } else if info.index < expected {
+                reset = true;
+                self.resets += 1;
+                debug!(
+                    "device position went back from {} to {}, stream was reset",
+                    expected, info.index
+                );
+            }
+        }
+        self.lost_frames += lost_frames;
+        let stream_frame_index = self.next_frame_index + lost_frames;
+        self.next_frame_index = stream_frame_index + nbr_frames as u64;
+        self.next_device_position = Some(info.index + nbr_frames as u64);
+        StreamFramePosition {
+            stream_frame_index,
+            lost_frames,
+            reset,
+        }
+    }
+
+    /// Get the stream-relative index of the next frame.
+    pub fn get_next_frame_index(&self) -> u64 {
+        self.next_frame_index
+    }
+
+    /// Get the total number of lost frames.
+    pub fn get_lost_frames(&self) -> u64 {
+        self.lost_frames
+    }
+
+    /// Get the number of detected stream resets.
+    pub fn get_resets(&self) -> u64 {
+        self.resets
+    }
+}
+
+/// Counters for the packets read by an [AudioCaptureClient],
+/// see [AudioCaptureClient::get_metrics].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamMetrics {
+    /// The number of packets that have been read.
+    pub packets: u64,
+    /// The number of frames that have been read.
+    pub frames: u64,
+    /// The number of packets with the data discontinuity flag set.
+    pub discontinuities: u64,
+    /// The number of packets with the timestamp error flag set.
+    pub timestamp_errors: u64,
+}
+
+type GlitchCallback = Box<dyn Fn(&BufferInfo)>;
+
+/// The minimum time between two warnings about glitches in the captured stream.
+const GLITCH_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Policy for handling captured data that does not fit in a bounded buffer,
+/// see [AudioCaptureClient::read_into_ringbuf].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest frames in the buffer to make room for the new ones.
+    DropOldest,
+    /// Keep the buffer as it is and discard the new frames that do not fit.
+    DropNewest,
+    /// Return an error. The packet is left in the device buffer and can be read again later.
+    Error,
+}
+
+/// Add a packet to a deque used as a ring buffer holding at most `capacity_bytes`, according to the policy.
+/// Returns the number of frames that were dropped,
+/// or `None` with the deque unchanged if the packet doesn't fit and the policy is [OverflowPolicy::Error].
+fn push_to_ringbuf(
+    data: &mut VecDeque<u8>,
+    packet: &[u8],
+    bytes_per_frame: usize,
+    capacity_bytes: usize,
+    policy: &OverflowPolicy,
+) -> Option<usize> {
+    if data.len() + packet.len() <= capacity_bytes {
+        data.extend(packet.iter());
+        return Some(0);
+    }
+    match policy {
+        OverflowPolicy::Error => None,
+        OverflowPolicy::DropNewest => {
+            let free_bytes = capacity_bytes.saturating_sub(data.len());
+            let keep_bytes = free_bytes - free_bytes % bytes_per_frame;
+            data.extend(packet[..keep_bytes].iter());
+            Some((packet.len() - keep_bytes) / bytes_per_frame)
+        }
+        OverflowPolicy::DropOldest => {
+            // The deque may already be over the capacity, if the capacity was lowered
+            let excess_bytes = data.len() + packet.len() - capacity_bytes;
+            let excess_frames = Integer::div_ceil(&excess_bytes, &bytes_per_frame);
+            let drain_bytes = cmp::min(excess_frames * bytes_per_frame, data.len());
+            data.drain(..drain_bytes);
+            // If the packet alone doesn't fit, its oldest frames are dropped too
+            let skip_bytes = cmp::min(excess_frames * bytes_per_frame - drain_bytes, packet.len());
+            data.extend(packet[skip_bytes..].iter());
+            Some(excess_frames)
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            OverflowPolicy::DropOldest => write!(f, "DropOldest"),
+            OverflowPolicy::DropNewest => write!(f, "DropNewest"),
+            OverflowPolicy::Error => write!(f, "Error"),
+        }
+    }
+}
+
 /// Struct wrapping an [IAudioCaptureClient](https://docs.microsoft.com/en-us/windows/win32/api/audioclient/nn-audioclient-iaudiocaptureclient).
 pub struct AudioCaptureClient {
     client: IAudioCaptureClient,
     sharemode: Option<ShareMode>,
     bytes_per_frame: usize,
+    metrics: Cell<StreamMetrics>,
+    glitch_callback: Option<GlitchCallback>,
+    pending_glitch: Cell<Option<BufferInfo>>,
+    last_glitch_warning: Cell<Option<Instant>>,
+    glitches_since_warning: Cell<u64>,
 }
 
 impl AudioCaptureClient {
+    /// Create an [AudioCaptureClient] from a raw [IAudioCaptureClient] pointer,
+    /// for interoperability with other crates.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid `IAudioCaptureClient` pointer,
+    /// obtained from the same `IAudioClient` as the one wrapped by `audio_client`.
+    /// The [AudioCaptureClient] takes ownership of the reference held by the pointer.
+    pub unsafe fn from_raw(raw: *mut c_void, audio_client: &AudioClient) -> WasapiRes<Self> {
+        let bytes_per_frame = audio_client
+            .bytes_per_frame
+            .ok_or_else(|| WasapiError::new("Client has not been initialized"))?;
+        Ok(AudioCaptureClient {
+            client: IAudioCaptureClient::from_raw(raw),
+            sharemode: audio_client.sharemode,
+            bytes_per_frame,
+            metrics: Cell::new(StreamMetrics::default()),
+            glitch_callback: None,
+            pending_glitch: Cell::new(None),
+            last_glitch_warning: Cell::new(None),
+            glitches_since_warning: Cell::new(0),
+        })
+    }
+
+    /// Consume the [AudioCaptureClient] and return the raw [IAudioCaptureClient] pointer.
+    /// The caller becomes responsible for releasing the reference.
+    pub fn into_raw(self) -> *mut c_void {
+        self.client.into_raw()
+    }
+
     /// Get number of frames in next packet when in shared mode.
     /// In exclusive mode it returns None, instead use [AudioClient::get_bufferframecount()].
     pub fn get_next_nbr_frames(&self) -> WasapiRes<Option<u32>> {
+        check_simulated_error()?;
         if let Some(ShareMode::Exclusive) = self.sharemode {
             return Ok(None);
         }
@@ -1123,30 +5403,55 @@ impl AudioCaptureClient {
     /// that was read, and the BufferFlags describing the buffer that the data was read from.
     /// The slice must be large enough to hold all data.
     /// If it is longer that needed, the unused elements will not be modified.
+    ///
+    /// This method does not allocate, except when returning an error.
     pub fn read_from_device(&self, data: &mut [u8]) -> WasapiRes<(u32, BufferFlags)> {
+        let (nbr_frames, info) = self.read_from_device_with_info(data)?;
+        Ok((nbr_frames, info.flags))
+    }
+
+    /// Wait for the buffer event, and then read the next packet into a slice, like [AudioCaptureClient::read_from_device].
+    /// The handle must be the event handle of the stream, see [AudioClient::set_get_eventhandle].
+    ///
+    /// The wait gives up at the deadline, and then nothing is read and
+    /// [DeadlineResult::DeadlineExceeded] is returned.
+    /// The deadline only limits the wait, the read itself takes a short and bounded time.
+    /// Returns the number of frames that was read, and the BufferFlags of the buffer.
+    ///
+    /// This method does not allocate, except when returning an error.
+    pub fn read_from_device_deadline(
+        &self,
+        handle: &Handle,
+        data: &mut [u8],
+        deadline: Instant,
+    ) -> WasapiRes<DeadlineResult<(u32, BufferFlags)>> {
+        if !handle.wait_until(deadline)? {
+            rt_trace!("deadline exceeded while waiting for capture event");
+            return Ok(DeadlineResult::DeadlineExceeded);
+        }
+        let result = self.read_from_device(data)?;
+        Ok(DeadlineResult::Completed(result))
+    }
+
+    /// Read raw bytes from a device into a slice. Returns the number of frames
+    /// that was read, and a [BufferInfo] with the flags, device position and timestamp
+    /// of the buffer that the data was read from.
+    /// The slice must be large enough to hold all data.
+    /// If it is longer that needed, the unused elements will not be modified.
+    ///
+    /// This method does not allocate, except when returning an error
+    /// or logging a warning about glitches, see [AudioCaptureClient::get_metrics].
+    pub fn read_from_device_with_info(&self, data: &mut [u8]) -> WasapiRes<(u32, BufferInfo)> {
+        let guard = NoAllocGuard::new();
         let data_len_in_frames = data.len() / self.bytes_per_frame;
         if data_len_in_frames == 0 {
-            return Ok((0, BufferFlags::none()));
-        }
-        let mut buffer_ptr = ptr::null_mut();
-        let mut nbr_frames_returned = 0;
-        let mut flags = 0;
-        unsafe {
-            self.client.GetBuffer(
-                &mut buffer_ptr,
-                &mut nbr_frames_returned,
-                &mut flags,
-                None,
-                None,
-            )?
-        };
-        let bufferflags = BufferFlags::new(flags);
-        if nbr_frames_returned == 0 {
-            unsafe { self.client.ReleaseBuffer(nbr_frames_returned)? };
-            return Ok((0, bufferflags));
+            return Ok((0, BufferInfo::none()));
         }
+        let mut buffer = self.get_buffer()?;
+        let bufferinfo = buffer.get_info();
+        let nbr_frames_returned = buffer.get_nbr_frames() as u32;
         if data_len_in_frames < nbr_frames_returned as usize {
-            unsafe { self.client.ReleaseBuffer(nbr_frames_returned)? };
+            // The packet is discarded when the buffer is dropped
             return Err(WasapiError::new(
                 format!(
                     "Wrong length of data, got {} frames, expected at least {} frames",
@@ -1157,45 +5462,120 @@ impl AudioCaptureClient {
             .into());
         }
         let len_in_bytes = nbr_frames_returned as usize * self.bytes_per_frame;
-        let bufferslice = unsafe { slice::from_raw_parts(buffer_ptr, len_in_bytes) };
-        data[..len_in_bytes].copy_from_slice(bufferslice);
-        if nbr_frames_returned > 0 {
-            unsafe { self.client.ReleaseBuffer(nbr_frames_returned)? };
-        }
-        trace!("read {} frames", nbr_frames_returned);
-        Ok((nbr_frames_returned, bufferflags))
+        data[..len_in_bytes].copy_from_slice(buffer.as_slice());
+        // Release without reporting glitches, that may allocate
+        buffer.release_frames(nbr_frames_returned)?;
+        guard.assert_no_alloc("read_from_device");
+        self.report_glitch();
+        rt_trace!("read {} frames", nbr_frames_returned);
+        Ok((nbr_frames_returned, bufferinfo))
     }
 
     /// Read raw bytes data from a device into a deque.
     /// Returns the [BufferFlags] describing the buffer that the data was read from.
     pub fn read_from_device_to_deque(&self, data: &mut VecDeque<u8>) -> WasapiRes<BufferFlags> {
+        let buffer = self.get_buffer()?;
+        let bufferflags = buffer.get_info().flags;
+        data.extend(buffer.as_slice().iter());
+        rt_trace!("read {} frames", buffer.get_nbr_frames());
+        buffer.release()?;
+        Ok(bufferflags)
+    }
+
+    /// Read raw bytes data from a device into a deque that is used as a ring buffer
+    /// holding at most `capacity_frames` frames.
+    /// If the new packet does not fit, the `policy` decides what happens, see [OverflowPolicy].
+    /// Returns the [BufferFlags] describing the buffer that the data was read from,
+    /// and the number of frames that were dropped.
+    pub fn read_into_ringbuf(
+        &self,
+        data: &mut VecDeque<u8>,
+        capacity_frames: usize,
+        policy: &OverflowPolicy,
+    ) -> WasapiRes<(BufferFlags, usize)> {
+        let buffer = self.get_buffer()?;
+        let bufferflags = buffer.get_info().flags;
+        let nbr_frames_returned = buffer.get_nbr_frames();
+        if nbr_frames_returned == 0 {
+            return Ok((bufferflags, 0));
+        }
+        let capacity_bytes = capacity_frames * self.bytes_per_frame;
+        let dropped_frames = match push_to_ringbuf(
+            data,
+            buffer.as_slice(),
+            self.bytes_per_frame,
+            capacity_bytes,
+            policy,
+        ) {
+            Some(dropped_frames) => dropped_frames,
+            None => {
+                // Leave the packet in the device buffer
+                buffer.keep()?;
+                return Err(WasapiError::new(
+                    format!(
+                        "Buffer overflow, got {} frames, space for {} frames",
+                        nbr_frames_returned,
+                        capacity_bytes.saturating_sub(data.len()) / self.bytes_per_frame
+                    )
+                    .as_str(),
+                )
+                .into());
+            }
+        };
+        buffer.release()?;
+        if dropped_frames > 0 {
+            debug!(
+                "ring buffer overflow, dropped {} frames with policy {}",
+                dropped_frames, policy
+            );
+        }
+        rt_trace!("read {} frames", nbr_frames_returned);
+        Ok((bufferflags, dropped_frames))
+    }
+
+    /// Get the next packet from the device.
+    ///
+    /// The returned [CaptureBuffer] gives direct access to the device buffer,
+    /// which avoids copying the data to an intermediate slice.
+    /// The packet is removed from the device buffer when the [CaptureBuffer] is released or dropped.
+    /// It contains zero frames if no packet is available.
+    ///
+    /// This method does not allocate, except when returning an error.
+    pub fn get_buffer(&self) -> WasapiRes<CaptureBuffer<'_>> {
+        check_simulated_error()?;
         let mut buffer_ptr = ptr::null_mut();
         let mut nbr_frames_returned = 0;
         let mut flags = 0;
+        let mut index = 0;
+        let mut timestamp = 0;
         unsafe {
             self.client.GetBuffer(
                 &mut buffer_ptr,
                 &mut nbr_frames_returned,
                 &mut flags,
-                None,
-                None,
+                Some(&mut index),
+                Some(&mut timestamp),
             )?
         };
-        let bufferflags = BufferFlags::new(flags);
-        if nbr_frames_returned == 0 {
+        let info = BufferInfo {
+            flags: BufferFlags::new(flags),
+            index,
+            timestamp,
+        };
+        let data: &[u8] = if nbr_frames_returned > 0 {
+            let len_in_bytes = nbr_frames_returned as usize * self.bytes_per_frame;
+            unsafe { slice::from_raw_parts(buffer_ptr, len_in_bytes) }
+        } else {
+            &[]
+        };
+        Ok(CaptureBuffer {
+            owner: self,
+            data,
+            nbr_frames: nbr_frames_returned,
+            info,
             // There is no need to release a buffer of 0 bytes
-            return Ok(bufferflags);
-        }
-        let len_in_bytes = nbr_frames_returned as usize * self.bytes_per_frame;
-        let bufferslice = unsafe { slice::from_raw_parts(buffer_ptr, len_in_bytes) };
-        for element in bufferslice.iter() {
-            data.push_back(*element);
-        }
-        if nbr_frames_returned > 0 {
-            unsafe { self.client.ReleaseBuffer(nbr_frames_returned).unwrap() };
-        }
-        trace!("read {} frames", nbr_frames_returned);
-        Ok(bufferflags)
+            released: nbr_frames_returned == 0,
+        })
     }
 
     /// Get the sharemode for this [AudioCaptureClient].
@@ -1203,6 +5583,148 @@ impl AudioCaptureClient {
     pub fn get_sharemode(&self) -> Option<ShareMode> {
         self.sharemode
     }
+
+    /// Get the [StreamMetrics] with the counts of packets, frames and glitches read so far.
+    ///
+    /// Packets with the data discontinuity or timestamp error flags set are counted as glitches.
+    /// A warning is logged for the first glitch, and after that at most once per second
+    /// with the number of glitches since the previous warning.
+    pub fn get_metrics(&self) -> StreamMetrics {
+        self.metrics.get()
+    }
+
+    /// Reset all the [StreamMetrics] counters to zero.
+    pub fn reset_metrics(&self) {
+        self.metrics.set(StreamMetrics::default());
+    }
+
+    /// Set a callback that is called with the [BufferInfo] of each packet
+    /// that has the data discontinuity or timestamp error flags set.
+    /// The callback is called from the thread reading the data, after the packet has been released,
+    /// and should return quickly.
+    pub fn set_glitch_callback(&mut self, c: impl Fn(&BufferInfo) + 'static) {
+        self.glitch_callback = Some(Box::new(c));
+    }
+
+    /// Remove the glitch callback.
+    pub fn unset_glitch_callback(&mut self) {
+        self.glitch_callback = None;
+    }
+
+    /// Update the metrics for a packet that has been read.
+    /// This only updates counters, the glitch is reported later by [AudioCaptureClient::report_glitch].
+    fn record_packet(&self, info: &BufferInfo, nbr_frames: u32) {
+        let mut metrics = self.metrics.get();
+        metrics.packets += 1;
+        metrics.frames += nbr_frames as u64;
+        if info.flags.data_discontinuity {
+            metrics.discontinuities += 1;
+        }
+        if info.flags.timestamp_error {
+            metrics.timestamp_errors += 1;
+        }
+        self.metrics.set(metrics);
+        if info.flags.data_discontinuity || info.flags.timestamp_error {
+            self.pending_glitch.set(Some(*info));
+        }
+    }
+
+    /// Call the glitch callback and log a warning for the glitch recorded by the last released packet, if any.
+    /// This may allocate, and is called after the no-allocation part of a read.
+    fn report_glitch(&self) {
+        let info = match self.pending_glitch.take() {
+            Some(info) => info,
+            None => return,
+        };
+        if let Some(callback) = &self.glitch_callback {
+            callback(&info);
+        }
+        let metrics = self.metrics.get();
+        let glitches = self.glitches_since_warning.get() + 1;
+        let now = Instant::now();
+        let warn_now = match self.last_glitch_warning.get() {
+            Some(last) => now.duration_since(last) >= GLITCH_WARNING_INTERVAL,
+            None => true,
+        };
+        if warn_now {
+            warn!(
+                "{} capture glitches since last warning, totals: {} discontinuities, {} timestamp errors",
+                glitches, metrics.discontinuities, metrics.timestamp_errors
+            );
+            self.last_glitch_warning.set(Some(now));
+            self.glitches_since_warning.set(0);
+        } else {
+            self.glitches_since_warning.set(glitches);
+        }
+    }
+}
+
+/// A packet borrowed from an [AudioCaptureClient] with [AudioCaptureClient::get_buffer].
+///
+/// The packet is removed from the device buffer with [CaptureBuffer::release],
+/// or left there to be read again with [CaptureBuffer::keep].
+/// If it is instead dropped, it is released automatically.
+pub struct CaptureBuffer<'a> {
+    owner: &'a AudioCaptureClient,
+    data: &'a [u8],
+    nbr_frames: u32,
+    info: BufferInfo,
+    released: bool,
+}
+
+impl CaptureBuffer<'_> {
+    /// Get the number of frames in the packet.
+    pub fn get_nbr_frames(&self) -> usize {
+        self.nbr_frames as usize
+    }
+
+    /// Get the [BufferInfo] with the flags, device position and timestamp of the packet.
+    pub fn get_info(&self) -> BufferInfo {
+        self.info
+    }
+
+    /// Get the packet data as a slice of bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        self.data
+    }
+
+    /// Release the packet, removing it from the device buffer.
+    ///
+    /// If the packet has the data discontinuity or timestamp error flags set,
+    /// the glitch callback is called and a warning may be logged after releasing it,
+    /// see [AudioCaptureClient::get_metrics].
+    pub fn release(mut self) -> WasapiRes<()> {
+        self.release_frames(self.nbr_frames)?;
+        self.owner.report_glitch();
+        Ok(())
+    }
+
+    /// Release the buffer without consuming the packet,
+    /// so that it is returned again by the next call to [AudioCaptureClient::get_buffer].
+    pub fn keep(mut self) -> WasapiRes<()> {
+        self.release_frames(0)
+    }
+
+    fn release_frames(&mut self, nbr_frames: u32) -> WasapiRes<()> {
+        if self.released {
+            return Ok(());
+        }
+        self.released = true;
+        unsafe { self.owner.client.ReleaseBuffer(nbr_frames)? };
+        if nbr_frames > 0 {
+            self.owner.record_packet(&self.info, nbr_frames);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CaptureBuffer<'_> {
+    fn drop(&mut self) {
+        match self.release_frames(self.nbr_frames) {
+            Ok(()) => self.owner.report_glitch(),
+            Err(err) => warn!("Failed to release capture buffer: {}", err),
+        }
+    }
 }
 
 /// Struct wrapping a [HANDLE] to an [Event Object](https://docs.microsoft.com/en-us/windows/win32/sync/event-objects).
@@ -1211,12 +5733,314 @@ pub struct Handle {
 }
 
 impl Handle {
-    /// Wait for an event on a handle, with a timeout given in ms
+    /// Wait for an event on a handle, with a timeout given in ms.
+    /// This method does not allocate, except when returning an error.
+    pub fn wait_for_event(&self, timeout_ms: u32) -> WasapiRes<()> {
+        let guard = NoAllocGuard::new();
+        let retval = unsafe { WaitForSingleObject(self.handle, timeout_ms) };
+        if retval.0 != WAIT_OBJECT_0.0 {
+            return Err(WasapiError::new("Wait timed out").into());
+        }
+        guard.assert_no_alloc("wait_for_event");
+        Ok(())
+    }
+
+    /// Wait for an event on a handle until the deadline.
+    /// Returns false if the deadline passed without an event.
+    /// If the deadline has already passed, this checks for an event without waiting.
+    fn wait_until(&self, deadline: Instant) -> WasapiRes<bool> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // Round down, to return before the deadline rather than after.
+        let timeout_ms = cmp::min(remaining.as_millis(), u32::MAX as u128 - 1) as u32;
+        let retval = unsafe { WaitForSingleObject(self.handle, timeout_ms) };
+        if retval.0 == WAIT_OBJECT_0.0 {
+            Ok(true)
+        } else if retval.0 == WAIT_TIMEOUT.0 {
+            Ok(false)
+        } else {
+            Err(windows::core::Error::from_win32().into())
+        }
+    }
+}
+
+/// Result of the methods that wait for an event before reading or writing,
+/// and that give up at a deadline, like [AudioRenderClient::write_to_device_deadline].
+/// A missed deadline is an expected outcome for these methods, and is returned as
+/// [DeadlineResult::DeadlineExceeded] rather than as an error, so that it can be handled without allocating.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeadlineResult<T> {
+    /// The event arrived in time, and the operation was performed.
+    Completed(T),
+    /// The deadline passed before the event arrived, nothing was read or written.
+    DeadlineExceeded,
+}
+
+impl<T> DeadlineResult<T> {
+    /// Returns true if the deadline passed before the operation could be performed.
+    pub fn is_exceeded(&self) -> bool {
+        matches!(self, DeadlineResult::DeadlineExceeded)
+    }
+
+    /// Get the value of a completed operation, or None if the deadline was exceeded.
+    pub fn completed(self) -> Option<T> {
+        match self {
+            DeadlineResult::Completed(value) => Some(value),
+            DeadlineResult::DeadlineExceeded => None,
+        }
+    }
+}
+
+/// A periodic timer based on a [Waitable Timer Object](https://learn.microsoft.com/en-us/windows/win32/sync/waitable-timer-objects),
+/// that can be used instead of a [Handle] for streams that poll the device buffer.
+///
+/// A high resolution timer is used when available, which is from Windows 10 version 1803.
+/// The timer follows a fixed schedule, so that the time spent between the waits does not add up to a drift.
+/// If a wait is started after the next tick has already passed, the schedule is restarted from the current time.
+pub struct PeriodicTimer {
+    handle: HANDLE,
+    period: Duration,
+    next_tick: Cell<Option<Instant>>,
+}
+
+impl PeriodicTimer {
+    /// Create a new [PeriodicTimer] with the given period.
+    pub fn new(period: Duration) -> WasapiRes<Self> {
+        if period.is_zero() {
+            return Err(WasapiError::new("The timer period must be non-zero").into());
+        }
+        let handle = match unsafe {
+            CreateWaitableTimerExW(
+                None,
+                PCWSTR::null(),
+                CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                TIMER_ALL_ACCESS.0,
+            )
+        } {
+            Ok(handle) => handle,
+            Err(_) => {
+                debug!("High resolution timer not available, using a normal timer");
+                unsafe { CreateWaitableTimerExW(None, PCWSTR::null(), 0, TIMER_ALL_ACCESS.0)? }
+            }
+        };
+        Ok(PeriodicTimer {
+            handle,
+            period,
+            next_tick: Cell::new(None),
+        })
+    }
+
+    /// Get the period of the timer.
+    pub fn get_period(&self) -> Duration {
+        self.period
+    }
+
+    /// Wait for the next tick of the timer, with a timeout given in ms.
+    /// The first call starts the schedule, and returns after one period.
+    /// This method does not allocate, except when returning an error.
     pub fn wait_for_event(&self, timeout_ms: u32) -> WasapiRes<()> {
+        let guard = NoAllocGuard::new();
+        let now = Instant::now();
+        let mut next_tick = self.next_tick.get().unwrap_or(now) + self.period;
+        if next_tick <= now {
+            next_tick = now + self.period;
+        }
+        self.next_tick.set(Some(next_tick));
+        // A negative due time is relative, in 100-nanosecond units.
+        let due_time = -(((next_tick - now).as_nanos() / 100) as i64);
+        unsafe { SetWaitableTimer(self.handle, &due_time, 0, None, None, false)? };
         let retval = unsafe { WaitForSingleObject(self.handle, timeout_ms) };
         if retval.0 != WAIT_OBJECT_0.0 {
             return Err(WasapiError::new("Wait timed out").into());
         }
+        guard.assert_no_alloc("PeriodicTimer::wait_for_event");
+        Ok(())
+    }
+}
+
+impl Drop for PeriodicTimer {
+    fn drop(&mut self) {
+        if let Err(err) = unsafe { CloseHandle(self.handle) } {
+            warn!("Failed to close timer handle: {}", err);
+        }
+    }
+}
+
+/// Watcher for the exit of a process, for use with process loopback capture,
+/// see [AudioClient::new_application_loopback_client].
+///
+/// A process loopback stream keeps running after the target process has exited,
+/// and then only delivers silence. The watcher can be polled from the capture loop
+/// with [ProcessExitWatcher::has_exited], or call a callback when the process exits,
+/// see [ProcessExitWatcher::set_exit_callback].
+pub struct ProcessExitWatcher {
+    process_id: u32,
+    apartment: Apartment,
+    process: HANDLE,
+    stop_event: Option<HANDLE>,
+    worker: Option<Worker<()>>,
+}
+
+impl ProcessExitWatcher {
+    /// Start watching the process with the given id.
+    pub fn new(process_id: u32) -> WasapiRes<Self> {
+        ProcessExitWatcher::with_apartment(process_id, &Apartment::MultiThreaded)
+    }
+
+    /// Start watching the process with the given id.
+    /// The exit callback is then called from a thread where COM is initialized in the given apartment,
+    /// instead of the multithreaded apartment.
+    pub fn with_apartment(process_id: u32, apartment: &Apartment) -> WasapiRes<Self> {
+        let process = unsafe { OpenProcess(PROCESS_SYNCHRONIZE, false, process_id)? };
+        Ok(ProcessExitWatcher {
+            process_id,
+            apartment: *apartment,
+            process,
+            stop_event: None,
+            worker: None,
+        })
+    }
+
+    /// Get the id of the watched process.
+    pub fn get_process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    /// Check if the process has exited, without waiting.
+    /// This is cheap enough to call once per period in a capture loop.
+    pub fn has_exited(&self) -> bool {
+        let retval = unsafe { WaitForSingleObject(self.process, 0) };
+        retval.0 == WAIT_OBJECT_0.0
+    }
+
+    /// Wait for the process to exit, with a timeout given in ms.
+    /// Returns true if the process exited, and false on timeout.
+    pub fn wait_for_exit(&self, timeout_ms: u32) -> bool {
+        let retval = unsafe { WaitForSingleObject(self.process, timeout_ms) };
+        retval.0 == WAIT_OBJECT_0.0
+    }
+
+    /// Set a callback that is called with the process id when the process exits.
+    /// The callback is called from a [Worker] thread owned by the watcher.
+    /// If the watcher is dropped before the process exits, the callback is not called.
+    /// The callback can only be set once.
+    /// Returns an [ApartmentError] if COM can't be initialized in the apartment of the watcher.
+    pub fn set_exit_callback(
+        &mut self,
+        callback: impl FnOnce(u32) + Send + 'static,
+    ) -> WasapiRes<()> {
+        if self.worker.is_some() {
+            return Err(WasapiError::new("The exit callback has already been set").into());
+        }
+        let stop_event = unsafe { CreateEventA(None, true, false, PCSTR::null())? };
+        let handles = [self.process, stop_event];
+        let process_id = self.process_id;
+        let (result_tx, result_rx) = mpsc::channel::<Result<(), String>>();
+        // The worker is stopped by setting the stop event, since it doesn't return from the wait otherwise
+        let worker = Worker::spawn(
+            &format!("ProcessExitWatcher {}", process_id),
+            &self.apartment,
+            move |_stop| {
+                let _ = result_tx.send(Ok(()));
+                let retval = unsafe { WaitForMultipleObjects(&handles, false, INFINITE) };
+                if retval.0 == WAIT_OBJECT_0.0 {
+                    debug!("Process {} exited", process_id);
+                    callback(process_id);
+                }
+            },
+        );
+        // This returns the error if COM could not be initialized in the apartment
+        let worker = match worker.and_then(|worker| wait_for_startup(worker, result_rx)) {
+            Ok(worker) => worker,
+            Err(err) => {
+                let _ = unsafe { CloseHandle(stop_event) };
+                return Err(err);
+            }
+        };
+        self.stop_event = Some(stop_event);
+        self.worker = Some(worker);
         Ok(())
     }
 }
+
+impl Drop for ProcessExitWatcher {
+    fn drop(&mut self) {
+        if let Some(stop_event) = self.stop_event {
+            if let Err(err) = unsafe { SetEvent(stop_event) } {
+                warn!("Failed to stop the process exit watcher thread: {}", err);
+            }
+            // Dropping the worker waits for the thread to finish
+            self.worker.take();
+            if let Err(err) = unsafe { CloseHandle(stop_event) } {
+                warn!("Failed to close event handle: {}", err);
+            }
+        }
+        if let Err(err) = unsafe { CloseHandle(self.process) } {
+            warn!("Failed to close process handle: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a deque of frames of two bytes, with the frame index in both bytes.
+    fn frames(range: std::ops::Range<u8>) -> VecDeque<u8> {
+        range.flat_map(|frame| [frame, frame]).collect()
+    }
+
+    #[test]
+    fn ringbuf_packet_that_fits_is_added() {
+        let mut data = frames(0..2);
+        let packet: Vec<u8> = frames(2..4).into();
+        let dropped = push_to_ringbuf(&mut data, &packet, 2, 8, &OverflowPolicy::Error);
+        assert_eq!(dropped, Some(0));
+        assert_eq!(data, frames(0..4));
+    }
+
+    #[test]
+    fn ringbuf_overflow_error_leaves_data_unchanged() {
+        let mut data = frames(0..3);
+        let packet: Vec<u8> = frames(3..5).into();
+        let dropped = push_to_ringbuf(&mut data, &packet, 2, 8, &OverflowPolicy::Error);
+        assert_eq!(dropped, None);
+        assert_eq!(data, frames(0..3));
+    }
+
+    #[test]
+    fn ringbuf_overflow_drops_newest() {
+        let mut data = frames(0..3);
+        let packet: Vec<u8> = frames(3..6).into();
+        let dropped = push_to_ringbuf(&mut data, &packet, 2, 8, &OverflowPolicy::DropNewest);
+        assert_eq!(dropped, Some(2));
+        assert_eq!(data, frames(0..4));
+    }
+
+    #[test]
+    fn ringbuf_overflow_drops_oldest() {
+        let mut data = frames(0..3);
+        let packet: Vec<u8> = frames(3..6).into();
+        let dropped = push_to_ringbuf(&mut data, &packet, 2, 8, &OverflowPolicy::DropOldest);
+        assert_eq!(dropped, Some(2));
+        assert_eq!(data, frames(2..6));
+    }
+
+    #[test]
+    fn ringbuf_large_packet_drops_oldest_of_packet() {
+        let mut data = frames(0..2);
+        let packet: Vec<u8> = frames(2..8).into();
+        let dropped = push_to_ringbuf(&mut data, &packet, 2, 8, &OverflowPolicy::DropOldest);
+        assert_eq!(dropped, Some(4));
+        assert_eq!(data, frames(4..8));
+    }
+
+    #[test]
+    fn ringbuf_over_capacity_is_trimmed_by_drop_oldest() {
+        // Six frames queued, after the capacity was lowered to four frames
+        let mut data = frames(0..6);
+        let packet: Vec<u8> = frames(6..7).into();
+        let dropped = push_to_ringbuf(&mut data, &packet, 2, 8, &OverflowPolicy::DropOldest);
+        assert_eq!(dropped, Some(3));
+        assert_eq!(data, frames(3..7));
+    }
+}
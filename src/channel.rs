@@ -0,0 +1,85 @@
+use std::fmt;
+use std::sync::mpsc;
+
+/// Error returned by a [ChannelSender] when a value could not be sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelSendError {
+    /// The channel is full.
+    Full,
+    /// The receiver has been dropped.
+    Disconnected,
+}
+
+impl fmt::Display for ChannelSendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ChannelSendError::Full => write!(f, "Channel is full"),
+            ChannelSendError::Disconnected => write!(f, "Channel is disconnected"),
+        }
+    }
+}
+
+/// The sending half of a channel, used by the helpers that deliver data over channels,
+/// such as the [CapturePump](crate::CapturePump).
+///
+/// This is implemented for the `std::sync::mpsc` senders,
+/// and for the `crossbeam-channel` and `flume` senders when the `crossbeam` and `flume` features are enabled.
+pub trait ChannelSender<T> {
+    /// Send a value, blocking until there is room in the channel if it is bounded.
+    fn send_blocking(&self, value: T) -> Result<(), ChannelSendError>;
+
+    /// Send a value if there is room in the channel, without blocking.
+    fn try_send(&self, value: T) -> Result<(), ChannelSendError>;
+}
+
+impl<T> ChannelSender<T> for mpsc::SyncSender<T> {
+    fn send_blocking(&self, value: T) -> Result<(), ChannelSendError> {
+        self.send(value).map_err(|_| ChannelSendError::Disconnected)
+    }
+
+    fn try_send(&self, value: T) -> Result<(), ChannelSendError> {
+        mpsc::SyncSender::try_send(self, value).map_err(|err| match err {
+            mpsc::TrySendError::Full(_) => ChannelSendError::Full,
+            mpsc::TrySendError::Disconnected(_) => ChannelSendError::Disconnected,
+        })
+    }
+}
+
+impl<T> ChannelSender<T> for mpsc::Sender<T> {
+    fn send_blocking(&self, value: T) -> Result<(), ChannelSendError> {
+        self.send(value).map_err(|_| ChannelSendError::Disconnected)
+    }
+
+    fn try_send(&self, value: T) -> Result<(), ChannelSendError> {
+        // An unbounded channel is never full
+        self.send_blocking(value)
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl<T> ChannelSender<T> for crossbeam_channel::Sender<T> {
+    fn send_blocking(&self, value: T) -> Result<(), ChannelSendError> {
+        self.send(value).map_err(|_| ChannelSendError::Disconnected)
+    }
+
+    fn try_send(&self, value: T) -> Result<(), ChannelSendError> {
+        crossbeam_channel::Sender::try_send(self, value).map_err(|err| match err {
+            crossbeam_channel::TrySendError::Full(_) => ChannelSendError::Full,
+            crossbeam_channel::TrySendError::Disconnected(_) => ChannelSendError::Disconnected,
+        })
+    }
+}
+
+#[cfg(feature = "flume")]
+impl<T> ChannelSender<T> for flume::Sender<T> {
+    fn send_blocking(&self, value: T) -> Result<(), ChannelSendError> {
+        self.send(value).map_err(|_| ChannelSendError::Disconnected)
+    }
+
+    fn try_send(&self, value: T) -> Result<(), ChannelSendError> {
+        flume::Sender::try_send(self, value).map_err(|err| match err {
+            flume::TrySendError::Full(_) => ChannelSendError::Full,
+            flume::TrySendError::Disconnected(_) => ChannelSendError::Disconnected,
+        })
+    }
+}
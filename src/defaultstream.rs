@@ -0,0 +1,211 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    compare_formats, get_default_device_for_role, recovery_action_for_error, AudioClient,
+    AudioRenderClient, BufferFlags, DefaultDeviceObserver, Direction, Handle, RecoveryAction, Role,
+    ShareMode, WasapiError, WasapiRes, WaveFormat,
+};
+
+/// Notification of a [DefaultRenderStream] switching to a new device,
+/// passed to the callback set with [DefaultRenderStream::set_switch_callback].
+#[derive(Clone, Debug)]
+pub struct StreamSwitch {
+    /// The id of the new device.
+    pub device_id: String,
+    /// The mix format of the new device.
+    pub mix_format: WaveFormat,
+    /// True if the mix format of the new device differs from the one of the previous device.
+    /// The stream keeps using the same format, but the audio engine now converts it differently,
+    /// and an application that matches the mix format may want to change its own processing.
+    pub mix_format_changed: bool,
+}
+
+type SwitchCallback = Box<dyn Fn(&StreamSwitch)>;
+
+/// The clients of the stream on the current device.
+struct StreamClients {
+    audio_client: AudioClient,
+    render_client: AudioRenderClient,
+    device_id: String,
+    mix_format: WaveFormat,
+}
+
+/// An event driven shared mode render stream that follows the default device.
+///
+/// When the default device for the role changes, or the current device is removed,
+/// the stream is closed and opened again on the new default device, with the same format.
+/// The switch happens on the next call to [DefaultRenderStream::write] or [DefaultRenderStream::wait_for_event],
+/// so all COM objects stay on the thread that uses the stream.
+/// The data that was queued in the buffer of the previous device is lost.
+/// If opening the new device fails, for example because there is no default device,
+/// the error is returned and opening is tried again on the next call.
+///
+/// With `convert` enabled the audio engine converts the format to the mix format of each device,
+/// so that the application can keep writing the same format.
+/// Without it, opening the new device fails if it doesn't accept the format.
+pub struct DefaultRenderStream {
+    role: Role,
+    format: WaveFormat,
+    period: i64,
+    convert: bool,
+    handle: Option<Handle>,
+    clients: Option<StreamClients>,
+    default_changed: Arc<AtomicBool>,
+    _observer: DefaultDeviceObserver,
+    switch_callback: Option<SwitchCallback>,
+}
+
+impl DefaultRenderStream {
+    /// Open a stream on the default render device for the given role,
+    /// initialized with the given format and period, and start it.
+    pub fn new(role: &Role, format: &WaveFormat, period: i64, convert: bool) -> WasapiRes<Self> {
+        let default_changed = Arc::new(AtomicBool::new(false));
+        let flag = default_changed.clone();
+        let observer = DefaultDeviceObserver::new(&Direction::Render, role, move |_device| {
+            flag.store(true, Ordering::Release);
+        })?;
+        let mut stream = DefaultRenderStream {
+            role: *role,
+            format: format.clone(),
+            period,
+            convert,
+            handle: None,
+            clients: None,
+            default_changed,
+            _observer: observer,
+            switch_callback: None,
+        };
+        stream.open()?;
+        Ok(stream)
+    }
+
+    /// Set a callback that is called when the stream has switched to a new device.
+    /// The callback is called on the thread using the stream, before the first write to the new device.
+    pub fn set_switch_callback(&mut self, c: impl Fn(&StreamSwitch) + 'static) {
+        self.switch_callback = Some(Box::new(c));
+    }
+
+    /// Remove the callback for device switches.
+    pub fn unset_switch_callback(&mut self) {
+        self.switch_callback = None;
+    }
+
+    /// Get the id of the device the stream is currently playing on,
+    /// or `None` if there is no device, for example when the last device was removed.
+    pub fn get_device_id(&self) -> Option<&str> {
+        self.clients
+            .as_ref()
+            .map(|clients| clients.device_id.as_str())
+    }
+
+    /// Get the format of the stream.
+    pub fn get_format(&self) -> &WaveFormat {
+        &self.format
+    }
+
+    /// Wait for the buffer event of the current device, with a timeout given in ms.
+    /// Switches to a new device first if needed.
+    /// Returns an error on timeout, or if there is no device to play on.
+    pub fn wait_for_event(&mut self, timeout_ms: u32) -> WasapiRes<()> {
+        self.switch_if_needed()?;
+        match &self.handle {
+            Some(handle) => handle.wait_for_event(timeout_ms),
+            None => Err(WasapiError::new("No event handle").into()),
+        }
+    }
+
+    /// Write as many whole frames as fit in the device buffer, see [AudioRenderClient::write_available].
+    /// Switches to a new device first if needed.
+    /// If the device was removed during the write, the stream is switched to the new default device
+    /// and zero is returned, so that the same data can be written again.
+    pub fn write(&mut self, data: &[u8], buffer_flags: Option<BufferFlags>) -> WasapiRes<usize> {
+        self.switch_if_needed()?;
+        let clients = match &self.clients {
+            Some(clients) => clients,
+            None => return Ok(0),
+        };
+        match clients.render_client.write_available(data, buffer_flags) {
+            Ok(nbr_frames) => Ok(nbr_frames),
+            Err(err) => match recovery_action_for_error(&*err) {
+                Some(RecoveryAction::ReopenDevice)
+                | Some(RecoveryAction::SwitchToDefaultDevice) => {
+                    debug!("Render device was lost, switching to the default device");
+                    self.reopen()?;
+                    Ok(0)
+                }
+                _ => Err(err),
+            },
+        }
+    }
+
+    fn switch_if_needed(&mut self) -> WasapiRes<()> {
+        if self.default_changed.swap(false, Ordering::AcqRel) {
+            debug!("Default render device changed, switching");
+        } else if self.clients.is_none() {
+            debug!("No render device, trying to open the default device again");
+        } else {
+            return Ok(());
+        }
+        self.reopen()
+    }
+
+    fn reopen(&mut self) -> WasapiRes<()> {
+        let previous_mix_format = self.clients.take().map(|clients| {
+            if let Err(err) = clients.audio_client.stop_stream() {
+                debug!("Failed to stop the previous stream, {}", err);
+            }
+            clients.mix_format
+        });
+        self.open()?;
+        if let (Some(clients), Some(callback)) = (&self.clients, &self.switch_callback) {
+            let mix_format_changed = previous_mix_format
+                .map(|previous| !compare_formats(&previous, &clients.mix_format).is_identical())
+                .unwrap_or(true);
+            callback(&StreamSwitch {
+                device_id: clients.device_id.clone(),
+                mix_format: clients.mix_format.clone(),
+                mix_format_changed,
+            });
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> WasapiRes<()> {
+        let device = get_default_device_for_role(&Direction::Render, &self.role)?;
+        let device_id = device.get_id()?;
+        let mut audio_client = device.get_iaudioclient_for_role(&self.role)?;
+        let mix_format = audio_client.get_mixformat()?;
+        audio_client.initialize_client(
+            &self.format,
+            self.period,
+            &Direction::Render,
+            &ShareMode::Shared,
+            self.convert,
+        )?;
+        match &self.handle {
+            Some(handle) => audio_client.set_eventhandle(handle)?,
+            None => self.handle = Some(audio_client.set_get_eventhandle()?),
+        }
+        let render_client = audio_client.get_audiorenderclient()?;
+        audio_client.start_stream()?;
+        debug!("Opened default render stream on device {}", device_id);
+        self.clients = Some(StreamClients {
+            audio_client,
+            render_client,
+            device_id,
+            mix_format,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for DefaultRenderStream {
+    fn drop(&mut self) {
+        if let Some(clients) = &self.clients {
+            if let Err(err) = clients.audio_client.stop_stream() {
+                debug!("Failed to stop the stream, {}", err);
+            }
+        }
+    }
+}
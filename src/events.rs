@@ -1,31 +1,65 @@
 use std::rc::Weak;
 use std::slice;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{error, fmt};
 use widestring::U16CString;
 use windows::{
-    core::{implement, Result, GUID, PCWSTR},
+    core::{implement, Error, Result, GUID, PCWSTR},
     Win32::Foundation::BOOL,
+    Win32::Media::Audio::Endpoints::{
+        IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl,
+    },
     Win32::Media::Audio::{
-        AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateActive,
-        AudioSessionStateExpired, AudioSessionStateInactive, DisconnectReasonDeviceRemoval,
+        eCapture, eCommunications, eConsole, eMultimedia, eRender, AudioSessionDisconnectReason,
+        AudioSessionState, AudioSessionStateActive, AudioSessionStateExpired,
+        AudioSessionStateInactive, DisconnectReasonDeviceRemoval,
         DisconnectReasonExclusiveModeOverride, DisconnectReasonFormatChanged,
         DisconnectReasonServerShutdown, DisconnectReasonSessionDisconnected,
-        DisconnectReasonSessionLogoff, IAudioSessionEvents, IAudioSessionEvents_Impl,
+        DisconnectReasonSessionLogoff, EDataFlow, ERole, IAudioSessionControl, IAudioSessionEvents,
+        IAudioSessionEvents_Impl, IAudioSessionNotification, IAudioSessionNotification_Impl,
+        IAudioVolumeDuckNotification, IAudioVolumeDuckNotification_Impl, IMMNotificationClient,
+        IMMNotificationClient_Impl, AUDCLNT_E_DEVICE_INVALIDATED, AUDCLNT_E_DEVICE_IN_USE,
+        AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED, AUDCLNT_E_RESOURCES_INVALIDATED,
+        AUDCLNT_E_SERVICE_NOT_RUNNING, AUDCLNT_E_UNSUPPORTED_FORMAT,
+        AUDIO_VOLUME_NOTIFICATION_DATA, DEVICE_STATE, DEVICE_STATE_ACTIVE, DEVICE_STATE_DISABLED,
+        DEVICE_STATE_NOTPRESENT, DEVICE_STATE_UNPLUGGED,
     },
+    Win32::UI::Shell::PropertiesSystem::PROPERTYKEY,
 };
 
-use crate::SessionState;
+use crate::{
+    get_default_device, AudioSessionControl, DeviceRef, DeviceState, Direction, PropertyKey, Role,
+    SessionState, WasapiRes,
+};
 
 type OptionBox<T> = Option<Box<T>>;
 
-/// A structure holding the callbacks for notifications
+/// A callback that may be called from several threads, and is therefore kept behind a lock.
+type OptionCallback<T> = Option<Mutex<Box<T>>>;
+
+/// Lock a callback for calling it. A callback that panicked is still called.
+fn lock_callback<T: ?Sized>(callback: &Mutex<Box<T>>) -> MutexGuard<'_, Box<T>> {
+    match callback.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// A structure holding the callbacks for notifications.
+///
+/// The callbacks can be `FnMut` closures, for example for counting events.
+/// Each callback is kept behind a lock, so that it is never called by more than one thread at a time.
+/// Create it with [EventCallbacks::builder], or with [EventCallbacks::new] and the `set_..._callback` methods.
 pub struct EventCallbacks {
-    simple_volume: OptionBox<dyn Fn(f32, bool, GUID)>,
-    channel_volume: OptionBox<dyn Fn(usize, f32, GUID)>,
-    state: OptionBox<dyn Fn(SessionState)>,
-    disconnected: OptionBox<dyn Fn(DisconnectReason)>,
-    iconpath: OptionBox<dyn Fn(String, GUID)>,
-    displayname: OptionBox<dyn Fn(String, GUID)>,
-    groupingparam: OptionBox<dyn Fn(GUID, GUID)>,
+    simple_volume: OptionCallback<dyn FnMut(f32, bool, GUID)>,
+    channel_volume: OptionCallback<dyn FnMut(usize, f32, GUID)>,
+    state: OptionCallback<dyn FnMut(SessionStateEvent)>,
+    disconnected: OptionCallback<dyn FnMut(DisconnectEvent)>,
+    iconpath: OptionCallback<dyn FnMut(String, GUID)>,
+    displayname: OptionCallback<dyn FnMut(String, GUID)>,
+    groupingparam: OptionCallback<dyn FnMut(GUID, GUID)>,
 }
 
 impl Default for EventCallbacks {
@@ -48,9 +82,26 @@ impl EventCallbacks {
         }
     }
 
+    /// Create an [EventCallbacksBuilder], for setting the callbacks in a chain.
+    /// ```ignore
+    /// let mut nbr_changes = 0;
+    /// let callbacks = EventCallbacks::builder()
+    ///     .on_state(|event| println!("New state: {:?}", event.state))
+    ///     .on_volume(move |volume, mute, _context| {
+    ///         nbr_changes += 1;
+    ///         println!("Volume change {}: {}, mute {}", nbr_changes, volume, mute);
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn builder() -> EventCallbacksBuilder {
+        EventCallbacksBuilder {
+            callbacks: EventCallbacks::new(),
+        }
+    }
+
     /// Set a callback for OnSimpleVolumeChanged notifications
-    pub fn set_simple_volume_callback(&mut self, c: impl Fn(f32, bool, GUID) + 'static) {
-        self.simple_volume = Some(Box::new(c));
+    pub fn set_simple_volume_callback(&mut self, c: impl FnMut(f32, bool, GUID) + 'static) {
+        self.simple_volume = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnSimpleVolumeChanged notifications
     pub fn unset_simple_volume_callback(&mut self) {
@@ -58,8 +109,8 @@ impl EventCallbacks {
     }
 
     /// Set a callback for OnChannelVolumeChanged notifications
-    pub fn set_channel_volume_callback(&mut self, c: impl Fn(usize, f32, GUID) + 'static) {
-        self.channel_volume = Some(Box::new(c));
+    pub fn set_channel_volume_callback(&mut self, c: impl FnMut(usize, f32, GUID) + 'static) {
+        self.channel_volume = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnChannelVolumeChanged notifications
     pub fn unset_channel_volume_callback(&mut self) {
@@ -67,8 +118,8 @@ impl EventCallbacks {
     }
 
     /// Set a callback for OnSessionDisconnected notifications
-    pub fn set_disconnected_callback(&mut self, c: impl Fn(DisconnectReason) + 'static) {
-        self.disconnected = Some(Box::new(c));
+    pub fn set_disconnected_callback(&mut self, c: impl FnMut(DisconnectEvent) + 'static) {
+        self.disconnected = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnSessionDisconnected notifications
     pub fn unset_disconnected_callback(&mut self) {
@@ -76,8 +127,8 @@ impl EventCallbacks {
     }
 
     /// Set a callback for OnStateChanged notifications
-    pub fn set_state_callback(&mut self, c: impl Fn(SessionState) + 'static) {
-        self.state = Some(Box::new(c));
+    pub fn set_state_callback(&mut self, c: impl FnMut(SessionStateEvent) + 'static) {
+        self.state = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnStateChanged notifications
     pub fn unset_state_callback(&mut self) {
@@ -85,8 +136,8 @@ impl EventCallbacks {
     }
 
     /// Set a callback for OnIconPathChanged notifications
-    pub fn set_iconpath_callback(&mut self, c: impl Fn(String, GUID) + 'static) {
-        self.iconpath = Some(Box::new(c));
+    pub fn set_iconpath_callback(&mut self, c: impl FnMut(String, GUID) + 'static) {
+        self.iconpath = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnIconPathChanged notifications
     pub fn unset_iconpath_callback(&mut self) {
@@ -94,8 +145,8 @@ impl EventCallbacks {
     }
 
     /// Set a callback for OnDisplayNameChanged notifications
-    pub fn set_displayname_callback(&mut self, c: impl Fn(String, GUID) + 'static) {
-        self.displayname = Some(Box::new(c));
+    pub fn set_displayname_callback(&mut self, c: impl FnMut(String, GUID) + 'static) {
+        self.displayname = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnDisplayNameChanged notifications
     pub fn unset_displayname_callback(&mut self) {
@@ -103,8 +154,8 @@ impl EventCallbacks {
     }
 
     /// Set a callback for OnGroupingParamChanged notifications
-    pub fn set_groupingparam_callback(&mut self, c: impl Fn(GUID, GUID) + 'static) {
-        self.groupingparam = Some(Box::new(c));
+    pub fn set_groupingparam_callback(&mut self, c: impl FnMut(GUID, GUID) + 'static) {
+        self.groupingparam = Some(Mutex::new(Box::new(c)));
     }
     /// Remove a callback for OnGroupingParamChanged notifications
     pub fn unset_groupingparam_callback(&mut self) {
@@ -112,9 +163,236 @@ impl EventCallbacks {
     }
 }
 
+/// Builder for [EventCallbacks], see [EventCallbacks::builder].
+/// Callbacks that are not set are left empty.
+pub struct EventCallbacksBuilder {
+    callbacks: EventCallbacks,
+}
+
+impl EventCallbacksBuilder {
+    /// Set a callback for OnSimpleVolumeChanged notifications,
+    /// called with the new volume, the mute state and the event context.
+    pub fn on_volume(mut self, c: impl FnMut(f32, bool, GUID) + 'static) -> Self {
+        self.callbacks.set_simple_volume_callback(c);
+        self
+    }
+
+    /// Set a callback for OnChannelVolumeChanged notifications,
+    /// called with the channel index, the new volume of the channel and the event context.
+    pub fn on_channel_volume(mut self, c: impl FnMut(usize, f32, GUID) + 'static) -> Self {
+        self.callbacks.set_channel_volume_callback(c);
+        self
+    }
+
+    /// Set a callback for OnStateChanged notifications.
+    pub fn on_state(mut self, c: impl FnMut(SessionStateEvent) + 'static) -> Self {
+        self.callbacks.set_state_callback(c);
+        self
+    }
+
+    /// Set a callback for OnSessionDisconnected notifications.
+    pub fn on_disconnected(mut self, c: impl FnMut(DisconnectEvent) + 'static) -> Self {
+        self.callbacks.set_disconnected_callback(c);
+        self
+    }
+
+    /// Set a callback for OnIconPathChanged notifications,
+    /// called with the new icon path and the event context.
+    pub fn on_icon_path(mut self, c: impl FnMut(String, GUID) + 'static) -> Self {
+        self.callbacks.set_iconpath_callback(c);
+        self
+    }
+
+    /// Set a callback for OnDisplayNameChanged notifications,
+    /// called with the new display name and the event context.
+    pub fn on_display_name(mut self, c: impl FnMut(String, GUID) + 'static) -> Self {
+        self.callbacks.set_displayname_callback(c);
+        self
+    }
+
+    /// Set a callback for OnGroupingParamChanged notifications,
+    /// called with the new grouping parameter and the event context.
+    pub fn on_grouping_param(mut self, c: impl FnMut(GUID, GUID) + 'static) -> Self {
+        self.callbacks.set_groupingparam_callback(c);
+        self
+    }
+
+    /// Get the finished [EventCallbacks].
+    pub fn build(self) -> EventCallbacks {
+        self.callbacks
+    }
+}
+
+/// A structure holding the callbacks for device notifications, see [DeviceNotifications](crate::DeviceNotifications).
+/// The devices are given to the callbacks as [DeviceRef] values,
+/// that can be opened with [DeviceRef::open] without enumerating the devices.
+/// The callbacks are called from threads owned by the system, possibly several at once,
+/// and must therefore be both `Send` and `Sync`.
+pub struct DeviceNotificationCallbacks {
+    device_added: OptionBox<dyn Fn(DeviceRef) + Send + Sync>,
+    device_removed: OptionBox<dyn Fn(DeviceRef) + Send + Sync>,
+    device_state: OptionBox<dyn Fn(DeviceRef, DeviceState) + Send + Sync>,
+    default_device: OptionBox<dyn Fn(Direction, Role, Option<DeviceRef>) + Send + Sync>,
+    property_value: OptionBox<dyn Fn(DeviceRef, GUID, u32) + Send + Sync>,
+    property_filter: Option<Vec<PropertyKey>>,
+}
+
+impl Default for DeviceNotificationCallbacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceNotificationCallbacks {
+    /// Create a new DeviceNotificationCallbacks with no callbacks set
+    pub fn new() -> Self {
+        Self {
+            device_added: None,
+            device_removed: None,
+            device_state: None,
+            default_device: None,
+            property_value: None,
+            property_filter: None,
+        }
+    }
+
+    /// Set a callback for OnDeviceAdded notifications, called with the new device
+    pub fn set_device_added_callback(&mut self, c: impl Fn(DeviceRef) + Send + Sync + 'static) {
+        self.device_added = Some(Box::new(c));
+    }
+    /// Remove a callback for OnDeviceAdded notifications
+    pub fn unset_device_added_callback(&mut self) {
+        self.device_added = None;
+    }
+
+    /// Set a callback for OnDeviceRemoved notifications, called with the removed device
+    pub fn set_device_removed_callback(&mut self, c: impl Fn(DeviceRef) + Send + Sync + 'static) {
+        self.device_removed = Some(Box::new(c));
+    }
+    /// Remove a callback for OnDeviceRemoved notifications
+    pub fn unset_device_removed_callback(&mut self) {
+        self.device_removed = None;
+    }
+
+    /// Set a callback for OnDeviceStateChanged notifications, called with the device and its new state
+    pub fn set_device_state_callback(
+        &mut self,
+        c: impl Fn(DeviceRef, DeviceState) + Send + Sync + 'static,
+    ) {
+        self.device_state = Some(Box::new(c));
+    }
+    /// Remove a callback for OnDeviceStateChanged notifications
+    pub fn unset_device_state_callback(&mut self) {
+        self.device_state = None;
+    }
+
+    /// Set a callback for OnDefaultDeviceChanged notifications,
+    /// called with the direction, the role and the new default device,
+    /// or `None` if there is no longer any default device.
+    /// Windows sends a separate notification for each role.
+    pub fn set_default_device_callback(
+        &mut self,
+        c: impl Fn(Direction, Role, Option<DeviceRef>) + Send + Sync + 'static,
+    ) {
+        self.default_device = Some(Box::new(c));
+    }
+    /// Remove a callback for OnDefaultDeviceChanged notifications
+    pub fn unset_default_device_callback(&mut self) {
+        self.default_device = None;
+    }
+
+    /// Set a callback for OnPropertyValueChanged notifications,
+    /// called with the device and the format id and property id of the changed property
+    pub fn set_property_value_callback(
+        &mut self,
+        c: impl Fn(DeviceRef, GUID, u32) + Send + Sync + 'static,
+    ) {
+        self.property_value = Some(Box::new(c));
+    }
+    /// Remove a callback for OnPropertyValueChanged notifications
+    pub fn unset_property_value_callback(&mut self) {
+        self.property_value = None;
+    }
+
+    /// Only forward OnPropertyValueChanged notifications for the given property keys,
+    /// for example only `PKEY_AudioEngine_DeviceFormat`.
+    /// Some devices, in particular Bluetooth devices, change other properties very often.
+    /// The notifications for other keys are dropped before the callback is called.
+    pub fn set_property_filter(&mut self, keys: &[PropertyKey]) {
+        self.property_filter = Some(keys.to_vec());
+    }
+    /// Remove the property key filter, and forward the notifications for all keys
+    pub fn unset_property_filter(&mut self) {
+        self.property_filter = None;
+    }
+}
+
+/// A structure holding the callbacks for ducking notifications,
+/// see [AudioSessionManager2::register_duck_notification](crate::AudioSessionManager2::register_duck_notification).
+/// The session ids given to the callbacks are session instance identifiers,
+/// see [AudioSessionControl2::get_session_instance_identifier](crate::AudioSessionControl2::get_session_instance_identifier).
+/// The callbacks may be called from several system threads at once, and must be both `Send` and `Sync`.
+pub struct DuckNotificationCallbacks {
+    volume_duck: OptionBox<dyn Fn(String, u32) + Send + Sync>,
+    volume_unduck: OptionBox<dyn Fn(String) + Send + Sync>,
+}
+
+impl Default for DuckNotificationCallbacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DuckNotificationCallbacks {
+    /// Create a new DuckNotificationCallbacks with no callbacks set
+    pub fn new() -> Self {
+        Self {
+            volume_duck: None,
+            volume_unduck: None,
+        }
+    }
+
+    /// Set a callback for OnVolumeDuckNotification notifications,
+    /// sent when a communication stream is opened and the system is about to duck other streams.
+    /// Called with the instance identifier of the communication session,
+    /// and the number of active communication sessions.
+    pub fn set_volume_duck_callback(&mut self, c: impl Fn(String, u32) + Send + Sync + 'static) {
+        self.volume_duck = Some(Box::new(c));
+    }
+    /// Remove a callback for OnVolumeDuckNotification notifications
+    pub fn unset_volume_duck_callback(&mut self) {
+        self.volume_duck = None;
+    }
+
+    /// Set a callback for OnVolumeUnduckNotification notifications,
+    /// sent when a communication stream is closed and the ducked streams are restored.
+    /// Called with the instance identifier of the communication session.
+    pub fn set_volume_unduck_callback(&mut self, c: impl Fn(String) + Send + Sync + 'static) {
+        self.volume_unduck = Some(Box::new(c));
+    }
+    /// Remove a callback for OnVolumeUnduckNotification notifications
+    pub fn unset_volume_unduck_callback(&mut self) {
+        self.volume_unduck = None;
+    }
+}
+
+/// Notification of a change of the volume or mute state of an endpoint device,
+/// see [AudioEndpointVolume::register_volume_notification](crate::AudioEndpointVolume::register_volume_notification).
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointVolumeEvent {
+    /// The new master volume, as a value between 0.0 and 1.0.
+    pub master_volume: f32,
+    /// The new mute state.
+    pub mute: bool,
+    /// The new volumes of the channels, as values between 0.0 and 1.0.
+    pub channel_volumes: Vec<f32>,
+    /// The event context given by the application that made the change.
+    pub event_context: GUID,
+}
+
 /// Reason for session disconnect, an enum representing the `DisconnectReasonXxx` values of the
 /// [AudioSessionDisconnectReason enum](https://learn.microsoft.com/en-us/windows/win32/api/audiopolicy/nf-audiopolicy-iaudiosessionevents-onsessiondisconnected)
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum DisconnectReason {
     /// The user removed the audio endpoint device.
     DeviceRemoval,
@@ -132,16 +410,344 @@ pub enum DisconnectReason {
     Unknown,
 }
 
+impl DisconnectReason {
+    /// Get the recommended [RecoveryAction] for a session that was disconnected for this reason.
+    pub fn recovery_action(&self) -> RecoveryAction {
+        match self {
+            DisconnectReason::DeviceRemoval => RecoveryAction::SwitchToDefaultDevice,
+            DisconnectReason::ServerShutdown => RecoveryAction::RetryLater,
+            DisconnectReason::FormatChanged => RecoveryAction::Reinitialize,
+            DisconnectReason::ExclusiveModeOverride => RecoveryAction::RetryLater,
+            DisconnectReason::SessionLogoff
+            | DisconnectReason::SessionDisconnected
+            | DisconnectReason::Unknown => RecoveryAction::Stop,
+        }
+    }
+}
+
+/// Recommended action for recovering a stream after a disconnect or an error,
+/// see [DisconnectReason::recovery_action] and [recovery_action_for_error].
+///
+/// A lost device gives the same action from both,
+/// [DisconnectReason::DeviceRemoval] and the `AUDCLNT_E_DEVICE_INVALIDATED` error
+/// both map to [RecoveryAction::SwitchToDefaultDevice].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecoveryAction {
+    /// Create a new client on the same device, with the same format.
+    /// This is recommended for `AUDCLNT_E_RESOURCES_INVALIDATED`, when the device is still there
+    /// but the stream was lost, for example after the system resumed from sleep.
+    ReopenDevice,
+    /// The device is gone. Open a client on the new default device.
+    /// An application that is tied to a specific device can instead try to open that device again,
+    /// since the error is also returned when the device was reconfigured or disabled.
+    SwitchToDefaultDevice,
+    /// Read the new mix format of the same device, and create a new client with a matching format.
+    Reinitialize,
+    /// The device or the audio service is temporarily unavailable.
+    /// Wait a while and then try to open the same device again.
+    RetryLater,
+    /// The stream can't be recovered automatically.
+    Stop,
+}
+
+impl fmt::Display for RecoveryAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RecoveryAction::ReopenDevice => write!(f, "ReopenDevice"),
+            RecoveryAction::SwitchToDefaultDevice => write!(f, "SwitchToDefaultDevice"),
+            RecoveryAction::Reinitialize => write!(f, "Reinitialize"),
+            RecoveryAction::RetryLater => write!(f, "RetryLater"),
+            RecoveryAction::Stop => write!(f, "Stop"),
+        }
+    }
+}
+
+/// Get the recommended [RecoveryAction] for an error returned by one of the stream methods,
+/// such as [AudioRenderClient::write_to_device](crate::AudioRenderClient::write_to_device).
+/// Returns `None` if the error is not one of the errors that signal a lost stream.
+pub fn recovery_action_for_error(err: &(dyn error::Error + 'static)) -> Option<RecoveryAction> {
+    let hresult = err.downcast_ref::<Error>()?.code();
+    match hresult {
+        AUDCLNT_E_DEVICE_INVALIDATED => Some(RecoveryAction::SwitchToDefaultDevice),
+        AUDCLNT_E_RESOURCES_INVALIDATED => Some(RecoveryAction::ReopenDevice),
+        AUDCLNT_E_UNSUPPORTED_FORMAT => Some(RecoveryAction::Reinitialize),
+        AUDCLNT_E_SERVICE_NOT_RUNNING => Some(RecoveryAction::RetryLater),
+        AUDCLNT_E_DEVICE_IN_USE => Some(RecoveryAction::RetryLater),
+        AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED => Some(RecoveryAction::Stop),
+        _ => None,
+    }
+}
+
+/// Wait until the audio service is available, for example after the system resumed from sleep
+/// or the service was restarted.
+///
+/// This tries to read the mix format of the default device for the given direction,
+/// at the given interval, until it succeeds or the timeout expires.
+/// Returns `true` when the service is available, and `false` on timeout.
+/// Errors other than `AUDCLNT_E_SERVICE_NOT_RUNNING` are returned,
+/// including the error returned when there is no default device.
+pub fn wait_for_audio_service(
+    direction: &Direction,
+    timeout: Duration,
+    interval: Duration,
+) -> WasapiRes<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let result = get_default_device(direction)
+            .and_then(|device| device.get_iaudioclient())
+            .and_then(|client| client.get_mixformat());
+        match result {
+            Ok(_) => return Ok(true),
+            Err(err) => {
+                let not_running = err
+                    .downcast_ref::<Error>()
+                    .map(|werr| werr.code() == AUDCLNT_E_SERVICE_NOT_RUNNING)
+                    .unwrap_or(false);
+                if !not_running {
+                    return Err(err);
+                }
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        debug!("Audio service is not running, retrying");
+        thread::sleep(interval.min(deadline - now));
+    }
+}
+
+/// Notification of a session state change, passed to the callback set with
+/// [EventCallbacks::set_state_callback].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SessionStateEvent {
+    /// The new state of the session.
+    pub state: SessionState,
+    /// The identifier of the session, if it could be read.
+    pub session_id: Option<String>,
+    /// The Id of the device the session belongs to, if known.
+    pub device_id: Option<String>,
+    /// The direction of the device the session belongs to.
+    pub direction: Direction,
+}
+
+/// Notification of a session disconnect, passed to the callback set with
+/// [EventCallbacks::set_disconnected_callback].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DisconnectEvent {
+    /// The reason for the disconnect.
+    pub reason: DisconnectReason,
+    /// The identifier of the session, if it could be read.
+    pub session_id: Option<String>,
+    /// The Id of the device the session belongs to, if known.
+    pub device_id: Option<String>,
+    /// The direction of the device the session belongs to.
+    pub direction: Direction,
+}
+
+/// Polling alternative to [EventCallbacks::set_state_callback], for when registering COM callbacks is not possible.
+///
+/// The tracker reads the state of the session with [AudioSessionControl::get_state],
+/// and reports each change as a [SessionStateEvent], the same as the callback receives.
+pub struct SessionStateTracker {
+    control: AudioSessionControl,
+    interval: Duration,
+    session_id: Option<String>,
+    last_state: Option<SessionState>,
+}
+
+impl SessionStateTracker {
+    /// Create a new [SessionStateTracker] for a session.
+    /// The `interval` is the time between polls in [SessionStateTracker::wait_for_change].
+    /// The current state is read on the first poll, and is not reported as a change.
+    pub fn new(control: AudioSessionControl, interval: Duration) -> Self {
+        let session_id = control.get_session_identifier().ok();
+        SessionStateTracker {
+            control,
+            interval,
+            session_id,
+            last_state: None,
+        }
+    }
+
+    /// Read the state once, and return a [SessionStateEvent] if it changed since the previous poll.
+    pub fn poll(&mut self) -> WasapiRes<Option<SessionStateEvent>> {
+        let state = self.control.get_state()?;
+        let previous = self.last_state.replace(state);
+        match previous {
+            Some(previous) if previous != state => {
+                trace!("Session state changed from {} to {}", previous, state);
+                Ok(Some(SessionStateEvent {
+                    state,
+                    session_id: self.session_id.clone(),
+                    device_id: self.control.get_device_id().map(|id| id.to_string()),
+                    direction: self.control.get_direction(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Poll the state at the configured interval until it changes, or the timeout expires.
+    /// Returns `None` on timeout.
+    pub fn wait_for_change(&mut self, timeout: Duration) -> WasapiRes<Option<SessionStateEvent>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.poll()? {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(self.interval.min(deadline - now));
+        }
+    }
+
+    /// Get the last polled state, or None if the state has not been polled yet.
+    pub fn get_last_state(&self) -> Option<SessionState> {
+        self.last_state
+    }
+
+    /// Get the [AudioSessionControl] of the tracked session.
+    pub fn get_session_control(&self) -> &AudioSessionControl {
+        &self.control
+    }
+}
+
+/// A session notification, as delivered by
+/// [AudioSessionControl::register_session_notification_channel](crate::AudioSessionControl::register_session_notification_channel).
+/// The event context GUIDs are the ones given by the application that made the change.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionEvent {
+    /// The session state changed.
+    StateChanged(SessionStateEvent),
+    /// The session was disconnected.
+    Disconnected(DisconnectEvent),
+    /// The volume or mute state of the session changed.
+    SimpleVolumeChanged {
+        volume: f32,
+        mute: bool,
+        event_context: GUID,
+    },
+    /// The volume of a channel of the session changed.
+    ChannelVolumeChanged {
+        channel: usize,
+        volume: f32,
+        event_context: GUID,
+    },
+    /// The display name of the session changed.
+    DisplayNameChanged { name: String, event_context: GUID },
+    /// The icon path of the session changed.
+    IconPathChanged { path: String, event_context: GUID },
+    /// The grouping parameter of the session changed.
+    GroupingParamChanged {
+        grouping_param: GUID,
+        event_context: GUID,
+    },
+}
+
+/// Receiver of the notifications of an [AudioSessionEvents].
+pub(crate) enum SessionEventSink {
+    /// The callbacks registered with [AudioSessionControl::register_session_notification].
+    /// These are called as long as the callbacks are alive.
+    Callbacks(Weak<EventCallbacks>),
+    /// A callback receiving all notifications as [SessionEvent] values.
+    /// It can be called from several system threads at once, and must be `Sync`.
+    Forward(Box<dyn Fn(SessionEvent) + Send + Sync>),
+}
+
 /// Wrapper for [IAudioSessionEvents](https://docs.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessionevents).
 #[implement(IAudioSessionEvents)]
 pub(crate) struct AudioSessionEvents {
-    callbacks: Weak<EventCallbacks>,
+    sink: SessionEventSink,
+    session_id: Option<String>,
+    device_id: Option<String>,
+    direction: Direction,
 }
 
 impl AudioSessionEvents {
     /// Create a new [AudioSessionEvents] instance, returned as a [IAudioSessionEvent].
-    pub fn new(callbacks: Weak<EventCallbacks>) -> Self {
-        Self { callbacks }
+    pub fn new(
+        sink: SessionEventSink,
+        session_id: Option<String>,
+        device_id: Option<String>,
+        direction: Direction,
+    ) -> Self {
+        Self {
+            sink,
+            session_id,
+            device_id,
+            direction,
+        }
+    }
+
+    /// Pass an event on to the sink.
+    fn dispatch(&self, event: SessionEvent) {
+        let callbacks = match &self.sink {
+            SessionEventSink::Forward(callback) => {
+                callback(event);
+                return;
+            }
+            SessionEventSink::Callbacks(callbacks) => match callbacks.upgrade() {
+                Some(callbacks) => callbacks,
+                None => return,
+            },
+        };
+        match event {
+            SessionEvent::StateChanged(event) => {
+                if let Some(callback) = &callbacks.state {
+                    (*lock_callback(callback))(event);
+                }
+            }
+            SessionEvent::Disconnected(event) => {
+                if let Some(callback) = &callbacks.disconnected {
+                    (*lock_callback(callback))(event);
+                }
+            }
+            SessionEvent::SimpleVolumeChanged {
+                volume,
+                mute,
+                event_context,
+            } => {
+                if let Some(callback) = &callbacks.simple_volume {
+                    (*lock_callback(callback))(volume, mute, event_context);
+                }
+            }
+            SessionEvent::ChannelVolumeChanged {
+                channel,
+                volume,
+                event_context,
+            } => {
+                if let Some(callback) = &callbacks.channel_volume {
+                    (*lock_callback(callback))(channel, volume, event_context);
+                }
+            }
+            SessionEvent::DisplayNameChanged {
+                name,
+                event_context,
+            } => {
+                if let Some(callback) = &callbacks.displayname {
+                    (*lock_callback(callback))(name, event_context);
+                }
+            }
+            SessionEvent::IconPathChanged {
+                path,
+                event_context,
+            } => {
+                if let Some(callback) = &callbacks.iconpath {
+                    (*lock_callback(callback))(path, event_context);
+                }
+            }
+            SessionEvent::GroupingParamChanged {
+                grouping_param,
+                event_context,
+            } => {
+                if let Some(callback) = &callbacks.groupingparam {
+                    (*lock_callback(callback))(grouping_param, event_context);
+                }
+            }
+        }
     }
 }
 
@@ -162,11 +768,12 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
             AudioSessionStateExpired => SessionState::Expired,
             _ => return Ok(()),
         };
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.state {
-                callback(sessionstate);
-            }
-        }
+        self.dispatch(SessionEvent::StateChanged(SessionStateEvent {
+            state: sessionstate,
+            session_id: self.session_id.clone(),
+            device_id: self.device_id.clone(),
+            direction: self.direction,
+        }));
         Ok(())
     }
 
@@ -182,12 +789,12 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
             DisconnectReasonExclusiveModeOverride => DisconnectReason::ExclusiveModeOverride,
             _ => DisconnectReason::Unknown,
         };
-
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.disconnected {
-                callback(reason);
-            }
-        }
+        self.dispatch(SessionEvent::Disconnected(DisconnectEvent {
+            reason,
+            session_id: self.session_id.clone(),
+            device_id: self.device_id.clone(),
+            direction: self.direction,
+        }));
         Ok(())
     }
 
@@ -199,12 +806,11 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
         let wide_name = unsafe { U16CString::from_ptr_str(newdisplayname.0) };
         let name = wide_name.to_string_lossy();
         trace!("New display name: {}", name);
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.displayname {
-                let context = unsafe { *eventcontext };
-                callback(name, context);
-            }
-        }
+        let event_context = unsafe { *eventcontext };
+        self.dispatch(SessionEvent::DisplayNameChanged {
+            name,
+            event_context,
+        });
         Ok(())
     }
 
@@ -212,12 +818,11 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
         let wide_path = unsafe { U16CString::from_ptr_str(newiconpath.0) };
         let path = wide_path.to_string_lossy();
         trace!("New icon path: {}", path);
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.iconpath {
-                let context = unsafe { *eventcontext };
-                callback(path, context);
-            }
-        }
+        let event_context = unsafe { *eventcontext };
+        self.dispatch(SessionEvent::IconPathChanged {
+            path,
+            event_context,
+        });
         Ok(())
     }
 
@@ -228,12 +833,12 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
         eventcontext: *const GUID,
     ) -> Result<()> {
         trace!("New volume: {}, mute: {:?}", newvolume, newmute);
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.simple_volume {
-                let context = unsafe { *eventcontext };
-                callback(newvolume, bool::from(newmute), context);
-            }
-        }
+        let event_context = unsafe { *eventcontext };
+        self.dispatch(SessionEvent::SimpleVolumeChanged {
+            volume: newvolume,
+            mute: bool::from(newmute),
+            event_context,
+        });
         Ok(())
     }
 
@@ -247,28 +852,29 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
         trace!("New channel volume for channel: {}", changedchannel);
         let volslice =
             unsafe { slice::from_raw_parts(newchannelvolumearray, channelcount as usize) };
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.channel_volume {
-                let context = unsafe { *eventcontext };
-                if changedchannel == u32::MAX {
-                    // special meaning by specs: (DWORD)(-1) - "more than one channel have changed"
-                    // using all channels
-                    for (idx, newvol) in volslice.iter().enumerate() {
-                        callback(idx, *newvol, context);
-                    }
-                }
-                if (changedchannel as usize) < volslice.len() {
-                    let newvol = volslice[changedchannel as usize];
-                    callback(changedchannel as usize, newvol, context);
-                } else {
-                    warn!(
-                        "OnChannelVolumeChanged: received unsupported changedchannel value {} for volume array length of {}",
-                        changedchannel,
-                        volslice.len()
-                    );
-                    return Ok(());
-                }
+        let event_context = unsafe { *eventcontext };
+        if changedchannel == u32::MAX {
+            // special meaning by specs: (DWORD)(-1) - "more than one channel have changed"
+            // using all channels
+            for (channel, volume) in volslice.iter().enumerate() {
+                self.dispatch(SessionEvent::ChannelVolumeChanged {
+                    channel,
+                    volume: *volume,
+                    event_context,
+                });
             }
+        } else if (changedchannel as usize) < volslice.len() {
+            self.dispatch(SessionEvent::ChannelVolumeChanged {
+                channel: changedchannel as usize,
+                volume: volslice[changedchannel as usize],
+                event_context,
+            });
+        } else {
+            warn!(
+                "OnChannelVolumeChanged: received unsupported changedchannel value {} for volume array length of {}",
+                changedchannel,
+                volslice.len()
+            );
         }
         Ok(())
     }
@@ -279,13 +885,243 @@ impl IAudioSessionEvents_Impl for AudioSessionEvents {
         eventcontext: *const GUID,
     ) -> Result<()> {
         trace!("Grouping changed");
-        if let Some(callbacks) = &mut self.callbacks.upgrade() {
-            if let Some(callback) = &callbacks.groupingparam {
-                let context = unsafe { *eventcontext };
-                let grouping = unsafe { *newgroupingparam };
-                callback(grouping, context);
+        let event_context = unsafe { *eventcontext };
+        let grouping_param = unsafe { *newgroupingparam };
+        self.dispatch(SessionEvent::GroupingParamChanged {
+            grouping_param,
+            event_context,
+        });
+        Ok(())
+    }
+}
+
+/// Convert a device id from a notification to a String.
+fn device_id_to_string(pwstrdeviceid: &PCWSTR) -> Option<String> {
+    if pwstrdeviceid.is_null() {
+        return None;
+    }
+    let wide_id = unsafe { U16CString::from_ptr_str(pwstrdeviceid.0) };
+    Some(wide_id.to_string_lossy())
+}
+
+/// Convert a device id from a notification to a [DeviceRef].
+fn device_id_to_ref(pwstrdeviceid: &PCWSTR) -> Option<DeviceRef> {
+    device_id_to_string(pwstrdeviceid).map(|id| DeviceRef::new(&id))
+}
+
+/// Wrapper for [IMMNotificationClient](https://learn.microsoft.com/en-us/windows/win32/api/mmdeviceapi/nn-mmdeviceapi-immnotificationclient)
+/// that forwards all notifications to a [DeviceNotificationCallbacks].
+#[implement(IMMNotificationClient)]
+pub(crate) struct DeviceNotifier {
+    callbacks: DeviceNotificationCallbacks,
+}
+
+impl DeviceNotifier {
+    /// Create a new [DeviceNotifier] instance, returned as a [IMMNotificationClient].
+    pub fn new(callbacks: DeviceNotificationCallbacks) -> Self {
+        Self { callbacks }
+    }
+}
+
+impl IMMNotificationClient_Impl for DeviceNotifier {
+    fn OnDeviceStateChanged(&self, pwstrdeviceid: &PCWSTR, dwnewstate: DEVICE_STATE) -> Result<()> {
+        let state = match dwnewstate {
+            DEVICE_STATE_ACTIVE => DeviceState::Active,
+            DEVICE_STATE_DISABLED => DeviceState::Disabled,
+            DEVICE_STATE_NOTPRESENT => DeviceState::NotPresent,
+            DEVICE_STATE_UNPLUGGED => DeviceState::Unplugged,
+            _ => {
+                warn!("Got an illegal state: {:?}", dwnewstate);
+                return Ok(());
+            }
+        };
+        if let (Some(callback), Some(id)) = (
+            &self.callbacks.device_state,
+            device_id_to_ref(pwstrdeviceid),
+        ) {
+            trace!("Device {} changed state to {:?}", id, state);
+            callback(id, state);
+        }
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, pwstrdeviceid: &PCWSTR) -> Result<()> {
+        if let (Some(callback), Some(id)) = (
+            &self.callbacks.device_added,
+            device_id_to_ref(pwstrdeviceid),
+        ) {
+            trace!("Device {} added", id);
+            callback(id);
+        }
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, pwstrdeviceid: &PCWSTR) -> Result<()> {
+        if let (Some(callback), Some(id)) = (
+            &self.callbacks.device_removed,
+            device_id_to_ref(pwstrdeviceid),
+        ) {
+            trace!("Device {} removed", id);
+            callback(id);
+        }
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(
+        &self,
+        flow: EDataFlow,
+        role: ERole,
+        pwstrdefaultdeviceid: &PCWSTR,
+    ) -> Result<()> {
+        let callback = match &self.callbacks.default_device {
+            Some(callback) => callback,
+            None => return Ok(()),
+        };
+        #[allow(non_upper_case_globals)]
+        let direction = match flow {
+            eRender => Direction::Render,
+            eCapture => Direction::Capture,
+            _ => return Ok(()),
+        };
+        #[allow(non_upper_case_globals)]
+        let role = match role {
+            eConsole => Role::Console,
+            eMultimedia => Role::Multimedia,
+            eCommunications => Role::Communications,
+            _ => return Ok(()),
+        };
+        let device = device_id_to_ref(pwstrdefaultdeviceid);
+        trace!(
+            "New default {} device for {}: {:?}",
+            direction,
+            role,
+            device
+        );
+        callback(direction, role, device);
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, pwstrdeviceid: &PCWSTR, key: &PROPERTYKEY) -> Result<()> {
+        if let Some(filter) = &self.callbacks.property_filter {
+            if !filter.contains(&PropertyKey::from(*key)) {
+                return Ok(());
             }
         }
+        if let (Some(callback), Some(id)) = (
+            &self.callbacks.property_value,
+            device_id_to_ref(pwstrdeviceid),
+        ) {
+            callback(id, key.fmtid, key.pid);
+        }
+        Ok(())
+    }
+}
+
+/// Wrapper for [IAudioSessionNotification](https://learn.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiosessionnotification)
+/// that forwards new sessions to a callback.
+#[implement(IAudioSessionNotification)]
+pub(crate) struct SessionCreatedNotifier {
+    callback: Box<dyn Fn(IAudioSessionControl) + Send + Sync>,
+}
+
+impl SessionCreatedNotifier {
+    /// Create a new [SessionCreatedNotifier] instance, returned as a [IAudioSessionNotification].
+    pub fn new(callback: Box<dyn Fn(IAudioSessionControl) + Send + Sync>) -> Self {
+        Self { callback }
+    }
+}
+
+impl IAudioSessionNotification_Impl for SessionCreatedNotifier {
+    fn OnSessionCreated(&self, newsession: Option<&IAudioSessionControl>) -> Result<()> {
+        if let Some(session) = newsession {
+            trace!("New audio session created");
+            (self.callback)(session.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Wrapper for [IAudioVolumeDuckNotification](https://learn.microsoft.com/en-us/windows/win32/api/audiopolicy/nn-audiopolicy-iaudiovolumeducknotification)
+/// that forwards the notifications to a [DuckNotificationCallbacks].
+#[implement(IAudioVolumeDuckNotification)]
+pub(crate) struct DuckNotifier {
+    callbacks: DuckNotificationCallbacks,
+}
+
+impl DuckNotifier {
+    /// Create a new [DuckNotifier] instance, returned as a [IAudioVolumeDuckNotification].
+    pub fn new(callbacks: DuckNotificationCallbacks) -> Self {
+        Self { callbacks }
+    }
+}
+
+impl IAudioVolumeDuckNotification_Impl for DuckNotifier {
+    fn OnVolumeDuckNotification(
+        &self,
+        sessionid: &PCWSTR,
+        countcommunicationsessions: u32,
+    ) -> Result<()> {
+        if let (Some(callback), Some(id)) =
+            (&self.callbacks.volume_duck, device_id_to_string(sessionid))
+        {
+            trace!(
+                "Volume duck for session {}, {} communication sessions",
+                id,
+                countcommunicationsessions
+            );
+            callback(id, countcommunicationsessions);
+        }
+        Ok(())
+    }
+
+    fn OnVolumeUnduckNotification(&self, sessionid: &PCWSTR) -> Result<()> {
+        if let (Some(callback), Some(id)) = (
+            &self.callbacks.volume_unduck,
+            device_id_to_string(sessionid),
+        ) {
+            trace!("Volume unduck for session {}", id);
+            callback(id);
+        }
+        Ok(())
+    }
+}
+
+/// Wrapper for [IAudioEndpointVolumeCallback](https://learn.microsoft.com/en-us/windows/win32/api/endpointvolume/nn-endpointvolume-iaudioendpointvolumecallback)
+/// that forwards the notifications to a callback.
+#[implement(IAudioEndpointVolumeCallback)]
+pub(crate) struct EndpointVolumeNotifier {
+    callback: Box<dyn Fn(EndpointVolumeEvent) + Send + Sync>,
+}
+
+impl EndpointVolumeNotifier {
+    /// Create a new [EndpointVolumeNotifier] instance, returned as a [IAudioEndpointVolumeCallback].
+    pub fn new(callback: Box<dyn Fn(EndpointVolumeEvent) + Send + Sync>) -> Self {
+        Self { callback }
+    }
+}
+
+impl IAudioEndpointVolumeCallback_Impl for EndpointVolumeNotifier {
+    fn OnNotify(&self, pnotify: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> Result<()> {
+        if pnotify.is_null() {
+            return Ok(());
+        }
+        let data = unsafe { &*pnotify };
+        // The channel volumes are a variable length array, that starts with the last field of the struct.
+        let channel_volumes = unsafe {
+            slice::from_raw_parts(data.afChannelVolumes.as_ptr(), data.nChannels as usize)
+        }
+        .to_vec();
+        trace!(
+            "New endpoint volume: {}, mute: {:?}",
+            data.fMasterVolume,
+            data.bMuted
+        );
+        (self.callback)(EndpointVolumeEvent {
+            master_volume: data.fMasterVolume,
+            mute: bool::from(data.bMuted),
+            channel_volumes,
+            event_context: data.guidEventContext,
+        });
         Ok(())
     }
 }
@@ -0,0 +1,191 @@
+//! Building blocks shared by the examples.
+//!
+//! These are kept simple on purpose. They are meant as a starting point
+//! for applications, that can be copied and adapted when more control is needed.
+use crate::{
+    AudioCaptureClient, AudioClient, AudioRenderClient, BufferFlags, Handle, ShareMode, WasapiRes,
+    WaveFormat,
+};
+
+pub use crate::sine::SineGenerator;
+
+/// Check if a format is supported in shared mode.
+/// If the check fails, it is repeated with the format converted to a `WAVEFORMATEX`,
+/// since some devices only accept that form.
+/// Returns `true` if the format needs to be converted by the audio engine,
+/// meaning that the client should be initialized with `convert` enabled.
+pub fn negotiate_shared_format(audio_client: &AudioClient, desired_format: &WaveFormat) -> bool {
+    negotiate_format(desired_format, |format| {
+        audio_client.is_supported(format, &ShareMode::Shared)
+    })
+}
+
+/// The logic of [negotiate_shared_format], with the format check given as a closure.
+fn negotiate_format(
+    desired_format: &WaveFormat,
+    is_supported: impl Fn(&WaveFormat) -> WasapiRes<Option<WaveFormat>>,
+) -> bool {
+    match is_supported(desired_format) {
+        Ok(None) => {
+            debug!("Device supports format {:?}", desired_format);
+            return false;
+        }
+        Ok(Some(modified)) => {
+            debug!(
+                "Device doesn't support format:\n{:#?}\nClosest match is:\n{:#?}",
+                desired_format, modified
+            );
+            return true;
+        }
+        Err(err) => {
+            debug!(
+                "Device doesn't support format:\n{:#?}\nError: {}",
+                desired_format, err
+            );
+        }
+    }
+    debug!("Repeating query with format as WAVEFORMATEX");
+    let desired_formatex = match desired_format.to_waveformatex() {
+        Ok(fmt) => fmt,
+        Err(_) => return true,
+    };
+    match is_supported(&desired_formatex) {
+        Ok(None) => {
+            debug!("Device supports format {:?}", desired_formatex);
+            false
+        }
+        Ok(Some(modified)) => {
+            debug!(
+                "Device doesn't support format:\n{:#?}\nClosest match is:\n{:#?}",
+                desired_formatex, modified
+            );
+            true
+        }
+        Err(err) => {
+            debug!(
+                "Device doesn't support format:\n{:#?}\nError: {}",
+                desired_formatex, err
+            );
+            true
+        }
+    }
+}
+
+/// Run an event driven render loop for an initialized and started shared mode stream.
+///
+/// For each event, `fill` is called with a buffer that has space for all the available frames.
+/// The loop stops the stream and returns when `fill` returns `false`,
+/// or when waiting for an event times out after `timeout_ms` milliseconds.
+pub fn run_render_loop(
+    audio_client: &AudioClient,
+    render_client: &AudioRenderClient,
+    h_event: &Handle,
+    blockalign: usize,
+    timeout_ms: u32,
+    mut fill: impl FnMut(&mut [u8]) -> bool,
+) -> WasapiRes<()> {
+    let mut data = Vec::new();
+    loop {
+        let buffer_frame_count = audio_client.get_available_space_in_frames()? as usize;
+        data.resize(buffer_frame_count * blockalign, 0);
+        if !fill(&mut data) {
+            break;
+        }
+        render_client.write_to_device(buffer_frame_count, &data, None)?;
+        if h_event.wait_for_event(timeout_ms).is_err() {
+            error!("timeout error, stopping playback");
+            break;
+        }
+    }
+    audio_client.stop_stream()
+}
+
+/// Run an event driven capture loop for an initialized and started stream.
+///
+/// For each event, `consume` is called for every available packet,
+/// with the data and the [BufferFlags] of the packet.
+/// The loop stops the stream and returns when `consume` returns `false`,
+/// or when waiting for an event times out after `timeout_ms` milliseconds.
+pub fn run_capture_loop(
+    audio_client: &AudioClient,
+    capture_client: &AudioCaptureClient,
+    h_event: &Handle,
+    timeout_ms: u32,
+    mut consume: impl FnMut(&[u8], BufferFlags) -> bool,
+) -> WasapiRes<()> {
+    'outer: loop {
+        loop {
+            let buffer = capture_client.get_buffer()?;
+            if buffer.get_nbr_frames() == 0 {
+                break;
+            }
+            let keep_going = consume(buffer.as_slice(), buffer.get_info().flags);
+            buffer.release()?;
+            if !keep_going {
+                break 'outer;
+            }
+        }
+        if h_event.wait_for_event(timeout_ms).is_err() {
+            error!("timeout error, stopping capture");
+            break;
+        }
+    }
+    audio_client.stop_stream()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{SampleType, WasapiError};
+
+    fn desired_format() -> WaveFormat {
+        WaveFormat::new(32, 32, &SampleType::Float, 44100, 2, None)
+    }
+
+    #[test]
+    fn supported_format_needs_no_conversion() {
+        let calls = Cell::new(0);
+        let convert = negotiate_format(&desired_format(), |_| {
+            calls.set(calls.get() + 1);
+            Ok(None)
+        });
+        assert!(!convert);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn closest_match_needs_conversion() {
+        let convert = negotiate_format(&desired_format(), |format| Ok(Some(format.clone())));
+        assert!(convert);
+    }
+
+    #[test]
+    fn failed_check_is_repeated_as_waveformatex() {
+        let tags = Cell::new(Vec::new());
+        let convert = negotiate_format(&desired_format(), |format| {
+            let mut seen = tags.take();
+            seen.push(format.as_waveformatex_ref().wFormatTag);
+            let first = seen.len() == 1;
+            tags.set(seen);
+            if first {
+                Err(WasapiError::new("not supported").into())
+            } else {
+                Ok(None)
+            }
+        });
+        assert!(!convert);
+        let tags = tags.take();
+        assert_eq!(tags.len(), 2);
+        assert_ne!(tags[0], tags[1]);
+    }
+
+    #[test]
+    fn failed_repeated_check_needs_conversion() {
+        let convert = negotiate_format(&desired_format(), |_| {
+            Err(WasapiError::new("not supported").into())
+        });
+        assert!(convert);
+    }
+}
@@ -0,0 +1,42 @@
+//! Common formats, for quick tools and tests.
+//!
+//! Each function returns a new [WaveFormat] with the default channel mask for the number of channels.
+
+use crate::{SampleType, WaveFormat};
+
+/// CD quality, 16-bit integer at 44.1 kHz, stereo.
+pub fn cd_quality() -> WaveFormat {
+    WaveFormat::new(16, 16, &SampleType::Int, 44100, 2, None)
+}
+
+/// DVD quality, 16-bit integer at 48 kHz, stereo.
+pub fn dvd_quality() -> WaveFormat {
+    WaveFormat::new(16, 16, &SampleType::Int, 48000, 2, None)
+}
+
+/// 24-bit integer at 48 kHz, stereo, stored in 32 bits.
+/// This is what most devices use for 24-bit samples.
+pub fn studio_48k_24() -> WaveFormat {
+    WaveFormat::new(32, 24, &SampleType::Int, 48000, 2, None)
+}
+
+/// 24-bit integer at 96 kHz, stereo, stored in 32 bits.
+/// This is what most devices use for 24-bit samples.
+pub fn studio_96k_24() -> WaveFormat {
+    WaveFormat::new(32, 24, &SampleType::Int, 96000, 2, None)
+}
+
+/// 32-bit float at 44.1 kHz, stereo.
+pub fn float_44k_stereo() -> WaveFormat {
+    WaveFormat::new(32, 32, &SampleType::Float, 44100, 2, None)
+}
+
+/// 32-bit float at 48 kHz, stereo. This is the most common shared mode mix format.
+pub fn float_48k_stereo() -> WaveFormat {
+    WaveFormat::new(32, 32, &SampleType::Float, 48000, 2, None)
+}
+
+/// 32-bit float at 48 kHz, mono.
+pub fn float_48k_mono() -> WaveFormat {
+    WaveFormat::new(32, 32, &SampleType::Float, 48000, 1, None)
+}
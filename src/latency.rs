@@ -0,0 +1,244 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use crate::sine::SineGenerator;
+use crate::{
+    AudioClient, AudioRenderClient, Device, Direction, SampleType, ShareMode, WasapiError,
+    WasapiRes, WaveFormat,
+};
+
+const TEST_SAMPLERATE: usize = 48000;
+const TEST_CHANNELS: usize = 2;
+// The burst starts after 0.3 seconds of silence, and the test runs for 1.5 seconds.
+const BURST_START_FRAME: usize = TEST_SAMPLERATE * 3 / 10;
+const BURST_LENGTH: usize = TEST_SAMPLERATE / 1000;
+const BURST_FREQUENCY: f64 = 3000.0;
+const TEST_LENGTH_FRAMES: usize = TEST_SAMPLERATE * 3 / 2;
+// Capture buffer duration in 100ns units, 200 ms.
+const CAPTURE_BUFFER_DURATION: i64 = 2_000_000;
+// Peaks closer than this to the main peak, in frames, are considered part of it.
+const PEAK_WINDOW: usize = TEST_SAMPLERATE / 200;
+const MIN_PEAK_LEVEL: f32 = 0.001;
+
+/// Result of a [loopback_latency_test].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencyMeasurement {
+    /// The measured round trip latency in milliseconds.
+    pub latency_ms: f64,
+    /// How clearly the test signal was detected, from 0.0 to 1.0.
+    /// This compares the detected peak with the strongest signal outside of it.
+    /// Values below about 0.5 mean that the result is unreliable.
+    pub confidence: f64,
+}
+
+impl fmt::Display for LatencyMeasurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} ms (confidence {:.2})",
+            self.latency_ms, self.confidence
+        )
+    }
+}
+
+/// The test signal, a short Hann-windowed tone burst after a period of silence.
+/// Returns one sample per frame.
+struct TestSignal {
+    frame: usize,
+    tone: SineGenerator,
+}
+
+impl TestSignal {
+    fn new() -> Self {
+        TestSignal {
+            frame: 0,
+            tone: SineGenerator::new(BURST_FREQUENCY, TEST_SAMPLERATE as f64, 0.5),
+        }
+    }
+}
+
+impl Iterator for TestSignal {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        let frame = self.frame;
+        self.frame += 1;
+        if !(BURST_START_FRAME..BURST_START_FRAME + BURST_LENGTH).contains(&frame) {
+            return Some(0.0);
+        }
+        let n = (frame - BURST_START_FRAME) as f64;
+        let window = 0.5 - 0.5 * (2.0 * PI * n / BURST_LENGTH as f64).cos();
+        Some(window as f32 * self.tone.next().unwrap_or_default())
+    }
+}
+
+/// Measure the round trip latency from a render device to a capture device.
+///
+/// This plays a short tone burst on the render device while recording from the capture device,
+/// both in shared mode. The playback time of the burst is calculated from the [AudioClock]
+/// of the render stream, and the recording time from the capture timestamps, see [BufferInfo].
+/// The difference is the latency.
+///
+/// The capture device can be an input that picks up the sound from the render device,
+/// for example a microphone or a cable from an output to an input.
+/// It can also be a render device, which is then captured in loopback mode.
+///
+/// The test takes about 1.5 seconds.
+/// The thread must have initialized COM, see [initialize_mta](crate::initialize_mta).
+///
+/// [AudioClock]: crate::AudioClock
+/// [BufferInfo]: crate::BufferInfo
+pub fn loopback_latency_test(
+    render_device: &Device,
+    capture_device: &Device,
+) -> WasapiRes<LatencyMeasurement> {
+    let format = WaveFormat::new(
+        32,
+        32,
+        &SampleType::Float,
+        TEST_SAMPLERATE,
+        TEST_CHANNELS,
+        None,
+    );
+    let blockalign = format.get_blockalign() as usize;
+
+    let mut render_audio_client = render_device.get_iaudioclient()?;
+    let (def_period, _min_period) = render_audio_client.get_periods()?;
+    render_audio_client.initialize_client(
+        &format,
+        def_period,
+        &Direction::Render,
+        &ShareMode::Shared,
+        true,
+    )?;
+    let render_event = render_audio_client.set_get_eventhandle()?;
+    let render_client = render_audio_client.get_audiorenderclient()?;
+    let render_clock = render_audio_client.get_audioclock()?;
+
+    let mut capture_audio_client = capture_device.get_iaudioclient()?;
+    capture_audio_client.initialize_client(
+        &format,
+        CAPTURE_BUFFER_DURATION,
+        &Direction::Capture,
+        &ShareMode::Shared,
+        true,
+    )?;
+    // The event handle must be set for an event driven client, even if it isn't waited on.
+    let _capture_event = capture_audio_client.set_get_eventhandle()?;
+    let capture_client = capture_audio_client.get_audiocaptureclient()?;
+    let capture_buffer_frames = capture_audio_client.get_bufferframecount()? as usize;
+
+    let mut signal = TestSignal::new();
+    let mut frames_written = 0;
+    let mut captured: Vec<f32> = Vec::with_capacity(2 * TEST_LENGTH_FRAMES);
+    // Start index in `captured` and timestamp of each captured packet
+    let mut packets: Vec<(usize, u64)> = Vec::new();
+    let mut capture_data = vec![0u8; capture_buffer_frames * blockalign];
+    // Position in seconds and timestamp in 100ns units, from the render clock
+    let mut render_timing: Option<(f64, u64)> = None;
+
+    capture_audio_client.start_stream()?;
+    write_test_signal(
+        &render_audio_client,
+        &render_client,
+        &mut signal,
+        &mut frames_written,
+    )?;
+    render_audio_client.start_stream()?;
+    let result = loop {
+        if let Err(err) = render_event.wait_for_event(1000) {
+            break Err(err);
+        }
+        if render_timing.is_none() && frames_written > BURST_START_FRAME / 2 {
+            // Sample the clock once the stream is running, but before the burst is played.
+            render_timing = Some(render_clock.get_position_seconds()?);
+        }
+        if frames_written < TEST_LENGTH_FRAMES {
+            write_test_signal(
+                &render_audio_client,
+                &render_client,
+                &mut signal,
+                &mut frames_written,
+            )?;
+        }
+        while let Some(nbr_frames) = capture_client.get_next_nbr_frames()? {
+            if nbr_frames == 0 {
+                break;
+            }
+            let (nbr_read, info) = capture_client.read_from_device_with_info(&mut capture_data)?;
+            packets.push((captured.len(), info.timestamp));
+            captured.extend(
+                capture_data[..nbr_read as usize * blockalign]
+                    .chunks_exact(blockalign)
+                    .map(|frame| f32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]])),
+            );
+        }
+        if captured.len() >= TEST_LENGTH_FRAMES {
+            break Ok(());
+        }
+    };
+    render_audio_client.stop_stream()?;
+    capture_audio_client.stop_stream()?;
+    result?;
+
+    let (render_position, render_timestamp) = match render_timing {
+        Some(timing) => timing,
+        None => return Err(WasapiError::new("Unable to read the render clock").into()),
+    };
+    // Time in 100ns units when the first frame of the burst was played
+    let burst_time = render_timestamp as f64
+        + (BURST_START_FRAME as f64 / TEST_SAMPLERATE as f64 - render_position) * 1.0e7;
+
+    let mut peak_index = 0;
+    let mut peak_value = 0.0;
+    for (idx, value) in captured.iter().enumerate() {
+        if value.abs() > peak_value {
+            peak_index = idx;
+            peak_value = value.abs();
+        }
+    }
+    if peak_value < MIN_PEAK_LEVEL {
+        return Err(WasapiError::new("The test signal was not detected").into());
+    }
+    let background = captured
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| {
+            *idx + PEAK_WINDOW < peak_index || *idx > peak_index + BURST_LENGTH + PEAK_WINDOW
+        })
+        .map(|(_, value)| value.abs())
+        .fold(0.0, f32::max);
+    let (packet_start, packet_timestamp) = packets
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= peak_index)
+        .copied()
+        .unwrap_or((0, 0));
+    // The peak of the window is in the middle of the burst
+    let offset_frames = (peak_index - packet_start) as f64 - (BURST_LENGTH / 2) as f64;
+    let peak_time = packet_timestamp as f64 + offset_frames / TEST_SAMPLERATE as f64 * 1.0e7;
+    let measurement = LatencyMeasurement {
+        latency_ms: (peak_time - burst_time) / 1.0e4,
+        confidence: (1.0 - background / peak_value).clamp(0.0, 1.0) as f64,
+    };
+    debug!("measured latency {}", measurement);
+    Ok(measurement)
+}
+
+/// Fill the available space in the render buffer with the test signal.
+fn write_test_signal(
+    audio_client: &AudioClient,
+    render_client: &AudioRenderClient,
+    signal: &mut TestSignal,
+    frames_written: &mut usize,
+) -> WasapiRes<()> {
+    let nbr_frames = audio_client.get_available_space_in_frames()? as usize;
+    let mut data = Vec::with_capacity(nbr_frames * TEST_CHANNELS * 4);
+    for value in signal.take(nbr_frames) {
+        for _ in 0..TEST_CHANNELS {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    render_client.write_to_device(nbr_frames, &data, None)?;
+    *frames_written += nbr_frames;
+    Ok(())
+}
@@ -17,6 +17,91 @@
 //! - Event-driven buffering
 //! - Loopback capture
 //! - Notifications for volume change, device disconnect etc
+//! - Simple up- and downmixing between mono, stereo and 5.1
+//! - Round trip latency measurement
+//! - Walking the device topology of endpoints and audio adapters
+//!
+//! ## Real-time use
+//!
+//! After initialization, [AudioRenderClient::write_to_device], [AudioRenderClient::write_to_device_from_deque],
+//! [AudioRenderClient::write_available], [AudioRenderClient::write_to_device_deadline],
+//! [AudioCaptureClient::read_from_device], [AudioCaptureClient::read_from_device_deadline], [Handle::wait_for_event] and [PeriodicTimer::wait_for_event]
+//! perform no heap allocations,
+//! except when they return an error.
+//! The per-period logging in these methods, and in [AudioClient::get_current_padding]
+//! and [AudioClient::get_available_space_in_frames], is compiled out by default.
+//! Enable the `verbose-trace` feature to get these messages back at the `trace` level.
+//!
+//! The `rt-assert` feature can be used to verify this while debugging.
+//! It provides the `RtAssertAllocator` global allocator,
+//! and when this is installed the methods above panic if they allocate.
+//!
+//! ## Test signals
+//!
+//! The `testsignal` feature enables the `testsignal` module,
+//! with sine, noise and sweep generators that can be written as frames in the format of a stream.
+//! The sine generator alone is always available from the [examples_common] module.
+//!
+//! ## Enabling and disabling devices, and changing the default device
+//!
+//! The `policy-config` feature adds [set_endpoint_enabled] and `Device::set_enabled`,
+//! for enabling and disabling endpoint devices like in the Sound control panel,
+//! and [set_default_endpoint] and `Device::set_as_default` for changing the default device for a role.
+//! This uses an undocumented Windows interface.
+//! Enabling and disabling devices requires administrator privileges.
+//!
+//! ## Notification registrations and threads
+//!
+//! The callbacks for notifications are called from threads owned by the system.
+//! The registrations that keep them alive, like [DeviceNotifications], [DefaultDeviceObserver],
+//! [SessionNotifications], [DuckNotifications], [EndpointVolumeNotifications] and [SessionEventChannel],
+//! can be moved to other threads, so they can be stored in a struct that lives on another thread
+//! than the one that registered them.
+//! Dropping a registration unregisters the callbacks, and this needs COM to be initialized on the dropping thread.
+//!
+//! The exception is [AudioSessionControl::register_session_notification], where the [EventCallbacks]
+//! are shared with a `Weak` reference and must stay on the thread that created them.
+//! Use [AudioSessionControl::register_session_notification_channel] when the notifications
+//! are needed on another thread.
+//!
+//! ## Sleep, resume and audio service restarts
+//!
+//! Streams don't survive all system events. When the system resumes from sleep,
+//! the buffer methods may return `AUDCLNT_E_RESOURCES_INVALIDATED` or `AUDCLNT_E_DEVICE_INVALIDATED`,
+//! and when the audio service is stopped or restarted they return `AUDCLNT_E_SERVICE_NOT_RUNNING`,
+//! while registered sessions get a disconnect with [DisconnectReason::ServerShutdown].
+//! The stream can't be used after any of these, the client must be dropped and a new one created.
+//!
+//! Use [recovery_action_for_error] and [DisconnectReason::recovery_action] to decide what to do.
+//! For [RecoveryAction::RetryLater], [wait_for_audio_service] waits until the service is back,
+//! before opening the device again.
+//!
+//! For playback on the default device, [DefaultRenderStream] handles this automatically.
+//! It reopens the stream on the new default device when the default changes or the device is lost.
+//!
+//! The `simulate-errors` feature adds `simulate_stream_error`,
+//! that makes the next buffer call return one of these errors, for testing the recovery code.
+//!
+//! ## Cross-platform applications
+//!
+//! The crate compiles on all targets, but on targets other than Windows
+//! the system functions it calls are missing, and applications using it fail to link.
+//! The `stub` feature provides stand-ins for these functions on other targets, that always fail.
+//! The full API is then available everywhere, and the first call that needs the system returns an error,
+//! for example [initialize_mta] or [get_default_device].
+//! This lets a cross-platform application depend on the crate unconditionally,
+//! and choose an audio backend when it starts, instead of putting `#[cfg(windows)]` on all code using it.
+//! The feature has no effect when building for Windows.
+//!
+//! The stand-ins are exported under the names of the Windows functions, like `CoInitializeEx`,
+//! since that is what the `windows` crate links against.
+//! They are global symbols, so only one crate in an application may define them.
+//! Linking fails with duplicate symbols if the feature is enabled in two semver-incompatible
+//! versions of this crate, or if another crate provides its own stand-ins for the same functions.
+//! Enable the feature only in the application, not in libraries that depend on this crate.
+//!
+//! Applications that only use the crate on Windows can instead make it a Windows-only dependency,
+//! with `[target.'cfg(windows)'.dependencies]` in `Cargo.toml`.
 //!
 //! ## Included examples
 //!
@@ -29,14 +114,71 @@
 //! | `record`              | Records audio from the default device, and saves the raw samples to a file.                            |
 //! | `devices`             | Lists all available audio devices and displays the default devices.                                    |
 //! | `record_application`  | Records audio from a single application, and saves the raw samples to a file.                          |
+//! | `record_system`       | Records audio from all applications except itself, and saves the raw samples to a file.                |
+//!
+//! The building blocks shared by the examples, like the sine generator and the render and capture loops,
+//! are available in the [examples_common] module.
+
+// Trace logging for the per-period buffer paths,
+// compiled out unless the `verbose-trace` feature is enabled.
+macro_rules! rt_trace {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "verbose-trace")]
+        trace!($($arg)+)
+    };
+}
 
 mod api;
+mod channel;
+mod defaultstream;
 mod events;
+pub mod examples_common;
+pub mod formats;
+mod latency;
+mod meter;
+#[cfg(feature = "policy-config")]
+mod policyconfig;
+mod polling;
+mod priority;
+mod pump;
+mod registration;
+mod remix;
+mod rtassert;
+mod sharedf32;
+mod simulate;
+mod sine;
+#[cfg(all(feature = "stub", not(windows)))]
+mod stub;
+mod systemevents;
+#[cfg(feature = "testsignal")]
+pub mod testsignal;
+mod topology;
+mod watcher;
 mod waveformat;
+mod worker;
 pub use api::*;
+pub use channel::*;
+pub use defaultstream::*;
 pub use events::*;
+pub use latency::*;
+pub use meter::*;
+#[cfg(feature = "policy-config")]
+pub use policyconfig::*;
+pub use polling::*;
+pub use priority::*;
+pub use pump::*;
+pub use remix::*;
+#[cfg(feature = "rt-assert")]
+pub use rtassert::RtAssertAllocator;
+pub use sharedf32::*;
+#[cfg(feature = "simulate-errors")]
+pub use simulate::*;
+pub use systemevents::*;
+pub use topology::*;
+pub use watcher::*;
 pub use waveformat::*;
 pub use windows::core::GUID;
+pub use worker::*;
 
 #[macro_use]
 extern crate log;
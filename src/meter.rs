@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+use crate::{AudioMeterInformation, HardwarePeakMeter, WasapiError, WasapiRes};
+
+// The lowest level reported by default, in dBFS.
+const DEFAULT_FLOOR_DB: f32 = -96.0;
+
+/// A source of peak values for a [LevelMeter].
+/// The peak values are linear, between 0.0 and 1.0.
+///
+/// This is implemented for [AudioMeterInformation], [HardwarePeakMeter], and for closures
+/// returning a peak value, so that any other source can be used.
+pub trait PeakSource {
+    /// Read the current peak value.
+    fn get_peak_value(&self) -> WasapiRes<f32>;
+}
+
+impl PeakSource for AudioMeterInformation {
+    fn get_peak_value(&self) -> WasapiRes<f32> {
+        AudioMeterInformation::get_peak_value(self)
+    }
+}
+
+impl PeakSource for HardwarePeakMeter {
+    /// The highest level of all channels, converted from dB to a linear value.
+    fn get_peak_value(&self) -> WasapiRes<f32> {
+        let mut peak_db = f32::NEG_INFINITY;
+        for channel in 0..self.get_channel_count()? {
+            peak_db = peak_db.max(self.get_level_db(channel)?);
+        }
+        Ok(10.0_f32.powf(peak_db / 20.0))
+    }
+}
+
+impl<F: Fn() -> WasapiRes<f32>> PeakSource for F {
+    fn get_peak_value(&self) -> WasapiRes<f32> {
+        self()
+    }
+}
+
+/// A level meter that smooths the values of a [PeakSource] for display.
+///
+/// Rising peaks are followed with the attack time constant, and falling peaks with the release time constant.
+/// A time constant is the time it takes to move about 63% (1 - 1/e) of the way to a new value,
+/// and a zero time constant means that the level follows the peaks immediately.
+/// Typical values are a few milliseconds of attack and a few hundred milliseconds of release.
+pub struct LevelMeter<S: PeakSource> {
+    source: S,
+    attack: Duration,
+    release: Duration,
+    floor_db: f32,
+    level: f32,
+    last_update: Option<Instant>,
+}
+
+impl<S: PeakSource> LevelMeter<S> {
+    /// Create a new [LevelMeter] with the given attack and release times.
+    pub fn new(source: S, attack: Duration, release: Duration) -> Self {
+        LevelMeter {
+            source,
+            attack,
+            release,
+            floor_db: DEFAULT_FLOOR_DB,
+            level: 0.0,
+            last_update: None,
+        }
+    }
+
+    /// Set the lowest level in dBFS returned by [LevelMeter::get_level_dbfs], the default is -96 dB.
+    /// Returns an error if the value is not finite, or above 0 dB.
+    pub fn set_floor_db(&mut self, floor_db: f32) -> WasapiRes<()> {
+        if !floor_db.is_finite() || floor_db > 0.0 {
+            return Err(WasapiError::new(&format!("Invalid floor level {} dB", floor_db)).into());
+        }
+        self.floor_db = floor_db;
+        Ok(())
+    }
+
+    /// Read a new peak value from the source, and return the smoothed linear level.
+    /// Call this regularly, for example once per frame of the user interface.
+    pub fn update(&mut self) -> WasapiRes<f32> {
+        let peak = self.source.get_peak_value()?.clamp(0.0, 1.0);
+        let now = Instant::now();
+        let elapsed = self.last_update.map(|last| now - last);
+        self.last_update = Some(now);
+        let time_constant = if peak > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        let keep = match elapsed {
+            Some(elapsed) if !time_constant.is_zero() => {
+                (-elapsed.as_secs_f32() / time_constant.as_secs_f32()).exp()
+            }
+            _ => 0.0,
+        };
+        self.level = peak + (self.level - peak) * keep;
+        Ok(self.level)
+    }
+
+    /// Get the smoothed linear level from the last update, between 0.0 and 1.0.
+    pub fn get_level(&self) -> f32 {
+        self.level
+    }
+
+    /// Get the smoothed level from the last update in dBFS,
+    /// clamped to the range from the floor value to 0 dB.
+    pub fn get_level_dbfs(&self) -> f32 {
+        linear_to_dbfs(self.level).max(self.floor_db).min(0.0)
+    }
+
+    /// Get a reference to the peak source.
+    pub fn get_source(&self) -> &S {
+        &self.source
+    }
+}
+
+/// Convert a linear level to dBFS. A level of zero gives negative infinity.
+pub fn linear_to_dbfs(level: f32) -> f32 {
+    20.0 * level.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_floor_is_rejected() {
+        let mut meter = LevelMeter::new(|| Ok(0.5), Duration::ZERO, Duration::ZERO);
+        assert!(meter.set_floor_db(f32::NAN).is_err());
+        assert!(meter.set_floor_db(f32::NEG_INFINITY).is_err());
+        assert!(meter.set_floor_db(6.0).is_err());
+        assert!(meter.set_floor_db(-60.0).is_ok());
+    }
+
+    #[test]
+    fn level_is_clamped_to_floor() {
+        let mut meter = LevelMeter::new(|| Ok(0.0), Duration::ZERO, Duration::ZERO);
+        meter.set_floor_db(-60.0).unwrap();
+        meter.update().unwrap();
+        assert_eq!(meter.get_level_dbfs(), -60.0);
+        let mut meter = LevelMeter::new(|| Ok(1.0), Duration::ZERO, Duration::ZERO);
+        meter.update().unwrap();
+        assert_eq!(meter.get_level_dbfs(), 0.0);
+    }
+}
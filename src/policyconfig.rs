@@ -0,0 +1,138 @@
+//! Enabling and disabling of endpoint devices, and changing the default device.
+//!
+//! This uses the undocumented `IPolicyConfig` interface, that is also used by the Sound control panel.
+//! The interface is not part of the public Windows SDK, and may change in future Windows versions.
+//! Changing the state of a device requires administrator privileges,
+//! while changing the default device does not.
+//!
+//! This module is only available when the `policy-config` feature is enabled.
+
+// The interface methods keep the names used by Windows.
+#![allow(non_snake_case)]
+use std::ffi::c_void;
+
+use widestring::U16CString;
+use windows::{
+    core::{interface, IUnknown, IUnknown_Vtbl, GUID, HRESULT, PCWSTR},
+    Win32::Foundation::{BOOL, E_ACCESSDENIED},
+    Win32::System::Com::{CoCreateInstance, CLSCTX_ALL},
+};
+
+use crate::{role_to_erole, Device, Role, WasapiError, WasapiRes};
+
+// CLSID of the CPolicyConfigClient class.
+const CLSID_POLICY_CONFIG_CLIENT: GUID = GUID::from_u128(0x870af99c_171d_4f9e_af0d_e63df40c2bc9);
+
+// The `IPolicyConfig` interface, as implemented since Windows 7.
+// Only the last method is used, the others are needed to get the vtable layout right.
+#[interface("f8679f50-850a-41cf-9c72-430f290290c8")]
+unsafe trait IPolicyConfig: IUnknown {
+    fn GetMixFormat(&self, device_id: PCWSTR, format: *mut *mut c_void) -> HRESULT;
+    fn GetDeviceFormat(
+        &self,
+        device_id: PCWSTR,
+        default: BOOL,
+        format: *mut *mut c_void,
+    ) -> HRESULT;
+    fn ResetDeviceFormat(&self, device_id: PCWSTR) -> HRESULT;
+    fn SetDeviceFormat(
+        &self,
+        device_id: PCWSTR,
+        endpoint_format: *mut c_void,
+        mix_format: *mut c_void,
+    ) -> HRESULT;
+    fn GetProcessingPeriod(
+        &self,
+        device_id: PCWSTR,
+        default: BOOL,
+        default_period: *mut i64,
+        min_period: *mut i64,
+    ) -> HRESULT;
+    fn SetProcessingPeriod(&self, device_id: PCWSTR, period: *mut i64) -> HRESULT;
+    fn GetShareMode(&self, device_id: PCWSTR, mode: *mut c_void) -> HRESULT;
+    fn SetShareMode(&self, device_id: PCWSTR, mode: *mut c_void) -> HRESULT;
+    fn GetPropertyValue(
+        &self,
+        device_id: PCWSTR,
+        fx_store: BOOL,
+        key: *const c_void,
+        value: *mut c_void,
+    ) -> HRESULT;
+    fn SetPropertyValue(
+        &self,
+        device_id: PCWSTR,
+        fx_store: BOOL,
+        key: *const c_void,
+        value: *mut c_void,
+    ) -> HRESULT;
+    fn SetDefaultEndpoint(&self, device_id: PCWSTR, role: u32) -> HRESULT;
+    fn SetEndpointVisibility(&self, device_id: PCWSTR, visible: BOOL) -> HRESULT;
+}
+
+/// Enable or disable the endpoint device with the given id.
+/// This is the same as enabling or disabling the device in the Sound control panel.
+/// Disabled devices are only listed by a [DeviceCollection](crate::DeviceCollection)
+/// created with [DeviceCollection::new_with_states](crate::DeviceCollection::new_with_states),
+/// that includes [DeviceState::Disabled](crate::DeviceState::Disabled).
+///
+/// This requires administrator privileges,
+/// and returns an error saying so when the process is not elevated.
+pub fn set_endpoint_enabled(device_id: &str, enabled: bool) -> WasapiRes<()> {
+    let policy_config = create_policy_config()?;
+    let id = wide_device_id(device_id)?;
+    let result = unsafe {
+        policy_config.SetEndpointVisibility(PCWSTR::from_raw(id.as_ptr()), BOOL::from(enabled))
+    };
+    if result == E_ACCESSDENIED {
+        return Err(WasapiError::new(
+            "Access denied, enabling or disabling a device requires administrator privileges",
+        )
+        .into());
+    }
+    result.ok()?;
+    debug!(
+        "{} endpoint device {}",
+        if enabled { "Enabled" } else { "Disabled" },
+        device_id
+    );
+    Ok(())
+}
+
+/// Make the endpoint device with the given id the default device for a role.
+/// This is the same as selecting the default device in the Sound control panel,
+/// and applies to all applications.
+/// The device must be active.
+pub fn set_default_endpoint(device_id: &str, role: &Role) -> WasapiRes<()> {
+    let policy_config = create_policy_config()?;
+    let id = wide_device_id(device_id)?;
+    unsafe {
+        policy_config
+            .SetDefaultEndpoint(PCWSTR::from_raw(id.as_ptr()), role_to_erole(role).0 as u32)
+            .ok()?
+    };
+    debug!("Set endpoint device {} as default for {}", device_id, role);
+    Ok(())
+}
+
+fn create_policy_config() -> WasapiRes<IPolicyConfig> {
+    let policy_config = unsafe { CoCreateInstance(&CLSID_POLICY_CONFIG_CLIENT, None, CLSCTX_ALL)? };
+    Ok(policy_config)
+}
+
+fn wide_device_id(device_id: &str) -> WasapiRes<U16CString> {
+    let id = U16CString::from_str(device_id)
+        .map_err(|_| WasapiError::new("Device id contains a null character"))?;
+    Ok(id)
+}
+
+impl Device {
+    /// Enable or disable the device, see [set_endpoint_enabled].
+    pub fn set_enabled(&self, enabled: bool) -> WasapiRes<()> {
+        set_endpoint_enabled(&self.get_id()?, enabled)
+    }
+
+    /// Make the device the default device for a role, see [set_default_endpoint].
+    pub fn set_as_default(&self, role: &Role) -> WasapiRes<()> {
+        set_default_endpoint(&self.get_id()?, role)
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use windows::Win32::System::Threading::INFINITE;
+
+use crate::{PeriodicTimer, WasapiError, WasapiRes, WaveFormat};
+
+/// The strategy used by a [PollingScheduler] to wait for the next cycle.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WaitStrategy {
+    /// Sleep with [std::thread::sleep]. This has the lowest cpu usage,
+    /// but the wakeup time may be off by a millisecond or more.
+    Sleep,
+    /// Wait on a [PeriodicTimer], using a high resolution timer when available.
+    WaitableTimer,
+    /// Sleep until shortly before the wakeup time, and then spin for the given duration.
+    /// This gives the lowest jitter, at the cost of some cpu usage while spinning.
+    Hybrid {
+        /// How long to spin before the wakeup time.
+        spin: Duration,
+    },
+}
+
+impl fmt::Display for WaitStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WaitStrategy::Sleep => write!(f, "Sleep"),
+            WaitStrategy::WaitableTimer => write!(f, "WaitableTimer"),
+            WaitStrategy::Hybrid { spin } => write!(f, "Hybrid (spin {:?})", spin),
+        }
+    }
+}
+
+/// Helper for timing the loop of a stream that polls the device buffer
+/// instead of waiting for buffer events.
+///
+/// The interval is half the duration of the device buffer,
+/// so that the buffer is serviced about twice per period.
+/// The wakeup times follow a fixed schedule, so that the time spent processing
+/// in each cycle does not add up to a drift. If a cycle overruns,
+/// the schedule is restarted from the current time.
+pub struct PollingScheduler {
+    interval: Duration,
+    strategy: WaitStrategy,
+    next_wakeup: Option<Instant>,
+    timer: Option<PeriodicTimer>,
+}
+
+impl PollingScheduler {
+    /// Create a new [PollingScheduler] for a stream with the given format and buffer size in frames,
+    /// see [AudioClient::get_bufferframecount](crate::AudioClient::get_bufferframecount).
+    pub fn new(
+        wave_fmt: &WaveFormat,
+        buffer_frames: u32,
+        strategy: &WaitStrategy,
+    ) -> WasapiRes<Self> {
+        let samplerate = wave_fmt.get_samplespersec() as u64;
+        if samplerate == 0 || buffer_frames == 0 {
+            return Err(WasapiError::new("Sample rate and buffer size must be non-zero").into());
+        }
+        let interval = Duration::from_nanos(500_000_000 * buffer_frames as u64 / samplerate);
+        let timer = match strategy {
+            WaitStrategy::WaitableTimer => Some(PeriodicTimer::new(interval)?),
+            _ => None,
+        };
+        debug!(
+            "polling scheduler with interval {:?} and strategy {}",
+            interval, strategy
+        );
+        Ok(PollingScheduler {
+            interval,
+            strategy: *strategy,
+            next_wakeup: None,
+            timer,
+        })
+    }
+
+    /// Get the interval between the cycles.
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Wait until it is time for the next cycle.
+    /// The first call starts the schedule, and returns after one interval.
+    pub fn wait_next_cycle(&mut self) -> WasapiRes<()> {
+        if let Some(timer) = &self.timer {
+            // The timer keeps its own schedule
+            return timer.wait_for_event(INFINITE);
+        }
+        let now = Instant::now();
+        let mut next_wakeup = self.next_wakeup.unwrap_or(now) + self.interval;
+        if next_wakeup <= now {
+            trace!("polling cycle overrun, restarting the schedule");
+            next_wakeup = now + self.interval;
+        }
+        self.next_wakeup = Some(next_wakeup);
+        let remaining = next_wakeup - now;
+        match self.strategy {
+            WaitStrategy::Sleep => thread::sleep(remaining),
+            // Handled by the timer above
+            WaitStrategy::WaitableTimer => {}
+            WaitStrategy::Hybrid { spin } => {
+                if remaining > spin {
+                    thread::sleep(remaining - spin);
+                }
+                while Instant::now() < next_wakeup {
+                    hint::spin_loop();
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use windows::Win32::System::Threading::{
+    GetCurrentThread, GetThreadPriority, SetThreadPriority, THREAD_PRIORITY,
+    THREAD_PRIORITY_ABOVE_NORMAL, THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST,
+    THREAD_PRIORITY_IDLE, THREAD_PRIORITY_LOWEST, THREAD_PRIORITY_NORMAL,
+    THREAD_PRIORITY_TIME_CRITICAL,
+};
+
+use crate::{WasapiError, WasapiRes};
+
+/// Returned by `GetThreadPriority` on failure.
+const THREAD_PRIORITY_ERROR_RETURN: i32 = 0x7fff_ffff;
+
+/// Priority of a thread, an enum representing the values accepted by
+/// [SetThreadPriority](https://learn.microsoft.com/en-us/windows/win32/api/processthreadsapi/nf-processthreadsapi-setthreadpriority).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ThreadPriority {
+    /// `THREAD_PRIORITY_IDLE`, the thread only runs when nothing else needs the CPU.
+    Idle,
+    /// `THREAD_PRIORITY_LOWEST`, two steps below normal.
+    Lowest,
+    /// `THREAD_PRIORITY_BELOW_NORMAL`, one step below normal.
+    BelowNormal,
+    /// `THREAD_PRIORITY_NORMAL`, the default priority of new threads.
+    Normal,
+    /// `THREAD_PRIORITY_ABOVE_NORMAL`, one step above normal.
+    AboveNormal,
+    /// `THREAD_PRIORITY_HIGHEST`, two steps above normal.
+    Highest,
+    /// `THREAD_PRIORITY_TIME_CRITICAL`, the highest priority,
+    /// for threads that must not be delayed, like the loop of a polling stream.
+    TimeCritical,
+}
+
+impl fmt::Display for ThreadPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ThreadPriority::Idle => write!(f, "Idle"),
+            ThreadPriority::Lowest => write!(f, "Lowest"),
+            ThreadPriority::BelowNormal => write!(f, "BelowNormal"),
+            ThreadPriority::Normal => write!(f, "Normal"),
+            ThreadPriority::AboveNormal => write!(f, "AboveNormal"),
+            ThreadPriority::Highest => write!(f, "Highest"),
+            ThreadPriority::TimeCritical => write!(f, "TimeCritical"),
+        }
+    }
+}
+
+impl ThreadPriority {
+    fn to_thread_priority(self) -> THREAD_PRIORITY {
+        match self {
+            ThreadPriority::Idle => THREAD_PRIORITY_IDLE,
+            ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+            ThreadPriority::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+            ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+            ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+            ThreadPriority::Highest => THREAD_PRIORITY_HIGHEST,
+            ThreadPriority::TimeCritical => THREAD_PRIORITY_TIME_CRITICAL,
+        }
+    }
+}
+
+/// Read the raw priority of the calling thread.
+fn get_raw_thread_priority() -> WasapiRes<THREAD_PRIORITY> {
+    let priority = unsafe { GetThreadPriority(GetCurrentThread()) };
+    if priority == THREAD_PRIORITY_ERROR_RETURN {
+        return Err(windows::core::Error::from_win32().into());
+    }
+    Ok(THREAD_PRIORITY(priority))
+}
+
+/// Set the priority of the calling thread.
+///
+/// This is an alternative to registering the thread with MMCSS,
+/// for when that isn't available or desired.
+/// Note that a thread running at [ThreadPriority::TimeCritical] that never waits
+/// can starve other threads, including the ones of the audio engine.
+pub fn set_thread_priority(priority: &ThreadPriority) -> WasapiRes<()> {
+    unsafe { SetThreadPriority(GetCurrentThread(), priority.to_thread_priority())? };
+    debug!("set thread priority to {}", priority);
+    Ok(())
+}
+
+/// Get the priority of the calling thread.
+/// Returns an error if the priority isn't one of the [ThreadPriority] values,
+/// which can happen for threads in a process with the real-time priority class.
+pub fn get_thread_priority() -> WasapiRes<ThreadPriority> {
+    let raw = get_raw_thread_priority()?;
+    let priority = match raw {
+        THREAD_PRIORITY_IDLE => ThreadPriority::Idle,
+        THREAD_PRIORITY_LOWEST => ThreadPriority::Lowest,
+        THREAD_PRIORITY_BELOW_NORMAL => ThreadPriority::BelowNormal,
+        THREAD_PRIORITY_NORMAL => ThreadPriority::Normal,
+        THREAD_PRIORITY_ABOVE_NORMAL => ThreadPriority::AboveNormal,
+        THREAD_PRIORITY_HIGHEST => ThreadPriority::Highest,
+        THREAD_PRIORITY_TIME_CRITICAL => ThreadPriority::TimeCritical,
+        THREAD_PRIORITY(value) => {
+            return Err(
+                WasapiError::new(&format!("Got an unknown thread priority: {}", value)).into(),
+            )
+        }
+    };
+    Ok(priority)
+}
+
+/// Guard that raises the priority of the calling thread,
+/// and restores the previous priority when dropped.
+/// The guard must be dropped on the same thread, and therefore isn't `Send`.
+pub struct ThreadPriorityGuard {
+    previous: THREAD_PRIORITY,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ThreadPriorityGuard {
+    /// Set the priority of the calling thread, and remember the previous priority.
+    pub fn new(priority: &ThreadPriority) -> WasapiRes<Self> {
+        let previous = get_raw_thread_priority()?;
+        set_thread_priority(priority)?;
+        Ok(ThreadPriorityGuard {
+            previous,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for ThreadPriorityGuard {
+    fn drop(&mut self) {
+        if let Err(err) = unsafe { SetThreadPriority(GetCurrentThread(), self.previous) } {
+            warn!("Failed to restore thread priority, {}", err);
+        }
+    }
+}
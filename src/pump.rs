@@ -0,0 +1,348 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    AudioCaptureClient, AudioClient, BufferFlags, ChannelSendError, ChannelSender, Handle,
+    WasapiError, WasapiRes,
+};
+
+/// Policy for a [CapturePump] when the channel is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelFullPolicy {
+    /// Block until there is room in the channel.
+    /// The device buffer keeps filling up while blocked,
+    /// and may overflow if the receiver is too slow.
+    Block,
+    /// Drop the chunk and keep capturing.
+    DropChunk,
+}
+
+impl fmt::Display for ChannelFullPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ChannelFullPolicy::Block => write!(f, "Block"),
+            ChannelFullPolicy::DropChunk => write!(f, "DropChunk"),
+        }
+    }
+}
+
+/// Token for stopping a running [CapturePump] or [Worker](crate::Worker) from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct StopToken {
+    stopped: Arc<AtomicBool>,
+}
+
+impl StopToken {
+    /// Create a new [StopToken].
+    pub fn new() -> Self {
+        StopToken::default()
+    }
+
+    /// Request a stop. The pump stops after handling the current buffer event.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+
+    /// Check if a stop has been requested.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+}
+
+/// Event loop that reads from a capture client, and sends the data over a channel
+/// in chunks of a fixed number of frames.
+/// Any channel implementing [ChannelSender] can be used.
+/// Packets marked as silent are sent as zeros.
+///
+/// The pump runs on the calling thread, which is usually a thread dedicated to the capture.
+/// It runs until it is stopped with its [StopToken], the receiver is dropped,
+/// or an error occurs.
+pub struct CapturePump {
+    chunk_frames: usize,
+    policy: ChannelFullPolicy,
+    stop_token: StopToken,
+    dropped_chunks: usize,
+    high_water_frames: usize,
+}
+
+impl CapturePump {
+    /// Create a new [CapturePump] sending chunks of `chunk_frames` frames.
+    pub fn new(chunk_frames: usize, policy: &ChannelFullPolicy) -> Self {
+        CapturePump {
+            chunk_frames,
+            policy: *policy,
+            stop_token: StopToken::new(),
+            dropped_chunks: 0,
+            high_water_frames: 0,
+        }
+    }
+
+    /// Get a [StopToken] that can be used to stop the pump.
+    pub fn get_stop_token(&self) -> StopToken {
+        self.stop_token.clone()
+    }
+
+    /// Replace the [StopToken] of the pump, for example with the token of the [Worker](crate::Worker)
+    /// the pump runs in, so that stopping the worker also stops the pump.
+    pub fn set_stop_token(&mut self, stop_token: StopToken) {
+        self.stop_token = stop_token;
+    }
+
+    /// Get the number of chunks dropped because the channel was full.
+    pub fn get_dropped_chunks(&self) -> usize {
+        self.dropped_chunks
+    }
+
+    /// Get the highest number of frames that have been waiting in the internal queue.
+    /// The queue is emptied into chunks after each read from the device,
+    /// so it never holds more than one chunk plus the frames of one buffer event.
+    pub fn get_high_water_frames(&self) -> usize {
+        self.high_water_frames
+    }
+
+    /// Start the stream and run the event loop until stopped.
+    /// The `audio_client` must be initialized in event driven mode,
+    /// with `h_event` being the handle from [AudioClient::set_get_eventhandle].
+    /// The stream is stopped before returning.
+    ///
+    /// Returning because the receiver was dropped, or because of the [StopToken], is not an error.
+    /// Frames still queued that don't fill a whole chunk are discarded.
+    pub fn run(
+        &mut self,
+        audio_client: &AudioClient,
+        capture_client: &AudioCaptureClient,
+        h_event: &Handle,
+        timeout_ms: u32,
+        tx: &impl ChannelSender<Vec<u8>>,
+    ) -> WasapiRes<()> {
+        let bytes_per_frame = audio_client
+            .get_init_info()
+            .map(|info| info.format.get_blockalign() as usize)
+            .ok_or_else(|| WasapiError::new("Client has not been initialized"))?;
+        let chunk_bytes = self.chunk_frames * bytes_per_frame;
+        if chunk_bytes == 0 {
+            return Err(WasapiError::new("Chunk size must be non-zero").into());
+        }
+        let mut queue = VecDeque::with_capacity(2 * chunk_bytes);
+        audio_client.start_stream()?;
+        let result = self.pump(
+            capture_client,
+            h_event,
+            timeout_ms,
+            tx,
+            bytes_per_frame,
+            &mut queue,
+        );
+        audio_client.stop_stream()?;
+        result
+    }
+
+    fn pump(
+        &mut self,
+        capture_client: &AudioCaptureClient,
+        h_event: &Handle,
+        timeout_ms: u32,
+        tx: &impl ChannelSender<Vec<u8>>,
+        bytes_per_frame: usize,
+        queue: &mut VecDeque<u8>,
+    ) -> WasapiRes<()> {
+        let chunk_bytes = self.chunk_frames * bytes_per_frame;
+        while !self.stop_token.is_stopped() {
+            loop {
+                let buffer = capture_client.get_buffer()?;
+                if buffer.get_nbr_frames() == 0 {
+                    break;
+                }
+                queue_packet(queue, buffer.as_slice(), &buffer.get_info().flags);
+                buffer.release()?;
+            }
+            self.high_water_frames = self.high_water_frames.max(queue.len() / bytes_per_frame);
+            if !self.send_chunks(queue, chunk_bytes, tx) {
+                debug!("Receiver was dropped, stopping capture pump");
+                return Ok(());
+            }
+            h_event.wait_for_event(timeout_ms)?;
+        }
+        debug!("Capture pump was stopped");
+        Ok(())
+    }
+
+    /// Send all the whole chunks in the queue, according to the [ChannelFullPolicy].
+    /// Returns `false` if the receiver was dropped.
+    fn send_chunks(
+        &mut self,
+        queue: &mut VecDeque<u8>,
+        chunk_bytes: usize,
+        tx: &impl ChannelSender<Vec<u8>>,
+    ) -> bool {
+        while queue.len() >= chunk_bytes {
+            let chunk: Vec<u8> = queue.drain(..chunk_bytes).collect();
+            match self.policy {
+                ChannelFullPolicy::Block => {
+                    if tx.send_blocking(chunk).is_err() {
+                        return false;
+                    }
+                }
+                ChannelFullPolicy::DropChunk => match tx.try_send(chunk) {
+                    Ok(()) => {}
+                    Err(ChannelSendError::Full) => {
+                        self.dropped_chunks += 1;
+                        debug!(
+                            "Channel is full, dropped chunk, {} dropped in total",
+                            self.dropped_chunks
+                        );
+                    }
+                    Err(ChannelSendError::Disconnected) => return false,
+                },
+            }
+        }
+        true
+    }
+}
+
+/// Add the data of a captured packet to the queue.
+/// The data of a packet marked as silent must be ignored, and zeros are queued instead.
+fn queue_packet(queue: &mut VecDeque<u8>, data: &[u8], flags: &BufferFlags) {
+    if flags.silent {
+        queue.extend(iter::repeat(0).take(data.len()));
+    } else {
+        queue.extend(data.iter());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A channel that holds at most `capacity` chunks, and can be disconnected.
+    struct FakeSender {
+        sent: RefCell<Vec<Vec<u8>>>,
+        capacity: usize,
+        disconnected: bool,
+    }
+
+    impl FakeSender {
+        fn new(capacity: usize) -> Self {
+            FakeSender {
+                sent: RefCell::new(Vec::new()),
+                capacity,
+                disconnected: false,
+            }
+        }
+    }
+
+    impl ChannelSender<Vec<u8>> for FakeSender {
+        fn send_blocking(&self, value: Vec<u8>) -> Result<(), ChannelSendError> {
+            if self.disconnected {
+                return Err(ChannelSendError::Disconnected);
+            }
+            // Nothing would ever make room, a real channel would block forever
+            assert!(self.sent.borrow().len() < self.capacity);
+            self.sent.borrow_mut().push(value);
+            Ok(())
+        }
+
+        fn try_send(&self, value: Vec<u8>) -> Result<(), ChannelSendError> {
+            if self.disconnected {
+                return Err(ChannelSendError::Disconnected);
+            }
+            if self.sent.borrow().len() >= self.capacity {
+                return Err(ChannelSendError::Full);
+            }
+            self.sent.borrow_mut().push(value);
+            Ok(())
+        }
+    }
+
+    fn flags(silent: bool) -> BufferFlags {
+        BufferFlags {
+            data_discontinuity: false,
+            silent,
+            timestamp_error: false,
+        }
+    }
+
+    /// Queue the packets and send the chunks, like the pump does for each buffer event.
+    fn run_packets(
+        pump: &mut CapturePump,
+        packets: &[(Vec<u8>, BufferFlags)],
+        chunk_bytes: usize,
+        tx: &FakeSender,
+    ) -> (bool, VecDeque<u8>) {
+        let mut queue = VecDeque::new();
+        let mut connected = true;
+        for (data, flags) in packets {
+            queue_packet(&mut queue, data, flags);
+            connected = pump.send_chunks(&mut queue, chunk_bytes, tx);
+            if !connected {
+                break;
+            }
+        }
+        (connected, queue)
+    }
+
+    #[test]
+    fn packets_are_split_into_chunks() {
+        // Two bytes per frame and chunks of three frames
+        let mut pump = CapturePump::new(3, &ChannelFullPolicy::Block);
+        let tx = FakeSender::new(10);
+        let packets = vec![
+            ((0..4).collect::<Vec<u8>>(), flags(false)),
+            ((4..14).collect(), flags(false)),
+            ((14..17).collect(), flags(false)),
+        ];
+        let (connected, queue) = run_packets(&mut pump, &packets, 6, &tx);
+        assert!(connected);
+        assert_eq!(
+            *tx.sent.borrow(),
+            vec![(0..6).collect::<Vec<u8>>(), (6..12).collect()]
+        );
+        // The remainder waits for the next packet
+        assert_eq!(queue, (12..17).collect::<VecDeque<u8>>());
+    }
+
+    #[test]
+    fn silent_packets_are_sent_as_zeros() {
+        let mut pump = CapturePump::new(2, &ChannelFullPolicy::Block);
+        let tx = FakeSender::new(10);
+        let packets = vec![
+            (vec![1, 2], flags(false)),
+            (vec![3, 4, 5, 6], flags(true)),
+            (vec![7, 8], flags(false)),
+        ];
+        let (connected, queue) = run_packets(&mut pump, &packets, 4, &tx);
+        assert!(connected);
+        assert_eq!(*tx.sent.borrow(), vec![vec![1, 2, 0, 0], vec![0, 0, 7, 8]]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn full_channel_drops_chunks() {
+        let mut pump = CapturePump::new(1, &ChannelFullPolicy::DropChunk);
+        let tx = FakeSender::new(2);
+        let packets = vec![(vec![1, 2, 3, 4], flags(false)), (vec![5], flags(false))];
+        let (connected, queue) = run_packets(&mut pump, &packets, 1, &tx);
+        assert!(connected);
+        assert_eq!(*tx.sent.borrow(), vec![vec![1], vec![2]]);
+        assert_eq!(pump.get_dropped_chunks(), 3);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn disconnected_receiver_stops_sending() {
+        for policy in [ChannelFullPolicy::Block, ChannelFullPolicy::DropChunk] {
+            let mut pump = CapturePump::new(1, &policy);
+            let mut tx = FakeSender::new(10);
+            tx.disconnected = true;
+            let packets = vec![(vec![1, 2], flags(false))];
+            let (connected, _) = run_packets(&mut pump, &packets, 1, &tx);
+            assert!(!connected);
+            assert!(tx.sent.borrow().is_empty());
+            assert_eq!(pump.get_dropped_chunks(), 0);
+        }
+    }
+}
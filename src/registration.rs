@@ -0,0 +1,82 @@
+use windows::core::{AgileReference, Interface};
+use windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolumeCallback;
+use windows::Win32::Media::Audio::{
+    IAudioSessionEvents, IAudioSessionNotification, IAudioVolumeDuckNotification,
+    IMMNotificationClient,
+};
+
+use crate::WasapiRes;
+
+/// Marker for the notification interfaces that are implemented by the notification objects of this crate.
+///
+/// # Safety
+///
+/// Only implement this for interfaces where all objects passed to [AgileRegistration::register]
+/// are notification objects of this crate. These only hold callbacks and data that are `Send + Sync`,
+/// so that they can be called from several threads at once and released from any thread.
+pub(crate) unsafe trait AgileNotifier: Interface {}
+
+unsafe impl AgileNotifier for IAudioEndpointVolumeCallback {}
+unsafe impl AgileNotifier for IAudioSessionEvents {}
+unsafe impl AgileNotifier for IAudioSessionNotification {}
+unsafe impl AgileNotifier for IAudioVolumeDuckNotification {}
+unsafe impl AgileNotifier for IMMNotificationClient {}
+
+/// Unregisters a notification object from the object it was registered on.
+type UnregisterFn<S, N> = fn(&S, &N) -> windows::core::Result<()>;
+
+/// Registration of a notification object, implemented by this crate, on a Windows object.
+/// The notification object is unregistered when the registration is dropped.
+///
+/// The Windows object is held as an agile reference, that is resolved in the context of the thread
+/// that drops the registration.
+/// The notification object is one of the notification objects of this crate, see [AgileNotifier],
+/// that can be called and released from any thread.
+/// Together this makes it safe to move the registration to another thread, and to drop it there.
+/// COM must be initialized on the thread that drops the registration,
+/// otherwise the notification object can't be unregistered, and a warning is logged.
+pub(crate) struct AgileRegistration<S: Interface, N: AgileNotifier> {
+    source: AgileReference<S>,
+    notifier: N,
+    unregister: UnregisterFn<S, N>,
+    description: &'static str,
+}
+
+// The notifier is only used for unregistering, and is one of the notification objects of this crate,
+// that are safe to use from any thread. The source is only used through the agile reference.
+unsafe impl<S: Interface, N: AgileNotifier> Send for AgileRegistration<S, N> {}
+unsafe impl<S: Interface, N: AgileNotifier> Sync for AgileRegistration<S, N> {}
+
+impl<S: Interface, N: AgileNotifier> AgileRegistration<S, N> {
+    /// Register `notifier` on `source` with the `register` function,
+    /// and keep what is needed for unregistering it with the `unregister` function.
+    /// The description is used in the log message if unregistering fails.
+    pub(crate) fn register(
+        source: &S,
+        notifier: N,
+        register: impl FnOnce(&S, &N) -> WasapiRes<()>,
+        unregister: UnregisterFn<S, N>,
+        description: &'static str,
+    ) -> WasapiRes<Self> {
+        let agile_source = AgileReference::new(source)?;
+        register(source, &notifier)?;
+        Ok(AgileRegistration {
+            source: agile_source,
+            notifier,
+            unregister,
+            description,
+        })
+    }
+}
+
+impl<S: Interface, N: AgileNotifier> Drop for AgileRegistration<S, N> {
+    fn drop(&mut self) {
+        let result = self
+            .source
+            .resolve()
+            .and_then(|source| (self.unregister)(&source, &self.notifier));
+        if let Err(err) = result {
+            warn!("Failed to unregister {}, {}", self.description, err);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+use crate::{WasapiError, WasapiRes};
+
+// Downmix coefficient for the center and surround channels, -3 dB.
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Channel layouts supported by the remixing helpers.
+/// The channel order of the interleaved frames follows the
+/// [WAVEFORMATEXTENSIBLE](https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible) convention.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelLayout {
+    /// A single channel.
+    Mono,
+    /// Front left, front right.
+    Stereo,
+    /// Front left, front right, front center, low frequency, surround left, surround right.
+    Surround51,
+}
+
+impl fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ChannelLayout::Mono => write!(f, "Mono"),
+            ChannelLayout::Stereo => write!(f, "Stereo"),
+            ChannelLayout::Surround51 => write!(f, "Surround51"),
+        }
+    }
+}
+
+impl ChannelLayout {
+    /// Get the number of channels in this layout.
+    pub fn get_nchannels(&self) -> usize {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Surround51 => 6,
+        }
+    }
+
+    /// Get the layout for a number of channels, if there is one.
+    pub fn from_nchannels(channels: usize) -> Option<Self> {
+        match channels {
+            1 => Some(ChannelLayout::Mono),
+            2 => Some(ChannelLayout::Stereo),
+            6 => Some(ChannelLayout::Surround51),
+            _ => None,
+        }
+    }
+}
+
+/// Get the mixing matrix for converting between two layouts.
+/// There is one row per output channel, and one column per input channel.
+fn mixing_matrix(from: &ChannelLayout, to: &ChannelLayout) -> Vec<Vec<f32>> {
+    match (from, to) {
+        (ChannelLayout::Mono, ChannelLayout::Stereo) => vec![vec![1.0], vec![1.0]],
+        (ChannelLayout::Mono, ChannelLayout::Surround51) => vec![
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![0.0],
+            vec![0.0],
+            vec![0.0],
+        ],
+        (ChannelLayout::Stereo, ChannelLayout::Mono) => vec![vec![0.5, 0.5]],
+        (ChannelLayout::Stereo, ChannelLayout::Surround51) => vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![0.0, 0.0],
+            vec![0.0, 0.0],
+            vec![0.0, 0.0],
+            vec![0.0, 0.0],
+        ],
+        // Standard ITU-R BS.775 downmix, the LFE channel is discarded.
+        (ChannelLayout::Surround51, ChannelLayout::Stereo) => vec![
+            vec![1.0, 0.0, MINUS_3DB, 0.0, MINUS_3DB, 0.0],
+            vec![0.0, 1.0, MINUS_3DB, 0.0, 0.0, MINUS_3DB],
+        ],
+        (ChannelLayout::Surround51, ChannelLayout::Mono) => vec![vec![
+            0.5,
+            0.5,
+            MINUS_3DB,
+            0.0,
+            0.5 * MINUS_3DB,
+            0.5 * MINUS_3DB,
+        ]],
+        _ => {
+            let channels = from.get_nchannels();
+            (0..channels)
+                .map(|out| (0..channels).map(|inp| (out == inp) as u8 as f32).collect())
+                .collect()
+        }
+    }
+}
+
+/// Convert interleaved f32 frames from one channel layout to another.
+///
+/// The supported conversions are:
+/// - Stereo to mono: the two channels are summed and scaled by 0.5 to avoid clipping.
+/// - Mono to stereo: the channel is duplicated.
+/// - 5.1 to stereo: using the standard coefficients, center and surround channels at -3 dB, LFE discarded.
+/// - 5.1 to mono: the stereo downmix, summed and scaled by 0.5.
+/// - Mono and stereo to 5.1: mono goes to the center channel, stereo to the front left and right.
+///
+/// The input length must be a whole number of frames.
+pub fn remix_f32(input: &[f32], from: &ChannelLayout, to: &ChannelLayout) -> WasapiRes<Vec<f32>> {
+    let in_channels = from.get_nchannels();
+    if input.len() % in_channels != 0 {
+        return Err(WasapiError::new(
+            format!(
+                "Wrong length of data, got {} samples, not a multiple of {} channels",
+                input.len(),
+                in_channels
+            )
+            .as_str(),
+        )
+        .into());
+    }
+    let matrix = mixing_matrix(from, to);
+    let mut output = Vec::with_capacity(input.len() / in_channels * to.get_nchannels());
+    for frame in input.chunks_exact(in_channels) {
+        for row in matrix.iter() {
+            let value = row
+                .iter()
+                .zip(frame.iter())
+                .map(|(coeff, sample)| coeff * sample)
+                .sum();
+            output.push(value);
+        }
+    }
+    trace!(
+        "remixed {} frames from {} to {}",
+        input.len() / in_channels,
+        from,
+        to
+    );
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(left: &[f32], right: &[f32]) {
+        assert_eq!(left.len(), right.len());
+        for (l, r) in left.iter().zip(right.iter()) {
+            assert!((l - r).abs() < 1e-6, "{:?} != {:?}", left, right);
+        }
+    }
+
+    #[test]
+    fn surround51_to_stereo_uses_itu_coefficients() {
+        let frame = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let out = remix_f32(&frame, &ChannelLayout::Surround51, &ChannelLayout::Stereo).unwrap();
+        assert_close(&out, &[1.0 + MINUS_3DB * 8.0, 2.0 + MINUS_3DB * 9.0]);
+        // The LFE channel is discarded.
+        let lfe = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let out = remix_f32(&lfe, &ChannelLayout::Surround51, &ChannelLayout::Stereo).unwrap();
+        assert_close(&out, &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn surround51_to_mono_is_scaled_stereo_downmix() {
+        let frame = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mono = remix_f32(&frame, &ChannelLayout::Surround51, &ChannelLayout::Mono).unwrap();
+        let stereo = remix_f32(&frame, &ChannelLayout::Surround51, &ChannelLayout::Stereo).unwrap();
+        assert_close(&mono, &[0.5 * (stereo[0] + stereo[1])]);
+    }
+
+    #[test]
+    fn mono_and_stereo_conversions() {
+        let out = remix_f32(&[0.5], &ChannelLayout::Mono, &ChannelLayout::Surround51).unwrap();
+        assert_close(&out, &[0.0, 0.0, 0.5, 0.0, 0.0, 0.0]);
+        let out = remix_f32(&[0.5, -0.5], &ChannelLayout::Mono, &ChannelLayout::Stereo).unwrap();
+        assert_close(&out, &[0.5, 0.5, -0.5, -0.5]);
+        let out = remix_f32(&[0.2, 0.4], &ChannelLayout::Stereo, &ChannelLayout::Mono).unwrap();
+        assert_close(&out, &[0.3]);
+        let out = remix_f32(
+            &[0.2, 0.4],
+            &ChannelLayout::Stereo,
+            &ChannelLayout::Surround51,
+        )
+        .unwrap();
+        assert_close(&out, &[0.2, 0.4, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn same_layout_is_identity() {
+        for layout in [
+            ChannelLayout::Mono,
+            ChannelLayout::Stereo,
+            ChannelLayout::Surround51,
+        ] {
+            let input: Vec<f32> = (0..2 * layout.get_nchannels())
+                .map(|n| n as f32 / 10.0)
+                .collect();
+            let out = remix_f32(&input, &layout, &layout).unwrap();
+            assert_eq!(out, input);
+        }
+    }
+
+    #[test]
+    fn partial_frame_is_rejected() {
+        let input = [0.0; 7];
+        assert!(remix_f32(&input, &ChannelLayout::Surround51, &ChannelLayout::Stereo).is_err());
+        assert!(remix_f32(&input[..3], &ChannelLayout::Stereo, &ChannelLayout::Mono).is_err());
+    }
+}
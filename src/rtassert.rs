@@ -0,0 +1,91 @@
+//! Support for checking that the real-time paths do not allocate.
+//!
+//! With the `rt-assert` feature enabled, the crate provides [RtAssertAllocator],
+//! a global allocator that counts the allocations made by each thread.
+//! When it is installed, the real-time methods panic if they allocate after initialization.
+//! Without the feature, the checks compile to nothing.
+
+#[cfg(feature = "rt-assert")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(feature = "rt-assert")]
+use std::cell::Cell;
+
+#[cfg(feature = "rt-assert")]
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A global allocator that counts allocations per thread, for use with the `rt-assert` feature.
+/// Install it in the application with:
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: wasapi::RtAssertAllocator = wasapi::RtAssertAllocator;
+/// ```
+/// This is meant for debugging only, it should not be used in release builds.
+#[cfg(feature = "rt-assert")]
+pub struct RtAssertAllocator;
+
+#[cfg(feature = "rt-assert")]
+fn count_allocation() {
+    // The thread local may already be destroyed when a thread exits.
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+#[cfg(feature = "rt-assert")]
+unsafe impl GlobalAlloc for RtAssertAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Records the allocation count of the current thread when created,
+/// and checks that it is unchanged when asked to.
+pub(crate) struct NoAllocGuard {
+    #[cfg(feature = "rt-assert")]
+    start: usize,
+}
+
+#[cfg(feature = "rt-assert")]
+impl NoAllocGuard {
+    pub(crate) fn new() -> Self {
+        NoAllocGuard {
+            start: ALLOCATIONS.with(|count| count.get()),
+        }
+    }
+
+    pub(crate) fn assert_no_alloc(&self, context: &str) {
+        let allocations = ALLOCATIONS.with(|count| count.get()) - self.start;
+        assert!(
+            allocations == 0,
+            "{} made {} heap allocations",
+            context,
+            allocations
+        );
+    }
+}
+
+#[cfg(not(feature = "rt-assert"))]
+impl NoAllocGuard {
+    #[inline(always)]
+    pub(crate) fn new() -> Self {
+        NoAllocGuard {}
+    }
+
+    #[inline(always)]
+    pub(crate) fn assert_no_alloc(&self, _context: &str) {}
+}
@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use crate::{
+    AudioCaptureClient, AudioClient, AudioRenderClient, Device, Direction, Handle, SampleType,
+    ShareMode, WasapiError, WasapiRes, WaveFormat,
+};
+
+/// Create and initialize a shared mode client for interleaved 32-bit float samples.
+/// The audio engine converts to and from the mix format of the device.
+fn initialize_f32_client(
+    device: &Device,
+    direction: &Direction,
+    samplerate: usize,
+    channels: usize,
+    period: Option<i64>,
+) -> WasapiRes<(AudioClient, Handle)> {
+    if channels == 0 || samplerate == 0 {
+        return Err(WasapiError::new("Sample rate and number of channels must be non-zero").into());
+    }
+    let mut audio_client = device.get_iaudioclient()?;
+    let format = WaveFormat::new(32, 32, &SampleType::Float, samplerate, channels, None);
+    let period = match period {
+        Some(period) => period,
+        None => audio_client.get_periods()?.0,
+    };
+    audio_client.initialize_client(&format, period, direction, &ShareMode::Shared, true)?;
+    let h_event = audio_client.set_get_eventhandle()?;
+    Ok((audio_client, h_event))
+}
+
+/// Convert a time in 100-nanosecond units to a [Duration].
+fn duration_from_100ns(time: i64) -> Duration {
+    Duration::from_nanos(100 * time.max(0) as u64)
+}
+
+/// Get the duration of the buffer of an initialized client.
+fn buffer_duration(audio_client: &AudioClient) -> WasapiRes<Duration> {
+    let samplerate = audio_client
+        .get_init_info()
+        .map(|info| info.format.get_samplespersec())
+        .ok_or_else(|| WasapiError::new("Client has not been initialized"))?;
+    let buffer_frames = audio_client.get_bufferframecount()?;
+    Ok(Duration::from_secs_f64(
+        buffer_frames as f64 / samplerate as f64,
+    ))
+}
+
+/// A shared mode render stream for interleaved 32-bit float samples.
+///
+/// The stream is always initialized with automatic format conversion,
+/// so any sample rate and number of channels can be used regardless of the mix format of the device.
+/// Data is written as `f32` samples, without dealing with bytes or formats.
+pub struct SharedF32Render {
+    audio_client: AudioClient,
+    render_client: AudioRenderClient,
+    h_event: Handle,
+    channels: usize,
+}
+
+impl SharedF32Render {
+    /// Create a new render stream on a device.
+    /// The `period` is in 100-nanosecond units, use `None` for the default period of the device.
+    pub fn new(
+        device: &Device,
+        samplerate: usize,
+        channels: usize,
+        period: Option<i64>,
+    ) -> WasapiRes<Self> {
+        let (audio_client, h_event) =
+            initialize_f32_client(device, &Direction::Render, samplerate, channels, period)?;
+        let render_client = audio_client.get_audiorenderclient()?;
+        Ok(SharedF32Render {
+            audio_client,
+            render_client,
+            h_event,
+            channels,
+        })
+    }
+
+    /// Start the stream.
+    pub fn start(&self) -> WasapiRes<()> {
+        self.audio_client.start_stream()
+    }
+
+    /// Stop the stream.
+    pub fn stop(&self) -> WasapiRes<()> {
+        self.audio_client.stop_stream()
+    }
+
+    /// Wait for the device to request more data, with a timeout given in ms.
+    pub fn wait_for_event(&self, timeout_ms: u32) -> WasapiRes<()> {
+        self.h_event.wait_for_event(timeout_ms)
+    }
+
+    /// Get the number of frames that can currently be written.
+    pub fn get_available_frames(&self) -> WasapiRes<usize> {
+        let frames = self.audio_client.get_available_space_in_frames()?;
+        Ok(frames as usize)
+    }
+
+    /// Write interleaved samples to the device.
+    /// The data is written up to the available space, see [SharedF32Render::get_available_frames].
+    /// Samples of an incomplete frame at the end of the data are ignored.
+    /// Returns the number of frames written.
+    pub fn write(&self, data: &[f32]) -> WasapiRes<usize> {
+        let data_frames = data.len() / self.channels;
+        let nbr_frames = data_frames.min(self.get_available_frames()?);
+        if nbr_frames == 0 {
+            return Ok(0);
+        }
+        let mut buffer = self.render_client.get_buffer(nbr_frames)?;
+        for (bytes, sample) in buffer.as_mut_slice().chunks_exact_mut(4).zip(data.iter()) {
+            bytes.copy_from_slice(&sample.to_le_bytes());
+        }
+        buffer.release()?;
+        Ok(nbr_frames)
+    }
+
+    /// Get the number of channels.
+    pub fn get_channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Get the latency reported by the audio engine and the device, not including the buffer,
+    /// see [AudioClient::get_stream_latency].
+    pub fn get_reported_latency(&self) -> WasapiRes<Duration> {
+        Ok(duration_from_100ns(self.audio_client.get_stream_latency()?))
+    }
+
+    /// Get the period of the buffer events.
+    /// In shared mode this is the default period of the device.
+    pub fn get_period(&self) -> WasapiRes<Duration> {
+        Ok(duration_from_100ns(self.audio_client.get_periods()?.0))
+    }
+
+    /// Get the duration of the allocated buffer.
+    pub fn get_buffer_size(&self) -> WasapiRes<Duration> {
+        buffer_duration(&self.audio_client)
+    }
+
+    /// Get the underlying [AudioClient], for example to read the [AudioClient::get_audioclock].
+    pub fn get_audio_client(&self) -> &AudioClient {
+        &self.audio_client
+    }
+}
+
+/// A shared mode capture stream for interleaved 32-bit float samples.
+///
+/// The stream is always initialized with automatic format conversion,
+/// so any sample rate and number of channels can be used regardless of the mix format of the device.
+/// If the device is a render device, its output is captured in loopback mode.
+/// Data is read as `f32` samples, without dealing with bytes or formats.
+pub struct SharedF32Capture {
+    audio_client: AudioClient,
+    capture_client: AudioCaptureClient,
+    h_event: Handle,
+    channels: usize,
+}
+
+impl SharedF32Capture {
+    /// Create a new capture stream on a device.
+    /// The `period` is in 100-nanosecond units, use `None` for the default period of the device.
+    pub fn new(
+        device: &Device,
+        samplerate: usize,
+        channels: usize,
+        period: Option<i64>,
+    ) -> WasapiRes<Self> {
+        let (audio_client, h_event) =
+            initialize_f32_client(device, &Direction::Capture, samplerate, channels, period)?;
+        let capture_client = audio_client.get_audiocaptureclient()?;
+        Ok(SharedF32Capture {
+            audio_client,
+            capture_client,
+            h_event,
+            channels,
+        })
+    }
+
+    /// Start the stream.
+    pub fn start(&self) -> WasapiRes<()> {
+        self.audio_client.start_stream()
+    }
+
+    /// Stop the stream.
+    pub fn stop(&self) -> WasapiRes<()> {
+        self.audio_client.stop_stream()
+    }
+
+    /// Wait for the device to provide more data, with a timeout given in ms.
+    pub fn wait_for_event(&self, timeout_ms: u32) -> WasapiRes<()> {
+        self.h_event.wait_for_event(timeout_ms)
+    }
+
+    /// Read all available data, and append the interleaved samples to the end of `data`.
+    /// Packets marked as silent are read as zeros.
+    /// Returns the number of frames read.
+    pub fn read(&self, data: &mut Vec<f32>) -> WasapiRes<usize> {
+        let mut nbr_frames = 0;
+        loop {
+            let buffer = self.capture_client.get_buffer()?;
+            let frames = buffer.get_nbr_frames();
+            if frames == 0 {
+                break;
+            }
+            if buffer.get_info().flags.silent {
+                data.resize(data.len() + frames * self.channels, 0.0);
+            } else {
+                data.extend(
+                    buffer
+                        .as_slice()
+                        .chunks_exact(4)
+                        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+                );
+            }
+            buffer.release()?;
+            nbr_frames += frames;
+        }
+        Ok(nbr_frames)
+    }
+
+    /// Get the number of channels.
+    pub fn get_channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Get the latency reported by the audio engine and the device, not including the buffer,
+    /// see [AudioClient::get_stream_latency].
+    pub fn get_reported_latency(&self) -> WasapiRes<Duration> {
+        Ok(duration_from_100ns(self.audio_client.get_stream_latency()?))
+    }
+
+    /// Get the period of the buffer events.
+    /// In shared mode this is the default period of the device.
+    pub fn get_period(&self) -> WasapiRes<Duration> {
+        Ok(duration_from_100ns(self.audio_client.get_periods()?.0))
+    }
+
+    /// Get the duration of the allocated buffer.
+    pub fn get_buffer_size(&self) -> WasapiRes<Duration> {
+        buffer_duration(&self.audio_client)
+    }
+
+    /// Get the underlying [AudioClient], for example to read the [AudioClient::get_audioclock].
+    pub fn get_audio_client(&self) -> &AudioClient {
+        &self.audio_client
+    }
+}
@@ -0,0 +1,102 @@
+//! Simulation of the errors that streams get around system sleep and resume,
+//! and when the audio service restarts.
+//!
+//! These errors are difficult to trigger on demand, which makes the recovery code
+//! of long-running applications hard to test.
+//! With the `simulate-errors` feature enabled, [simulate_stream_error] makes the next call to
+//! one of the buffer methods return the chosen error, exactly as if it came from Windows.
+//! Without the feature, the check compiles to nothing.
+use windows::core::Error;
+
+#[cfg(feature = "simulate-errors")]
+use std::{
+    fmt,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+#[cfg(feature = "simulate-errors")]
+use windows::{
+    core::HRESULT,
+    Win32::Media::Audio::{
+        AUDCLNT_E_DEVICE_INVALIDATED, AUDCLNT_E_RESOURCES_INVALIDATED,
+        AUDCLNT_E_SERVICE_NOT_RUNNING,
+    },
+};
+
+/// The HRESULT of the pending simulated error, or zero if there is none.
+#[cfg(feature = "simulate-errors")]
+static PENDING_ERROR: AtomicI32 = AtomicI32::new(0);
+
+/// Error that can be simulated with [simulate_stream_error].
+#[cfg(feature = "simulate-errors")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SimulatedError {
+    /// `AUDCLNT_E_DEVICE_INVALIDATED`, the device was removed or disabled.
+    DeviceInvalidated,
+    /// `AUDCLNT_E_RESOURCES_INVALIDATED`, the stream resources were lost, typically after the system resumed from sleep.
+    ResourcesInvalidated,
+    /// `AUDCLNT_E_SERVICE_NOT_RUNNING`, the audio service was stopped or restarted.
+    ServiceNotRunning,
+}
+
+#[cfg(feature = "simulate-errors")]
+impl fmt::Display for SimulatedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SimulatedError::DeviceInvalidated => write!(f, "DeviceInvalidated"),
+            SimulatedError::ResourcesInvalidated => write!(f, "ResourcesInvalidated"),
+            SimulatedError::ServiceNotRunning => write!(f, "ServiceNotRunning"),
+        }
+    }
+}
+
+#[cfg(feature = "simulate-errors")]
+impl SimulatedError {
+    fn to_hresult(self) -> HRESULT {
+        match self {
+            SimulatedError::DeviceInvalidated => AUDCLNT_E_DEVICE_INVALIDATED,
+            SimulatedError::ResourcesInvalidated => AUDCLNT_E_RESOURCES_INVALIDATED,
+            SimulatedError::ServiceNotRunning => AUDCLNT_E_SERVICE_NOT_RUNNING,
+        }
+    }
+}
+
+/// Make the next call to one of the buffer methods return the given error.
+/// The methods that check for a simulated error are
+/// [AudioClient::get_current_padding](crate::AudioClient::get_current_padding),
+/// [AudioClient::get_available_space_in_frames](crate::AudioClient::get_available_space_in_frames),
+/// [AudioCaptureClient::get_next_nbr_frames](crate::AudioCaptureClient::get_next_nbr_frames),
+/// and the methods that get a buffer from the device, like
+/// [AudioRenderClient::write_to_device](crate::AudioRenderClient::write_to_device) and
+/// [AudioCaptureClient::read_from_device](crate::AudioCaptureClient::read_from_device).
+///
+/// The error is shared by all streams of the process, and is returned only once,
+/// by whichever stream makes the next call.
+/// This is only available when the `simulate-errors` feature is enabled.
+#[cfg(feature = "simulate-errors")]
+pub fn simulate_stream_error(error: &SimulatedError) {
+    debug!("Simulating stream error {}", error);
+    PENDING_ERROR.store(error.to_hresult().0, Ordering::SeqCst);
+}
+
+/// Remove a pending simulated error, that has not yet been returned.
+/// This is only available when the `simulate-errors` feature is enabled.
+#[cfg(feature = "simulate-errors")]
+pub fn clear_simulated_stream_error() {
+    PENDING_ERROR.store(0, Ordering::SeqCst);
+}
+
+/// Return the pending simulated error, if there is one.
+#[cfg(feature = "simulate-errors")]
+pub(crate) fn check_simulated_error() -> Result<(), Error> {
+    match PENDING_ERROR.swap(0, Ordering::SeqCst) {
+        0 => Ok(()),
+        hresult => Err(Error::from(HRESULT(hresult))),
+    }
+}
+
+#[cfg(not(feature = "simulate-errors"))]
+#[inline(always)]
+pub(crate) fn check_simulated_error() -> Result<(), Error> {
+    Ok(())
+}
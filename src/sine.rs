@@ -0,0 +1,107 @@
+//! Sine wave generator, used by the examples and the latency measurement.
+//!
+//! It is re-exported by the `testsignal` module and by [crate::examples_common],
+//! so that it is available also without the `testsignal` feature.
+use std::f64::consts::PI;
+
+/// Sine wave generator.
+pub struct SineGenerator {
+    phase: f64,
+    phase_step: f64,
+    amplitude: f64,
+}
+
+impl SineGenerator {
+    /// Create a new [SineGenerator] with the given frequency in Hz,
+    /// sample rate in Hz and amplitude.
+    pub fn new(freq: f64, samplerate: f64, amplitude: f64) -> Self {
+        SineGenerator {
+            phase: 0.0,
+            phase_step: 2.0 * PI * freq / samplerate,
+            amplitude,
+        }
+    }
+
+    /// Fill a buffer of interleaved 32-bit float samples,
+    /// writing the same sample to all channels of each frame.
+    /// Use `fill_frames` from the `testsignal` module for other sample formats.
+    pub fn fill_interleaved_f32(&mut self, data: &mut [u8], channels: usize) {
+        let blockalign = 4 * channels;
+        for frame in data.chunks_exact_mut(blockalign) {
+            let sample_bytes = self.next().unwrap_or_default().to_le_bytes();
+            for value in frame.chunks_exact_mut(4) {
+                value.copy_from_slice(&sample_bytes);
+            }
+        }
+    }
+}
+
+impl Iterator for SineGenerator {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        let output = (self.phase.sin() * self.amplitude) as f32;
+        self.phase = (self.phase + self.phase_step) % (2.0 * PI);
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read the first channel of each frame from a buffer of interleaved 32-bit float samples.
+    fn first_channel(data: &[u8], channels: usize) -> Vec<f32> {
+        data.chunks_exact(4 * channels)
+            .map(|frame| f32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn phase_is_continuous_across_calls() {
+        let mut gen = SineGenerator::new(1000.0, 48000.0, 1.0);
+        let mut first = vec![0u8; 4 * 100];
+        let mut second = vec![0u8; 4 * 100];
+        gen.fill_interleaved_f32(&mut first, 1);
+        gen.fill_interleaved_f32(&mut second, 1);
+        let mut samples = first_channel(&first, 1);
+        samples.extend(first_channel(&second, 1));
+
+        let expected: Vec<f32> = SineGenerator::new(1000.0, 48000.0, 1.0).take(200).collect();
+        assert_eq!(samples, expected);
+        // Any three consecutive samples of a sine satisfy x[n+1] + x[n-1] = 2 cos(w) x[n],
+        // also at the boundary between the calls.
+        let coeff = 2.0 * (2.0 * PI * 1000.0 / 48000.0).cos();
+        for window in samples.windows(3) {
+            let predicted = coeff * window[1] as f64 - window[0] as f64;
+            assert!((window[2] as f64 - predicted).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn samples_are_within_amplitude() {
+        let gen = SineGenerator::new(997.0, 44100.0, 0.25);
+        let samples: Vec<f32> = gen.take(44100).collect();
+        assert!(samples.iter().all(|value| value.abs() <= 0.25));
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, value| peak.max(value.abs()));
+        assert!(peak > 0.249);
+    }
+
+    #[test]
+    fn interleaved_channels_get_the_same_sample() {
+        let channels = 3;
+        let mut gen = SineGenerator::new(440.0, 48000.0, 0.5);
+        // Ten frames and a partial frame at the end, that must be left unchanged.
+        let mut data = vec![0xAAu8; 4 * channels * 10 + 4];
+        gen.fill_interleaved_f32(&mut data, channels);
+
+        let expected: Vec<f32> = SineGenerator::new(440.0, 48000.0, 0.5).take(10).collect();
+        for (frame, value) in data.chunks_exact(4 * channels).zip(expected) {
+            for sample in frame.chunks_exact(4) {
+                assert_eq!(sample, value.to_le_bytes());
+            }
+        }
+        assert_eq!(data[4 * channels * 10..], [0xAA; 4]);
+    }
+}
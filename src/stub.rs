@@ -0,0 +1,394 @@
+// Stand-ins for the Windows system functions used by this crate and the `windows` crate,
+// for building on other targets with the `stub` feature.
+//
+// On targets other than Windows, the `windows` crate declares the system functions without a library to link them from.
+// The definitions below fill in these symbols, so that applications link,
+// and each function fails the way the real one does when something is not supported.
+// Initializing COM and creating the device enumerator return `E_NOTIMPL`,
+// and functions that report errors with `GetLastError` get `ERROR_CALL_NOT_IMPLEMENTED`.
+// All devices and clients are created through COM, so every method that talks to the system returns an error.
+// The functions that release memory and clear variants do nothing.
+//
+// The signatures follow the declarations in the `windows` crate, with the handles and flags as plain integers.
+//
+// The symbols are global, so they collide with any other definition of the same functions,
+// for example from another version of this crate with the feature enabled.
+// This is documented for users in the crate docs and the readme.
+
+#![allow(non_snake_case, clippy::upper_case_acronyms)]
+
+use std::ffi::c_void;
+use std::ptr;
+
+type HRESULT = i32;
+type BOOL = i32;
+type HANDLE = isize;
+
+const S_OK: HRESULT = 0;
+const S_FALSE: HRESULT = 1;
+const E_NOTIMPL: HRESULT = 0x80004001_u32 as i32;
+const FALSE: BOOL = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+const WAIT_FAILED: u32 = 0xFFFFFFFF;
+const THREAD_PRIORITY_ERROR_RETURN: i32 = 0x7FFFFFFF;
+const STATUS_NOT_IMPLEMENTED: i32 = 0xC0000002_u32 as i32;
+// The pseudo handle returned by GetCurrentThread on Windows.
+const CURRENT_THREAD: HANDLE = -2;
+
+// COM
+
+#[no_mangle]
+extern "system" fn CoInitializeEx(_reserved: *const c_void, _coinit: u32) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn CoUninitialize() {}
+
+#[no_mangle]
+extern "system" fn CoIncrementMTAUsage(_cookie: *mut c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn CoGetApartmentType(_apttype: *mut i32, _qualifier: *mut i32) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn CoCreateInstance(
+    _clsid: *const c_void,
+    _outer: *mut c_void,
+    _context: u32,
+    _iid: *const c_void,
+    _ppv: *mut *mut c_void,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn CoCreateGuid(_guid: *mut c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn CoTaskMemAlloc(_size: usize) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+extern "system" fn CoTaskMemFree(_pv: *const c_void) {}
+
+#[no_mangle]
+extern "system" fn IIDFromString(_string: *const u16, _iid: *mut c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn GetErrorInfo(_reserved: u32, _errorinfo: *mut *mut c_void) -> HRESULT {
+    // There is never any error info
+    S_FALSE
+}
+
+#[no_mangle]
+extern "system" fn SetErrorInfo(_reserved: u32, _errorinfo: *mut c_void) -> HRESULT {
+    S_OK
+}
+
+#[no_mangle]
+extern "system" fn RoGetAgileReference(
+    _options: i32,
+    _iid: *const c_void,
+    _unknown: *mut c_void,
+    _agile: *mut *mut c_void,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn RoGetActivationFactory(
+    _class_id: *mut c_void,
+    _iid: *const c_void,
+    _factory: *mut *mut c_void,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn ActivateAudioInterfaceAsync(
+    _path: *const u16,
+    _iid: *const c_void,
+    _params: *const c_void,
+    _handler: *mut c_void,
+    _operation: *mut *mut c_void,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+// Strings and variants
+
+#[no_mangle]
+extern "system" fn SysAllocStringLen(_string: *const u16, _len: u32) -> *const u16 {
+    ptr::null()
+}
+
+#[no_mangle]
+extern "system" fn SysFreeString(_string: *const u16) {}
+
+#[no_mangle]
+extern "system" fn SysStringLen(_string: *const u16) -> u32 {
+    0
+}
+
+#[no_mangle]
+extern "system" fn PropVariantClear(_var: *mut c_void) -> HRESULT {
+    S_OK
+}
+
+#[no_mangle]
+extern "system" fn VariantClear(_var: *mut c_void) -> HRESULT {
+    S_OK
+}
+
+#[no_mangle]
+extern "system" fn PropVariantCopy(_dest: *mut c_void, _src: *const c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn VariantCopy(_dest: *mut c_void, _src: *const c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn PropVariantCompareEx(
+    _var1: *const c_void,
+    _var2: *const c_void,
+    _unit: i32,
+    _flags: i32,
+) -> i32 {
+    0
+}
+
+#[no_mangle]
+extern "system" fn PropVariantToVariant(_propvar: *const c_void, _var: *mut c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn VariantToPropVariant(_var: *const c_void, _propvar: *mut c_void) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn PropVariantToStringAlloc(_var: *const c_void, _out: *mut *mut u16) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[no_mangle]
+extern "system" fn PropVariantToBSTR(_var: *const c_void, _out: *mut *const u16) -> HRESULT {
+    E_NOTIMPL
+}
+
+/// Define conversions from a PROPVARIANT or VARIANT to a value, that all fail.
+macro_rules! stub_conversions {
+    ($($name:ident: $ty:ty),+ $(,)?) => {
+        $(
+            #[no_mangle]
+            extern "system" fn $name(_var: *const c_void, _out: *mut $ty) -> HRESULT {
+                E_NOTIMPL
+            }
+        )+
+    };
+}
+
+stub_conversions!(
+    PropVariantToBoolean: BOOL,
+    PropVariantToDouble: f64,
+    PropVariantToInt16: i16,
+    PropVariantToInt32: i32,
+    PropVariantToInt64: i64,
+    PropVariantToUInt16: u16,
+    PropVariantToUInt32: u32,
+    PropVariantToUInt64: u64,
+    VariantToBoolean: BOOL,
+    VariantToDouble: f64,
+    VariantToInt16: i16,
+    VariantToInt32: i32,
+    VariantToInt64: i64,
+    VariantToUInt16: u16,
+    VariantToUInt32: u32,
+    VariantToUInt64: u64,
+);
+
+// Errors and libraries
+
+#[no_mangle]
+extern "system" fn GetLastError() -> u32 {
+    ERROR_CALL_NOT_IMPLEMENTED
+}
+
+#[no_mangle]
+extern "system" fn FormatMessageW(
+    _flags: u32,
+    _source: *const c_void,
+    _message_id: u32,
+    _language_id: u32,
+    _buffer: *mut u16,
+    _size: u32,
+    _arguments: *const *const i8,
+) -> u32 {
+    // No message, the error is then displayed with its code
+    0
+}
+
+#[no_mangle]
+extern "system" fn LoadLibraryExA(_name: *const u8, _file: HANDLE, _flags: u32) -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn FreeLibrary(_module: HANDLE) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn GetProcAddress(_module: HANDLE, _name: *const u8) -> *const c_void {
+    ptr::null()
+}
+
+#[no_mangle]
+extern "system" fn RtlGetVersion(_info: *mut c_void) -> i32 {
+    STATUS_NOT_IMPLEMENTED
+}
+
+// Memory
+
+#[no_mangle]
+extern "system" fn GetProcessHeap() -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn HeapAlloc(_heap: HANDLE, _flags: u32, _size: usize) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+extern "system" fn HeapFree(_heap: HANDLE, _flags: u32, _mem: *const c_void) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn GlobalFree(_mem: HANDLE) -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn LocalFree(_mem: HANDLE) -> HANDLE {
+    0
+}
+
+// Events, timers, threads and processes
+
+#[no_mangle]
+extern "system" fn CreateEventA(
+    _attributes: *const c_void,
+    _manual_reset: BOOL,
+    _initial_state: BOOL,
+    _name: *const u8,
+) -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn CreateEventW(
+    _attributes: *const c_void,
+    _manual_reset: BOOL,
+    _initial_state: BOOL,
+    _name: *const u16,
+) -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn SetEvent(_event: HANDLE) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn ResetEvent(_event: HANDLE) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn CreateWaitableTimerExW(
+    _attributes: *const c_void,
+    _name: *const u16,
+    _flags: u32,
+    _access: u32,
+) -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn SetWaitableTimer(
+    _timer: HANDLE,
+    _due_time: *const i64,
+    _period: i32,
+    _completion: *const c_void,
+    _argument: *const c_void,
+    _resume: BOOL,
+) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn WaitForSingleObject(_handle: HANDLE, _milliseconds: u32) -> u32 {
+    WAIT_FAILED
+}
+
+#[no_mangle]
+extern "system" fn WaitForMultipleObjects(
+    _count: u32,
+    _handles: *const HANDLE,
+    _wait_all: BOOL,
+    _milliseconds: u32,
+) -> u32 {
+    WAIT_FAILED
+}
+
+#[no_mangle]
+extern "system" fn CloseHandle(_handle: HANDLE) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn OpenProcess(_access: u32, _inherit: BOOL, _process_id: u32) -> HANDLE {
+    0
+}
+
+#[no_mangle]
+extern "system" fn GetCurrentThread() -> HANDLE {
+    CURRENT_THREAD
+}
+
+#[no_mangle]
+extern "system" fn GetThreadPriority(_thread: HANDLE) -> i32 {
+    THREAD_PRIORITY_ERROR_RETURN
+}
+
+#[no_mangle]
+extern "system" fn SetThreadPriority(_thread: HANDLE, _priority: i32) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn QueryPerformanceCounter(_count: *mut i64) -> BOOL {
+    FALSE
+}
+
+#[no_mangle]
+extern "system" fn QueryPerformanceFrequency(_frequency: *mut i64) -> BOOL {
+    FALSE
+}
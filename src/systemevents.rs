@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::watcher::{device_event_callbacks, wait_for_startup};
+use crate::{
+    Apartment, AudioSessionControl, AudioSessionManager2, ChannelSendError, ChannelSender, Device,
+    DeviceCollection, DeviceEvent, DeviceNotifications, Direction, EndpointVolumeEvent,
+    EndpointVolumeNotifications, SessionEvent, SessionEventRegistration, SessionNotifications,
+    WasapiRes, Worker,
+};
+
+/// How often the internal thread checks if it has been asked to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A notification delivered by [SystemEvents].
+#[derive(Clone, Debug, PartialEq)]
+pub enum SystemAudioEvent {
+    /// A device notification, the same as delivered by a [DeviceWatcher](crate::DeviceWatcher).
+    Device(DeviceEvent),
+    /// The volume or mute state of an active endpoint device changed.
+    EndpointVolumeChanged {
+        device_id: String,
+        volume: EndpointVolumeEvent,
+    },
+    /// A new session was created on an active endpoint device.
+    /// The instance identifier is `None` if it could not be read.
+    SessionCreated {
+        device_id: String,
+        direction: Direction,
+        session_instance_id: Option<String>,
+    },
+    /// A notification from a session on an active endpoint device.
+    Session {
+        device_id: String,
+        session_instance_id: String,
+        event: SessionEvent,
+    },
+}
+
+type EventSink = Arc<dyn Fn(SystemAudioEvent) + Send + Sync>;
+
+/// Requests from the notification callbacks to the internal thread.
+enum Update {
+    /// The set of active devices may have changed.
+    Devices,
+    /// The sessions of the device with this id may have changed.
+    Sessions(String),
+}
+
+/// The sink for the events, and the queue of requests to the internal thread.
+#[derive(Clone)]
+struct Context {
+    sink: EventSink,
+    updates: Arc<Mutex<mpsc::Sender<Update>>>,
+}
+
+impl Context {
+    fn send(&self, event: SystemAudioEvent) {
+        (self.sink)(event);
+    }
+
+    fn request(&self, update: Update) {
+        let updates = match self.updates.lock() {
+            Ok(updates) => updates,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // This only fails when the internal thread has exited.
+        let _ = updates.send(update);
+    }
+}
+
+/// The registrations on one active endpoint device.
+struct DeviceRegistrations {
+    manager: AudioSessionManager2,
+    sessions: BTreeMap<String, SessionEventRegistration>,
+    _volume: EndpointVolumeNotifications,
+    _session_created: SessionNotifications,
+}
+
+/// A single subscription to device notifications, endpoint volume changes and session notifications,
+/// delivered as [SystemAudioEvent] values to one channel or callback.
+///
+/// The registrations are owned by an internal thread, so the subscription can be created and dropped on any thread.
+/// COM is initialized in the multithreaded apartment on that thread,
+/// use [SystemEvents::with_apartment] or [SystemEvents::with_callback_in_apartment] to choose another.
+/// That thread follows the active endpoint devices of both directions, and their sessions,
+/// and registers and unregisters the volume and session notifications as devices and sessions come and go.
+/// Devices and sessions where the registration fails are skipped with a warning.
+///
+/// The events are delivered from threads owned by the system.
+/// When sending to a channel, the events are sent without blocking,
+/// and are dropped with a warning if a bounded channel is full.
+/// A callback should return quickly.
+///
+/// The subscription is stopped when it is dropped, or with [SystemEvents::stop].
+pub struct SystemEvents {
+    worker: Worker<()>,
+}
+
+impl SystemEvents {
+    /// Start the subscription, and send the events to the given channel.
+    /// Any channel implementing [ChannelSender] can be used.
+    pub fn new<S>(sender: S) -> WasapiRes<Self>
+    where
+        S: ChannelSender<SystemAudioEvent> + Send + 'static,
+    {
+        SystemEvents::with_apartment(sender, &Apartment::MultiThreaded)
+    }
+
+    /// Start the subscription with the internal thread in the given apartment,
+    /// and send the events to the given channel.
+    /// Returns an [ApartmentError](crate::ApartmentError) if COM can't be initialized in the apartment.
+    pub fn with_apartment<S>(sender: S, apartment: &Apartment) -> WasapiRes<Self>
+    where
+        S: ChannelSender<SystemAudioEvent> + Send + 'static,
+    {
+        let sender = Mutex::new(sender);
+        let sink: EventSink = Arc::new(move |event| {
+            let sender = match sender.lock() {
+                Ok(sender) => sender,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match sender.try_send(event) {
+                Ok(()) => {}
+                Err(ChannelSendError::Full) => warn!("Channel is full, dropped system audio event"),
+                Err(ChannelSendError::Disconnected) => {
+                    trace!("Receiver was dropped, ignoring system audio event")
+                }
+            }
+        });
+        SystemEvents::start(sink, apartment)
+    }
+
+    /// Start the subscription, and pass the events to the given callback.
+    /// The callback is never called from more than one thread at a time.
+    pub fn with_callback(callback: impl Fn(SystemAudioEvent) + Send + 'static) -> WasapiRes<Self> {
+        SystemEvents::with_callback_in_apartment(&Apartment::MultiThreaded, callback)
+    }
+
+    /// Start the subscription with the internal thread in the given apartment,
+    /// and pass the events to the given callback.
+    /// Returns an [ApartmentError](crate::ApartmentError) if COM can't be initialized in the apartment.
+    pub fn with_callback_in_apartment(
+        apartment: &Apartment,
+        callback: impl Fn(SystemAudioEvent) + Send + 'static,
+    ) -> WasapiRes<Self> {
+        let callback = Mutex::new(callback);
+        let sink: EventSink = Arc::new(move |event| {
+            let callback = match callback.lock() {
+                Ok(callback) => callback,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            callback(event);
+        });
+        SystemEvents::start(sink, apartment)
+    }
+
+    fn start(sink: EventSink, apartment: &Apartment) -> WasapiRes<Self> {
+        let (result_tx, result_rx) = mpsc::channel::<Result<(), String>>();
+        let worker = Worker::spawn("SystemEvents", apartment, move |stop| {
+            let (updates_tx, updates_rx) = mpsc::channel();
+            let context = Context {
+                sink,
+                updates: Arc::new(Mutex::new(updates_tx)),
+            };
+            let device_context = context.clone();
+            let callbacks = device_event_callbacks(Arc::new(move |event: DeviceEvent| {
+                match event {
+                    DeviceEvent::Added { .. }
+                    | DeviceEvent::Removed { .. }
+                    | DeviceEvent::StateChanged { .. } => device_context.request(Update::Devices),
+                    _ => {}
+                }
+                device_context.send(SystemAudioEvent::Device(event));
+            }));
+            let notifications = match DeviceNotifications::new(callbacks) {
+                Ok(notifications) => notifications,
+                Err(err) => {
+                    let _ = result_tx.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            let mut devices = BTreeMap::new();
+            refresh_devices(&mut devices, &context);
+            let _ = result_tx.send(Ok(()));
+            while !stop.is_stopped() {
+                match updates_rx.recv_timeout(STOP_POLL_INTERVAL) {
+                    Ok(Update::Devices) => refresh_devices(&mut devices, &context),
+                    Ok(Update::Sessions(device_id)) => {
+                        if let Some(registrations) = devices.get_mut(&device_id) {
+                            refresh_sessions(registrations, &device_id, &context);
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+            drop(devices);
+            drop(notifications);
+            debug!("System events subscription was stopped");
+        })?;
+        let worker = wait_for_startup(worker, result_rx)?;
+        Ok(SystemEvents { worker })
+    }
+
+    /// Request the subscription to stop. No more events are sent after it has stopped,
+    /// use [SystemEvents::join] to wait for that.
+    pub fn stop(&self) {
+        self.worker.stop();
+    }
+
+    /// Check if the subscription is still running.
+    pub fn is_running(&self) -> bool {
+        self.worker.is_running()
+    }
+
+    /// Wait for the subscription to stop, with a timeout.
+    /// Returns false if it is still running when the timeout expires.
+    pub fn join(&mut self, timeout: Duration) -> WasapiRes<bool> {
+        Ok(self.worker.join(timeout)?.is_some())
+    }
+}
+
+/// Register on the devices that became active, and unregister from the ones that are no longer active.
+fn refresh_devices(devices: &mut BTreeMap<String, DeviceRegistrations>, context: &Context) {
+    let mut active = BTreeMap::new();
+    for direction in [Direction::Render, Direction::Capture].iter() {
+        let collection = match DeviceCollection::new(direction) {
+            Ok(collection) => collection,
+            Err(err) => {
+                warn!("Unable to list {} devices, {}", direction, err);
+                continue;
+            }
+        };
+        for device in &collection {
+            match device.and_then(|device| Ok((device.get_id()?, device))) {
+                Ok((id, device)) => {
+                    active.insert(id, device);
+                }
+                Err(err) => warn!("Unable to read device, {}", err),
+            }
+        }
+    }
+    devices.retain(|id, _| active.contains_key(id));
+    for (id, device) in active {
+        if devices.contains_key(&id) {
+            continue;
+        }
+        match register_device(&device, &id, context) {
+            Ok(mut registrations) => {
+                refresh_sessions(&mut registrations, &id, context);
+                devices.insert(id, registrations);
+            }
+            Err(err) => warn!("Unable to register notifications on device {}, {}", id, err),
+        }
+    }
+    debug!("System events registered on {} devices", devices.len());
+}
+
+fn register_device(
+    device: &Device,
+    device_id: &str,
+    context: &Context,
+) -> WasapiRes<DeviceRegistrations> {
+    let volume_context = context.clone();
+    let volume_id = device_id.to_string();
+    let volume = device
+        .get_endpointvolume()?
+        .register_volume_notification(move |volume| {
+            volume_context.send(SystemAudioEvent::EndpointVolumeChanged {
+                device_id: volume_id.clone(),
+                volume,
+            })
+        })?;
+    let manager = device.get_sessionmanager()?;
+    let session_context = context.clone();
+    let session_id = device_id.to_string();
+    let direction = device.get_direction();
+    let session_created =
+        manager.register_session_notification(move |control: AudioSessionControl| {
+            session_context.send(SystemAudioEvent::SessionCreated {
+                device_id: session_id.clone(),
+                direction,
+                session_instance_id: get_instance_id(&control),
+            });
+            session_context.request(Update::Sessions(session_id.clone()));
+        })?;
+    Ok(DeviceRegistrations {
+        manager,
+        sessions: BTreeMap::new(),
+        _volume: volume,
+        _session_created: session_created,
+    })
+}
+
+/// Register on the new sessions of a device, and unregister from the ones that are gone.
+fn refresh_sessions(registrations: &mut DeviceRegistrations, device_id: &str, context: &Context) {
+    let sessions = match registrations.manager.get_sessions() {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            warn!("Unable to list sessions of device {}, {}", device_id, err);
+            return;
+        }
+    };
+    let mut current = BTreeMap::new();
+    for session in sessions {
+        if let Some(instance_id) = get_instance_id(&session) {
+            current.insert(instance_id, session);
+        }
+    }
+    registrations
+        .sessions
+        .retain(|instance_id, _| current.contains_key(instance_id));
+    for (instance_id, session) in current {
+        if registrations.sessions.contains_key(&instance_id) {
+            continue;
+        }
+        let event_context = context.clone();
+        let event_device_id = device_id.to_string();
+        let event_instance_id = instance_id.clone();
+        let registration = session.register_session_event_callback(Box::new(move |event| {
+            event_context.send(SystemAudioEvent::Session {
+                device_id: event_device_id.clone(),
+                session_instance_id: event_instance_id.clone(),
+                event,
+            })
+        }));
+        match registration {
+            Ok(registration) => {
+                registrations.sessions.insert(instance_id, registration);
+            }
+            Err(err) => warn!(
+                "Unable to register notifications on session {}, {}",
+                instance_id, err
+            ),
+        }
+    }
+}
+
+fn get_instance_id(control: &AudioSessionControl) -> Option<String> {
+    control
+        .upgrade()
+        .control2
+        .and_then(|control2| control2.get_session_instance_identifier().ok())
+}
@@ -0,0 +1,290 @@
+//! Test signal generators, for quick checks of devices and streams.
+//!
+//! The generators produce one `f32` sample per call to `next()`, in the range -1.0 to +1.0
+//! scaled by the amplitude. Use [fill_frames] to write them as frames in the sample format
+//! of a stream, given as a [WaveFormat].
+//!
+//! This module is only available when the `testsignal` feature is enabled.
+use std::f64::consts::PI;
+
+use crate::{SampleType, WasapiError, WasapiRes, WaveFormat};
+
+pub use crate::sine::SineGenerator;
+
+/// White noise generator, using a simple xorshift pseudo-random number generator.
+/// The sequence is determined by the seed, so that measurements can be repeated.
+pub struct WhiteNoise {
+    state: u64,
+    amplitude: f64,
+}
+
+impl WhiteNoise {
+    /// Create a new [WhiteNoise] generator with the given amplitude and seed.
+    pub fn new(amplitude: f64, seed: u64) -> Self {
+        WhiteNoise {
+            // The xorshift state must never be zero.
+            state: seed.max(1),
+            amplitude,
+        }
+    }
+
+    fn next_value(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        // Use the upper 53 bits for a value between 0.0 and 1.0, then scale to -1.0 to +1.0.
+        2.0 * (self.state >> 11) as f64 / (1u64 << 53) as f64 - 1.0
+    }
+}
+
+impl Iterator for WhiteNoise {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        Some((self.next_value() * self.amplitude) as f32)
+    }
+}
+
+/// Pink noise generator, made by filtering white noise with
+/// Paul Kellet's economy filter, that is accurate to within about 0.05 dB above 9.2 Hz
+/// at a sample rate of 44.1 kHz.
+pub struct PinkNoise {
+    white: WhiteNoise,
+    b: [f64; 3],
+    amplitude: f64,
+}
+
+impl PinkNoise {
+    /// Create a new [PinkNoise] generator with the given amplitude and seed.
+    pub fn new(amplitude: f64, seed: u64) -> Self {
+        PinkNoise {
+            white: WhiteNoise::new(1.0, seed),
+            b: [0.0; 3],
+            amplitude,
+        }
+    }
+}
+
+impl Iterator for PinkNoise {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        let white = self.white.next_value();
+        self.b[0] = 0.99765 * self.b[0] + white * 0.0990460;
+        self.b[1] = 0.96300 * self.b[1] + white * 0.2965164;
+        self.b[2] = 0.57000 * self.b[2] + white * 1.0526913;
+        // The filter has a gain of about 4.7 at low frequencies, scale to keep the peaks within range.
+        let pink = (self.b[0] + self.b[1] + self.b[2] + white * 0.1848) / 5.0;
+        Some((pink * self.amplitude) as f32)
+    }
+}
+
+/// Logarithmic sine sweep generator.
+/// The sweep goes from the start frequency to the end frequency over the given duration,
+/// and then starts over.
+pub struct Sweep {
+    frame: usize,
+    length: usize,
+    start_freq: f64,
+    rate: f64,
+    samplerate: f64,
+    amplitude: f64,
+}
+
+impl Sweep {
+    /// Create a new [Sweep] generator.
+    /// The frequencies are given in Hz and must be positive, and the duration is given in seconds.
+    pub fn new(
+        start_freq: f64,
+        end_freq: f64,
+        duration: f64,
+        samplerate: f64,
+        amplitude: f64,
+    ) -> WasapiRes<Self> {
+        if start_freq <= 0.0 || end_freq <= 0.0 {
+            return Err(WasapiError::new("Sweep frequencies must be positive").into());
+        }
+        let length = (duration * samplerate) as usize;
+        if length == 0 {
+            return Err(WasapiError::new("Sweep duration is too short").into());
+        }
+        Ok(Sweep {
+            frame: 0,
+            length,
+            start_freq,
+            rate: (end_freq / start_freq).ln() / duration,
+            samplerate,
+            amplitude,
+        })
+    }
+}
+
+impl Iterator for Sweep {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        let time = self.frame as f64 / self.samplerate;
+        // The phase is the integral of the instantaneous frequency f0 * exp(rate * t).
+        let phase = if self.rate == 0.0 {
+            2.0 * PI * self.start_freq * time
+        } else {
+            2.0 * PI * self.start_freq * ((self.rate * time).exp() - 1.0) / self.rate
+        };
+        self.frame = (self.frame + 1) % self.length;
+        Some((phase.sin() * self.amplitude) as f32)
+    }
+}
+
+/// Fill a buffer with whole frames in the given format, writing the same sample to all channels of each frame.
+/// Integer formats with 8, 16, 24 and 32 bits per sample and float formats with 32 and 64 bits are supported.
+/// Samples are clipped to the range -1.0 to +1.0.
+/// Bytes after the last whole frame are left unchanged.
+/// Returns the number of frames written.
+pub fn fill_frames(
+    source: &mut impl Iterator<Item = f32>,
+    data: &mut [u8],
+    format: &WaveFormat,
+) -> WasapiRes<usize> {
+    let sample_type = format.get_subformat()?;
+    let bytes_per_sample = format.get_bitspersample() as usize / 8;
+    let valid_bits = format.get_validbitspersample() as u32;
+    let blockalign = format.get_blockalign() as usize;
+    match (&sample_type, bytes_per_sample) {
+        (SampleType::Int, 1..=4) | (SampleType::Float, 4) | (SampleType::Float, 8) => {}
+        _ => {
+            return Err(WasapiError::new(&format!(
+                "Unsupported sample format, {} with {} bits per sample",
+                sample_type,
+                format.get_bitspersample()
+            ))
+            .into())
+        }
+    }
+    if blockalign == 0 || valid_bits == 0 || valid_bits as usize > 8 * bytes_per_sample {
+        return Err(WasapiError::new("Invalid format").into());
+    }
+    let mut nbr_frames = 0;
+    for frame in data.chunks_exact_mut(blockalign) {
+        let value = source.next().unwrap_or_default().clamp(-1.0, 1.0);
+        let mut sample_bytes = [0u8; 8];
+        match sample_type {
+            SampleType::Float if bytes_per_sample == 4 => {
+                sample_bytes[..4].copy_from_slice(&value.to_le_bytes());
+            }
+            SampleType::Float => {
+                sample_bytes.copy_from_slice(&(value as f64).to_le_bytes());
+            }
+            SampleType::Int => {
+                // Scale to the valid bits, and left-justify in the container.
+                let max = ((1i64 << (valid_bits - 1)) - 1) as f64;
+                let scaled = (value as f64 * max).round() as i64;
+                let shifted = scaled << (8 * bytes_per_sample as u32 - valid_bits);
+                if bytes_per_sample == 1 {
+                    // 8-bit samples are unsigned.
+                    sample_bytes[0] = (shifted + 128) as u8;
+                } else {
+                    sample_bytes.copy_from_slice(&shifted.to_le_bytes());
+                }
+            }
+        }
+        for sample in frame.chunks_exact_mut(bytes_per_sample) {
+            sample.copy_from_slice(&sample_bytes[..bytes_per_sample]);
+        }
+        nbr_frames += 1;
+    }
+    Ok(nbr_frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eight_bit_samples_are_offset() {
+        let format = WaveFormat::new(8, 8, &SampleType::Int, 48000, 1, None);
+        let mut data = [0u8; 3];
+        let mut source = vec![0.0, 1.0, -1.0].into_iter();
+        assert_eq!(fill_frames(&mut source, &mut data, &format).unwrap(), 3);
+        assert_eq!(data, [128, 255, 1]);
+    }
+
+    #[test]
+    fn twentyfour_bit_samples_are_left_justified() {
+        let format = WaveFormat::new(32, 24, &SampleType::Int, 48000, 1, None);
+        let mut data = [0u8; 8];
+        let mut source = vec![1.0, -1.0].into_iter();
+        fill_frames(&mut source, &mut data, &format).unwrap();
+        assert_eq!(data[..4], 0x7FFFFF00_i32.to_le_bytes());
+        assert_eq!(data[4..], (-0x7FFFFF00_i32).to_le_bytes());
+    }
+
+    #[test]
+    fn f64_samples_are_written_to_all_channels() {
+        let format = WaveFormat::new(64, 64, &SampleType::Float, 48000, 2, None);
+        let mut data = [0u8; 16];
+        let mut source = vec![0.5].into_iter();
+        assert_eq!(fill_frames(&mut source, &mut data, &format).unwrap(), 1);
+        assert_eq!(data[..8], 0.5f64.to_le_bytes());
+        assert_eq!(data[8..], 0.5f64.to_le_bytes());
+    }
+
+    #[test]
+    fn samples_are_clipped() {
+        let format = WaveFormat::new(32, 32, &SampleType::Float, 48000, 1, None);
+        let mut data = [0u8; 8];
+        let mut source = vec![2.0, -3.0].into_iter();
+        fill_frames(&mut source, &mut data, &format).unwrap();
+        assert_eq!(data[..4], 1.0f32.to_le_bytes());
+        assert_eq!(data[4..], (-1.0f32).to_le_bytes());
+
+        let format = WaveFormat::new(16, 16, &SampleType::Int, 48000, 1, None);
+        let mut data = [0u8; 4];
+        let mut source = vec![1.5, -1.5].into_iter();
+        fill_frames(&mut source, &mut data, &format).unwrap();
+        assert_eq!(data[..2], i16::MAX.to_le_bytes());
+        assert_eq!(data[2..], (-i16::MAX).to_le_bytes());
+    }
+
+    #[test]
+    fn partial_frame_is_left_unchanged() {
+        let format = WaveFormat::new(16, 16, &SampleType::Int, 48000, 2, None);
+        let mut data = vec![0xAAu8; 4 * 3 + 2];
+        let mut source = WhiteNoise::new(0.5, 1);
+        assert_eq!(fill_frames(&mut source, &mut data, &format).unwrap(), 3);
+        assert_eq!(data[12..], [0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn unsupported_format_is_rejected() {
+        let format = WaveFormat::new(16, 16, &SampleType::Float, 48000, 2, None);
+        let mut data = [0u8; 8];
+        let mut source = WhiteNoise::new(0.5, 1);
+        assert!(fill_frames(&mut source, &mut data, &format).is_err());
+        assert_eq!(data, [0; 8]);
+    }
+
+    #[test]
+    fn sweep_parameters_are_validated() {
+        assert!(Sweep::new(0.0, 1000.0, 1.0, 48000.0, 0.5).is_err());
+        assert!(Sweep::new(20.0, -1000.0, 1.0, 48000.0, 0.5).is_err());
+        assert!(Sweep::new(20.0, 1000.0, 0.0, 48000.0, 0.5).is_err());
+        assert!(Sweep::new(20.0, 1000.0, 1.0, 48000.0, 0.5).is_ok());
+        // A sweep with equal frequencies is a plain sine.
+        assert!(Sweep::new(1000.0, 1000.0, 1.0, 48000.0, 0.5).is_ok());
+    }
+
+    #[test]
+    fn noise_is_reproducible_from_seed() {
+        let first: Vec<f32> = WhiteNoise::new(0.5, 42).take(100).collect();
+        let second: Vec<f32> = WhiteNoise::new(0.5, 42).take(100).collect();
+        let other: Vec<f32> = WhiteNoise::new(0.5, 43).take(100).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert!(first.iter().all(|value| value.abs() <= 0.5));
+
+        let first: Vec<f32> = PinkNoise::new(0.5, 42).take(100).collect();
+        let second: Vec<f32> = PinkNoise::new(0.5, 42).take(100).collect();
+        assert_eq!(first, second);
+
+        // A zero seed must not give a constant output.
+        let zero: Vec<f32> = WhiteNoise::new(0.5, 0).take(10).collect();
+        assert!(zero.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,620 @@
+//! Access to the [device topology](https://learn.microsoft.com/en-us/windows/win32/coreaudio/device-topologies)
+//! of endpoint devices and audio adapters.
+//!
+//! The topology of an endpoint device consists of a single connector,
+//! that is connected to a connector of the audio adapter.
+//! The topology of the adapter describes the hardware, as connectors and subunits
+//! (for example volume controls, mutes and multiplexers) linked together in a graph.
+//! Start with [Device::get_device_topology](crate::Device::get_device_topology),
+//! and use [DeviceTopology::get_adapter_connector] to get to the adapter.
+use std::fmt;
+use std::ptr;
+
+use windows::{
+    core::{Interface, GUID, HRESULT},
+    Win32::Foundation::BOOL,
+    Win32::Media::Audio::{
+        self, IAudioAutoGainControl, IAudioLoudness, IAudioMute, IAudioPeakMeter,
+        IAudioVolumeLevel, IConnector, IDeviceTopology, IPart, IPartsList, ISubunit,
+    },
+    Win32::System::Com::CLSCTX_INPROC_SERVER,
+};
+
+use crate::{take_pwstr, WasapiError, WasapiRes};
+
+// Returned when a part has no links in the requested direction, `HRESULT_FROM_WIN32(ERROR_NOT_FOUND)`.
+const E_NOTFOUND: HRESULT = HRESULT(0x8007_0490_u32 as i32);
+
+/// Type of a [Connector].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectorType {
+    /// The type of the connector is not known, or the connector is not connected.
+    Unknown,
+    /// A physical connector that is not accessible to the user, for example an internal speaker.
+    PhysicalInternal,
+    /// A physical connector that is accessible to the user, for example a jack.
+    PhysicalExternal,
+    /// A software connection to the system, used by the connectors between endpoints and adapters.
+    SoftwareIo,
+    /// A software connection that can't be changed.
+    SoftwareFixed,
+    /// A connection to a network, for example for a network audio device.
+    Network,
+}
+
+impl fmt::Display for ConnectorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConnectorType::Unknown => write!(f, "Unknown"),
+            ConnectorType::PhysicalInternal => write!(f, "PhysicalInternal"),
+            ConnectorType::PhysicalExternal => write!(f, "PhysicalExternal"),
+            ConnectorType::SoftwareIo => write!(f, "SoftwareIo"),
+            ConnectorType::SoftwareFixed => write!(f, "SoftwareFixed"),
+            ConnectorType::Network => write!(f, "Network"),
+        }
+    }
+}
+
+/// Direction of the data flow through a [Connector].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectorDataFlow {
+    /// Data flows into the device through the connector.
+    In,
+    /// Data flows out of the device through the connector.
+    Out,
+}
+
+impl fmt::Display for ConnectorDataFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConnectorDataFlow::In => write!(f, "In"),
+            ConnectorDataFlow::Out => write!(f, "Out"),
+        }
+    }
+}
+
+/// Type of a [Part].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartType {
+    /// The part is a [Connector], a point where the device connects to another device.
+    Connector,
+    /// The part is a [Subunit], a processing element like a volume control or a mute.
+    Subunit,
+}
+
+impl fmt::Display for PartType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            PartType::Connector => write!(f, "Connector"),
+            PartType::Subunit => write!(f, "Subunit"),
+        }
+    }
+}
+
+/// Struct wrapping an [IDeviceTopology](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-idevicetopology),
+/// the topology of an endpoint device or an audio adapter.
+pub struct DeviceTopology {
+    topology: IDeviceTopology,
+}
+
+impl DeviceTopology {
+    pub(crate) fn new(topology: IDeviceTopology) -> Self {
+        DeviceTopology { topology }
+    }
+
+    /// Get the id of the device this topology belongs to.
+    /// For an endpoint device this is the same as [Device::get_id](crate::Device::get_id),
+    /// for an adapter it is the id of the kernel streaming filter.
+    pub fn get_device_id(&self) -> WasapiRes<String> {
+        let id = unsafe { self.topology.GetDeviceId()? };
+        Ok(take_pwstr(id))
+    }
+
+    /// Get the number of connectors in the topology.
+    pub fn get_connector_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.topology.GetConnectorCount()? };
+        Ok(count)
+    }
+
+    /// Get the connector at the given index.
+    pub fn get_connector(&self, index: u32) -> WasapiRes<Connector> {
+        let connector = unsafe { self.topology.GetConnector(index)? };
+        Ok(Connector { connector })
+    }
+
+    /// Get all connectors of the topology.
+    pub fn get_connectors(&self) -> WasapiRes<Vec<Connector>> {
+        (0..self.get_connector_count()?)
+            .map(|index| self.get_connector(index))
+            .collect()
+    }
+
+    /// Get the number of subunits in the topology.
+    pub fn get_subunit_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.topology.GetSubunitCount()? };
+        Ok(count)
+    }
+
+    /// Get the subunit at the given index.
+    pub fn get_subunit(&self, index: u32) -> WasapiRes<Subunit> {
+        let subunit = unsafe { self.topology.GetSubunit(index)? };
+        Ok(Subunit { subunit })
+    }
+
+    /// Get all subunits of the topology.
+    pub fn get_subunits(&self) -> WasapiRes<Vec<Subunit>> {
+        (0..self.get_subunit_count()?)
+            .map(|index| self.get_subunit(index))
+            .collect()
+    }
+
+    /// Get the automatic gain controls of all subunits of the topology.
+    /// Use this on the topology of the adapter, see [DeviceTopology::get_adapter_connector],
+    /// for example to turn off the AGC of a microphone for measurements.
+    pub fn get_auto_gain_controls(&self) -> WasapiRes<Vec<AutoGainControl>> {
+        let mut controls = Vec::new();
+        for subunit in self.get_subunits()? {
+            if let Ok(control) = subunit.as_part()?.get_auto_gain_control() {
+                controls.push(control);
+            }
+        }
+        Ok(controls)
+    }
+
+    /// Get a part by its local id, see [Part::get_local_id].
+    pub fn get_part_by_id(&self, local_id: u32) -> WasapiRes<Part> {
+        let part = unsafe { self.topology.GetPartById(local_id)? };
+        Ok(Part { part })
+    }
+
+    /// Get the parts on the signal path between two parts of the topology,
+    /// including the start and end parts.
+    /// If `reject_mixed_paths` is true, paths that pass through a mixer or multiplexer are not accepted.
+    pub fn get_signal_path(
+        &self,
+        from: &Part,
+        to: &Part,
+        reject_mixed_paths: bool,
+    ) -> WasapiRes<Vec<Part>> {
+        let list = unsafe {
+            self.topology
+                .GetSignalPath(&from.part, &to.part, BOOL::from(reject_mixed_paths))?
+        };
+        parts_from_list(&list)
+    }
+
+    /// Get the connector of the audio adapter that the endpoint device is connected to.
+    /// This is meant for the topology of an endpoint device, which has a single connector.
+    /// Use [Connector::get_topology] on the result to get the topology of the adapter.
+    pub fn get_adapter_connector(&self) -> WasapiRes<Connector> {
+        let connector = self.get_connector(0)?;
+        if !connector.is_connected()? {
+            return Err(WasapiError::new("The endpoint is not connected to an adapter").into());
+        }
+        connector.get_connected_to()
+    }
+}
+
+/// Struct wrapping an [IConnector](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iconnector),
+/// a connection point of a [DeviceTopology].
+pub struct Connector {
+    connector: IConnector,
+}
+
+impl Connector {
+    /// Get the type of the connector.
+    pub fn get_type(&self) -> WasapiRes<ConnectorType> {
+        let connector_type = unsafe { self.connector.GetType()? };
+        let value = match connector_type {
+            Audio::ConnectorType::Physical_Internal => ConnectorType::PhysicalInternal,
+            Audio::ConnectorType::Physical_External => ConnectorType::PhysicalExternal,
+            Audio::ConnectorType::Software_IO => ConnectorType::SoftwareIo,
+            Audio::ConnectorType::Software_Fixed => ConnectorType::SoftwareFixed,
+            Audio::ConnectorType::Network => ConnectorType::Network,
+            _ => ConnectorType::Unknown,
+        };
+        Ok(value)
+    }
+
+    /// Get the direction of the data flow through the connector.
+    pub fn get_dataflow(&self) -> WasapiRes<ConnectorDataFlow> {
+        let dataflow = unsafe { self.connector.GetDataFlow()? };
+        match dataflow {
+            Audio::In => Ok(ConnectorDataFlow::In),
+            Audio::Out => Ok(ConnectorDataFlow::Out),
+            x => Err(WasapiError::new(&format!("Got an illegal data flow: {}", x.0)).into()),
+        }
+    }
+
+    /// Check if the connector is connected to a connector of another device.
+    pub fn is_connected(&self) -> WasapiRes<bool> {
+        let connected = unsafe { self.connector.IsConnected()? };
+        Ok(connected.as_bool())
+    }
+
+    /// Get the connector of the other device that this connector is connected to.
+    pub fn get_connected_to(&self) -> WasapiRes<Connector> {
+        let connector = unsafe { self.connector.GetConnectedTo()? };
+        Ok(Connector { connector })
+    }
+
+    /// Get the global id of the connector that this connector is connected to.
+    pub fn get_connector_id_connected_to(&self) -> WasapiRes<String> {
+        let id = unsafe { self.connector.GetConnectorIdConnectedTo()? };
+        Ok(take_pwstr(id))
+    }
+
+    /// Get the id of the device that this connector is connected to.
+    pub fn get_device_id_connected_to(&self) -> WasapiRes<String> {
+        let id = unsafe { self.connector.GetDeviceIdConnectedTo()? };
+        Ok(take_pwstr(id))
+    }
+
+    /// Get the connector as a [Part], for reading the name and id, and for walking the topology.
+    pub fn as_part(&self) -> WasapiRes<Part> {
+        let part: IPart = self.connector.cast()?;
+        Ok(Part { part })
+    }
+
+    /// Get the [DeviceTopology] that this connector belongs to.
+    pub fn get_topology(&self) -> WasapiRes<DeviceTopology> {
+        self.as_part()?.get_topology()
+    }
+}
+
+/// Struct wrapping an [ISubunit](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-isubunit),
+/// a processing unit of a [DeviceTopology], for example a volume control.
+pub struct Subunit {
+    subunit: ISubunit,
+}
+
+impl Subunit {
+    /// Get the subunit as a [Part], for reading the name and id, and for walking the topology.
+    pub fn as_part(&self) -> WasapiRes<Part> {
+        let part: IPart = self.subunit.cast()?;
+        Ok(Part { part })
+    }
+}
+
+/// Struct wrapping an [IPart](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-ipart),
+/// a [Connector] or [Subunit] of a [DeviceTopology].
+pub struct Part {
+    part: IPart,
+}
+
+impl Part {
+    /// Get the friendly name of the part.
+    pub fn get_name(&self) -> WasapiRes<String> {
+        let name = unsafe { self.part.GetName()? };
+        Ok(take_pwstr(name))
+    }
+
+    /// Get the local id of the part, that identifies it within its [DeviceTopology].
+    pub fn get_local_id(&self) -> WasapiRes<u32> {
+        let id = unsafe { self.part.GetLocalId()? };
+        Ok(id)
+    }
+
+    /// Get the global id of the part, that identifies it across all topologies.
+    pub fn get_global_id(&self) -> WasapiRes<String> {
+        let id = unsafe { self.part.GetGlobalId()? };
+        Ok(take_pwstr(id))
+    }
+
+    /// Get the type of the part.
+    pub fn get_part_type(&self) -> WasapiRes<PartType> {
+        let part_type = unsafe { self.part.GetPartType()? };
+        match part_type {
+            Audio::Connector => Ok(PartType::Connector),
+            Audio::Subunit => Ok(PartType::Subunit),
+            x => Err(WasapiError::new(&format!("Got an illegal part type: {}", x.0)).into()),
+        }
+    }
+
+    /// Get the subtype of the part.
+    /// For subunits this is a `KSNODETYPE_*` GUID, and for connectors a `KSNODETYPE_*` pin category GUID.
+    pub fn get_subtype(&self) -> WasapiRes<GUID> {
+        let subtype = unsafe { self.part.GetSubType()? };
+        Ok(subtype)
+    }
+
+    /// Get the number of control interfaces of the part.
+    pub fn get_control_interface_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.part.GetControlInterfaceCount()? };
+        Ok(count)
+    }
+
+    /// Get the parts that have links to this part, in the direction of the data flow.
+    pub fn get_parts_incoming(&self) -> WasapiRes<Vec<Part>> {
+        match unsafe { self.part.EnumPartsIncoming() } {
+            Ok(list) => parts_from_list(&list),
+            Err(err) if err.code() == E_NOTFOUND => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Get the parts that this part has links to, in the direction of the data flow.
+    pub fn get_parts_outgoing(&self) -> WasapiRes<Vec<Part>> {
+        match unsafe { self.part.EnumPartsOutgoing() } {
+            Ok(list) => parts_from_list(&list),
+            Err(err) if err.code() == E_NOTFOUND => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Get the [DeviceTopology] that this part belongs to.
+    pub fn get_topology(&self) -> WasapiRes<DeviceTopology> {
+        let topology = unsafe { self.part.GetTopologyObject()? };
+        Ok(DeviceTopology { topology })
+    }
+
+    /// Get the hardware volume control of the part.
+    /// Returns an error if the part is not a volume control.
+    pub fn get_volume_level(&self) -> WasapiRes<HardwareVolume> {
+        let volume = self.activate::<IAudioVolumeLevel>()?;
+        Ok(HardwareVolume { volume })
+    }
+
+    /// Get the hardware mute control of the part.
+    /// Returns an error if the part is not a mute control.
+    pub fn get_mute_control(&self) -> WasapiRes<HardwareMute> {
+        let mute = self.activate::<IAudioMute>()?;
+        Ok(HardwareMute { mute })
+    }
+
+    /// Get the automatic gain control of the part.
+    /// Returns an error if the part is not an automatic gain control.
+    pub fn get_auto_gain_control(&self) -> WasapiRes<AutoGainControl> {
+        let agc = self.activate::<IAudioAutoGainControl>()?;
+        Ok(AutoGainControl { agc })
+    }
+
+    /// Get the loudness control of the part.
+    /// Returns an error if the part is not a loudness control.
+    pub fn get_loudness_control(&self) -> WasapiRes<LoudnessControl> {
+        let loudness = self.activate::<IAudioLoudness>()?;
+        Ok(LoudnessControl { loudness })
+    }
+
+    /// Get the hardware peak meter of the part.
+    /// Returns an error if the part is not a peak meter.
+    pub fn get_peak_meter(&self) -> WasapiRes<HardwarePeakMeter> {
+        let meter = self.activate::<IAudioPeakMeter>()?;
+        Ok(HardwarePeakMeter { meter })
+    }
+
+    fn activate<T: Interface>(&self) -> WasapiRes<T> {
+        let mut interface_ptr = ptr::null_mut();
+        unsafe {
+            self.part
+                .Activate(CLSCTX_INPROC_SERVER.0, &T::IID, Some(&mut interface_ptr))?
+        };
+        Ok(unsafe { T::from_raw(interface_ptr) })
+    }
+
+    /// Get the part as a [Connector], returns an error if the part is a subunit.
+    pub fn as_connector(&self) -> WasapiRes<Connector> {
+        let connector: IConnector = self.part.cast()?;
+        Ok(Connector { connector })
+    }
+
+    /// Get the part as a [Subunit], returns an error if the part is a connector.
+    pub fn as_subunit(&self) -> WasapiRes<Subunit> {
+        let subunit: ISubunit = self.part.cast()?;
+        Ok(Subunit { subunit })
+    }
+}
+
+/// The range of a hardware volume control in dB, see [HardwareVolume::get_level_range].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DbRange {
+    /// The minimum level in dB.
+    pub min_db: f32,
+    /// The maximum level in dB.
+    pub max_db: f32,
+    /// The size of the steps between levels in dB.
+    pub step_db: f32,
+}
+
+/// Struct wrapping an [IAudioVolumeLevel](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iaudiovolumelevel),
+/// a volume control in the hardware, see [Part::get_volume_level].
+///
+/// The levels are set per channel, in dB.
+/// This is independent of the software volume of the endpoint,
+/// see [AudioEndpointVolume](crate::AudioEndpointVolume).
+pub struct HardwareVolume {
+    volume: IAudioVolumeLevel,
+}
+
+impl HardwareVolume {
+    /// Get the number of channels of the volume control.
+    pub fn get_channel_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.volume.GetChannelCount()? };
+        Ok(count)
+    }
+
+    /// Get the range of the levels of a channel.
+    pub fn get_level_range(&self, channel: u32) -> WasapiRes<DbRange> {
+        let mut min_db = 0.0;
+        let mut max_db = 0.0;
+        let mut step_db = 0.0;
+        unsafe {
+            self.volume
+                .GetLevelRange(channel, &mut min_db, &mut max_db, &mut step_db)?
+        };
+        Ok(DbRange {
+            min_db,
+            max_db,
+            step_db,
+        })
+    }
+
+    /// Get the level of a channel in dB.
+    pub fn get_level(&self, channel: u32) -> WasapiRes<f32> {
+        let level = unsafe { self.volume.GetLevel(channel)? };
+        Ok(level)
+    }
+
+    /// Get the levels of all channels in dB.
+    pub fn get_levels(&self) -> WasapiRes<Vec<f32>> {
+        (0..self.get_channel_count()?)
+            .map(|channel| self.get_level(channel))
+            .collect()
+    }
+
+    /// Set the level of a channel in dB.
+    /// The driver rounds the level to the nearest step, and clamps it to the range.
+    /// The optional `event_context` is passed on to the control change notifications.
+    pub fn set_level(
+        &self,
+        channel: u32,
+        level_db: f32,
+        event_context: Option<&GUID>,
+    ) -> WasapiRes<()> {
+        let context = event_context.map(|ctx| ctx as *const GUID);
+        unsafe { self.volume.SetLevel(channel, level_db, context)? };
+        Ok(())
+    }
+
+    /// Set all channels to the same level in dB.
+    /// The optional `event_context` is passed on to the control change notifications.
+    pub fn set_level_uniform(&self, level_db: f32, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map(|ctx| ctx as *const GUID);
+        unsafe { self.volume.SetLevelUniform(level_db, context)? };
+        Ok(())
+    }
+
+    /// Set the levels of all channels in dB, one value per channel.
+    /// The optional `event_context` is passed on to the control change notifications.
+    pub fn set_levels(&self, levels_db: &[f32], event_context: Option<&GUID>) -> WasapiRes<()> {
+        let nbr_channels = self.get_channel_count()?;
+        if levels_db.len() != nbr_channels as usize {
+            return Err(WasapiError::new(&format!(
+                "Got {} levels for a control with {} channels",
+                levels_db.len(),
+                nbr_channels
+            ))
+            .into());
+        }
+        let context = event_context.map(|ctx| ctx as *const GUID);
+        unsafe { self.volume.SetLevelAllChannels(levels_db, context)? };
+        Ok(())
+    }
+}
+
+/// Struct wrapping an [IAudioMute](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iaudiomute),
+/// a mute control in the hardware, see [Part::get_mute_control].
+///
+/// This is independent of the mute of the endpoint,
+/// see [AudioEndpointVolume](crate::AudioEndpointVolume).
+pub struct HardwareMute {
+    mute: IAudioMute,
+}
+
+impl HardwareMute {
+    /// Get the mute state.
+    pub fn get_mute(&self) -> WasapiRes<bool> {
+        let mute = unsafe { self.mute.GetMute()? };
+        Ok(mute.as_bool())
+    }
+
+    /// Set the mute state.
+    /// The optional `event_context` is passed on to the control change notifications.
+    pub fn set_mute(&self, mute: bool, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map(|ctx| ctx as *const GUID);
+        unsafe { self.mute.SetMute(BOOL::from(mute), context)? };
+        Ok(())
+    }
+}
+
+/// Struct wrapping an [IAudioAutoGainControl](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iaudioautogaincontrol),
+/// an automatic gain control in the hardware or driver, see [Part::get_auto_gain_control].
+pub struct AutoGainControl {
+    agc: IAudioAutoGainControl,
+}
+
+impl AutoGainControl {
+    /// Check if the automatic gain control is enabled.
+    pub fn get_enabled(&self) -> WasapiRes<bool> {
+        let enabled = unsafe { self.agc.GetEnabled()? };
+        Ok(enabled.as_bool())
+    }
+
+    /// Enable or disable the automatic gain control.
+    /// The optional `event_context` is passed on to the control change notifications.
+    pub fn set_enabled(&self, enabled: bool, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map(|ctx| ctx as *const GUID);
+        unsafe { self.agc.SetEnabled(BOOL::from(enabled), context)? };
+        debug!(
+            "{} automatic gain control",
+            if enabled { "Enabled" } else { "Disabled" }
+        );
+        Ok(())
+    }
+}
+
+/// Struct wrapping an [IAudioLoudness](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iaudioloudness),
+/// a loudness compensation control in the hardware or driver, see [Part::get_loudness_control].
+pub struct LoudnessControl {
+    loudness: IAudioLoudness,
+}
+
+impl LoudnessControl {
+    /// Check if loudness compensation is enabled.
+    pub fn get_enabled(&self) -> WasapiRes<bool> {
+        let enabled = unsafe { self.loudness.GetEnabled()? };
+        Ok(enabled.as_bool())
+    }
+
+    /// Enable or disable loudness compensation.
+    /// The optional `event_context` is passed on to the control change notifications.
+    pub fn set_enabled(&self, enabled: bool, event_context: Option<&GUID>) -> WasapiRes<()> {
+        let context = event_context.map(|ctx| ctx as *const GUID);
+        unsafe { self.loudness.SetEnabled(BOOL::from(enabled), context)? };
+        debug!(
+            "{} loudness compensation",
+            if enabled { "Enabled" } else { "Disabled" }
+        );
+        Ok(())
+    }
+}
+
+/// Struct wrapping an [IAudioPeakMeter](https://learn.microsoft.com/en-us/windows/win32/api/devicetopology/nn-devicetopology-iaudiopeakmeter),
+/// a peak meter in the hardware, see [Part::get_peak_meter].
+///
+/// The levels are measured by the device, and are given per channel in dB.
+/// This can be used as the source of a [LevelMeter](crate::LevelMeter).
+pub struct HardwarePeakMeter {
+    meter: IAudioPeakMeter,
+}
+
+impl HardwarePeakMeter {
+    /// Get the number of channels of the peak meter.
+    pub fn get_channel_count(&self) -> WasapiRes<u32> {
+        let count = unsafe { self.meter.GetChannelCount()? };
+        Ok(count)
+    }
+
+    /// Get the peak level of a channel in dB.
+    pub fn get_level_db(&self, channel: u32) -> WasapiRes<f32> {
+        let level = unsafe { self.meter.GetLevel(channel)? };
+        Ok(level)
+    }
+
+    /// Get the peak levels of all channels in dB.
+    pub fn get_levels_db(&self) -> WasapiRes<Vec<f32>> {
+        (0..self.get_channel_count()?)
+            .map(|channel| self.get_level_db(channel))
+            .collect()
+    }
+}
+
+fn parts_from_list(list: &IPartsList) -> WasapiRes<Vec<Part>> {
+    let count = unsafe { list.GetCount()? };
+    let mut parts = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let part = unsafe { list.GetPart(index)? };
+        parts.push(Part { part });
+    }
+    Ok(parts)
+}
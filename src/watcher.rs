@@ -0,0 +1,633 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{
+    Apartment, ChannelSendError, ChannelSender, DefaultDevices, DeviceCollection,
+    DeviceNotificationCallbacks, DeviceNotifications, DeviceRef, DeviceState, Direction,
+    PropertyKey, Role, WasapiError, WasapiRes, Worker,
+};
+
+/// How often the internal thread of a [DefaultDeviceDebouncer] checks if it has been asked to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A device notification, delivered by a [DeviceWatcher] or a [PollingDeviceWatcher].
+/// The devices are given as [DeviceRef] values, that can be opened with [DeviceRef::open].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceEvent {
+    /// A new endpoint device was added.
+    Added { device: DeviceRef },
+    /// An endpoint device was removed.
+    Removed { device: DeviceRef },
+    /// The state of an endpoint device changed.
+    StateChanged {
+        device: DeviceRef,
+        state: DeviceState,
+    },
+    /// The default device for a direction and role changed.
+    /// The device is `None` if there is no longer any default device.
+    DefaultChanged {
+        direction: Direction,
+        role: Role,
+        device: Option<DeviceRef>,
+    },
+    /// A property of an endpoint device changed.
+    PropertyChanged { device: DeviceRef, key: PropertyKey },
+}
+
+/// Watcher for device notifications, that delivers them as [DeviceEvent] values over a channel.
+/// Any channel implementing [ChannelSender] can be used.
+///
+/// This is an alternative to the callbacks of [DeviceNotifications], that is easier to use from an event loop.
+/// The registration is owned by an internal thread, so the watcher can be created and dropped on any thread.
+/// COM is initialized in the multithreaded apartment on that thread, use [DeviceWatcher::with_apartment] to choose another.
+/// The events are sent without blocking, and are dropped with a warning if a bounded channel is full.
+/// See [PollingDeviceWatcher] for a watcher that sends the same events without using notifications.
+///
+/// The watcher is stopped when it is dropped, or with [DeviceWatcher::stop].
+pub struct DeviceWatcher {
+    worker: Worker<()>,
+}
+
+impl DeviceWatcher {
+    /// Start watching for device notifications, and send them to the given channel.
+    /// Returns an error if the notifications could not be registered.
+    pub fn new<S>(sender: S) -> WasapiRes<Self>
+    where
+        S: ChannelSender<DeviceEvent> + Send + 'static,
+    {
+        DeviceWatcher::start(make_callbacks(sender), &Apartment::MultiThreaded)
+    }
+
+    /// Start watching for device notifications, and send them to the given channel.
+    /// [DeviceEvent::PropertyChanged] events are only sent for the given property keys,
+    /// see [DeviceNotificationCallbacks::set_property_filter].
+    pub fn with_property_filter<S>(sender: S, keys: &[PropertyKey]) -> WasapiRes<Self>
+    where
+        S: ChannelSender<DeviceEvent> + Send + 'static,
+    {
+        let mut callbacks = make_callbacks(sender);
+        callbacks.set_property_filter(keys);
+        DeviceWatcher::start(callbacks, &Apartment::MultiThreaded)
+    }
+
+    /// Start watching for device notifications with the internal thread in the given apartment,
+    /// and send them to the given channel, optionally only with the given property keys.
+    /// Returns an [ApartmentError](crate::ApartmentError) if COM can't be initialized in the apartment.
+    pub fn with_apartment<S>(
+        sender: S,
+        property_filter: Option<&[PropertyKey]>,
+        apartment: &Apartment,
+    ) -> WasapiRes<Self>
+    where
+        S: ChannelSender<DeviceEvent> + Send + 'static,
+    {
+        let mut callbacks = make_callbacks(sender);
+        if let Some(keys) = property_filter {
+            callbacks.set_property_filter(keys);
+        }
+        DeviceWatcher::start(callbacks, apartment)
+    }
+
+    fn start(callbacks: DeviceNotificationCallbacks, apartment: &Apartment) -> WasapiRes<Self> {
+        let (result_tx, result_rx) = mpsc::channel::<Result<(), String>>();
+        let worker = Worker::spawn("DeviceWatcher", apartment, move |stop| {
+            let notifications = match DeviceNotifications::new(callbacks) {
+                Ok(notifications) => notifications,
+                Err(err) => {
+                    let _ = result_tx.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            let _ = result_tx.send(Ok(()));
+            while !stop.is_stopped() {
+                thread::park();
+            }
+            drop(notifications);
+            debug!("Device watcher was stopped");
+        })?;
+        let worker = wait_for_startup(worker, result_rx)?;
+        Ok(DeviceWatcher { worker })
+    }
+
+    /// Request the watcher to stop. No more events are sent after the watcher has stopped,
+    /// use [DeviceWatcher::join] to wait for that.
+    pub fn stop(&self) {
+        self.worker.stop();
+    }
+
+    /// Check if the watcher is still running.
+    pub fn is_running(&self) -> bool {
+        self.worker.is_running()
+    }
+
+    /// Wait for the watcher to stop, with a timeout.
+    /// Returns false if it is still running when the timeout expires.
+    pub fn join(&mut self, timeout: Duration) -> WasapiRes<bool> {
+        Ok(self.worker.join(timeout)?.is_some())
+    }
+}
+
+/// The endpoint devices of both directions, and their default devices, at one point in time.
+struct DeviceSnapshot {
+    states: BTreeMap<String, DeviceState>,
+    defaults: Vec<DefaultDevices>,
+}
+
+impl DeviceSnapshot {
+    fn take() -> WasapiRes<Self> {
+        let all_states = [
+            DeviceState::Active,
+            DeviceState::Disabled,
+            DeviceState::NotPresent,
+            DeviceState::Unplugged,
+        ];
+        let mut states = BTreeMap::new();
+        let mut defaults = Vec::new();
+        for direction in [Direction::Render, Direction::Capture].iter() {
+            let collection = DeviceCollection::new_with_states(direction, &all_states)?;
+            for device in &collection {
+                let device = device?;
+                states.insert(device.get_id()?, device.get_state()?);
+            }
+            defaults.push(DefaultDevices::snapshot(direction)?);
+        }
+        Ok(DeviceSnapshot { states, defaults })
+    }
+
+    /// Get the events that describe the changes from a previous snapshot.
+    fn events_since(&self, previous: &DeviceSnapshot) -> Vec<DeviceEvent> {
+        let mut events = Vec::new();
+        for (device_id, state) in self.states.iter() {
+            match previous.states.get(device_id) {
+                None => events.push(DeviceEvent::Added {
+                    device: DeviceRef::new(device_id),
+                }),
+                Some(previous_state) if previous_state != state => {
+                    events.push(DeviceEvent::StateChanged {
+                        device: DeviceRef::new(device_id),
+                        state: *state,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for device_id in previous.states.keys() {
+            if !self.states.contains_key(device_id) {
+                events.push(DeviceEvent::Removed {
+                    device: DeviceRef::new(device_id),
+                });
+            }
+        }
+        for (defaults, previous_defaults) in self.defaults.iter().zip(previous.defaults.iter()) {
+            for role in defaults.changed_roles(previous_defaults) {
+                events.push(DeviceEvent::DefaultChanged {
+                    direction: defaults.get_direction(),
+                    role,
+                    device: defaults.get_id(&role).map(DeviceRef::new),
+                });
+            }
+        }
+        events
+    }
+}
+
+/// Watcher that polls the endpoint devices, and delivers the changes as [DeviceEvent] values over a channel.
+/// Any channel implementing [ChannelSender] can be used.
+///
+/// This is a fallback for [DeviceWatcher], for applications where notification callbacks from COM threads
+/// are unreliable or not wanted, for example some headless services.
+/// It sends the same events, so the two can be swapped, with these differences:
+/// - Changes are only seen once per polling interval.
+///   A device that is added and removed again between two polls is not reported.
+/// - Each poll compares the states of all endpoint devices, and the default devices of all roles,
+///   with the previous poll. [DeviceEvent::PropertyChanged] events are never sent.
+/// - The events of one poll are sent with the added devices and state changes first,
+///   then the removed devices, and last the default device changes.
+///
+/// The polling runs on an internal thread, and a poll that fails is skipped with a warning.
+/// COM is initialized in the multithreaded apartment on that thread,
+/// use [PollingDeviceWatcher::with_apartment] to choose another.
+/// The events are sent without blocking, and are dropped with a warning if a bounded channel is full.
+///
+/// The watcher is stopped when it is dropped, or with [PollingDeviceWatcher::stop].
+pub struct PollingDeviceWatcher {
+    worker: Worker<()>,
+}
+
+impl PollingDeviceWatcher {
+    /// Start polling the devices with the given interval, and send the changes to the given channel.
+    /// Returns an error if the devices could not be read the first time.
+    pub fn new<S>(sender: S, interval: Duration) -> WasapiRes<Self>
+    where
+        S: ChannelSender<DeviceEvent> + Send + 'static,
+    {
+        PollingDeviceWatcher::with_apartment(sender, interval, &Apartment::MultiThreaded)
+    }
+
+    /// Start polling the devices with the given interval, from an internal thread in the given apartment.
+    /// Returns an [ApartmentError](crate::ApartmentError) if COM can't be initialized in the apartment.
+    pub fn with_apartment<S>(
+        sender: S,
+        interval: Duration,
+        apartment: &Apartment,
+    ) -> WasapiRes<Self>
+    where
+        S: ChannelSender<DeviceEvent> + Send + 'static,
+    {
+        let send = channel_send(sender);
+        let (result_tx, result_rx) = mpsc::channel::<Result<(), String>>();
+        let worker = Worker::spawn("PollingDeviceWatcher", apartment, move |stop| {
+            let mut previous = match DeviceSnapshot::take() {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    let _ = result_tx.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            let _ = result_tx.send(Ok(()));
+            let mut next_poll = Instant::now() + interval;
+            while !stop.is_stopped() {
+                let now = Instant::now();
+                if now < next_poll {
+                    // The thread is unparked when a stop is requested.
+                    thread::park_timeout(next_poll - now);
+                    continue;
+                }
+                next_poll = now + interval;
+                let current = match DeviceSnapshot::take() {
+                    Ok(snapshot) => snapshot,
+                    Err(err) => {
+                        warn!("Unable to poll the devices, {}", err);
+                        continue;
+                    }
+                };
+                for event in current.events_since(&previous) {
+                    send(event);
+                }
+                previous = current;
+            }
+            debug!("Polling device watcher was stopped");
+        })?;
+        let worker = wait_for_startup(worker, result_rx)?;
+        Ok(PollingDeviceWatcher { worker })
+    }
+
+    /// Request the watcher to stop. No more events are sent after the watcher has stopped,
+    /// use [PollingDeviceWatcher::join] to wait for that.
+    pub fn stop(&self) {
+        self.worker.stop();
+    }
+
+    /// Check if the watcher is still running.
+    pub fn is_running(&self) -> bool {
+        self.worker.is_running()
+    }
+
+    /// Wait for the watcher to stop, with a timeout.
+    /// Returns false if it is still running when the timeout expires.
+    pub fn join(&mut self, timeout: Duration) -> WasapiRes<bool> {
+        Ok(self.worker.join(timeout)?.is_some())
+    }
+}
+
+/// Wait for the thread of a worker to report if it started successfully.
+/// The thread sends `Ok` when it is up and running, or the error that made it exit.
+pub(crate) fn wait_for_startup(
+    mut worker: Worker<()>,
+    result_rx: mpsc::Receiver<Result<(), String>>,
+) -> WasapiRes<Worker<()>> {
+    match result_rx.recv() {
+        Ok(Ok(())) => Ok(worker),
+        Ok(Err(err)) => Err(WasapiError::new(&err).into()),
+        // The thread exited without sending, COM could not be initialized.
+        Err(_) => match worker.join(Duration::from_secs(1)) {
+            Err(err) => Err(err),
+            Ok(_) => {
+                Err(WasapiError::new(&format!("The {} thread exited", worker.get_name())).into())
+            }
+        },
+    }
+}
+
+fn make_callbacks<S>(sender: S) -> DeviceNotificationCallbacks
+where
+    S: ChannelSender<DeviceEvent> + Send + 'static,
+{
+    device_event_callbacks(Arc::new(channel_send(sender)))
+}
+
+/// Make a function that sends device events to a channel without blocking.
+fn channel_send<S>(sender: S) -> impl Fn(DeviceEvent) + Send + Sync
+where
+    S: ChannelSender<DeviceEvent> + Send + 'static,
+{
+    // The callbacks are called from COM threads, and the sender is shared between them.
+    let sender = Mutex::new(sender);
+    move |event: DeviceEvent| {
+        let sender = match sender.lock() {
+            Ok(sender) => sender,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match sender.try_send(event) {
+            Ok(()) => {}
+            Err(ChannelSendError::Full) => warn!("Channel is full, dropped device event"),
+            Err(ChannelSendError::Disconnected) => {
+                trace!("Receiver was dropped, ignoring device event")
+            }
+        }
+    }
+}
+
+/// Make device notification callbacks that pass each notification as a [DeviceEvent] to `send`.
+pub(crate) fn device_event_callbacks(
+    send: Arc<dyn Fn(DeviceEvent) + Send + Sync>,
+) -> DeviceNotificationCallbacks {
+    let mut callbacks = DeviceNotificationCallbacks::new();
+    let s = send.clone();
+    callbacks.set_device_added_callback(move |device| s(DeviceEvent::Added { device }));
+    let s = send.clone();
+    callbacks.set_device_removed_callback(move |device| s(DeviceEvent::Removed { device }));
+    let s = send.clone();
+    callbacks.set_device_state_callback(move |device, state| {
+        s(DeviceEvent::StateChanged { device, state })
+    });
+    let s = send.clone();
+    callbacks.set_default_device_callback(move |direction, role, device| {
+        s(DeviceEvent::DefaultChanged {
+            direction,
+            role,
+            device,
+        })
+    });
+    callbacks.set_property_value_callback(move |device, fmtid, pid| {
+        send(DeviceEvent::PropertyChanged {
+            device,
+            key: PropertyKey { fmtid, pid },
+        })
+    });
+    callbacks
+}
+
+/// A default device change reported by a [DefaultDeviceDebouncer],
+/// after the notifications for the individual roles have been coalesced.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DefaultDeviceChange {
+    /// The direction of the devices that changed.
+    pub direction: Direction,
+    /// The default devices before the change.
+    pub previous: DefaultDevices,
+    /// The default devices after the change.
+    pub current: DefaultDevices,
+    /// All roles where the default device changed, also when a role filter is used.
+    pub changed_roles: Vec<Role>,
+}
+
+/// The state of one direction in a [DefaultDeviceDebouncer].
+struct DebounceState {
+    defaults: DefaultDevices,
+    deadline: Option<Instant>,
+}
+
+/// Watcher for default device changes, that coalesces the notifications into one [DefaultDeviceChange] per direction.
+///
+/// When the default device changes, Windows sends one notification per role,
+/// and sometimes repeats them.
+/// The debouncer waits until no notifications have arrived for a direction during the debounce interval,
+/// and then compares the default devices with the ones before the change.
+/// If a role is given, only changes that include that role are reported.
+///
+/// The callback is called from an internal thread where COM is initialized,
+/// so it may open devices. The thread is in the multithreaded apartment,
+/// unless another one is given to [DefaultDeviceDebouncer::with_apartment]. It should return quickly, to not delay the following changes.
+/// The debouncer is stopped when it is dropped, or with [DefaultDeviceDebouncer::stop].
+pub struct DefaultDeviceDebouncer {
+    worker: Worker<()>,
+}
+
+impl DefaultDeviceDebouncer {
+    /// Start watching for default device changes of both directions,
+    /// optionally only for one role, with the given debounce interval.
+    pub fn new(
+        role: Option<&Role>,
+        interval: Duration,
+        callback: impl Fn(DefaultDeviceChange) + Send + 'static,
+    ) -> WasapiRes<Self> {
+        DefaultDeviceDebouncer::with_apartment(role, interval, &Apartment::MultiThreaded, callback)
+    }
+
+    /// Start watching for default device changes, with the internal thread in the given apartment.
+    /// Returns an [ApartmentError](crate::ApartmentError) if COM can't be initialized in the apartment.
+    pub fn with_apartment(
+        role: Option<&Role>,
+        interval: Duration,
+        apartment: &Apartment,
+        callback: impl Fn(DefaultDeviceChange) + Send + 'static,
+    ) -> WasapiRes<Self> {
+        let role = role.copied();
+        let (result_tx, result_rx) = mpsc::channel::<Result<(), String>>();
+        let worker = Worker::spawn("DefaultDeviceDebouncer", apartment, move |stop| {
+            let (notify_tx, notify_rx) = mpsc::channel::<Direction>();
+            let notify_tx = Mutex::new(notify_tx);
+            let mut callbacks = DeviceNotificationCallbacks::new();
+            callbacks.set_default_device_callback(move |direction, _role, _device| {
+                let notify_tx = match notify_tx.lock() {
+                    Ok(notify_tx) => notify_tx,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                let _ = notify_tx.send(direction);
+            });
+            let mut states = Vec::new();
+            for direction in [Direction::Render, Direction::Capture].iter() {
+                match DefaultDevices::snapshot(direction) {
+                    Ok(defaults) => states.push(DebounceState {
+                        defaults,
+                        deadline: None,
+                    }),
+                    Err(err) => {
+                        let _ = result_tx.send(Err(err.to_string()));
+                        return;
+                    }
+                }
+            }
+            let notifications = match DeviceNotifications::new(callbacks) {
+                Ok(notifications) => notifications,
+                Err(err) => {
+                    let _ = result_tx.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            let _ = result_tx.send(Ok(()));
+            while !stop.is_stopped() {
+                let now = Instant::now();
+                let timeout = states
+                    .iter()
+                    .filter_map(|state| state.deadline)
+                    .map(|deadline| deadline.saturating_duration_since(now))
+                    .fold(STOP_POLL_INTERVAL, Duration::min);
+                if let Ok(direction) = notify_rx.recv_timeout(timeout) {
+                    if let Some(state) = states
+                        .iter_mut()
+                        .find(|state| state.defaults.get_direction() == direction)
+                    {
+                        state.deadline = Some(Instant::now() + interval);
+                    }
+                    continue;
+                }
+                let now = Instant::now();
+                for state in states.iter_mut() {
+                    match state.deadline {
+                        Some(deadline) if deadline <= now => state.deadline = None,
+                        _ => continue,
+                    }
+                    let direction = state.defaults.get_direction();
+                    let current = match DefaultDevices::snapshot(&direction) {
+                        Ok(current) => current,
+                        Err(err) => {
+                            warn!("Unable to read the default {} devices, {}", direction, err);
+                            continue;
+                        }
+                    };
+                    let changed_roles = current.changed_roles(&state.defaults);
+                    let previous = std::mem::replace(&mut state.defaults, current.clone());
+                    if changed_roles.is_empty()
+                        || role.map_or(false, |role| !changed_roles.contains(&role))
+                    {
+                        trace!("No relevant default {} device change", direction);
+                        continue;
+                    }
+                    debug!(
+                        "Default {} device changed for roles {:?}",
+                        direction, changed_roles
+                    );
+                    callback(DefaultDeviceChange {
+                        direction,
+                        previous,
+                        current,
+                        changed_roles,
+                    });
+                }
+            }
+            drop(notifications);
+            debug!("Default device debouncer was stopped");
+        })?;
+        let worker = wait_for_startup(worker, result_rx)?;
+        Ok(DefaultDeviceDebouncer { worker })
+    }
+
+    /// Request the debouncer to stop. Changes that are waiting for the debounce interval are not reported.
+    pub fn stop(&self) {
+        self.worker.stop();
+    }
+
+    /// Check if the debouncer is still running.
+    pub fn is_running(&self) -> bool {
+        self.worker.is_running()
+    }
+
+    /// Wait for the debouncer to stop, with a timeout.
+    /// Returns false if it is still running when the timeout expires.
+    pub fn join(&mut self, timeout: Duration) -> WasapiRes<bool> {
+        Ok(self.worker.join(timeout)?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(
+        states: &[(&str, DeviceState)],
+        render_defaults: [Option<&str>; 3],
+    ) -> DeviceSnapshot {
+        DeviceSnapshot {
+            states: states
+                .iter()
+                .map(|(id, state)| (id.to_string(), *state))
+                .collect(),
+            defaults: vec![
+                DefaultDevices::from_ids(&Direction::Render, render_defaults),
+                DefaultDevices::from_ids(&Direction::Capture, [Some("mic"); 3]),
+            ],
+        }
+    }
+
+    #[test]
+    fn unchanged_snapshot_gives_no_events() {
+        let first = snapshot(&[("a", DeviceState::Active)], [Some("a"); 3]);
+        let second = snapshot(&[("a", DeviceState::Active)], [Some("a"); 3]);
+        assert!(second.events_since(&first).is_empty());
+    }
+
+    #[test]
+    fn events_are_ordered_added_removed_default() {
+        let previous = snapshot(
+            &[("a", DeviceState::Active), ("b", DeviceState::Active)],
+            [Some("b"); 3],
+        );
+        let current = snapshot(
+            &[("a", DeviceState::Active), ("c", DeviceState::Active)],
+            [Some("c"), Some("c"), Some("b")],
+        );
+        let events = current.events_since(&previous);
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::Added {
+                    device: DeviceRef::new("c"),
+                },
+                DeviceEvent::Removed {
+                    device: DeviceRef::new("b"),
+                },
+                DeviceEvent::DefaultChanged {
+                    direction: Direction::Render,
+                    role: Role::Console,
+                    device: Some(DeviceRef::new("c")),
+                },
+                DeviceEvent::DefaultChanged {
+                    direction: Direction::Render,
+                    role: Role::Multimedia,
+                    device: Some(DeviceRef::new("c")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn state_changes_come_before_removals() {
+        let previous = snapshot(
+            &[("a", DeviceState::Active), ("b", DeviceState::Active)],
+            [None; 3],
+        );
+        let current = snapshot(&[("b", DeviceState::Unplugged)], [None; 3]);
+        let events = current.events_since(&previous);
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::StateChanged {
+                    device: DeviceRef::new("b"),
+                    state: DeviceState::Unplugged,
+                },
+                DeviceEvent::Removed {
+                    device: DeviceRef::new("a"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lost_default_device_is_reported_as_none() {
+        let previous = snapshot(&[("a", DeviceState::Active)], [Some("a"); 3]);
+        let current = snapshot(&[("a", DeviceState::Active)], [Some("a"), Some("a"), None]);
+        let events = current.events_since(&previous);
+        assert_eq!(
+            events,
+            vec![DeviceEvent::DefaultChanged {
+                direction: Direction::Render,
+                role: Role::Communications,
+                device: None,
+            }]
+        );
+    }
+}
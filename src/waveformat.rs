@@ -1,4 +1,7 @@
+use std::error;
 use std::fmt;
+use std::mem::size_of;
+use std::ptr;
 use windows::{
     core::GUID,
     Win32::Media::Audio::{
@@ -63,6 +66,62 @@ const CUSTOM_SPEAKER_4POINT1_SURROUND: u32 = KSAUDIO_SPEAKER_SURROUND | SPEAKER_
 const CUSTOM_SPEAKER_6POINT1: u32 = KSAUDIO_SPEAKER_5POINT1 | SPEAKER_BACK_CENTER;
 const CUSTOM_SPEAKER_6POINT1_SURROUND: u32 = KSAUDIO_SPEAKER_5POINT1_SURROUND | SPEAKER_BACK_CENTER;
 
+/// Error returned by [WaveFormat::parse_bytes] when the bytes don't contain a valid format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FormatParseError {
+    /// The bytes are too short to contain a `WAVEFORMATEX` structure.
+    TooShort {
+        /// The number of bytes.
+        len: usize,
+    },
+    /// The `cbSize` field claims more extra bytes than are available,
+    /// or too few for a `WAVEFORMATEXTENSIBLE` structure.
+    Truncated {
+        /// The value of the `cbSize` field.
+        cb_size: u16,
+        /// The number of bytes.
+        len: usize,
+    },
+    /// The format has zero channels.
+    ZeroChannels,
+    /// The `nBlockAlign` field doesn't match the number of channels and the bits per sample.
+    BlockAlignMismatch {
+        /// The value of the `nBlockAlign` field.
+        block_align: u16,
+        /// The block align calculated from the `nChannels` and `wBitsPerSample` fields.
+        expected: usize,
+    },
+    /// The format tag is not supported.
+    UnsupportedFormatTag(u16),
+}
+
+impl fmt::Display for FormatParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FormatParseError::TooShort { len } => {
+                write!(f, "Format is too short, got {} bytes", len)
+            }
+            FormatParseError::Truncated { cb_size, len } => {
+                write!(f, "Truncated format, cbSize {}, got {} bytes", cb_size, len)
+            }
+            FormatParseError::ZeroChannels => write!(f, "Invalid format with zero channels"),
+            FormatParseError::BlockAlignMismatch {
+                block_align,
+                expected,
+            } => write!(
+                f,
+                "Invalid block align {}, expected {}",
+                block_align, expected
+            ),
+            FormatParseError::UnsupportedFormatTag(tag) => {
+                write!(f, "Unsupported format tag {}", tag)
+            }
+        }
+    }
+}
+
+impl error::Error for FormatParseError {}
+
 /// Struct wrapping a [WAVEFORMATEXTENSIBLE](https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible) format descriptor.
 #[derive(Clone)]
 pub struct WaveFormat {
@@ -144,16 +203,26 @@ impl WaveFormat {
 
     /// Create from a [WAVEFORMATEX](https://docs.microsoft.com/en-us/previous-versions/dd757713(v=vs.85)) structure
     pub fn from_waveformatex(wavefmt: WAVEFORMATEX) -> WasapiRes<Self> {
+        let format = WaveFormat::parse_waveformatex(wavefmt)
+            .map_err(|err| WasapiError::new(&err.to_string()))?;
+        Ok(format)
+    }
+
+    fn parse_waveformatex(wavefmt: WAVEFORMATEX) -> Result<Self, FormatParseError> {
         let validbits = wavefmt.wBitsPerSample as usize;
         let blockalign = wavefmt.nBlockAlign as usize;
         let samplerate = wavefmt.nSamplesPerSec as usize;
         let formattag = wavefmt.wFormatTag;
         let channels = wavefmt.nChannels as usize;
+        if channels == 0 {
+            return Err(FormatParseError::ZeroChannels);
+        }
+        check_block_align(&wavefmt)?;
         let sample_type = match formattag as u32 {
             WAVE_FORMAT_PCM => SampleType::Int,
             WAVE_FORMAT_IEEE_FLOAT => SampleType::Float,
             _ => {
-                return Err(WasapiError::new("Unsupported format").into());
+                return Err(FormatParseError::UnsupportedFormatTag(formattag));
             }
         };
         let storebits = 8 * blockalign / channels;
@@ -167,6 +236,50 @@ impl WaveFormat {
         ))
     }
 
+    /// Create a [WaveFormat] from the raw bytes of a [WAVEFORMATEX](https://docs.microsoft.com/en-us/previous-versions/dd757713(v=vs.85))
+    /// or [WAVEFORMATEXTENSIBLE](https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible) structure,
+    /// for example as returned by [AudioClient::get_mixformat_raw](crate::AudioClient::get_mixformat_raw).
+    /// This is the same as [WaveFormat::parse_bytes], but returns the error boxed like the rest of the crate.
+    pub fn from_bytes(bytes: &[u8]) -> WasapiRes<Self> {
+        let format = WaveFormat::parse_bytes(bytes)?;
+        Ok(format)
+    }
+
+    /// Parse a [WaveFormat] from the raw bytes of a [WAVEFORMATEX](https://docs.microsoft.com/en-us/previous-versions/dd757713(v=vs.85))
+    /// or [WAVEFORMATEXTENSIBLE](https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible) structure.
+    /// A WAVEFORMATEXTENSIBLE is used as is, while a plain WAVEFORMATEX with integer or float samples is converted.
+    ///
+    /// The bytes may come from an untrusted source, such as a file or another process.
+    /// The `cbSize` field is checked against the length of the slice,
+    /// and a [FormatParseError] is returned if the structure is truncated or not supported.
+    /// Any bytes after the end of the structure are ignored.
+    pub fn parse_bytes(bytes: &[u8]) -> Result<Self, FormatParseError> {
+        let len = bytes.len();
+        if len < size_of::<WAVEFORMATEX>() {
+            return Err(FormatParseError::TooShort { len });
+        }
+        let wavefmt: WAVEFORMATEX =
+            unsafe { ptr::read_unaligned(bytes.as_ptr() as *const WAVEFORMATEX) };
+        let cb_size = wavefmt.cbSize;
+        if len - size_of::<WAVEFORMATEX>() < cb_size as usize {
+            return Err(FormatParseError::Truncated { cb_size, len });
+        }
+        if wavefmt.wFormatTag as u32 != WAVE_FORMAT_EXTENSIBLE {
+            return WaveFormat::parse_waveformatex(wavefmt);
+        }
+        let extensible_size = size_of::<WAVEFORMATEXTENSIBLE>() - size_of::<WAVEFORMATEX>();
+        if (cb_size as usize) < extensible_size {
+            return Err(FormatParseError::Truncated { cb_size, len });
+        }
+        if wavefmt.nChannels == 0 {
+            return Err(FormatParseError::ZeroChannels);
+        }
+        check_block_align(&wavefmt)?;
+        let wave_fmt: WAVEFORMATEXTENSIBLE =
+            unsafe { ptr::read_unaligned(bytes.as_ptr() as *const WAVEFORMATEXTENSIBLE) };
+        Ok(WaveFormat { wave_fmt })
+    }
+
     /// Return a copy in the simpler [WAVEFORMATEX](https://docs.microsoft.com/en-us/previous-versions/dd757713(v=vs.85)) format.
     pub fn to_waveformatex(&self) -> WasapiRes<Self> {
         let blockalign = self.wave_fmt.Format.nBlockAlign;
@@ -260,6 +373,19 @@ impl WaveFormat {
     }
 }
 
+/// Check that the block align is the size of one sample times the number of channels.
+fn check_block_align(wavefmt: &WAVEFORMATEX) -> Result<(), FormatParseError> {
+    let block_align = wavefmt.nBlockAlign;
+    let expected = wavefmt.nChannels as usize * wavefmt.wBitsPerSample as usize / 8;
+    if block_align as usize != expected {
+        return Err(FormatParseError::BlockAlignMismatch {
+            block_align,
+            expected,
+        });
+    }
+    Ok(())
+}
+
 /// Return a vector with suggested channel masks for the given number of channels.
 /// Used to find a format that a device accepts in exclusive mode.
 /// The values are sorted according to how likely they are to be accepted, with the most likely first.
@@ -315,3 +441,444 @@ pub fn make_simple_channelmask(channels: usize) -> u32 {
         _ => 0,
     }
 }
+
+/// A speaker position, one of the 18 positions that can be set in a channel mask.
+/// The positions are listed in the order of the bits of the mask,
+/// see [WAVEFORMATEXTENSIBLE](https://learn.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SpeakerPosition {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    FrontLeftOfCenter,
+    FrontRightOfCenter,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+}
+
+impl SpeakerPosition {
+    /// All speaker positions, in the order of the bits of a channel mask.
+    pub const ALL: [SpeakerPosition; 18] = [
+        SpeakerPosition::FrontLeft,
+        SpeakerPosition::FrontRight,
+        SpeakerPosition::FrontCenter,
+        SpeakerPosition::LowFrequency,
+        SpeakerPosition::BackLeft,
+        SpeakerPosition::BackRight,
+        SpeakerPosition::FrontLeftOfCenter,
+        SpeakerPosition::FrontRightOfCenter,
+        SpeakerPosition::BackCenter,
+        SpeakerPosition::SideLeft,
+        SpeakerPosition::SideRight,
+        SpeakerPosition::TopCenter,
+        SpeakerPosition::TopFrontLeft,
+        SpeakerPosition::TopFrontCenter,
+        SpeakerPosition::TopFrontRight,
+        SpeakerPosition::TopBackLeft,
+        SpeakerPosition::TopBackCenter,
+        SpeakerPosition::TopBackRight,
+    ];
+
+    /// Get the bit of the position in a channel mask, for example `SPEAKER_FRONT_LEFT` for [SpeakerPosition::FrontLeft].
+    pub fn get_mask(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+impl fmt::Display for SpeakerPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A set of speaker positions, decoded from a channel mask.
+/// This is used for the channel mask of a [WaveFormat],
+/// and for the speaker configuration of a device, see [Device::get_physical_speakers](crate::Device::get_physical_speakers).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SpeakerMask {
+    mask: u32,
+}
+
+impl SpeakerMask {
+    /// Create a [SpeakerMask] from a channel mask.
+    pub fn from_mask(mask: u32) -> Self {
+        SpeakerMask { mask }
+    }
+
+    /// Create a [SpeakerMask] containing the given positions.
+    pub fn from_positions(positions: &[SpeakerPosition]) -> Self {
+        let mask = positions
+            .iter()
+            .fold(0, |mask, position| mask | position.get_mask());
+        SpeakerMask { mask }
+    }
+
+    /// Get the channel mask. Bits above the 18 defined positions are kept as they were.
+    pub fn get_mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Check if the mask contains a position.
+    pub fn contains(&self, position: &SpeakerPosition) -> bool {
+        self.mask & position.get_mask() != 0
+    }
+
+    /// Get the positions in the mask, in the order of the bits of the mask.
+    /// This is also the order of the channels in a stream using the mask.
+    pub fn get_positions(&self) -> Vec<SpeakerPosition> {
+        SpeakerPosition::ALL
+            .iter()
+            .filter(|position| self.contains(position))
+            .copied()
+            .collect()
+    }
+
+    /// Get the number of speaker positions in the mask.
+    pub fn get_nbr_speakers(&self) -> usize {
+        (self.mask & ((1 << SpeakerPosition::ALL.len()) - 1)).count_ones() as usize
+    }
+}
+
+impl From<u32> for SpeakerMask {
+    fn from(mask: u32) -> Self {
+        SpeakerMask::from_mask(mask)
+    }
+}
+
+impl fmt::Display for SpeakerMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self
+            .get_positions()
+            .iter()
+            .map(|position| position.to_string())
+            .collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// The basic properties of a [WaveFormat], as plain data.
+/// This is used in [DeviceInfo](crate::DeviceInfo), and can be serialized when the `serde` feature is enabled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FormatInfo {
+    pub samplerate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub valid_bits_per_sample: u16,
+    /// The sample type, `None` if the subformat is unknown.
+    pub sample_type: Option<SampleType>,
+    pub channel_mask: u32,
+}
+
+impl From<&WaveFormat> for FormatInfo {
+    fn from(format: &WaveFormat) -> Self {
+        FormatInfo {
+            samplerate: format.get_samplespersec(),
+            channels: format.get_nchannels(),
+            bits_per_sample: format.get_bitspersample(),
+            valid_bits_per_sample: format.get_validbitspersample(),
+            sample_type: format.get_subformat().ok(),
+            channel_mask: format.get_dwchannelmask(),
+        }
+    }
+}
+
+/// The differences between two formats, see [compare_formats].
+/// Each field is `None` if the formats are equal in that respect,
+/// and otherwise holds the values of the first and second format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FormatDiff {
+    /// The sample rates in Hz, `Some` if the formats have different sample rates.
+    pub samplerate: Option<(u32, u32)>,
+    /// The numbers of channels, `Some` if the formats have different numbers of channels.
+    pub channels: Option<(u16, u16)>,
+    /// The container sizes of the samples in bits, `Some` if they differ.
+    pub bits_per_sample: Option<(u16, u16)>,
+    /// The numbers of valid bits per sample, `Some` if they differ.
+    pub valid_bits_per_sample: Option<(u16, u16)>,
+    /// The sample types, `None` in the pair means that the subformat is unknown.
+    pub sample_type: Option<(Option<SampleType>, Option<SampleType>)>,
+    /// The channel masks, `Some` if the formats have different channel masks.
+    pub channel_mask: Option<(u32, u32)>,
+    known_sample_types: bool,
+}
+
+impl FormatDiff {
+    /// Check if the formats are identical.
+    pub fn is_identical(&self) -> bool {
+        self.samplerate.is_none()
+            && self.channels.is_none()
+            && self.bits_per_sample.is_none()
+            && self.valid_bits_per_sample.is_none()
+            && self.sample_type.is_none()
+            && self.channel_mask.is_none()
+    }
+
+    /// Check if the automatic conversion of a shared mode stream can bridge the differences,
+    /// meaning that the client can be initialized with `convert` enabled, see
+    /// [AudioClient::initialize_client](crate::AudioClient::initialize_client).
+    /// The conversion handles any difference between integer and float formats,
+    /// but not formats with an unknown subformat.
+    /// It is not available in exclusive mode.
+    pub fn autoconvert_can_bridge(&self) -> bool {
+        self.is_identical() || self.known_sample_types
+    }
+}
+
+impl fmt::Display for FormatDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_identical() {
+            return write!(f, "identical formats");
+        }
+        let mut diffs = Vec::new();
+        if let Some((a, b)) = self.samplerate {
+            diffs.push(format!("sample rate {} -> {}", a, b));
+        }
+        if let Some((a, b)) = self.channels {
+            diffs.push(format!("channels {} -> {}", a, b));
+        }
+        if let Some((a, b)) = self.bits_per_sample {
+            diffs.push(format!("bits per sample {} -> {}", a, b));
+        }
+        if let Some((a, b)) = self.valid_bits_per_sample {
+            diffs.push(format!("valid bits per sample {} -> {}", a, b));
+        }
+        if let Some((a, b)) = self.sample_type {
+            let name = |sample_type: Option<SampleType>| match sample_type {
+                Some(sample_type) => sample_type.to_string(),
+                None => "unknown".to_string(),
+            };
+            diffs.push(format!("sample type {} -> {}", name(a), name(b)));
+        }
+        if let Some((a, b)) = self.channel_mask {
+            diffs.push(format!("channel mask {:#x} -> {:#x}", a, b));
+        }
+        write!(f, "{}", diffs.join(", "))
+    }
+}
+
+/// Compare two formats, for example a desired format and the mix format of a device,
+/// and list the differences.
+/// Use [FormatDiff::autoconvert_can_bridge] to check if a shared mode stream with automatic conversion
+/// can be used, or if the application needs to convert the data itself.
+pub fn compare_formats(a: &WaveFormat, b: &WaveFormat) -> FormatDiff {
+    fn diff<T: PartialEq>(a: T, b: T) -> Option<(T, T)> {
+        if a == b {
+            None
+        } else {
+            Some((a, b))
+        }
+    }
+    FormatDiff {
+        samplerate: diff(a.get_samplespersec(), b.get_samplespersec()),
+        channels: diff(a.get_nchannels(), b.get_nchannels()),
+        bits_per_sample: diff(a.get_bitspersample(), b.get_bitspersample()),
+        valid_bits_per_sample: diff(a.get_validbitspersample(), b.get_validbitspersample()),
+        sample_type: diff(a.get_subformat().ok(), b.get_subformat().ok()),
+        channel_mask: diff(a.get_dwchannelmask(), b.get_dwchannelmask()),
+        known_sample_types: a.get_subformat().is_ok() && b.get_subformat().is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make the bytes of a WAVEFORMATEX with the given fields and cbSize 0.
+    fn waveformatex_bytes(
+        tag: u32,
+        channels: u16,
+        samplerate: u32,
+        block_align: u16,
+        bits: u16,
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(tag as u16).to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&samplerate.to_le_bytes());
+        bytes.extend_from_slice(&(samplerate * block_align as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes
+    }
+
+    /// Make the bytes of a WAVEFORMATEXTENSIBLE with the given fields and cbSize 22.
+    fn extensible_bytes(
+        channels: u16,
+        samplerate: u32,
+        bits: u16,
+        valid_bits: u16,
+        mask: u32,
+        subformat: GUID,
+    ) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut bytes = waveformatex_bytes(
+            WAVE_FORMAT_EXTENSIBLE,
+            channels,
+            samplerate,
+            block_align,
+            bits,
+        );
+        bytes[16..18].copy_from_slice(&22u16.to_le_bytes());
+        bytes.extend_from_slice(&valid_bits.to_le_bytes());
+        bytes.extend_from_slice(&mask.to_le_bytes());
+        bytes.extend_from_slice(&subformat.data1.to_le_bytes());
+        bytes.extend_from_slice(&subformat.data2.to_le_bytes());
+        bytes.extend_from_slice(&subformat.data3.to_le_bytes());
+        bytes.extend_from_slice(&subformat.data4);
+        bytes
+    }
+
+    #[test]
+    fn too_short() {
+        let bytes = waveformatex_bytes(WAVE_FORMAT_PCM, 2, 44100, 4, 16);
+        // Shorter than a PCMWAVEFORMAT
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes[..14]).err(),
+            Some(FormatParseError::TooShort { len: 14 })
+        );
+        // A PCMWAVEFORMAT, without the cbSize field
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes[..16]).err(),
+            Some(FormatParseError::TooShort { len: 16 })
+        );
+    }
+
+    #[test]
+    fn plain_pcm() {
+        let bytes = waveformatex_bytes(WAVE_FORMAT_PCM, 2, 44100, 4, 16);
+        let format = WaveFormat::parse_bytes(&bytes).unwrap();
+        assert_eq!(format.get_nchannels(), 2);
+        assert_eq!(format.get_samplespersec(), 44100);
+        assert_eq!(format.get_blockalign(), 4);
+        assert_eq!(format.get_bitspersample(), 16);
+        assert_eq!(format.get_validbitspersample(), 16);
+        assert_eq!(format.get_subformat().unwrap(), SampleType::Int);
+    }
+
+    #[test]
+    fn ieee_float() {
+        let bytes = waveformatex_bytes(WAVE_FORMAT_IEEE_FLOAT, 2, 48000, 8, 32);
+        let format = WaveFormat::parse_bytes(&bytes).unwrap();
+        assert_eq!(format.get_nchannels(), 2);
+        assert_eq!(format.get_samplespersec(), 48000);
+        assert_eq!(format.get_bitspersample(), 32);
+        assert_eq!(format.get_subformat().unwrap(), SampleType::Float);
+    }
+
+    #[test]
+    fn extensible_with_channel_mask() {
+        let bytes = extensible_bytes(
+            6,
+            96000,
+            32,
+            24,
+            KSAUDIO_SPEAKER_5POINT1_SURROUND,
+            KSDATAFORMAT_SUBTYPE_PCM,
+        );
+        assert_eq!(bytes.len(), size_of::<WAVEFORMATEXTENSIBLE>());
+        let format = WaveFormat::parse_bytes(&bytes).unwrap();
+        assert_eq!(format.get_nchannels(), 6);
+        assert_eq!(format.get_samplespersec(), 96000);
+        assert_eq!(format.get_blockalign(), 24);
+        assert_eq!(format.get_bitspersample(), 32);
+        assert_eq!(format.get_validbitspersample(), 24);
+        assert_eq!(format.get_dwchannelmask(), KSAUDIO_SPEAKER_5POINT1_SURROUND);
+        assert_eq!(format.get_subformat().unwrap(), SampleType::Int);
+    }
+
+    #[test]
+    fn extensible_with_small_cb_size() {
+        let mut bytes = extensible_bytes(2, 48000, 32, 32, 3, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT);
+        bytes[16..18].copy_from_slice(&10u16.to_le_bytes());
+        bytes.truncate(28);
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes).err(),
+            Some(FormatParseError::Truncated {
+                cb_size: 10,
+                len: 28
+            })
+        );
+    }
+
+    #[test]
+    fn extensible_truncated() {
+        let bytes = extensible_bytes(2, 48000, 32, 32, 3, KSDATAFORMAT_SUBTYPE_IEEE_FLOAT);
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes[..30]).err(),
+            Some(FormatParseError::Truncated {
+                cb_size: 22,
+                len: 30
+            })
+        );
+    }
+
+    #[test]
+    fn zero_channels() {
+        let bytes = waveformatex_bytes(WAVE_FORMAT_PCM, 0, 44100, 0, 16);
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes).err(),
+            Some(FormatParseError::ZeroChannels)
+        );
+        let bytes = extensible_bytes(0, 44100, 16, 16, 0, KSDATAFORMAT_SUBTYPE_PCM);
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes).err(),
+            Some(FormatParseError::ZeroChannels)
+        );
+    }
+
+    #[test]
+    fn block_align_mismatch() {
+        let bytes = waveformatex_bytes(WAVE_FORMAT_PCM, 2, 44100, 3, 16);
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes).err(),
+            Some(FormatParseError::BlockAlignMismatch {
+                block_align: 3,
+                expected: 4
+            })
+        );
+        let mut bytes = extensible_bytes(2, 44100, 16, 16, 3, KSDATAFORMAT_SUBTYPE_PCM);
+        bytes[12..14].copy_from_slice(&8u16.to_le_bytes());
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes).err(),
+            Some(FormatParseError::BlockAlignMismatch {
+                block_align: 8,
+                expected: 4
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_format_tag() {
+        // WAVE_FORMAT_MPEGLAYER3
+        let bytes = waveformatex_bytes(0x0055, 2, 44100, 4, 16);
+        assert_eq!(
+            WaveFormat::parse_bytes(&bytes).err(),
+            Some(FormatParseError::UnsupportedFormatTag(0x0055))
+        );
+    }
+
+    #[test]
+    fn from_bytes_error_can_be_downcast() {
+        let bytes = waveformatex_bytes(WAVE_FORMAT_PCM, 2, 44100, 4, 16);
+        let err = WaveFormat::from_bytes(&bytes[..10]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FormatParseError>(),
+            Some(&FormatParseError::TooShort { len: 10 })
+        );
+    }
+}
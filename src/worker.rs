@@ -0,0 +1,190 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{
+    deinitialize, initialize_apartment, Apartment, ApartmentError, StopToken, WasapiError,
+    WasapiRes,
+};
+
+/// The longest time that dropping a [Worker] waits for the thread to finish.
+const DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Flag telling if the thread of a [Worker] has finished, with a condition variable to wait for it.
+type FinishedFlag = Arc<(Mutex<bool>, Condvar)>;
+
+/// Sets the finished flag when dropped, so that it is set also when the worker function panics.
+struct FinishedGuard {
+    finished: FinishedFlag,
+}
+
+impl Drop for FinishedGuard {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.finished;
+        match lock.lock() {
+            Ok(mut finished) => *finished = true,
+            Err(poisoned) => *poisoned.into_inner() = true,
+        }
+        condvar.notify_all();
+    }
+}
+
+/// Handle for a background thread that runs until it is stopped,
+/// for example a thread running a [CapturePump](crate::CapturePump) or a render loop.
+///
+/// All background work is controlled the same way:
+/// [Worker::stop] requests a stop via the [StopToken] passed to the worker function,
+/// [Worker::is_running] tells if the thread is still running,
+/// and [Worker::join] waits, with a timeout, for the thread to finish and returns its result.
+/// The worker function is responsible for checking the token regularly, for example once per buffer event.
+/// A worker that has nothing else to wait for can wait with `std::thread::park`,
+/// since the thread is unparked when a stop is requested.
+///
+/// The thread initializes COM in the given apartment before running the function,
+/// and closes COM after the function returns.
+/// All COM objects must be created and dropped within the function,
+/// so that they are released before COM is closed.
+///
+/// Dropping a [Worker] that is still running requests a stop and waits up to five seconds for the thread to finish.
+/// If it is still running after that, a warning is logged and the thread is detached.
+/// It then keeps running until the function returns, and its result is discarded.
+pub struct Worker<T> {
+    name: String,
+    stop_token: StopToken,
+    finished: FinishedFlag,
+    thread: Option<thread::JoinHandle<Result<T, ApartmentError>>>,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    /// Spawn a named thread running `f` in the given apartment.
+    /// The function gets the [StopToken] of the worker.
+    pub fn spawn<F>(name: &str, apartment: &Apartment, f: F) -> WasapiRes<Self>
+    where
+        F: FnOnce(StopToken) -> T + Send + 'static,
+    {
+        let stop_token = StopToken::new();
+        let finished: FinishedFlag = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_token = stop_token.clone();
+        let guard = FinishedGuard {
+            finished: finished.clone(),
+        };
+        let apartment = *apartment;
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let _guard = guard;
+                initialize_apartment(&apartment)?;
+                let result = f(thread_token);
+                deinitialize();
+                Ok(result)
+            })?;
+        debug!("Started worker {}", name);
+        Ok(Worker {
+            name: name.to_string(),
+            stop_token,
+            finished,
+            thread: Some(thread),
+        })
+    }
+
+    /// Get the name of the worker thread.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the [StopToken] of the worker.
+    pub fn get_stop_token(&self) -> StopToken {
+        self.stop_token.clone()
+    }
+
+    /// Request the worker to stop. This does not wait for the thread to finish, use [Worker::join] for that.
+    pub fn stop(&self) {
+        debug!("Requesting worker {} to stop", self.name);
+        self.stop_token.stop();
+        if let Some(thread) = &self.thread {
+            thread.thread().unpark();
+        }
+    }
+
+    /// Check if the worker thread is still running.
+    /// Returns false when the function has returned or panicked, even if the result has not been read with [Worker::join].
+    pub fn is_running(&self) -> bool {
+        !self.is_finished()
+    }
+
+    fn is_finished(&self) -> bool {
+        let (lock, _) = &*self.finished;
+        match lock.lock() {
+            Ok(finished) => *finished,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    /// Wait for the worker thread to finish, with a timeout, and return the result of the function.
+    /// This does not request a stop, call [Worker::stop] first to shut the worker down.
+    /// Returns `None` if the thread is still running when the timeout expires,
+    /// and then `join` can be called again.
+    ///
+    /// Returns an error if the thread panicked, if COM could not be initialized in the requested apartment,
+    /// or if the result has already been returned by a previous call.
+    pub fn join(&mut self, timeout: Duration) -> WasapiRes<Option<T>> {
+        if self.thread.is_none() {
+            return Err(WasapiError::new("The worker has already been joined").into());
+        }
+        if !self.wait_for_finished(timeout) {
+            return Ok(None);
+        }
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Err(WasapiError::new("The worker has already been joined").into()),
+        };
+        match thread.join() {
+            Ok(Ok(value)) => {
+                debug!("Worker {} finished", self.name);
+                Ok(Some(value))
+            }
+            Ok(Err(err)) => Err(err.into()),
+            Err(_) => Err(WasapiError::new(&format!("The worker {} panicked", self.name)).into()),
+        }
+    }
+}
+
+impl<T> Worker<T> {
+    /// Wait for the finished flag to be set, with a timeout.
+    /// Returns `false` if the thread is still running when the timeout expires.
+    fn wait_for_finished(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (lock, condvar) = &*self.finished;
+        let mut finished = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while !*finished {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            finished = match condvar.wait_timeout(finished, deadline - now) {
+                Ok((guard, _)) => guard,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+        true
+    }
+}
+
+impl<T> Drop for Worker<T> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop_token.stop();
+            thread.thread().unpark();
+            if !self.wait_for_finished(DROP_TIMEOUT) {
+                warn!(
+                    "The worker {} did not stop within {:?}, detaching the thread",
+                    self.name, DROP_TIMEOUT
+                );
+                return;
+            }
+            if thread.join().is_err() {
+                warn!("The worker {} panicked", self.name);
+            }
+        }
+    }
+}